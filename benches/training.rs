@@ -220,8 +220,10 @@ pub fn run_lbfgs_training<M: SimpleModel + Model>(
         let res = optimiser.backward_step(&loss)?;
         match res {
             candle_optimisers::ModelOutcome::Converged(_, _) => break,
-            candle_optimisers::ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            candle_optimisers::ModelOutcome::Stepped(new_loss, _)
+            | candle_optimisers::ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
     Ok(())
@@ -0,0 +1,387 @@
+/*!
+Gradient accumulation
+
+[`GradAccumulator`] wraps any optimiser in this crate, summing the gradients of several micro-batches
+before taking a single step with their mean. This allows larger effective batch sizes than fit in memory.
+
+Gradients are added with [`GradAccumulator::accumulate`] (or [`GradAccumulator::accumulate_grads`]) and the
+step is taken with [`GradAccumulator::step_accumulated`]. Calling `step` or `backward_step` directly whilst the buffer holds
+gradients is an error, as the accumulated gradients would otherwise be silently ignored: either step with them or discard them
+with [`GradAccumulator::flush`] first.
+
+The mean divides by the number of micro-batches accumulated, including any in which a variable had no gradient.
+As the wrapped optimiser takes a single step, any weight decay is applied once per step rather than once per micro-batch.
+
+[`StreamingGradAverage`] is for online learning: every step is taken, but with the mean of the gradients of the last
+`window` steps (held in a ring buffer) rather than the current gradient alone. Until the window fills, the mean is over the
+gradients seen so far.
+*/
+
+use std::collections::{HashMap, VecDeque};
+
+use candle_core::{backprop::GradStore, Result, Tensor, TensorId, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{empty_grad_store, OptimVars};
+
+/// Optimiser wrapper accumulating gradients over several backward passes
+#[derive(Debug)]
+pub struct GradAccumulator<O: Optimizer + OptimVars> {
+    inner: O,
+    buffer: HashMap<TensorId, Tensor>,
+    count: usize,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for GradAccumulator<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        if self.count > 0 {
+            candle_core::bail!(
+                "step called with {} accumulated gradients: use step_accumulated to step with them or flush to discard them",
+                self.count
+            );
+        }
+        self.inner.step(grads)
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for GradAccumulator<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> GradAccumulator<O> {
+    /// wrap an optimiser, with an empty accumulation buffer
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self {
+            inner,
+            buffer: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    /// backpropagate the loss and add the gradients to the buffer
+    ///
+    /// # Errors
+    ///
+    /// Errors if the backward pass fails
+    pub fn accumulate(&mut self, loss: &Tensor) -> Result<()> {
+        let grads = loss.backward()?;
+        self.accumulate_grads(&grads)
+    }
+
+    /// add the gradients of the optimised variables in `grads` to the buffer
+    ///
+    /// # Errors
+    ///
+    /// Errors if the gradients cannot be added to the buffer
+    pub fn accumulate_grads(&mut self, grads: &GradStore) -> Result<()> {
+        for var in self.inner.vars() {
+            if let Some(grad) = grads.get(var) {
+                let sum = match self.buffer.get(&var.id()) {
+                    Some(sum) => (sum + grad)?,
+                    None => grad.clone(),
+                };
+                self.buffer.insert(var.id(), sum);
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// step the optimiser with the mean of the accumulated gradients, emptying the buffer
+    ///
+    /// does nothing if no gradients have been accumulated
+    ///
+    /// # Errors
+    ///
+    /// Errors if the step fails; the buffer is emptied regardless
+    pub fn step_accumulated(&mut self) -> Result<()> {
+        match self.take_mean()? {
+            Some(grads) => self.inner.step(&grads),
+            None => Ok(()),
+        }
+    }
+
+    /// take the mean of the accumulated gradients, emptying the buffer
+    ///
+    /// returns `None` if no gradients have been accumulated
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn take_mean(&mut self) -> Result<Option<GradStore>> {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        let count = self.count as f64;
+        let mut grads = empty_grad_store()?;
+        for var in self.inner.vars() {
+            if let Some(sum) = self.buffer.get(&var.id()) {
+                grads.insert(var, (sum / count)?);
+            }
+        }
+        self.flush();
+        Ok(Some(grads))
+    }
+
+    /// discard any accumulated gradients
+    pub fn flush(&mut self) {
+        self.buffer.clear();
+        self.count = 0;
+    }
+
+    /// number of gradients accumulated since the last step or flush
+    #[must_use]
+    pub fn accumulated(&self) -> usize {
+        self.count
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser, discarding any accumulated gradients
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+/// Optimiser wrapper stepping with the mean gradient over a sliding window of recent steps
+#[derive(Debug)]
+pub struct StreamingGradAverage<O: Optimizer + OptimVars> {
+    inner: O,
+    window: usize,
+    ring: VecDeque<HashMap<TensorId, Tensor>>,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for StreamingGradAverage<O> {
+    type Config = O::Config;
+
+    /// create the wrapped optimiser, with a window of a single step (so stepping with the current gradient)
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Self::new(O::new(vars, config)?, 1)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        if self.ring.len() == self.window {
+            self.ring.pop_front();
+        }
+        let mut latest = HashMap::new();
+        for var in self.inner.vars() {
+            if let Some(grad) = grads.get(var) {
+                latest.insert(var.id(), grad.clone());
+            }
+        }
+        self.ring.push_back(latest);
+        let mut mean = empty_grad_store()?;
+        for var in self.inner.vars() {
+            // a var is averaged over the steps in which it had a gradient
+            let mut sum: Option<Tensor> = None;
+            let mut count = 0_u32;
+            for grad in self.ring.iter().filter_map(|grads| grads.get(&var.id())) {
+                sum = Some(match sum {
+                    Some(sum) => (sum + grad)?,
+                    None => grad.clone(),
+                });
+                count += 1;
+            }
+            if let Some(sum) = sum {
+                mean.insert(var, (sum / f64::from(count))?);
+            }
+        }
+        self.inner.step(&mean)
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for StreamingGradAverage<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> StreamingGradAverage<O> {
+    /// wrap an optimiser, stepping with the mean gradient of the last `window` steps
+    ///
+    /// # Errors
+    ///
+    /// Errors if `window` is 0
+    pub fn new(inner: O, window: usize) -> Result<Self> {
+        if window == 0 {
+            candle_core::bail!("streaming gradient window must be at least 1");
+        }
+        Ok(Self {
+            inner,
+            window,
+            ring: VecDeque::with_capacity(window),
+        })
+    }
+
+    /// the number of steps averaged over
+    #[must_use]
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// the number of gradients currently held, which is less than the window until it has filled
+    #[must_use]
+    pub fn filled(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// discard the held gradients, so the next step uses its own gradient alone
+    pub fn clear(&mut self) {
+        self.ring.clear();
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser, discarding the held gradients
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use crate::Decay;
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    fn setup() -> Result<(Var, GradAccumulator<SGD>)> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let optim = GradAccumulator::new(SGD::new(vec![w.clone()], params)?);
+        Ok((w, optim))
+    }
+
+    #[test]
+    fn accumulate_test() -> Result<()> {
+        let (w, mut optim) = setup()?;
+        // gradients of [1, 1] and [3, 3] average to [2, 2]
+        optim.accumulate(&w.as_tensor().sum_all()?)?;
+        optim.accumulate(&(w.as_tensor() * 3.)?.sum_all()?)?;
+        assert_eq!(optim.accumulated(), 2);
+        optim.step_accumulated()?;
+        assert_eq!(optim.accumulated(), 0);
+        let w = w.to_vec1::<f32>()?;
+        assert_approx_eq!(w[0], 0.8);
+        assert_approx_eq!(w[1], 1.8);
+        Ok(())
+    }
+
+    #[test]
+    fn weight_decay_once_test() -> Result<()> {
+        for decay in [Decay::WeightDecay(0.5), Decay::DecoupledWeightDecay(0.5)] {
+            let w = Var::new(&[1f32], &Device::Cpu)?;
+            let b = Var::new(&[1f32], &Device::Cpu)?;
+            let params = ParamsSGD {
+                lr: 0.1,
+                weight_decay: Some(decay),
+                ..Default::default()
+            };
+            let mut optim = GradAccumulator::new(SGD::new(vec![w.clone(), b.clone()], params)?);
+            // b only has a gradient in one of the micro-batches, but is still divided by the number of them
+            optim.accumulate(&(w.as_tensor() + b.as_tensor())?.sum_all()?)?;
+            for _ in 0..3 {
+                optim.accumulate(&w.as_tensor().sum_all()?)?;
+            }
+            optim.step_accumulated()?;
+            // the decay is applied once for the step rather than once per micro-batch, both when added to the
+            // gradient (1 - 0.1 * (1 + 0.5)) and when decoupled ((1 - 0.1 * 0.5) - 0.1 * 1)
+            assert_approx_eq!(w.to_vec1::<f32>()?[0], 0.85);
+            // a gradient of 1 / 4, with the same decay
+            assert_approx_eq!(b.to_vec1::<f32>()?[0], 0.925);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stale_buffer_test() -> Result<()> {
+        let (w, mut optim) = setup()?;
+        let loss = w.as_tensor().sum_all()?;
+        // the non-accumulating path is unaffected
+        optim.backward_step(&loss)?;
+        optim.accumulate(&loss)?;
+        let err = optim.backward_step(&loss).unwrap_err().to_string();
+        assert!(err.contains("step_accumulated"));
+        // the erroneous step did not change the variable
+        assert_eq!(w.to_vec1::<f32>()?, [0.9, 1.9]);
+        optim.flush();
+        optim.backward_step(&loss)?;
+        let w = w.to_vec1::<f32>()?;
+        assert_approx_eq!(w[0], 0.8);
+        assert_approx_eq!(w[1], 1.8);
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_test() -> Result<()> {
+        let w = Var::new(&[0f32], &Device::Cpu)?;
+        let sgd = SGD::new(
+            vec![w.clone()],
+            ParamsSGD {
+                lr: 1.,
+                ..Default::default()
+            },
+        )?;
+        let mut optim = StreamingGradAverage::new(sgd, 3)?;
+        // a stream of gradients 1, 2, 6, 4, 8: the applied gradients are the means of the available window
+        let mut expected = 0.;
+        for (grad, mean) in [(1., 1.), (2., 1.5), (6., 3.), (4., 4.), (8., 6.)] {
+            optim.backward_step(&(w.as_tensor() * grad)?.sum_all()?)?;
+            expected -= mean;
+            assert_approx_eq!(w.to_vec1::<f32>()?[0], expected);
+        }
+        assert_eq!(optim.filled(), 3);
+        optim.clear();
+        optim.backward_step(&w.as_tensor().sum_all()?)?;
+        assert_approx_eq!(w.to_vec1::<f32>()?[0], expected - 1.);
+        assert!(StreamingGradAverage::new(optim.into_inner(), 0).is_err());
+        Ok(())
+    }
+}
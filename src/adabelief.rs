@@ -0,0 +1,314 @@
+/*!
+AdaBelief optimiser
+
+Described in [AdaBelief Optimizer: Adapting Stepsizes by the Belief in Observed Gradients](https://arxiv.org/abs/2010.07468)
+
+This replaces the second moment of the gradient used by Adam with the second moment of the difference between the gradient
+and its exponential moving average, so the step size is large when the gradient agrees with the "belief" given by the EMA.
+
+Optionally the RAdam rectification can be applied, as described in
+[On the Variance of the Adaptive Learning Rate and Beyond](https://arxiv.org/abs/1908.03265)
+
+Pseudocode (including decoupling of weight decay and rectification):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\epsilon \\text{ (epsilon)}, \\: \\textit{rectify}                      \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+        s_0 \\leftarrow 0 \\text{ ( belief)},                                       \\\\
+    &\\hspace{18mm} \\rho_{\\infty} \\leftarrow 2/(1-\\beta_2) -1                      \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}  \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\lambda \\textbf{ is } \\text{Some}                        \\\\
+    &\\hspace{10mm}\\textbf{if} \\: \\textit{decoupled}                       \\\\
+    &\\hspace{15mm} \\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\lambda \\theta_{t-1}                    \\\\
+    &\\hspace{10mm}\\textbf{else}                                                              \\\\
+    &\\hspace{15mm} g_t \\leftarrow g_t + \\lambda  \\theta_{t-1}                            \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}s_t           \\leftarrow   \\beta_2 s_{t-1} + (1-\\beta_2) (g_t - m_t)^2 + \\epsilon          \\\\
+    &\\hspace{5mm}\\widehat{m_t} \\leftarrow   m_t/\\big(1-\\beta_1^t \\big)                   \\\\
+    &\\hspace{5mm}\\widehat{s_t} \\leftarrow   s_t/\\big(1-\\beta_2^t \\big)                   \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\textit{rectify}                                               \\\\
+    &\\hspace{10mm}\\rho_t \\leftarrow \\rho_{\\infty} -
+        2 t \\beta^t_2 /\\big(1-\\beta_2^t \\big)                                    \\\\[0.1.ex]
+    &\\hspace{10mm}\\textbf{if} \\: \\rho_t > 5                                               \\\\
+    &\\hspace{15mm} r_t \\leftarrow
+    \\sqrt{\\frac{(\\rho_t-4)(\\rho_t-2)\\rho_{\\infty}}{(\\rho_{\\infty}-4)(\\rho_{\\infty}-2) \\rho_t}} \\\\
+    &\\hspace{15mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma r_t \\widehat{m_t}/
+        \\big(\\sqrt{\\widehat{s_t}} + \\epsilon \\big)        \\\\
+    &\\hspace{10mm}\\textbf{else}                                                           \\\\
+    &\\hspace{15mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\widehat{m_t}                \\\\
+    &\\hspace{5mm}\\textbf{else}                                                           \\\\
+    &\\hspace{10mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\widehat{m_t}/
+        \\big(\\sqrt{\\widehat{s_t}} + \\epsilon \\big)        \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot,
+    OptimParams, OptimVars, UnusedConfig,
+};
+
+/// AdaBelief optimiser
+///
+/// Described in [AdaBelief Optimizer: Adapting Stepsizes by the Belief in Observed Gradients](https://arxiv.org/abs/2010.07468)
+#[derive(Debug)]
+pub struct AdaBelief {
+    vars: Vec<VarAdaBelief>,
+    params: ParamsAdaBelief,
+    rho_inf: f64,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarAdaBelief {
+    theta: Var,
+    m: Var,
+    s: Var,
+}
+
+/// Parameters for the AdaBelief optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAdaBelief {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of the belief (second moment of the gradient about its moving average)
+    pub beta_2: f64,
+    /// Term added to the belief and the denominator to improve numerical stability
+    pub eps: f64,
+    /// Weight decay
+    pub weight_decay: Option<Decay>,
+    /// Whether to apply the RAdam rectification to the step
+    pub rectify: bool,
+}
+
+impl Default for ParamsAdaBelief {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.9,
+            beta_2: 0.999,
+            eps: 1e-16,
+            weight_decay: None,
+            rectify: false,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsAdaBelief {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Optimizer for AdaBelief {
+    type Config = ParamsAdaBelief;
+
+    fn new(vars: Vec<Var>, params: ParamsAdaBelief) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let s = Var::zeros(shape, dtype, device)?;
+                Ok(VarAdaBelief { theta: var, m, s })
+            })
+            .collect::<Result<Vec<VarAdaBelief>>>()?;
+        let rho_inf = 2. / (1. - params.beta_2) - 1.;
+        Ok(Self {
+            vars,
+            params,
+            rho_inf,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let beta_1 = self.params.beta_1;
+        let beta_2 = self.params.beta_2;
+        let bias_correction_1 = bias_correction(beta_1, self.t);
+        let bias_correction_2 = bias_correction(beta_2, self.t);
+        // None if the adaptive step should be used unscaled, otherwise the rectification term
+        // (itself None if the variance is intractable and an un-adapted step is taken)
+        let rectification = if self.params.rectify {
+            let rho_t = self.rho_inf - 2. * self.t * beta_2.powf(self.t) / bias_correction_2;
+            if rho_t > 5. {
+                Some(Some(
+                    ((rho_t - 4.) * (rho_t - 2.) * self.rho_inf
+                        / ((self.rho_inf - 4.) * (self.rho_inf - 2.) * rho_t))
+                        .sqrt(),
+                ))
+            } else {
+                Some(None)
+            }
+        } else {
+            None
+        };
+
+        for var in &self.vars {
+            let theta = &var.theta;
+            let m = &var.m;
+            let s = &var.s;
+            if let Some(grad) = grads.get(theta) {
+                let grad = match self.params.weight_decay {
+                    Some(Decay::WeightDecay(wd)) => (grad + (wd * theta.as_tensor())?)?,
+                    Some(Decay::DecoupledWeightDecay(decay)) => {
+                        // decoupled weight decay step
+                        theta.set(&(theta.as_tensor() * self.params.lr.mul_add(-decay, 1.))?)?;
+                        grad.clone()
+                    }
+                    None => grad.clone(),
+                };
+                let m_next = ((beta_1 * m.as_tensor())? + ((1. - beta_1) * &grad)?)?;
+                let s_next = (((beta_2 * s.as_tensor())?
+                    + ((1. - beta_2) * (&grad - &m_next)?.sqr()?)?)?
+                    + self.params.eps)?;
+                let m_hat = (&m_next / bias_correction_1)?;
+
+                let delta = match rectification {
+                    Some(None) => (self.params.lr * m_hat)?,
+                    Some(Some(r)) => {
+                        let denom = ((&s_next / bias_correction_2)?.sqrt()? + self.params.eps)?;
+                        (self.params.lr * r * m_hat.div(&denom)?)?
+                    }
+                    None => {
+                        let denom = ((&s_next / bias_correction_2)?.sqrt()? + self.params.eps)?;
+                        (self.params.lr * m_hat.div(&denom)?)?
+                    }
+                };
+                theta.set(&theta.sub(&delta)?)?;
+                m.set(&m_next)?;
+                s.set(&s_next)?;
+            }
+        }
+
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for AdaBelief {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        // the length of the approximated SMA depends on beta_2, so must follow it
+        self.rho_inf = 2. / (1. - config.beta_2) - 1.;
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for AdaBelief {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for AdaBelief {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl AdaBelief {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsAdaBelief {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = AdaBelief::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn into_inner_test() -> Result<()> {
+        let params = ParamsAdaBelief::default();
+        let w = Var::new(&[[3f32, 1.]], &Device::Cpu)?;
+        let b = Var::new(-2f32, &Device::Cpu)?;
+        let optim = AdaBelief::new(vec![w.clone(), b.clone()], params)?;
+        let inner = optim.into_inner();
+        assert_eq!(inner[0].as_tensor().to_vec2::<f32>()?, &[[3f32, 1.]]);
+        assert_approx_eq!(inner[1].as_tensor().to_vec0::<f32>()?, -2_f32);
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsAdaBelief {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = AdaBelief::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsAdaBelief {
+            lr: 0.002,
+            rectify: true,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        optim.set_params(ParamsAdaBelief {
+            beta_2: 0.99,
+            ..new_params
+        });
+        assert_approx_eq!(optim.rho_inf, 199., 1e-9);
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+/*!
+AdaBound and AMSBound optimisers
+
+Described in [Adaptive Gradient Methods with Dynamic Bound of Learning Rate](https://arxiv.org/abs/1902.09843)
+
+The per-element learning rates of Adam (or AMSGrad for AMSBound) are clipped to bounds that start wide and
+converge to a final SGD learning rate, so the optimiser transitions smoothly from Adam to SGD.
+
+As in the reference implementation, the final learning rate is scaled with the learning rate, so that it follows
+any schedule applied with `set_learning_rate`: it is `final_lr` when the learning rate is at its value on construction
+(or when the parameters were last set).
+
+Pseudocode (including decoupling of weight decay and AMSBound):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\alpha^* \\text{ (final lr)}, \\: \\eta \\text{ (gamma)}, \\:
+        \\epsilon \\text{ (epsilon)}, \\: \\textit{amsbound}                                   \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+        v_0 \\leftarrow 0 \\text{ ( second moment)}, \\: \\widehat{v_0}^{max}\\leftarrow 0   \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\lambda \\textbf{ is } \\text{Some}                        \\\\
+    &\\hspace{10mm}\\textbf{if} \\: \\textit{decoupled}                       \\\\
+    &\\hspace{15mm} \\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\lambda \\theta_{t-1}                    \\\\
+    &\\hspace{10mm}\\textbf{else}                                                              \\\\
+    &\\hspace{15mm} g_t \\leftarrow g_t + \\lambda  \\theta_{t-1}                            \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}v_t           \\leftarrow   \\beta_2 v_{t-1} + (1-\\beta_2) g^2_t          \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\textit{amsbound}                                               \\\\
+    &\\hspace{10mm} v_t^{max} \\leftarrow \\mathrm{max}(v_{t-1}^{max}, v_t)       \\\\
+    &\\hspace{10mm} v_t \\leftarrow v_t^{max}                                                  \\\\
+    &\\hspace{5mm}\\eta_l \\leftarrow \\alpha^* \\big(1 - 1/(\\eta t + 1)\\big), \\:
+        \\eta_u \\leftarrow \\alpha^* \\big(1 + 1/(\\eta t)\\big)                            \\\\
+    &\\hspace{5mm}\\alpha_t \\leftarrow \\mathrm{clip}\\left(\\gamma
+        \\frac{\\sqrt{1-\\beta_2^t}}{\\big(1-\\beta_1^t\\big)\\big(\\sqrt{v_t} + \\epsilon\\big)}, \\eta_l, \\eta_u\\right)   \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\alpha_t m_t                                 \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot,
+    OptimParams, OptimVars, UnusedConfig,
+};
+
+/// AdaBound optimiser
+///
+/// Described in [Adaptive Gradient Methods with Dynamic Bound of Learning Rate](https://arxiv.org/abs/1902.09843)
+#[derive(Debug)]
+pub struct AdaBound {
+    vars: Vec<VarAdaBound>,
+    params: ParamsAdaBound,
+    /// learning rate the final learning rate is relative to
+    base_lr: f64,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarAdaBound {
+    theta: Var,
+    m: Var,
+    v: Var,
+    /// maximum of the second moment, only used by AMSBound
+    vmax: Option<Var>,
+}
+
+/// Parameters for the AdaBound optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAdaBound {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of second moment
+    pub beta_2: f64,
+    /// Final (SGD) learning rate the bounds converge to
+    pub final_lr: f64,
+    /// Convergence speed of the bounds
+    pub gamma: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Weight decay
+    pub weight_decay: Option<Decay>,
+    /// Whether to use the AMSBound variant
+    pub amsbound: bool,
+}
+
+impl Default for ParamsAdaBound {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.9,
+            beta_2: 0.999,
+            final_lr: 0.1,
+            gamma: 1e-3,
+            eps: 1e-8,
+            weight_decay: None,
+            amsbound: false,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsAdaBound {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Optimizer for AdaBound {
+    type Config = ParamsAdaBound;
+
+    fn new(vars: Vec<Var>, params: ParamsAdaBound) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let v = Var::zeros(shape, dtype, device)?;
+                let vmax = if params.amsbound {
+                    Some(Var::zeros(shape, dtype, device)?)
+                } else {
+                    None
+                };
+                Ok(VarAdaBound {
+                    theta: var,
+                    m,
+                    v,
+                    vmax,
+                })
+            })
+            .collect::<Result<Vec<VarAdaBound>>>()?;
+        Ok(Self {
+            vars,
+            base_lr: params.lr,
+            params,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let beta_1 = self.params.beta_1;
+        let beta_2 = self.params.beta_2;
+        let step_size = self.params.lr * bias_correction(beta_2, self.t).sqrt()
+            / bias_correction(beta_1, self.t);
+        let (lower, upper) = self.bounds();
+
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            let m = &var.m;
+            let v = &var.v;
+            if let Some(grad) = grads.get(theta) {
+                let grad = match self.params.weight_decay {
+                    Some(Decay::WeightDecay(wd)) => (grad + (wd * theta.as_tensor())?)?,
+                    Some(Decay::DecoupledWeightDecay(decay)) => {
+                        // decoupled weight decay step
+                        theta.set(&(theta.as_tensor() * self.params.lr.mul_add(-decay, 1.))?)?;
+                        grad.clone()
+                    }
+                    None => grad.clone(),
+                };
+                let m_next = ((beta_1 * m.as_tensor())? + ((1. - beta_1) * &grad)?)?;
+                let v_next = ((beta_2 * v.as_tensor())? + ((1. - beta_2) * grad.sqr()?)?)?;
+                let denom = if self.params.amsbound {
+                    // the maximum is allocated on the first AMSBound step if the parameters were changed to use it
+                    let vmax = match &var.vmax {
+                        Some(vmax) => vmax.as_tensor().maximum(&v_next)?,
+                        None => v_next.clone(),
+                    };
+                    let denom = (vmax.sqrt()? + self.params.eps)?;
+                    match &var.vmax {
+                        Some(var_vmax) => var_vmax.set(&vmax)?,
+                        None => var.vmax = Some(Var::from_tensor(&vmax)?),
+                    }
+                    denom
+                } else {
+                    (v_next.sqrt()? + self.params.eps)?
+                };
+                let rate = (step_size / denom)?.clamp(lower, upper)?;
+                theta.set(&theta.sub(&rate.mul(&m_next)?)?)?;
+                m.set(&m_next)?;
+                v.set(&v_next)?;
+            }
+        }
+
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for AdaBound {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// set the parameters, with `final_lr` taken relative to the new learning rate
+    fn set_params(&mut self, config: Self::Config) {
+        self.base_lr = config.lr;
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for AdaBound {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for AdaBound {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl AdaBound {
+    /// the lower and upper bounds on the per-element learning rate for the next step
+    #[must_use]
+    pub fn bounds(&self) -> (f64, f64) {
+        let final_lr = self.params.final_lr * self.params.lr / self.base_lr;
+        let gamma_t = self.params.gamma * self.t;
+        (
+            final_lr * (1. - 1. / (gamma_t + 1.)),
+            final_lr * (1. + 1. / gamma_t),
+        )
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsAdaBound {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = AdaBound::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsAdaBound {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = AdaBound::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsAdaBound {
+            lr: 0.002,
+            amsbound: true,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn bounds_test() -> Result<()> {
+        let params = ParamsAdaBound {
+            lr: 0.01,
+            final_lr: 0.1,
+            gamma: 0.5,
+            ..Default::default()
+        };
+        let w = Var::new(&[0f32], &Device::Cpu)?;
+        let mut optim = AdaBound::new(vec![w.clone()], params)?;
+        let (lower, upper) = optim.bounds();
+        assert_approx_eq!(lower, 0.1 / 3.);
+        assert_approx_eq!(upper, 0.3);
+        let loss = w.as_tensor().sum_all()?;
+        optim.backward_step(&loss)?;
+        let (lower, upper) = optim.bounds();
+        assert_approx_eq!(lower, 0.05);
+        assert_approx_eq!(upper, 0.2);
+        // halving the learning rate halves the final learning rate the bounds converge to
+        optim.set_learning_rate(0.005);
+        let (lower, upper) = optim.bounds();
+        assert_approx_eq!(lower, 0.025);
+        assert_approx_eq!(upper, 0.1);
+        Ok(())
+    }
+}
@@ -36,7 +36,7 @@ $$
 use candle_core::{Result, Var};
 use candle_nn::optim::Optimizer;
 
-use crate::{Decay, OptimParams};
+use crate::{warn_unused_config, zero_decay, Decay, OptimParams, OptimVars, UnusedConfig};
 
 /// Adadelta optimiser
 ///
@@ -79,10 +79,19 @@ impl Default for ParamsAdaDelta {
     }
 }
 
+impl UnusedConfig for ParamsAdaDelta {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
 impl Optimizer for Adadelta {
     type Config = ParamsAdaDelta;
 
     fn new(vars: Vec<Var>, params: ParamsAdaDelta) -> Result<Self> {
+        warn_unused_config(&params);
         let vars = vars
             .into_iter()
             .filter(|var| var.dtype().is_float())
@@ -193,6 +202,12 @@ impl OptimParams for Adadelta {
     }
 }
 
+impl OptimVars for Adadelta {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
 impl Adadelta {
     /// Return the vars being optimised
     #[must_use]
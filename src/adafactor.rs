@@ -0,0 +1,425 @@
+/*!
+Adafactor optimiser
+
+Described in [Adafactor: Adaptive Learning Rates with Sublinear Memory Cost](https://arxiv.org/abs/1804.04235)
+
+For variables of rank two or more, the second moment of the gradient is not stored in full: instead only its means over
+the rows and over the columns (of the last two dimensions) are kept, and the second moment is approximated by their outer
+product divided by the overall mean. For an $n \\times m$ matrix this needs $n + m$ values rather than $nm$.
+Variables of rank zero or one keep the full second moment.
+
+The step size can be relative to the scale of each variable, and the update is clipped to a maximum root mean square.
+The first moment is optional, and off by default to save memory.
+
+Pseudocode (for a matrix variable, following the Hugging Face implementation):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\theta_0 \\text{ (params)}, \\: f(\\theta) \\text{ (objective)},
+        \\: \\epsilon_1, \\epsilon_2 \\text{ (epsilons)}, \\: d \\text{ (clip threshold)},                          \\\\
+    &\\hspace{13mm} \\: c \\text{ (decay rate)}, \\: \\beta_1 \\text{ (first moment)}, \\: \\lambda \\text{ (weight decay)}          \\\\
+    &\\textbf{initialize} :  R_0 \\leftarrow 0, \\: C_0 \\leftarrow 0, \\: m_0 \\leftarrow 0     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\rho_t \\leftarrow \\min\\left(\\gamma, 1 / \\sqrt{t}\\right) \\text{ if relative step, else } \\gamma \\\\
+    &\\hspace{5mm}\\alpha_t \\leftarrow \\max\\left(\\epsilon_2, \\text{RMS}(\\theta_{t-1})\\right) \\rho_t \\text{ if scaling by the parameter, else } \\rho_t \\\\
+    &\\hspace{5mm}\\hat{\\beta}_{2t} \\leftarrow 1 - t^{c}                                   \\\\
+    &\\hspace{5mm}R_t \\leftarrow \\hat{\\beta}_{2t} R_{t-1} + (1 - \\hat{\\beta}_{2t}) \\text{mean}_{\\text{cols}}(g_t^2 + \\epsilon_1) \\\\
+    &\\hspace{5mm}C_t \\leftarrow \\hat{\\beta}_{2t} C_{t-1} + (1 - \\hat{\\beta}_{2t}) \\text{mean}_{\\text{rows}}(g_t^2 + \\epsilon_1) \\\\
+    &\\hspace{5mm}\\hat{V}_t \\leftarrow R_t C_t / \\text{mean}(R_t)                         \\\\
+    &\\hspace{5mm}U_t \\leftarrow g_t / \\sqrt{\\hat{V}_t}                                 \\\\
+    &\\hspace{5mm}U_t \\leftarrow \\alpha_t U_t / \\max\\left(1, \\text{RMS}(U_t) / d\\right)  \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\beta_1 \\textbf{ is } \\text{Some}                        \\\\
+    &\\hspace{10mm}m_t \\leftarrow \\beta_1 m_{t-1} + (1 - \\beta_1) U_t, \\: U_t \\leftarrow m_t \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\lambda \\textbf{ is } \\text{Some}                        \\\\
+    &\\hspace{10mm}\\theta_{t-1} \\leftarrow \\theta_{t-1} - \\lambda \\alpha_t \\theta_{t-1}     \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - U_t                                  \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+*/
+
+use candle_core::{DType, Result, Tensor, Var, D};
+use candle_nn::optim::Optimizer;
+use log::warn;
+
+use crate::{warn_unused_config, OptimParams, OptimVars, UnusedConfig};
+
+/// Adafactor optimiser
+///
+/// Described in [Adafactor: Adaptive Learning Rates with Sublinear Memory Cost](https://arxiv.org/abs/1804.04235)
+#[derive(Debug)]
+pub struct Adafactor {
+    vars: Vec<VarAdafactor>,
+    params: ParamsAdafactor,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarAdafactor {
+    theta: Var,
+    v: SecondMoment,
+    m: Option<Var>,
+}
+
+/// Estimate of the second moment of the gradient of a variable
+#[derive(Debug)]
+enum SecondMoment {
+    /// means over the last dimension (row) and second to last dimension (column), for variables of rank two or more
+    Factored { row: Var, col: Var },
+    /// the full second moment, for variables of rank zero or one
+    Full(Var),
+}
+
+impl SecondMoment {
+    fn new(theta: &Var) -> Result<Self> {
+        let dims = theta.dims();
+        let rank = dims.len();
+        if rank >= 2 {
+            let row = Var::zeros(&dims[..rank - 1], theta.dtype(), theta.device())?;
+            let mut col_dims = dims[..rank - 2].to_vec();
+            col_dims.push(dims[rank - 1]);
+            let col = Var::zeros(col_dims, theta.dtype(), theta.device())?;
+            Ok(Self::Factored { row, col })
+        } else {
+            Ok(Self::Full(Var::zeros(dims, theta.dtype(), theta.device())?))
+        }
+    }
+
+    /// update the estimate with the squared gradient, and return the gradient scaled by its inverse square root
+    fn update(&self, grad: &Tensor, grad_sq: &Tensor, beta_2: f64) -> Result<Tensor> {
+        match self {
+            Self::Factored { row, col } => {
+                let row_next =
+                    ((row.as_tensor() * beta_2)? + (grad_sq.mean(D::Minus1)? * (1. - beta_2))?)?;
+                let col_next =
+                    ((col.as_tensor() * beta_2)? + (grad_sq.mean(D::Minus2)? * (1. - beta_2))?)?;
+                row.set(&row_next)?;
+                col.set(&col_next)?;
+                // 1 / sqrt(R C / mean(R)) split into row and column factors
+                let row_factor = row_next
+                    .broadcast_div(&row_next.mean_keepdim(D::Minus1)?)?
+                    .sqrt()?
+                    .recip()?
+                    .unsqueeze(D::Minus1)?;
+                let col_factor = col_next.sqrt()?.recip()?.unsqueeze(D::Minus2)?;
+                grad.broadcast_mul(&row_factor)?.broadcast_mul(&col_factor)
+            }
+            Self::Full(v) => {
+                let v_next = ((v.as_tensor() * beta_2)? + (grad_sq * (1. - beta_2))?)?;
+                v.set(&v_next)?;
+                grad.div(&v_next.sqrt()?)
+            }
+        }
+    }
+}
+
+/// Parameters for the Adafactor optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAdafactor {
+    /// Learning rate: with `relative_step` this is the maximum of the relative step size
+    pub lr: f64,
+    /// Regularisation constants for the squared gradient and the parameter scale respectively
+    pub eps: (f64, f64),
+    /// Threshold for the root mean square of the update, above which it is scaled down
+    pub clip_threshold: Option<f64>,
+    /// Exponent of the step for the second moment decay $\\hat{\\beta}_{2t} = 1 - t^{c}$
+    pub decay_rate: f64,
+    /// Coefficient for the moving average of the update, if a first moment is used
+    pub beta_1: Option<f64>,
+    /// Decoupled weight decay, scaled by the step size
+    pub weight_decay: Option<f64>,
+    /// Scale the step size by the root mean square of each variable
+    pub scale_parameter: bool,
+    /// Use a step size decaying as $1 / \\sqrt{t}$, capped at `lr`
+    pub relative_step: bool,
+    /// With `relative_step`, warm up the step size as $10^{-6} t$ rather than starting at `lr`
+    pub warmup_init: bool,
+}
+
+impl Default for ParamsAdafactor {
+    fn default() -> Self {
+        Self {
+            lr: 1e-2,
+            eps: (1e-30, 1e-3),
+            clip_threshold: Some(1.),
+            decay_rate: -0.8,
+            beta_1: None,
+            weight_decay: None,
+            scale_parameter: true,
+            relative_step: true,
+            warmup_init: false,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsAdafactor {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused = Vec::new();
+        if !self.relative_step && self.warmup_init {
+            unused.push("warmup_init has no effect without relative_step".to_string());
+        }
+        unused
+    }
+}
+
+impl Optimizer for Adafactor {
+    type Config = ParamsAdafactor;
+
+    fn new(vars: Vec<Var>, params: ParamsAdafactor) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let v = SecondMoment::new(&var)?;
+                let m = if params.beta_1.is_some() {
+                    Some(Var::zeros(var.shape(), var.dtype(), var.device())?)
+                } else {
+                    None
+                };
+                Ok(VarAdafactor { theta: var, v, m })
+            })
+            .collect::<Result<Vec<VarAdafactor>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let rel_step = if self.params.relative_step {
+            let max_step = if self.params.warmup_init {
+                1e-6 * self.t
+            } else {
+                self.params.lr
+            };
+            max_step.min(self.t.sqrt().recip())
+        } else {
+            self.params.lr
+        };
+        let beta_2 = 1. - self.t.powf(self.params.decay_rate);
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let step_size = if self.params.scale_parameter {
+                    self.params.eps.1.max(rms(theta)?) * rel_step
+                } else {
+                    rel_step
+                };
+                let grad_sq = (grad.sqr()? + self.params.eps.0)?;
+                let mut update = var.v.update(grad, &grad_sq, beta_2)?;
+                if let Some(threshold) = self.params.clip_threshold {
+                    let denom = (rms(&update)? / threshold).max(1.);
+                    update = (update / denom)?;
+                }
+                update = (update * step_size)?;
+                if let (Some(beta_1), Some(m)) = (self.params.beta_1, &var.m) {
+                    let m_next = ((m.as_tensor() * beta_1)? + (update * (1. - beta_1))?)?;
+                    m.set(&m_next)?;
+                    update = m_next;
+                }
+                if let Some(decay) = self.params.weight_decay {
+                    theta.set(&(theta.as_tensor() * (-decay * step_size + 1.))?)?;
+                }
+                theta.set(&theta.sub(&update)?)?;
+            }
+        }
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+/// root mean square of a tensor as an f64
+fn rms(tensor: &Tensor) -> Result<f64> {
+    Ok(tensor
+        .sqr()?
+        .mean_all()?
+        .to_dtype(DType::F64)?
+        .to_scalar::<f64>()?
+        .sqrt())
+}
+
+impl OptimParams for Adafactor {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// Set the parameters for the optimiser
+    ///
+    /// # Warning
+    ///
+    /// As using a first moment requires having tracked an additional tensor,
+    /// whether `beta_1` is set cannot be changed once set initially on creation of the optimiser.
+    fn set_params(&mut self, config: Self::Config) {
+        if config.beta_1.is_some() == self.params.beta_1.is_some() {
+            self.params = config;
+        } else {
+            warn!("whether a first moment is used cannot be changed once set");
+            let beta_1 = self.params.beta_1;
+            self.params = config;
+            self.params.beta_1 = beta_1;
+        }
+    }
+}
+
+impl OptimVars for Adafactor {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Adafactor {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsAdafactor {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Adafactor::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsAdafactor {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Adafactor::new(vec![w.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsAdafactor {
+            lr: 0.002,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        // the first moment cannot be switched on
+        optim.set_params(ParamsAdafactor {
+            beta_1: Some(0.9),
+            ..Default::default()
+        });
+        assert_eq!(optim.params().beta_1, None);
+        Ok(())
+    }
+
+    #[test]
+    fn factored_layout_test() -> Result<()> {
+        let matrix = Var::zeros((3, 4), DType::F32, &Device::Cpu)?;
+        let batch = Var::zeros((2, 3, 4), DType::F32, &Device::Cpu)?;
+        let vector = Var::zeros(4, DType::F32, &Device::Cpu)?;
+        let scalar = Var::zeros((), DType::F32, &Device::Cpu)?;
+        let optim = Adafactor::new(
+            vec![matrix, batch, vector, scalar],
+            ParamsAdafactor::default(),
+        )?;
+        let layouts: Vec<(Vec<usize>, Vec<usize>)> = optim
+            .vars
+            .iter()
+            .map(|v| match &v.v {
+                SecondMoment::Factored { row, col } => (row.dims().to_vec(), col.dims().to_vec()),
+                SecondMoment::Full(v) => (v.dims().to_vec(), vec![]),
+            })
+            .collect();
+        assert_eq!(
+            layouts,
+            [
+                (vec![3], vec![4]),
+                (vec![2, 3], vec![2, 4]),
+                (vec![4], vec![]),
+                (vec![], vec![]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn first_step_test() -> Result<()> {
+        // on the first step the decay is 0, so the second moment is just the squared gradient and the unclipped update
+        // is the sign of the gradient: the step is then the relative step 1e-2 scaled by the RMS of the variable
+        let vector = Var::new(&[3f64, -4.], &Device::Cpu)?;
+        let matrix = Var::new(&[[1f64, 1.], [1., 1.]], &Device::Cpu)?;
+        let mut optim = Adafactor::new(
+            vec![vector.clone(), matrix.clone()],
+            ParamsAdafactor::default(),
+        )?;
+        let loss = (vector.as_tensor().sum_all()? + (matrix.as_tensor() * 2.)?.sum_all()?)?;
+        optim.backward_step(&loss)?;
+        let step = 1e-2 * 12.5_f64.sqrt();
+        let vector = vector.to_vec1::<f64>()?;
+        assert_approx_eq!(vector[0], 3. - step);
+        assert_approx_eq!(vector[1], -4. - step);
+        for row in matrix.to_vec2::<f64>()? {
+            for x in row {
+                assert_approx_eq!(x, 0.99);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn clip_test() -> Result<()> {
+        // a large gradient in one element: the normalised update has an RMS above 1 so is clipped
+        let params = ParamsAdafactor {
+            lr: 0.1,
+            relative_step: false,
+            scale_parameter: false,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f64, 0.], [0., 0.]], &Device::Cpu)?;
+        let mut optim = Adafactor::new(vec![w.clone()], params.clone())?;
+        let coeffs = Tensor::new(&[[100f64, 1.], [1., 1.]], &Device::Cpu)?;
+        optim.backward_step(&w.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        let clipped = w.to_vec2::<f64>()?;
+        let update = Tensor::new(clipped.clone(), &Device::Cpu)?;
+        assert_approx_eq!(rms(&update)?, 0.1);
+
+        let w = Var::new(&[[0f64, 0.], [0., 0.]], &Device::Cpu)?;
+        let mut optim = Adafactor::new(
+            vec![w.clone()],
+            ParamsAdafactor {
+                clip_threshold: None,
+                ..params
+            },
+        )?;
+        optim.backward_step(&w.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        let update = Tensor::new(w.to_vec2::<f64>()?, &Device::Cpu)?;
+        assert!(rms(&update)? > 0.1);
+        Ok(())
+    }
+}
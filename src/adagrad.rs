@@ -37,7 +37,7 @@ $$
 use candle_core::{Result, Var};
 use candle_nn::optim::Optimizer;
 
-use crate::{Decay, OptimParams};
+use crate::{warn_unused_config, zero_decay, Decay, OptimParams, OptimVars, UnusedConfig};
 
 /// Adagrad optimiser
 ///
@@ -82,10 +82,19 @@ impl Default for ParamsAdaGrad {
     }
 }
 
+impl UnusedConfig for ParamsAdaGrad {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
 impl Optimizer for Adagrad {
     type Config = ParamsAdaGrad;
 
     fn new(vars: Vec<Var>, params: ParamsAdaGrad) -> Result<Self> {
+        warn_unused_config(&params);
         let vars = vars
             .into_iter()
             .filter(|var| var.dtype().is_float())
@@ -182,6 +191,12 @@ impl OptimParams for Adagrad {
     }
 }
 
+impl OptimVars for Adagrad {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
 impl Adagrad {
     /// Return the vars being optimised
     #[must_use]
@@ -0,0 +1,359 @@
+/*!
+AdaHessian optimiser
+
+Described in [ADAHESSIAN: An Adaptive Second Order Optimizer for Machine Learning](https://arxiv.org/abs/2006.00719)
+
+AdaHessian replaces the squared gradient in Adam's second moment with the square of a
+[Hutchinson](crate::hessian::hutchinson) estimate of the diagonal of the Hessian. For convolution kernels (variables
+of rank 3 or more) the estimate is averaged over the spatial dimensions, those after the first two, to reduce its
+variance. Hessian-vector products are taken by central differences of the gradient, as described in the
+[hessian](crate::hessian) module.
+
+Pseudocode (with decoupled weight decay):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\epsilon \\text{ (epsilon)}, \\: k \\text{ (hessian power)}              \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+        v_0 \\leftarrow 0 \\text{ ( second moment)}                                        \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}D_t \\leftarrow \\text{spatially averaged estimate of } \\mathrm{diag}(H_t)  \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}v_t           \\leftarrow   \\beta_2 v_{t-1} + (1 - \\beta_2) D_t^2        \\\\
+    &\\hspace{5mm}\\widehat{m_t} \\leftarrow   m_t/\\big(1-\\beta_1^t \\big)                   \\\\
+    &\\hspace{5mm}\\widehat{v_t} \\leftarrow   v_t/\\big(1-\\beta_2^t \\big)                   \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\lambda \\theta_{t-1}          \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_t - \\gamma \\widehat{m_t}/
+        \\big(\\widehat{v_t}^{k/2} + \\epsilon \\big)                                       \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+As estimating the Hessian needs the loss to be evaluated again, steps are taken with
+[`AdaHessian::backward_step_with_hessian`], given a closure evaluating the loss. This estimates the Hessian every
+`hessian_interval` steps, with the second moment bias corrected by the number of estimates taken; the `Optimizer`
+trait's `step` and `backward_step` only use the current second moment, which can also be updated directly with
+[`AdaHessian::update_hessian`].
+*/
+
+use candle_core::{backprop::GradStore, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, hessian::hutchinson, warn_unused_config, zero_decay, CurrentHyperparams,
+    Decay, HyperSnapshot, OptimParams, OptimVars, UnusedConfig,
+};
+
+/// AdaHessian optimiser
+///
+/// Described in [ADAHESSIAN: An Adaptive Second Order Optimizer for Machine Learning](https://arxiv.org/abs/2006.00719)
+#[derive(Debug)]
+pub struct AdaHessian {
+    vars: Vec<VarAdaHessian>,
+    params: ParamsAdaHessian,
+    t: usize,
+    /// number of Hessian estimates in the second moment
+    estimates: usize,
+}
+
+#[derive(Debug)]
+struct VarAdaHessian {
+    theta: Var,
+    m: Var,
+    /// moving average of the squared estimates of the diagonal of the Hessian
+    v: Var,
+}
+
+/// Parameters for the AdaHessian optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAdaHessian {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of the squared Hessian estimates
+    pub beta_2: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Decoupled weight decay
+    pub weight_decay: Option<f64>,
+    /// Power of the Hessian in the denominator: 1 for a Newton-like step, 0 for SGD with momentum
+    pub hessian_power: f64,
+    /// Number of steps between Hessian estimates by `backward_step_with_hessian`: must be at least 1
+    pub hessian_interval: usize,
+    /// Number of random vectors the Hutchinson estimate averages over
+    pub samples: usize,
+    /// Step of the central differences for the Hessian-vector products
+    pub hvp_eps: f64,
+    /// Whether to average the estimate over the spatial dimensions of variables of rank 3 or more
+    pub spatial_averaging: bool,
+}
+
+impl Default for ParamsAdaHessian {
+    fn default() -> Self {
+        Self {
+            lr: 0.15,
+            beta_1: 0.9,
+            beta_2: 0.999,
+            eps: 1e-4,
+            weight_decay: None,
+            hessian_power: 1.,
+            hessian_interval: 1,
+            samples: 1,
+            hvp_eps: 1e-3,
+            spatial_averaging: true,
+        }
+    }
+}
+
+/// average the absolute value of an estimate over the dimensions after the first two, for variables of rank 3 or more
+fn spatial_average(estimate: &Tensor) -> Result<Tensor> {
+    if estimate.rank() < 3 {
+        return Ok(estimate.clone());
+    }
+    let spatial = (2..estimate.rank()).collect::<Vec<usize>>();
+    estimate
+        .abs()?
+        .mean_keepdim(spatial)?
+        .broadcast_as(estimate.shape())?
+        .contiguous()
+}
+
+impl UnusedConfig for ParamsAdaHessian {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused: Vec<String> = zero_decay(self.weight_decay).into_iter().collect();
+        if self.hessian_power == 0. && self.spatial_averaging {
+            unused.push("spatial_averaging has no effect with a hessian_power of zero".to_string());
+        }
+        unused
+    }
+}
+
+impl Optimizer for AdaHessian {
+    type Config = ParamsAdaHessian;
+
+    fn new(vars: Vec<Var>, params: ParamsAdaHessian) -> Result<Self> {
+        if params.hessian_interval == 0 {
+            candle_core::bail!("hessian_interval must be at least 1");
+        }
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let v = Var::zeros(shape, dtype, device)?;
+                Ok(VarAdaHessian { theta: var, m, v })
+            })
+            .collect::<Result<Vec<VarAdaHessian>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 1,
+            estimates: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        if self.estimates == 0 {
+            candle_core::bail!(
+                "AdaHessian has no estimate of the Hessian: step with `backward_step_with_hessian` or `update_hessian`"
+            );
+        }
+        let lr = self.params.lr;
+        let beta_1 = self.params.beta_1;
+        let bias_correction_1 = bias_correction(beta_1, self.t as f64);
+        let bias_correction_2 = bias_correction(self.params.beta_2, self.estimates as f64);
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                if let Some(decay) = self.params.weight_decay {
+                    theta.set(&(theta.as_tensor() * lr.mul_add(-decay, 1.))?)?;
+                }
+                let m_next = ((beta_1 * var.m.as_tensor())? + ((1. - beta_1) * grad)?)?;
+                let denom = ((var.v.as_tensor() / bias_correction_2)?
+                    .powf(self.params.hessian_power / 2.)?
+                    + self.params.eps)?;
+                let delta = ((&m_next / bias_correction_1)?.div(&denom)? * lr)?;
+                theta.set(&theta.sub(&delta)?)?;
+                var.m.set(&m_next)?;
+            }
+        }
+        self.t += 1;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for AdaHessian {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for AdaHessian {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay.map(Decay::DecoupledWeightDecay),
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for AdaHessian {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl AdaHessian {
+    /// whether the next step should estimate the Hessian
+    ///
+    /// this is true on the first step and every `hessian_interval` steps after it
+    #[must_use]
+    pub fn hessian_due(&self) -> bool {
+        (self.t - 1).checked_rem(self.params.hessian_interval) == Some(0)
+    }
+
+    /// update the second moment with an estimate of the diagonal of the Hessian, holding a tensor for each of the vars
+    ///
+    /// the estimate is spatially averaged if `spatial_averaging` is set
+    ///
+    /// # Errors
+    ///
+    /// Errors if the estimate does not match the vars
+    pub fn update_hessian(&mut self, estimate: &[Tensor]) -> Result<()> {
+        if estimate.len() != self.vars.len() {
+            candle_core::bail!(
+                "{} Hessian estimates given for {} variables",
+                estimate.len(),
+                self.vars.len()
+            );
+        }
+        let beta_2 = self.params.beta_2;
+        for (var, estimate) in self.vars.iter().zip(estimate) {
+            let estimate = estimate.to_dtype(var.v.dtype())?;
+            let estimate = if self.params.spatial_averaging {
+                spatial_average(&estimate)?
+            } else {
+                estimate
+            };
+            var.v
+                .set(&((beta_2 * var.v.as_tensor())? + ((1. - beta_2) * estimate.sqr()?)?)?)?;
+        }
+        self.estimates += 1;
+        Ok(())
+    }
+
+    /// take a step from the gradient of `loss`, first estimating the Hessian from `hessian_loss` if it is due
+    ///
+    /// `hessian_loss` should evaluate the loss at the current value of the variables
+    ///
+    /// # Errors
+    ///
+    /// Errors if the Hessian cannot be estimated or the step fails
+    pub fn backward_step_with_hessian<F: FnMut() -> Result<Tensor>>(
+        &mut self,
+        loss: &Tensor,
+        hessian_loss: F,
+    ) -> Result<()> {
+        if self.hessian_due() {
+            let vars = self.vars();
+            let estimate = hutchinson(
+                &vars,
+                hessian_loss,
+                self.params.samples,
+                self.params.hvp_eps,
+            )?;
+            self.update_hessian(&estimate)?;
+        }
+        self.backward_step(loss)
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsAdaHessian {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = AdaHessian::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn spatial_average_test() -> Result<()> {
+        // a kernel of shape [1, 2, 2, 1] is averaged over its last two dimensions for each input channel
+        let estimate = Tensor::new(&[[[[1f32], [-3.]], [[2.], [4.]]]], &Device::Cpu)?;
+        let averaged = spatial_average(&estimate)?;
+        assert_eq!(averaged.dims(), [1, 2, 2, 1]);
+        assert_eq!(averaged.flatten_all()?.to_vec1::<f32>()?, [2., 2., 3., 3.]);
+        // matrices are unchanged
+        let matrix = Tensor::new(&[[1f32, -3.]], &Device::Cpu)?;
+        assert_eq!(spatial_average(&matrix)?.to_vec2::<f32>()?, [[1., -3.]]);
+        Ok(())
+    }
+
+    #[test]
+    fn step_without_hessian_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut optim = AdaHessian::new(vec![w.clone()], ParamsAdaHessian::default())?;
+        assert!(optim
+            .backward_step(&w.as_tensor().sqr()?.sum_all()?)
+            .is_err());
+        assert!(optim.update_hessian(&[]).is_err());
+        optim.update_hessian(&[Tensor::new(&[2f32, 2.], &Device::Cpu)?])?;
+        optim.backward_step(&w.as_tensor().sqr()?.sum_all()?)?;
+        let never = ParamsAdaHessian {
+            hessian_interval: 0,
+            ..Default::default()
+        };
+        assert!(AdaHessian::new(vec![w.clone()], never).is_err());
+        Ok(())
+    }
+}
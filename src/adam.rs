@@ -47,17 +47,25 @@ $$
 $$
 */
 
-use candle_core::{Result, Var};
+use std::collections::HashMap;
+
+use candle_core::{DType, Result, Shape, Tensor, TensorId, Var};
 use candle_nn::optim::Optimizer;
 use log::warn;
 
-use crate::{Decay, OptimParams};
+use crate::{
+    bias_correction, empty_grad_store, is_low_precision, warn_unused_config, zero_decay,
+    CurrentHyperparams, Decay, HyperSnapshot, OptimParams, OptimVars, UnusedConfig,
+};
 
 trait AdamInner {
-    fn new(vars: Vec<Var>) -> Result<Self>
+    fn new(vars: Vec<Var>, contiguous: bool) -> Result<Self>
     where
         Self: Sized;
     fn into_inner(self) -> Vec<Var>;
+    fn vars(&self) -> Vec<&Var>;
+    /// the buffers backing the moments if they are held contiguously, otherwise empty
+    fn buffers(&self) -> &[Var];
     fn inner_step(
         &self,
         params: &ParamsAdam,
@@ -74,47 +82,159 @@ trait AdamInner {
 /// and [Decoupled Weight Decay Regularization](https://arxiv.org/abs/1711.05101)
 ///
 /// The AMSGrad variant is also implemented, described in [On the Convergence of Adam and Beyond](https://openreview.net/forum?id=ryQu7f-RZ)
+///
+/// f16 or bf16 variables can be updated through higher precision copies using [`Adam::new_with_master_precision`]
 #[derive(Debug)]
 pub struct Adam {
     vars: VarAdam,
     params: ParamsAdam,
     t: f64,
+    master_precision: Option<DType>,
+    /// the low precision variables, keyed by the id of the higher precision copy optimised in their place
+    masters: HashMap<TensorId, Var>,
+}
+
+/// A moment estimate for a single variable
+#[derive(Debug)]
+enum Moment {
+    /// held in its own variable
+    Owned(Var),
+    /// held in a slice of a buffer shared by all the variables, starting at `offset`
+    Slice {
+        buffer: Var,
+        offset: usize,
+        shape: Shape,
+    },
+}
+
+impl Moment {
+    /// the current value, as a view into the buffer if held contiguously
+    fn tensor(&self) -> Result<Tensor> {
+        match self {
+            Self::Owned(var) => Ok(var.as_tensor().clone()),
+            Self::Slice {
+                buffer,
+                offset,
+                shape,
+            } => buffer
+                .narrow(0, *offset, shape.elem_count())?
+                .reshape(shape),
+        }
+    }
+
+    /// overwrite the value in place
+    fn set(&self, value: &Tensor) -> Result<()> {
+        match self {
+            Self::Owned(var) => var.set(value),
+            Self::Slice { buffer, offset, .. } => {
+                buffer.slice_set(&value.flatten_all()?, 0, *offset)
+            }
+        }
+    }
+}
+
+/// Allocate `count` zeroed moments for each of `vars`, returned per variable along with any backing buffers
+///
+/// If `contiguous`, moment `i` of every variable is a slice of buffer `i`, at the offsets the variables
+/// would have if flattened and concatenated in order.
+fn allocate_moments(
+    vars: &[Var],
+    count: usize,
+    contiguous: bool,
+) -> Result<(Vec<Vec<Moment>>, Vec<Var>)> {
+    let first = match vars.first() {
+        Some(first) if contiguous => first,
+        _ => {
+            let moments = vars
+                .iter()
+                .map(|var| {
+                    (0..count)
+                        .map(|_| {
+                            Ok(Moment::Owned(Var::zeros(
+                                var.shape(),
+                                var.dtype(),
+                                var.device(),
+                            )?))
+                        })
+                        .collect::<Result<Vec<Moment>>>()
+                })
+                .collect::<Result<Vec<Vec<Moment>>>>()?;
+            return Ok((moments, Vec::new()));
+        }
+    };
+    if vars
+        .iter()
+        .any(|var| var.dtype() != first.dtype() || !var.device().same_device(first.device()))
+    {
+        candle_core::bail!("contiguous state requires all variables to share a dtype and device");
+    }
+    let total = vars.iter().map(|var| var.elem_count()).sum::<usize>();
+    let buffers = (0..count)
+        .map(|_| Var::zeros(total, first.dtype(), first.device()))
+        .collect::<Result<Vec<Var>>>()?;
+    let mut offset = 0;
+    let mut moments = Vec::with_capacity(vars.len());
+    for var in vars {
+        moments.push(
+            buffers
+                .iter()
+                .map(|buffer| Moment::Slice {
+                    buffer: buffer.clone(),
+                    offset,
+                    shape: var.shape().clone(),
+                })
+                .collect(),
+        );
+        offset += var.elem_count();
+    }
+    Ok((moments, buffers))
 }
 
 #[derive(Debug)]
 struct VarAdamBase {
     theta: Var,
-    m: Var,
-    v: Var,
+    m: Moment,
+    v: Moment,
 }
 
 #[derive(Debug)]
-struct VecAdamBase(Vec<VarAdamBase>);
+struct VecAdamBase(Vec<VarAdamBase>, Vec<Var>);
 
 impl AdamInner for VecAdamBase {
-    fn new(vars: Vec<Var>) -> Result<Self>
+    fn new(vars: Vec<Var>, contiguous: bool) -> Result<Self>
     where
         Self: Sized,
     {
-        Ok(VecAdamBase(
-            vars.into_iter()
-                .filter(|var| var.dtype().is_float())
-                .map(|var| {
-                    let dtype = var.dtype();
-                    let shape = var.shape();
-                    let device = var.device();
-                    let m = Var::zeros(shape, dtype, device)?;
-                    let v = Var::zeros(shape, dtype, device)?;
-                    Ok(VarAdamBase { theta: var, m, v })
-                })
-                .collect::<Result<Vec<VarAdamBase>>>()?,
-        ))
+        let vars: Vec<Var> = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect();
+        let (moments, buffers) = allocate_moments(&vars, 2, contiguous)?;
+        let vars = vars
+            .into_iter()
+            .zip(moments)
+            .map(|(theta, moments)| {
+                let [m, v]: [Moment; 2] = moments
+                    .try_into()
+                    .map_err(|_| candle_core::Error::Msg("expected two moments".to_string()))?;
+                Ok(VarAdamBase { theta, m, v })
+            })
+            .collect::<Result<Vec<VarAdamBase>>>()?;
+        Ok(VecAdamBase(vars, buffers))
     }
 
     fn into_inner(self) -> Vec<Var> {
         self.0.into_iter().map(|var| var.theta).collect()
     }
 
+    fn vars(&self) -> Vec<&Var> {
+        self.0.iter().map(|var| &var.theta).collect()
+    }
+
+    fn buffers(&self) -> &[Var] {
+        &self.1
+    }
+
     fn inner_step(
         &self,
         params: &ParamsAdam,
@@ -130,14 +250,13 @@ impl AdamInner for VecAdamBase {
                         let v = &var.v;
                         if let Some(grad) = grads.get(theta) {
                             let grad = &(grad + (decay * theta.as_tensor())?)?;
-                            let m_next = ((params.beta_1 * m.as_tensor())?
-                                + ((1. - params.beta_1) * grad)?)?;
-                            let v_next = ((params.beta_2 * v.as_tensor())?
+                            let m_next =
+                                ((params.beta_1 * m.tensor()?)? + ((1. - params.beta_1) * grad)?)?;
+                            let v_next = ((params.beta_2 * v.tensor()?)?
                                 + ((1. - params.beta_2) * grad.powf(2.)?)?)?;
-                            let m_hat = (&m_next / (1. - (params.beta_1).powf(t)))?;
-                            let v_hat = (&v_next / (1. - params.beta_2.powf(t)))?;
-                            let delta =
-                                (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)?;
+                            let m_hat = (&m_next / bias_correction(params.beta_1, t))?;
+                            let v_hat = (&v_next / bias_correction(params.beta_2, t))?;
+                            let delta = adam_delta(params, &m_hat, &v_hat)?;
                             theta.set(&theta.sub(&(delta))?)?;
                             m.set(&m_next)?;
                             v.set(&v_next)?;
@@ -151,14 +270,13 @@ impl AdamInner for VecAdamBase {
                         let v = &var.v;
                         if let Some(grad) = grads.get(theta) {
                             theta.set(&(theta.as_tensor() * params.lr.mul_add(-decay, 1.))?)?;
-                            let m_next = ((params.beta_1 * m.as_tensor())?
-                                + ((1. - params.beta_1) * grad)?)?;
-                            let v_next = ((params.beta_2 * v.as_tensor())?
+                            let m_next =
+                                ((params.beta_1 * m.tensor()?)? + ((1. - params.beta_1) * grad)?)?;
+                            let v_next = ((params.beta_2 * v.tensor()?)?
                                 + ((1. - params.beta_2) * grad.powf(2.)?)?)?;
-                            let m_hat = (&m_next / (1. - (params.beta_1).powf(t)))?;
-                            let v_hat = (&v_next / (1. - params.beta_2.powf(t)))?;
-                            let delta =
-                                (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)?;
+                            let m_hat = (&m_next / bias_correction(params.beta_1, t))?;
+                            let v_hat = (&v_next / bias_correction(params.beta_2, t))?;
+                            let delta = adam_delta(params, &m_hat, &v_hat)?;
                             theta.set(&theta.sub(&(delta))?)?;
                             m.set(&m_next)?;
                             v.set(&v_next)?;
@@ -172,13 +290,12 @@ impl AdamInner for VecAdamBase {
                 let m = &var.m;
                 let v = &var.v;
                 if let Some(grad) = grads.get(theta) {
-                    let m_next =
-                        ((params.beta_1 * m.as_tensor())? + ((1. - params.beta_1) * grad)?)?;
-                    let v_next = ((params.beta_2 * v.as_tensor())?
+                    let m_next = ((params.beta_1 * m.tensor()?)? + ((1. - params.beta_1) * grad)?)?;
+                    let v_next = ((params.beta_2 * v.tensor()?)?
                         + ((1. - params.beta_2) * grad.powf(2.)?)?)?;
-                    let m_hat = (&m_next / (1. - (params.beta_1).powf(t)))?;
-                    let v_hat = (&v_next / (1. - params.beta_2.powf(t)))?;
-                    let delta = (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)?;
+                    let m_hat = (&m_next / bias_correction(params.beta_1, t))?;
+                    let v_hat = (&v_next / bias_correction(params.beta_2, t))?;
+                    let delta = adam_delta(params, &m_hat, &v_hat)?;
                     theta.set(&theta.sub(&(delta))?)?;
                     m.set(&m_next)?;
                     v.set(&v_next)?;
@@ -192,44 +309,49 @@ impl AdamInner for VecAdamBase {
 #[derive(Debug)]
 struct VarAdamAmsgrad {
     theta: Var,
-    m: Var,
-    v: Var,
-    vmax: Var,
+    m: Moment,
+    v: Moment,
+    vmax: Moment,
 }
 
 #[derive(Debug)]
-struct VecAdamAmsgrad(Vec<VarAdamAmsgrad>);
+struct VecAdamAmsgrad(Vec<VarAdamAmsgrad>, Vec<Var>);
 
 impl AdamInner for VecAdamAmsgrad {
-    fn new(vars: Vec<Var>) -> Result<Self>
+    fn new(vars: Vec<Var>, contiguous: bool) -> Result<Self>
     where
         Self: Sized,
     {
-        Ok(VecAdamAmsgrad(
-            vars.into_iter()
-                .filter(|var| var.dtype().is_float())
-                .map(|var| {
-                    let dtype = var.dtype();
-                    let shape = var.shape();
-                    let device = var.device();
-                    let m = Var::zeros(shape, dtype, device)?;
-                    let v = Var::zeros(shape, dtype, device)?;
-                    let vmax = Var::zeros(shape, dtype, device)?;
-                    Ok(VarAdamAmsgrad {
-                        theta: var,
-                        m,
-                        v,
-                        vmax,
-                    })
-                })
-                .collect::<Result<Vec<VarAdamAmsgrad>>>()?,
-        ))
+        let vars: Vec<Var> = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect();
+        let (moments, buffers) = allocate_moments(&vars, 3, contiguous)?;
+        let vars = vars
+            .into_iter()
+            .zip(moments)
+            .map(|(theta, moments)| {
+                let [m, v, vmax]: [Moment; 3] = moments
+                    .try_into()
+                    .map_err(|_| candle_core::Error::Msg("expected three moments".to_string()))?;
+                Ok(VarAdamAmsgrad { theta, m, v, vmax })
+            })
+            .collect::<Result<Vec<VarAdamAmsgrad>>>()?;
+        Ok(VecAdamAmsgrad(vars, buffers))
     }
 
     fn into_inner(self) -> Vec<Var> {
         self.0.into_iter().map(|var| var.theta).collect()
     }
 
+    fn vars(&self) -> Vec<&Var> {
+        self.0.iter().map(|var| &var.theta).collect()
+    }
+
+    fn buffers(&self) -> &[Var] {
+        &self.1
+    }
+
     fn inner_step(
         &self,
         params: &ParamsAdam,
@@ -246,15 +368,14 @@ impl AdamInner for VecAdamAmsgrad {
                         let vmax = &var.vmax;
                         if let Some(grad) = grads.get(theta) {
                             let grad = &(grad + (decay * theta.as_tensor())?)?;
-                            let m_next = ((params.beta_1 * m.as_tensor())?
-                                + ((1. - params.beta_1) * grad)?)?;
-                            let v_next = ((params.beta_2 * v.as_tensor())?
+                            let m_next =
+                                ((params.beta_1 * m.tensor()?)? + ((1. - params.beta_1) * grad)?)?;
+                            let v_next = ((params.beta_2 * v.tensor()?)?
                                 + ((1. - params.beta_2) * grad.powf(2.)?)?)?;
-                            let m_hat = (&m_next / (1. - (params.beta_1).powf(t)))?;
-                            let vmax_next = vmax.maximum(&v_next)?;
-                            let v_hat = (&vmax_next / (1. - params.beta_2.powf(t)))?;
-                            let delta =
-                                (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)?;
+                            let m_hat = (&m_next / bias_correction(params.beta_1, t))?;
+                            let vmax_next = vmax.tensor()?.maximum(&v_next)?;
+                            let v_hat = (&vmax_next / bias_correction(params.beta_2, t))?;
+                            let delta = adam_delta(params, &m_hat, &v_hat)?;
                             theta.set(&theta.sub(&(delta))?)?;
                             m.set(&m_next)?;
                             v.set(&v_next)?;
@@ -270,15 +391,14 @@ impl AdamInner for VecAdamAmsgrad {
                         let vmax = &var.vmax;
                         if let Some(grad) = grads.get(theta) {
                             theta.set(&(theta.as_tensor() * params.lr.mul_add(-decay, 1.))?)?;
-                            let m_next = ((params.beta_1 * m.as_tensor())?
-                                + ((1. - params.beta_1) * grad)?)?;
-                            let v_next = ((params.beta_2 * v.as_tensor())?
+                            let m_next =
+                                ((params.beta_1 * m.tensor()?)? + ((1. - params.beta_1) * grad)?)?;
+                            let v_next = ((params.beta_2 * v.tensor()?)?
                                 + ((1. - params.beta_2) * grad.powf(2.)?)?)?;
-                            let m_hat = (&m_next / (1. - (params.beta_1).powf(t)))?;
-                            let vmax_next = vmax.maximum(&v_next)?;
-                            let v_hat = (&vmax_next / (1. - params.beta_2.powf(t)))?;
-                            let delta =
-                                (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)?;
+                            let m_hat = (&m_next / bias_correction(params.beta_1, t))?;
+                            let vmax_next = vmax.tensor()?.maximum(&v_next)?;
+                            let v_hat = (&vmax_next / bias_correction(params.beta_2, t))?;
+                            let delta = adam_delta(params, &m_hat, &v_hat)?;
                             theta.set(&theta.sub(&(delta))?)?;
                             m.set(&m_next)?;
                             v.set(&v_next)?;
@@ -294,14 +414,13 @@ impl AdamInner for VecAdamAmsgrad {
                 let v = &var.v;
                 let vmax = &var.vmax;
                 if let Some(grad) = grads.get(theta) {
-                    let m_next =
-                        ((params.beta_1 * m.as_tensor())? + ((1. - params.beta_1) * grad)?)?;
-                    let v_next = ((params.beta_2 * v.as_tensor())?
+                    let m_next = ((params.beta_1 * m.tensor()?)? + ((1. - params.beta_1) * grad)?)?;
+                    let v_next = ((params.beta_2 * v.tensor()?)?
                         + ((1. - params.beta_2) * grad.powf(2.)?)?)?;
-                    let m_hat = (&m_next / (1. - (params.beta_1).powf(t)))?;
-                    let vmax_next = vmax.maximum(&v_next)?;
-                    let v_hat = (&vmax_next / (1. - params.beta_2.powf(t)))?;
-                    let delta = (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)?;
+                    let m_hat = (&m_next / bias_correction(params.beta_1, t))?;
+                    let vmax_next = vmax.tensor()?.maximum(&v_next)?;
+                    let v_hat = (&vmax_next / bias_correction(params.beta_2, t))?;
+                    let delta = adam_delta(params, &m_hat, &v_hat)?;
                     theta.set(&theta.sub(&(delta))?)?;
                     m.set(&m_next)?;
                     v.set(&v_next)?;
@@ -313,6 +432,17 @@ impl AdamInner for VecAdamAmsgrad {
     }
 }
 
+/// the Adam update $\\gamma \\widehat{m_t}/\\big(\\sqrt{\\widehat{v_t}} + \\epsilon \\big)$,
+/// with the element-wise effective learning rate clamped if `effective_lr_bounds` is set
+fn adam_delta(params: &ParamsAdam, m_hat: &Tensor, v_hat: &Tensor) -> Result<Tensor> {
+    if let Some((lr_min, lr_max)) = params.effective_lr_bounds {
+        let effective_lr = ((v_hat.powf(0.5)? + params.eps)?.recip()? * params.lr)?;
+        m_hat.mul(&effective_lr.clamp(lr_min, lr_max)?)
+    } else {
+        (m_hat * params.lr)?.div(&(v_hat.powf(0.5)? + params.eps)?)
+    }
+}
+
 #[derive(Debug)]
 enum VarAdam {
     VecAdamBase(VecAdamBase),
@@ -335,6 +465,15 @@ pub struct ParamsAdam {
     pub weight_decay: Option<Decay>,
     /// Whether to use AMSGrad variant
     pub amsgrad: bool,
+    /// Bounds `(lr_min, lr_max)` to clamp the element-wise effective learning rate
+    /// $\\gamma/\\big(\\sqrt{\\widehat{v_t}} + \\epsilon \\big)$ to before it is applied to $\\widehat{m_t}$
+    pub effective_lr_bounds: Option<(f64, f64)>,
+    /// Whether to hold each kind of moment for all the variables in a single contiguous buffer
+    ///
+    /// The buffers are exposed through [`Adam::moment_buffers`] so the state can be saved without gathering the
+    /// moments of each variable. This requires all the variables to share a dtype and device, and cannot be
+    /// changed once the optimiser is created.
+    pub contiguous_state: bool,
 }
 
 impl Default for ParamsAdam {
@@ -346,30 +485,26 @@ impl Default for ParamsAdam {
             eps: 1e-8,
             weight_decay: None,
             amsgrad: false,
+            effective_lr_bounds: None,
+            contiguous_state: false,
             // decoupled_weight_decay: false,
         }
     }
 }
 
+impl UnusedConfig for ParamsAdam {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
 impl Optimizer for Adam {
     type Config = ParamsAdam;
 
     fn new(vars: Vec<Var>, params: ParamsAdam) -> Result<Self> {
-        if params.amsgrad {
-            let vars = VarAdam::VecAdamAmsgrad(VecAdamAmsgrad::new(vars)?);
-            Ok(Self {
-                vars,
-                params,
-                t: 1.,
-            })
-        } else {
-            let vars = VarAdam::VecAdamBase(VecAdamBase::new(vars)?);
-            Ok(Self {
-                vars,
-                params,
-                t: 1.,
-            })
-        }
+        Self::build(vars, params, None)
     }
 
     fn learning_rate(&self) -> f64 {
@@ -377,9 +512,23 @@ impl Optimizer for Adam {
     }
 
     fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
-        match &self.vars {
-            VarAdam::VecAdamBase(vars) => vars.inner_step(&self.params, grads, self.t)?,
-            VarAdam::VecAdamAmsgrad(vars) => vars.inner_step(&self.params, grads, self.t)?,
+        if self.masters.is_empty() {
+            self.inner_step(grads)?;
+        } else {
+            // step the master copies with upcast gradients, then cast them back to the variables
+            let mut working_grads = empty_grad_store()?;
+            for var in self.inner_vars() {
+                let original = self.masters.get(&var.id()).unwrap_or(var);
+                if let Some(grad) = grads.get(original) {
+                    working_grads.insert(var, grad.to_dtype(var.dtype())?);
+                }
+            }
+            self.inner_step(&working_grads)?;
+            for var in self.inner_vars() {
+                if let Some(original) = self.masters.get(&var.id()) {
+                    original.set(&var.to_dtype(original.dtype())?)?;
+                }
+            }
         }
         self.t += 1.;
         Ok(())
@@ -401,27 +550,138 @@ impl OptimParams for Adam {
     ///
     /// As the AMSGrad variant requires having tracked an additional tensor
     /// this variable cannot be changed once set initally on creation of the optimiser.
+    /// Similarly the layout of the state set by `contiguous_state` is fixed on creation.
     fn set_params(&mut self, config: Self::Config) {
-        let ams_grad = self.params.amsgrad;
-        if ams_grad == config.amsgrad {
-            self.params = config;
-        } else {
+        let mut config = config;
+        if self.params.amsgrad != config.amsgrad {
             warn!("AMSGrad cannot be changed once set");
-            let mut config = config;
-            config.amsgrad = ams_grad;
-            self.params = config;
+            config.amsgrad = self.params.amsgrad;
+        }
+        if self.params.contiguous_state != config.contiguous_state {
+            warn!("contiguous state cannot be changed once set");
+            config.contiguous_state = self.params.contiguous_state;
         }
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Adam {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Adam {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner_vars()
+            .into_iter()
+            .map(|var| self.masters.get(&var.id()).unwrap_or(var))
+            .collect()
     }
 }
 
 impl Adam {
+    /// Create an optimiser keeping the state of any f16 or bf16 variables in `master_precision`
+    ///
+    /// Such variables are updated through a copy in `master_precision` (alongside moments in the same dtype),
+    /// which is cast back to the variable's dtype after each step. Updates too small to change a low precision
+    /// variable on their own then accumulate until they do. Variables of other dtypes are unaffected.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `master_precision` is not a float dtype
+    pub fn new_with_master_precision(
+        vars: Vec<Var>,
+        params: ParamsAdam,
+        master_precision: DType,
+    ) -> Result<Self> {
+        if !master_precision.is_float() {
+            candle_core::bail!("master precision must be a float dtype, got {master_precision:?}");
+        }
+        Self::build(vars, params, Some(master_precision))
+    }
+
+    fn build(vars: Vec<Var>, params: ParamsAdam, master_precision: Option<DType>) -> Result<Self> {
+        warn_unused_config(&params);
+        let mut masters = HashMap::new();
+        let mut working = Vec::with_capacity(vars.len());
+        for var in vars {
+            match master_precision {
+                Some(dtype) if is_low_precision(var.dtype()) && dtype != var.dtype() => {
+                    let master = Var::from_tensor(&var.to_dtype(dtype)?)?;
+                    masters.insert(master.id(), var);
+                    working.push(master);
+                }
+                _ => working.push(var),
+            }
+        }
+        let vars = if params.amsgrad {
+            VarAdam::VecAdamAmsgrad(VecAdamAmsgrad::new(working, params.contiguous_state)?)
+        } else {
+            VarAdam::VecAdamBase(VecAdamBase::new(working, params.contiguous_state)?)
+        };
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+            master_precision,
+            masters,
+        })
+    }
+
+    /// the dtype low precision variables are updated in, if set with [`Adam::new_with_master_precision`]
+    #[must_use]
+    pub fn master_precision(&self) -> Option<DType> {
+        self.master_precision
+    }
+
+    /// the variables the inner optimiser steps: the master copies in place of any low precision variables
+    fn inner_vars(&self) -> Vec<&Var> {
+        match &self.vars {
+            VarAdam::VecAdamBase(vars) => vars.vars(),
+            VarAdam::VecAdamAmsgrad(vars) => vars.vars(),
+        }
+    }
+
+    /// the buffers holding the moments of all the variables if created with `contiguous_state`
+    ///
+    /// these are the first moments, the second moments and (for AMSGrad) the maximum second moments, each holding
+    /// the flattened moments of the variables concatenated in order
+    #[must_use]
+    pub fn moment_buffers(&self) -> Option<&[Var]> {
+        let buffers = match &self.vars {
+            VarAdam::VecAdamBase(vars) => vars.buffers(),
+            VarAdam::VecAdamAmsgrad(vars) => vars.buffers(),
+        };
+        if buffers.is_empty() {
+            None
+        } else {
+            Some(buffers)
+        }
+    }
+
+    fn inner_step(&self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        match &self.vars {
+            VarAdam::VecAdamBase(vars) => vars.inner_step(&self.params, grads, self.t),
+            VarAdam::VecAdamAmsgrad(vars) => vars.inner_step(&self.params, grads, self.t),
+        }
+    }
+
     /// Return the vars being optimised
     #[must_use]
-    pub fn into_inner(self) -> Vec<Var> {
-        match self.vars {
+    pub fn into_inner(mut self) -> Vec<Var> {
+        let vars = match self.vars {
             VarAdam::VecAdamBase(vars) => vars.into_inner(),
             VarAdam::VecAdamAmsgrad(vars) => vars.into_inner(),
-        }
+        };
+        vars.into_iter()
+            .map(|var| self.masters.remove(&var.id()).unwrap_or(var))
+            .collect()
     }
 
     /// set the betas
@@ -517,4 +777,137 @@ mod tests {
         assert_eq!(final_params, optim.params().clone());
         Ok(())
     }
+
+    #[test]
+    fn current_hyperparams_test() -> Result<()> {
+        let params = ParamsAdam {
+            weight_decay: Some(Decay::DecoupledWeightDecay(0.01)),
+            ..Default::default()
+        };
+        let w = Var::new(&[0f32, 0.], &Device::Cpu)?;
+        let mut optim = Adam::new(vec![w], params)?;
+        let snapshot = optim.current_hyperparams();
+        assert_eq!(snapshot.lr, 0.001);
+        assert_eq!(snapshot.betas, Some((0.9, 0.999)));
+        assert_eq!(
+            snapshot.weight_decay,
+            Some(Decay::DecoupledWeightDecay(0.01))
+        );
+        // changes made during training are reflected
+        optim.set_learning_rate(0.0005);
+        optim.set_betas(0.8, 0.99);
+        let snapshot = optim.current_hyperparams();
+        assert_eq!(snapshot.lr, 0.0005);
+        assert_eq!(snapshot.betas, Some((0.8, 0.99)));
+        Ok(())
+    }
+
+    #[test]
+    fn master_precision_test() -> Result<()> {
+        // each step moves the variable by about lr = 1e-3, below the bf16 resolution of 2^-8 just under 1
+        let run = |master: Option<DType>, amsgrad: bool| -> Result<(Var, Adam)> {
+            let params = ParamsAdam {
+                lr: 1e-3,
+                amsgrad,
+                ..Default::default()
+            };
+            let w = Var::from_tensor(&Tensor::new(&[1f32], &Device::Cpu)?.to_dtype(DType::BF16)?)?;
+            let mut optim = match master {
+                Some(dtype) => Adam::new_with_master_precision(vec![w.clone()], params, dtype)?,
+                None => Adam::new(vec![w.clone()], params)?,
+            };
+            let loss = (w.as_tensor() * 1e-6)?.sum_all()?;
+            for _ in 0..10 {
+                optim.backward_step(&loss)?;
+            }
+            Ok((w, optim))
+        };
+        let (w, _) = run(None, false)?;
+        assert_eq!(w.to_dtype(DType::F32)?.to_vec1::<f32>()?, [1.]);
+        for amsgrad in [false, true] {
+            let (w, optim) = run(Some(DType::F32), amsgrad)?;
+            assert_eq!(optim.master_precision(), Some(DType::F32));
+            // the original variable is still the one exposed
+            assert_eq!(optim.vars()[0].id(), w.id());
+            assert_eq!(optim.vars()[0].dtype(), DType::BF16);
+            let stepped = w.to_dtype(DType::F32)?.to_vec1::<f32>()?[0];
+            assert!(stepped < 1., "w {stepped}");
+            assert_eq!(optim.into_inner()[0].id(), w.id());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn contiguous_state_test() -> Result<()> {
+        let run = |contiguous_state: bool, amsgrad: bool| -> Result<(Vec<Var>, Adam)> {
+            let params = ParamsAdam {
+                lr: 0.1,
+                amsgrad,
+                weight_decay: Some(Decay::WeightDecay(0.1)),
+                contiguous_state,
+                ..Default::default()
+            };
+            let w = Var::new(&[[1f64, -2.], [3., 0.5]], &Device::Cpu)?;
+            let b = Var::new(&[0.5f64], &Device::Cpu)?;
+            let mut optim = Adam::new(vec![w.clone(), b.clone()], params)?;
+            for _ in 0..5 {
+                let loss = (w.as_tensor().sqr()?.sum_all()? + b.as_tensor().sum_all()?.exp()?)?;
+                optim.backward_step(&loss)?;
+            }
+            Ok((vec![w, b], optim))
+        };
+        for amsgrad in [false, true] {
+            let (separate, optim) = run(false, amsgrad)?;
+            assert!(optim.moment_buffers().is_none());
+            let (contiguous, optim) = run(true, amsgrad)?;
+            assert_eq!(
+                separate[0].to_vec2::<f64>()?,
+                contiguous[0].to_vec2::<f64>()?
+            );
+            assert_eq!(
+                separate[1].to_vec1::<f64>()?,
+                contiguous[1].to_vec1::<f64>()?
+            );
+            let buffers = optim.moment_buffers().unwrap_or_default();
+            assert_eq!(buffers.len(), if amsgrad { 3 } else { 2 });
+            // the last element of the first moment is that of the bias, whose gradient is always positive
+            assert_eq!(buffers[0].dims(), [5]);
+            assert!(buffers[0].to_vec1::<f64>()?[4] > 0.);
+        }
+        // mixed dtypes cannot share a buffer
+        let params = ParamsAdam {
+            contiguous_state: true,
+            ..Default::default()
+        };
+        let w = Var::new(&[1f64], &Device::Cpu)?;
+        let b = Var::new(&[1f32], &Device::Cpu)?;
+        assert!(Adam::new(vec![w, b], params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn effective_lr_bounds_test() -> Result<()> {
+        // on the first step m_hat = g and v_hat = g^2 so the effective learning rate is lr / (|g| + eps)
+        let coeffs = Tensor::new(&[1e-4f64, 10.], &Device::Cpu)?;
+        let unbounded = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let mut optim = Adam::new(vec![unbounded.clone()], ParamsAdam::default())?;
+        optim.backward_step(&unbounded.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        let unbounded = unbounded.to_vec1::<f64>()?;
+        assert_approx_eq!(unbounded[0], -0.001, 1e-6);
+        assert_approx_eq!(unbounded[1], -0.001, 1e-6);
+
+        let params = ParamsAdam {
+            effective_lr_bounds: Some((0., 1.)),
+            ..Default::default()
+        };
+        let bounded = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let mut optim = Adam::new(vec![bounded.clone()], params)?;
+        optim.backward_step(&bounded.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        let bounded = bounded.to_vec1::<f64>()?;
+        // the small gradient coordinate has effective learning rate ~10 which is clamped to 1
+        assert_approx_eq!(bounded[0], -1e-4, 1e-9);
+        // the large gradient coordinate is within the bounds so is unchanged
+        assert_approx_eq!(bounded[1], unbounded[1], 1e-12);
+        Ok(())
+    }
 }
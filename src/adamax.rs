@@ -11,9 +11,10 @@ $$
     &\\textbf{input}      : \\gamma \\text{ (lr)}, \\beta_1, \\beta_2
         \\text{ (betas)},\\theta_0 \\text{ (params)},f(\\theta) \\text{ (objective)},
         \\: \\lambda \\text{ (weight decay)},                                                \\\\
-    &\\hspace{13mm}    \\epsilon \\text{ (epsilon)}                                          \\\\
+    &\\hspace{13mm}    \\epsilon \\text{ (epsilon)}, \\: \\textit{amsgrad}                                          \\\\
     &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
-        u_0 \\leftarrow 0 \\text{ ( infinity norm)}                                 \\\\[-1.ex]
+        u_0 \\leftarrow 0 \\text{ ( infinity norm)}, \\:
+        u_0^{max} \\leftarrow 0                                 \\\\[-1.ex]
     &\\rule{110mm}{0.4pt}                                                                 \\\\
     &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
     &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
@@ -24,7 +25,11 @@ $$
     &\\hspace{15mm} g_t \\leftarrow g_t + \\lambda  \\theta_{t-1}                            \\\\
     &\\hspace{5mm}m_t      \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t               \\\\
     &\\hspace{5mm}u_t      \\leftarrow   \\mathrm{max}(\\beta_2 u_{t-1}, |g_{t}|+\\epsilon)   \\\\
-    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\frac{\\gamma m_t}{(1-\\beta^t_1) u_t} \\\\
+    &\\hspace{5mm}\\textbf{if} \\: amsgrad                                                  \\\\
+    &\\hspace{10mm}u_t^{max} \\leftarrow \\mathrm{max}(u_{t-1}^{max}, u_t)               \\\\
+    &\\hspace{10mm}\\theta_t \\leftarrow \\theta_{t-1} - \\frac{\\gamma m_t}{(1-\\beta^t_1) u_t^{max}} \\\\
+    &\\hspace{5mm}\\textbf{else}                                                           \\\\
+    &\\hspace{10mm}\\theta_t \\leftarrow \\theta_{t-1} - \\frac{\\gamma m_t}{(1-\\beta^t_1) u_t} \\\\
     &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
     &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
     &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
@@ -32,20 +37,30 @@ $$
 $$
 */
 
-use candle_core::{Result, Var};
+use std::collections::HashMap;
+
+use candle_core::{DType, Device, Result, Tensor, Var};
 use candle_nn::optim::Optimizer;
+use log::warn;
 
-use crate::{Decay, OptimParams};
+use crate::{
+    bias_correction, is_low_precision, state_for_var, take_state, warn_unused_config, zero_decay,
+    CurrentHyperparams, Decay, HyperSnapshot, OptimParams, OptimState, OptimVars, UnusedConfig,
+};
 
 /// Adamax optimiser
 ///
 /// An Adam optimiser based on infinity norm, described in [Adam: A Method for Stochastic Optimization](https://arxiv.org/abs/1412.6980)
-
+///
+/// Variables can be split into groups with different parameters using [`Adamax::new_with_groups`],
+/// and f16 or bf16 variables can be updated through higher precision copies using [`Adamax::new_with_master_precision`]
 #[derive(Debug)]
 pub struct Adamax {
     vars: Vec<VarAdaMax>,
-    params: ParamsAdaMax,
+    /// parameters of each group of variables: there is always at least one group
+    groups: Vec<ParamsAdaMax>,
     t: f64,
+    master_precision: Option<DType>,
 }
 
 #[derive(Debug)]
@@ -53,6 +68,24 @@ struct VarAdaMax {
     theta: Var,
     m: Var,
     u: Var,
+    u_max: Option<Var>,
+    /// higher precision copy of a low precision `theta`, which is updated in its place
+    master: Option<Var>,
+    /// index of the parameter group the variable belongs to
+    group: usize,
+}
+
+impl VarAdaMax {
+    /// the infinity norm to divide the step by: the running max of `u` if using amsgrad, otherwise `u_next`
+    fn denominator(&self, u_next: &Tensor) -> Result<Tensor> {
+        if let Some(u_max) = &self.u_max {
+            let u_max_next = u_max.maximum(u_next)?;
+            u_max.set(&u_max_next)?;
+            Ok(u_max_next)
+        } else {
+            Ok(u_next.clone())
+        }
+    }
 }
 
 /// Parameters for the Adamax optimiser
@@ -68,6 +101,8 @@ pub struct ParamsAdaMax {
     pub weight_decay: Option<Decay>,
     /// Term added to denominator to improve numerical stability
     pub eps: f64,
+    /// Whether to divide by the running maximum of the infinity norm (the AMSGrad variant)
+    pub amsgrad: bool,
 }
 
 impl Default for ParamsAdaMax {
@@ -78,99 +113,58 @@ impl Default for ParamsAdaMax {
             beta_2: 0.999,
             weight_decay: None,
             eps: 1e-8,
+            amsgrad: false,
         }
     }
 }
 
+impl UnusedConfig for ParamsAdaMax {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
 impl Optimizer for Adamax {
     type Config = ParamsAdaMax;
 
     fn new(vars: Vec<Var>, params: ParamsAdaMax) -> Result<Self> {
-        let vars = vars
-            .into_iter()
-            .filter(|var| var.dtype().is_float())
-            .map(|var| {
-                let dtype = var.dtype();
-                let shape = var.shape();
-                let device = var.device();
-                let m = Var::zeros(shape, dtype, device)?;
-                let u = Var::zeros(shape, dtype, device)?;
-                Ok(VarAdaMax { theta: var, m, u })
-            })
-            .collect::<Result<Vec<VarAdaMax>>>()?;
-        // // Err(SGDError::NoMomentum)?;
-        // let mut params = params;
-        // params.t = 0;
-        Ok(Self {
-            vars,
-            params,
-            t: 1.,
-        })
+        Self::new_with_groups(vec![(vars, params)])
     }
 
+    /// the learning rate of the first parameter group
     fn learning_rate(&self) -> f64 {
-        self.params.lr
+        self.groups[0].lr
     }
 
     fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
-        if let Some(decay) = self.params.weight_decay {
-            match decay {
-                Decay::WeightDecay(decay) => {
-                    for var in &self.vars {
-                        let theta = &var.theta;
-                        let m = &var.m;
-                        let u = &var.u;
-                        if let Some(grad) = grads.get(theta) {
-                            let grad = &(grad + (decay * theta.as_tensor())?)?;
-                            let m_next = ((self.params.beta_1 * m.as_tensor())?
-                                + (1. - self.params.beta_1) * grad)?;
-                            let u_next = (self.params.beta_2 * u.as_tensor())?
-                                .maximum(&(grad.abs()? + self.params.eps)?)?;
-                            let delta = (&m_next * self.params.lr)?
-                                .div(&(&u_next * (1. - self.params.beta_1.powf(self.t)))?)?;
-                            theta.set(&theta.sub(&(delta))?)?;
-                            m.set(&m_next)?;
-                            u.set(&u_next)?;
-                        }
+        for var in &self.vars {
+            let params = &self.groups[var.group];
+            let theta = var.master.as_ref().unwrap_or(&var.theta);
+            let m = &var.m;
+            let u = &var.u;
+            if let Some(grad) = grads.get(&var.theta) {
+                let grad = &grad.to_dtype(theta.dtype())?;
+                let grad = &match params.weight_decay {
+                    Some(Decay::WeightDecay(decay)) => (grad + (decay * theta.as_tensor())?)?,
+                    Some(Decay::DecoupledWeightDecay(decay)) => {
+                        // decoupled weight decay step
+                        theta.set(&(theta.as_tensor() * params.lr.mul_add(-decay, 1.))?)?;
+                        grad.clone()
                     }
-                }
-                Decay::DecoupledWeightDecay(decay) => {
-                    for var in &self.vars {
-                        let theta = &var.theta;
-                        let m = &var.m;
-                        let u = &var.u;
-                        if let Some(grad) = grads.get(theta) {
-                            // decoupled weight decay step
-                            theta
-                                .set(&(theta.as_tensor() * self.params.lr.mul_add(-decay, 1.))?)?;
-                            let m_next = ((self.params.beta_1 * m.as_tensor())?
-                                + (1. - self.params.beta_1) * grad)?;
-                            let u_next = (self.params.beta_2 * u.as_tensor())?
-                                .maximum(&(grad.abs()? + self.params.eps)?)?;
-                            let delta = (&m_next * self.params.lr)?
-                                .div(&(&u_next * (1. - self.params.beta_1.powf(self.t)))?)?;
-                            theta.set(&theta.sub(&(delta))?)?;
-                            m.set(&m_next)?;
-                            u.set(&u_next)?;
-                        }
-                    }
-                }
-            }
-        } else {
-            for var in &self.vars {
-                let theta = &var.theta;
-                let m = &var.m;
-                let u = &var.u;
-                if let Some(grad) = grads.get(theta) {
-                    let m_next =
-                        ((self.params.beta_1 * m.as_tensor())? + (1. - self.params.beta_1) * grad)?;
-                    let u_next = (self.params.beta_2 * u.as_tensor())?
-                        .maximum(&(grad.abs()? + self.params.eps)?)?;
-                    let delta = (&m_next * self.params.lr)?
-                        .div(&(&u_next * (1. - self.params.beta_1.powf(self.t)))?)?;
-                    theta.set(&theta.sub(&(delta))?)?;
-                    m.set(&m_next)?;
-                    u.set(&u_next)?;
+                    None => grad.clone(),
+                };
+                let m_next = ((params.beta_1 * m.as_tensor())? + (1. - params.beta_1) * grad)?;
+                let u_next =
+                    (params.beta_2 * u.as_tensor())?.maximum(&(grad.abs()? + params.eps)?)?;
+                let delta = (&m_next * params.lr)?
+                    .div(&(var.denominator(&u_next)? * bias_correction(params.beta_1, self.t))?)?;
+                theta.set(&theta.sub(&(delta))?)?;
+                m.set(&m_next)?;
+                u.set(&u_next)?;
+                if var.master.is_some() {
+                    var.theta.set(&theta.to_dtype(var.theta.dtype())?)?;
                 }
             }
         }
@@ -178,28 +172,275 @@ impl Optimizer for Adamax {
         Ok(())
     }
 
+    /// set the learning rate of the first parameter group, scaling those of any other groups proportionally
     fn set_learning_rate(&mut self, lr: f64) {
-        self.params.lr = lr;
+        let current = self.groups[0].lr;
+        for (i, group) in self.groups.iter_mut().enumerate() {
+            if i == 0 || current == 0. {
+                group.lr = lr;
+            } else {
+                group.lr *= lr / current;
+            }
+        }
     }
 }
 
 impl OptimParams for Adamax {
+    /// the parameters of the first parameter group
     fn params(&self) -> &Self::Config {
-        &self.params
+        &self.groups[0]
     }
 
+    /// Set the parameters for the optimiser (of the first parameter group)
+    ///
+    /// # Warning
+    ///
+    /// As the AMSGrad variant requires having tracked an additional tensor
+    /// this variable cannot be changed once set initally on creation of the optimiser.
     fn set_params(&mut self, config: Self::Config) {
-        self.params = config;
+        update_group(&mut self.groups[0], config);
+    }
+}
+
+/// replace the parameters of a group, keeping its AMSGrad setting
+fn update_group(params: &mut ParamsAdaMax, config: ParamsAdaMax) {
+    let ams_grad = params.amsgrad;
+    if ams_grad == config.amsgrad {
+        *params = config;
+    } else {
+        warn!("AMSGrad cannot be changed once set");
+        let mut config = config;
+        config.amsgrad = ams_grad;
+        *params = config;
+    }
+}
+
+impl CurrentHyperparams for Adamax {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        // the first group, as returned by `params`
+        HyperSnapshot {
+            lr: self.groups[0].lr,
+            betas: Some((self.groups[0].beta_1, self.groups[0].beta_2)),
+            weight_decay: self.groups[0].weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Adamax {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl OptimState for Adamax {
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        let mut state = HashMap::with_capacity(3 * self.vars.len() + 1);
+        state.insert("t".to_string(), Tensor::new(self.t, &Device::Cpu)?);
+        // the moments are updated in place so must be copied
+        for (i, var) in self.vars.iter().enumerate() {
+            state.insert(format!("m.{i}"), var.m.as_tensor().copy()?);
+            state.insert(format!("u.{i}"), var.u.as_tensor().copy()?);
+            if let Some(u_max) = &var.u_max {
+                state.insert(format!("u_max.{i}"), u_max.as_tensor().copy()?);
+            }
+            if let Some(master) = &var.master {
+                state.insert(format!("master.{i}"), master.as_tensor().copy()?);
+            }
+        }
+        Ok(state)
+    }
+
+    fn load_state_dict(&mut self, mut state: HashMap<String, Tensor>) -> Result<()> {
+        let t = take_state(&mut state, "t")?
+            .to_dtype(DType::F64)?
+            .to_scalar::<f64>()?;
+        // take and check every entry before setting any, so a bad state leaves the optimiser unchanged
+        let mut loaded = Vec::new();
+        for (i, var) in self.vars.iter().enumerate() {
+            let buffers = [
+                ("m", Some(&var.m)),
+                ("u", Some(&var.u)),
+                ("u_max", var.u_max.as_ref()),
+                ("master", var.master.as_ref()),
+            ];
+            for (name, buffer) in buffers {
+                if let Some(buffer) = buffer {
+                    let tensor = take_state(&mut state, &format!("{name}.{i}"))?;
+                    loaded.push((buffer, state_for_var(buffer, &tensor)?));
+                }
+            }
+        }
+        for (buffer, tensor) in loaded {
+            buffer.set(&tensor)?;
+        }
+        self.t = t;
+        Ok(())
     }
 }
 
 impl Adamax {
+    /// Create an optimiser with different parameters for each group of variables,
+    /// for example to only apply weight decay to the weights and not the biases
+    ///
+    /// # Errors
+    ///
+    /// Errors if there are no groups
+    pub fn new_with_groups(groups: Vec<(Vec<Var>, ParamsAdaMax)>) -> Result<Self> {
+        Self::build(groups, None)
+    }
+
+    /// Create an optimiser keeping the state of any f16 or bf16 variables in `master_precision`
+    ///
+    /// Such variables are updated through a copy in `master_precision` (alongside moments in the same dtype),
+    /// which is cast back to the variable's dtype after each step. Updates too small to change a low precision
+    /// variable on their own then accumulate until they do. Variables of other dtypes are unaffected.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `master_precision` is not a float dtype
+    pub fn new_with_master_precision(
+        vars: Vec<Var>,
+        params: ParamsAdaMax,
+        master_precision: DType,
+    ) -> Result<Self> {
+        if !master_precision.is_float() {
+            candle_core::bail!("master precision must be a float dtype, got {master_precision:?}");
+        }
+        Self::build(vec![(vars, params)], Some(master_precision))
+    }
+
+    fn build(
+        groups: Vec<(Vec<Var>, ParamsAdaMax)>,
+        master_precision: Option<DType>,
+    ) -> Result<Self> {
+        if groups.is_empty() {
+            candle_core::bail!("at least one parameter group is needed");
+        }
+        let mut vars = Vec::new();
+        let mut group_params = Vec::with_capacity(groups.len());
+        for (group, (group_vars, params)) in groups.into_iter().enumerate() {
+            warn_unused_config(&params);
+            for var in group_vars.into_iter().filter(|var| var.dtype().is_float()) {
+                let master = match master_precision {
+                    Some(dtype) if is_low_precision(var.dtype()) && dtype != var.dtype() => {
+                        Some(Var::from_tensor(&var.to_dtype(dtype)?)?)
+                    }
+                    _ => None,
+                };
+                let dtype = master.as_ref().map_or(var.dtype(), |master| master.dtype());
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let u = Var::zeros(shape, dtype, device)?;
+                let u_max = if params.amsgrad {
+                    Some(Var::zeros(shape, dtype, device)?)
+                } else {
+                    None
+                };
+                vars.push(VarAdaMax {
+                    theta: var,
+                    m,
+                    u,
+                    u_max,
+                    master,
+                    group,
+                });
+            }
+            group_params.push(params);
+        }
+        Ok(Self {
+            vars,
+            groups: group_params,
+            t: 1.,
+            master_precision,
+        })
+    }
+
+    /// the dtype low precision variables are updated in, if set with [`Adamax::new_with_master_precision`]
+    #[must_use]
+    pub fn master_precision(&self) -> Option<DType> {
+        self.master_precision
+    }
+
+    /// the number of parameter groups
+    #[must_use]
+    pub fn num_groups(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// the parameters of a group, or `None` if there is no such group
+    #[must_use]
+    pub fn group_params(&self, group: usize) -> Option<&ParamsAdaMax> {
+        self.groups.get(group)
+    }
+
+    /// set the parameters of a group
+    ///
+    /// As with [`OptimParams::set_params`], AMSGrad cannot be changed once set on creation
+    ///
+    /// # Errors
+    ///
+    /// Errors if there is no such group
+    pub fn set_group_params(&mut self, group: usize, config: ParamsAdaMax) -> Result<()> {
+        let Some(params) = self.groups.get_mut(group) else {
+            candle_core::bail!("no parameter group {group}");
+        };
+        update_group(params, config);
+        Ok(())
+    }
+
+    /// set the learning rate of a single group
+    ///
+    /// # Errors
+    ///
+    /// Errors if there is no such group
+    pub fn set_group_lr(&mut self, group: usize, lr: f64) -> Result<()> {
+        let Some(params) = self.groups.get_mut(group) else {
+            candle_core::bail!("no parameter group {group}");
+        };
+        params.lr = lr;
+        Ok(())
+    }
+
     /// Return the vars being optimised
+    ///
+    /// This discards the moments, so an optimiser created from the returned vars starts cold:
+    /// use [`Adamax::to_state`] to keep them
     #[must_use]
     pub fn into_inner(self) -> Vec<Var> {
         self.vars.into_iter().map(|v| v.theta).collect()
     }
 
+    /// Return the vars being optimised along with the internal state, as given by [`OptimState::state_dict`]
+    ///
+    /// Passing both to [`Adamax::from_state`] resumes optimisation as if the optimiser had never been taken apart
+    ///
+    /// # Errors
+    ///
+    /// Errors if the state cannot be copied
+    pub fn to_state(self) -> Result<(Vec<Var>, HashMap<String, Tensor>)> {
+        let state = self.state_dict()?;
+        Ok((self.into_inner(), state))
+    }
+
+    /// Create an optimiser from vars and state returned by [`Adamax::to_state`]
+    ///
+    /// The vars must be given in the same order they were returned in
+    ///
+    /// # Errors
+    ///
+    /// Errors if the state does not match the variables
+    pub fn from_state(
+        vars: Vec<Var>,
+        params: ParamsAdaMax,
+        state: HashMap<String, Tensor>,
+    ) -> Result<Self> {
+        let mut optim = Self::new(vars, params)?;
+        optim.load_state_dict(state)?;
+        Ok(optim)
+    }
+
     // pub fn push(&mut self, var: &Var) {
     //     self.vars.push(var.clone());
     // }
@@ -243,6 +484,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn to_state_test() -> Result<()> {
+        let params = ParamsAdaMax {
+            lr: 0.1,
+            amsgrad: true,
+            ..Default::default()
+        };
+        let w = Var::new(&[[1f64, -2.]], &Device::Cpu)?;
+        let w_copy = Var::new(&[[1f64, -2.]], &Device::Cpu)?;
+        let loss = |w: &Var| w.as_tensor().sqr()?.sum_all();
+        let mut optim = Adamax::new(vec![w.clone()], params.clone())?;
+        let mut uninterrupted = Adamax::new(vec![w_copy.clone()], params.clone())?;
+        for _ in 0..3 {
+            optim.backward_step(&loss(&w)?)?;
+            uninterrupted.backward_step(&loss(&w_copy)?)?;
+        }
+        let (vars, state) = optim.to_state()?;
+        assert_eq!(vars[0].id(), w.id());
+        let mut optim = Adamax::from_state(vars, params.clone(), state)?;
+        for _ in 0..2 {
+            optim.backward_step(&loss(&w)?)?;
+            uninterrupted.backward_step(&loss(&w_copy)?)?;
+        }
+        assert_eq!(w.to_vec2::<f64>()?, w_copy.to_vec2::<f64>()?);
+        // while rebuilding from into_inner starts again from cold moments
+        let mut cold = Adamax::new(uninterrupted.into_inner(), params)?;
+        optim.backward_step(&loss(&w)?)?;
+        cold.backward_step(&loss(&w_copy)?)?;
+        assert_ne!(w.to_vec2::<f64>()?, w_copy.to_vec2::<f64>()?);
+        Ok(())
+    }
+
     #[test]
     fn params_test() -> Result<()> {
         let params = ParamsAdaMax {
@@ -262,4 +535,94 @@ mod tests {
         assert_eq!(new_params, optim.params().clone());
         Ok(())
     }
+
+    #[test]
+    fn master_precision_test() -> Result<()> {
+        // each step moves the variable by about lr = 1e-3, below the bf16 resolution of 2^-8 just under 1
+        let params = ParamsAdaMax {
+            lr: 1e-3,
+            ..Default::default()
+        };
+        let run = |master: Option<DType>| -> Result<(Var, Adamax)> {
+            let w = Var::new(&[1f32], &Device::Cpu)?.to_dtype(DType::BF16)?;
+            let w = Var::from_tensor(&w)?;
+            let mut optim = match master {
+                Some(dtype) => {
+                    Adamax::new_with_master_precision(vec![w.clone()], params.clone(), dtype)?
+                }
+                None => Adamax::new(vec![w.clone()], params.clone())?,
+            };
+            let loss = (w.as_tensor() * 1e-6)?.sum_all()?;
+            for _ in 0..10 {
+                optim.backward_step(&loss)?;
+            }
+            Ok((w, optim))
+        };
+        let (w, _) = run(None)?;
+        assert_eq!(w.to_dtype(DType::F32)?.to_vec1::<f32>()?, [1.]);
+        let (w, optim) = run(Some(DType::F32))?;
+        assert_eq!(optim.master_precision(), Some(DType::F32));
+        assert_eq!(optim.vars[0].m.dtype(), DType::F32);
+        let w = w.to_dtype(DType::F32)?.to_vec1::<f32>()?[0];
+        assert!(w < 1., "w {w}");
+        // the variable holds the master copy rounded to bf16
+        let master = optim.vars[0].master.as_ref().unwrap();
+        assert_approx_eq!(master.to_vec1::<f32>()?[0], 0.99, 1e-4);
+        assert!(Adamax::new_with_master_precision(vec![], params, DType::U8).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn amsgrad_test() -> Result<()> {
+        assert!(!ParamsAdaMax::default().amsgrad);
+        let params = ParamsAdaMax {
+            lr: 0.1,
+            amsgrad: true,
+            ..Default::default()
+        };
+        let w = Var::new(&[1f32, -1.], &Device::Cpu)?;
+        let mut optim = Adamax::new(vec![w.clone()], params)?;
+        let mut prev_max = vec![0f32; 2];
+        // shrinking gradients let u decay below its running maximum
+        for scale in [4., 2., 1., 0.5, 0.25] {
+            optim.backward_step(&(w.as_tensor() * scale)?.sum_all()?)?;
+            let var = &optim.vars[0];
+            let u = var.u.to_vec1::<f32>()?;
+            let u_max = var.u_max.as_ref().unwrap().to_vec1::<f32>()?;
+            for i in 0..2 {
+                assert!(u_max[i] >= prev_max[i]);
+                assert!(u_max[i] >= u[i]);
+            }
+            prev_max = u_max;
+        }
+        assert!(optim.vars[0].u.to_vec1::<f32>()?[0] < prev_max[0]);
+        let inner = optim.into_inner();
+        assert_eq!(inner.len(), 1);
+        assert_eq!(inner[0].id(), w.id());
+        Ok(())
+    }
+
+    #[test]
+    fn amsgrad_params_test() -> Result<()> {
+        let params = ParamsAdaMax {
+            amsgrad: true,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Adamax::new(vec![w.clone()], params.clone())?;
+        let new_params = ParamsAdaMax {
+            lr: 0.002,
+            amsgrad: false,
+            ..Default::default()
+        };
+        optim.set_params(new_params);
+        // amsgrad cannot be changed once set
+        let expected = ParamsAdaMax {
+            lr: 0.002,
+            amsgrad: true,
+            ..Default::default()
+        };
+        assert_eq!(optim.params(), &expected);
+        Ok(())
+    }
 }
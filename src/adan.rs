@@ -0,0 +1,280 @@
+/*!
+Adan optimiser
+
+Described in [Adan: Adaptive Nesterov Momentum Algorithm for Faster Optimizing Deep Models](https://arxiv.org/abs/2208.06677)
+
+Adan keeps moving averages of the gradient, of the difference between consecutive gradients, and of the square of the
+Nesterov corrected gradient $g_t + \\beta_2 (g_t - g_{t-1})$, giving an estimate of Nesterov momentum without
+evaluating the gradient at an extrapolated point.
+
+Pseudocode (with the bias correction and decoupled weight decay of the reference implementation):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2, \\beta_3
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\epsilon \\text{ (epsilon)}, \\: \\textit{no prox}                      \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0, \\: v_0 \\leftarrow 0, \\: n_0 \\leftarrow 0   \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}d_t           \\leftarrow   g_t - g_{t-1} \\text{ (0 on the first step)}   \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}v_t           \\leftarrow   \\beta_2 v_{t-1} + (1 - \\beta_2) d_t          \\\\
+    &\\hspace{5mm}n_t           \\leftarrow   \\beta_3 n_{t-1} + (1 - \\beta_3) (g_t + \\beta_2 d_t)^2  \\\\
+    &\\hspace{5mm}u_t \\leftarrow \\left(\\frac{m_t}{1 - \\beta_1^t} + \\beta_2 \\frac{v_t}{1 - \\beta_2^t}\\right)
+        \\Big/ \\left(\\sqrt{\\frac{n_t}{1 - \\beta_3^t}} + \\epsilon\\right)                \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\textit{no prox}                                          \\\\
+    &\\hspace{10mm} \\theta_t \\leftarrow (1 - \\gamma \\lambda) \\theta_{t-1} - \\gamma u_t   \\\\
+    &\\hspace{5mm}\\textbf{else}                                                           \\\\
+    &\\hspace{10mm} \\theta_t \\leftarrow (\\theta_{t-1} - \\gamma u_t) / (1 + \\gamma \\lambda)  \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot,
+    OptimParams, OptimVars, UnusedConfig,
+};
+
+/// Adan optimiser
+///
+/// Described in [Adan: Adaptive Nesterov Momentum Algorithm for Faster Optimizing Deep Models](https://arxiv.org/abs/2208.06677)
+#[derive(Debug)]
+pub struct Adan {
+    vars: Vec<VarAdan>,
+    params: ParamsAdan,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarAdan {
+    theta: Var,
+    /// moving average of the gradient
+    m: Var,
+    /// moving average of the gradient difference
+    v: Var,
+    /// moving average of the squared Nesterov corrected gradient
+    n: Var,
+    /// gradient of the previous step
+    prev_grad: Var,
+}
+
+/// Parameters for the Adan optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAdan {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of the gradient
+    pub beta_1: f64,
+    /// Coefficient for moving average of the gradient difference
+    pub beta_2: f64,
+    /// Coefficient for moving average of the squared Nesterov corrected gradient
+    pub beta_3: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Decoupled weight decay
+    pub weight_decay: Option<f64>,
+    /// Apply the weight decay before the step, as in AdamW, rather than as a proximal step after it
+    pub no_prox: bool,
+}
+
+impl Default for ParamsAdan {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.98,
+            beta_2: 0.92,
+            beta_3: 0.99,
+            eps: 1e-8,
+            weight_decay: None,
+            no_prox: false,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsAdan {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused: Vec<String> = zero_decay(self.weight_decay).into_iter().collect();
+        if self.no_prox && self.weight_decay.is_none() {
+            unused.push("no_prox has no effect without weight_decay".to_string());
+        }
+        unused
+    }
+}
+
+impl Optimizer for Adan {
+    type Config = ParamsAdan;
+
+    fn new(vars: Vec<Var>, params: ParamsAdan) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let v = Var::zeros(shape, dtype, device)?;
+                let n = Var::zeros(shape, dtype, device)?;
+                let prev_grad = Var::zeros(shape, dtype, device)?;
+                Ok(VarAdan {
+                    theta: var,
+                    m,
+                    v,
+                    n,
+                    prev_grad,
+                })
+            })
+            .collect::<Result<Vec<VarAdan>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let lr = self.params.lr;
+        let beta_1 = self.params.beta_1;
+        let beta_2 = self.params.beta_2;
+        let beta_3 = self.params.beta_3;
+        let bias_correction_1 = bias_correction(beta_1, self.t);
+        let bias_correction_2 = bias_correction(beta_2, self.t);
+        let bias_correction_3 = bias_correction(beta_3, self.t);
+
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                // the previous gradient is taken to be the current one on the first step
+                let diff = if self.t > 1. {
+                    (grad - var.prev_grad.as_tensor())?
+                } else {
+                    grad.zeros_like()?
+                };
+                let m_next = ((beta_1 * var.m.as_tensor())? + ((1. - beta_1) * grad)?)?;
+                let v_next = ((beta_2 * var.v.as_tensor())? + ((1. - beta_2) * &diff)?)?;
+                let nesterov = (grad + (beta_2 * &diff)?)?;
+                let n_next =
+                    ((beta_3 * var.n.as_tensor())? + ((1. - beta_3) * nesterov.sqr()?)?)?;
+                let denom = ((&n_next / bias_correction_3)?.sqrt()? + self.params.eps)?;
+                let update = ((&m_next / bias_correction_1)?
+                    + (&v_next * (beta_2 / bias_correction_2))?)?
+                    .div(&denom)?;
+                let stepped = match self.params.weight_decay {
+                    Some(decay) if self.params.no_prox => {
+                        ((theta.as_tensor() * lr.mul_add(-decay, 1.))? - (update * lr)?)?
+                    }
+                    Some(decay) => ((theta.as_tensor() - (update * lr)?)? / lr.mul_add(decay, 1.))?,
+                    None => (theta.as_tensor() - (update * lr)?)?,
+                };
+                theta.set(&stepped)?;
+                var.m.set(&m_next)?;
+                var.v.set(&v_next)?;
+                var.n.set(&n_next)?;
+                var.prev_grad.set(grad)?;
+            }
+        }
+
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Adan {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Adan {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_3)),
+            weight_decay: self.params.weight_decay.map(Decay::DecoupledWeightDecay),
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Adan {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Adan {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsAdan {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Adan::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsAdan {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Adan::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsAdan {
+            lr: 0.002,
+            weight_decay: Some(0.02),
+            no_prox: true,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+/*!
+Simulated annealing
+
+A derivative-free method, described in [Optimization by Simulated Annealing](https://doi.org/10.1126/science.220.4598.671),
+which only evaluates [`Model::loss`]. Each move proposes a random perturbation $x' = x + \\delta$ of the current
+flattened variables, and accepts it by the Metropolis criterion: always if $f(x') \\leq f(x)$, and otherwise with
+probability
+
+$$ \\exp\\left(-\\frac{f(x') - f(x)}{T_k}\\right) $$
+
+where the temperature $T_k$ at step $k$ falls from `initial_temperature` following the [`Cooling`] schedule.
+Accepting uphill moves while the temperature is high lets the chain escape local minima, which suits small
+non-convex problems where the gradient based methods get stuck. The perturbations are drawn from the [`Proposal`]
+distribution, independently for each element.
+
+After each step the variables hold the best point found so far, while the chain continues from its current point,
+and the optimiser has converged once the temperature falls below `min_temperature`. Loss evaluations that are NaN are
+treated as infinite, so moves to them are always rejected.
+*/
+
+use crate::{flatten_vars, unflatten, LossOptimizer, Model, ModelOutcome, OptimVars};
+use candle_core::Result as CResult;
+use candle_core::{DType, Device, Tensor, Var};
+use log::info;
+
+/// The distribution of the perturbation of each element of the flattened variables
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Proposal {
+    /// normal with standard deviation `std`
+    Gaussian {
+        /// the standard deviation of the perturbation
+        std: f64,
+    },
+    /// uniform on $[-h, h]$ for the half width $h$
+    Uniform {
+        /// the largest perturbation
+        half_width: f64,
+    },
+    /// Cauchy with scale `scale`, whose heavy tails give occasional long jumps, as in fast annealing
+    Cauchy {
+        /// the half width at half maximum of the distribution
+        scale: f64,
+    },
+}
+
+/// The schedule of the temperature $T_k$ at step $k$, starting from $T_0$
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Cooling {
+    /// $T_k = T_0 \\alpha^{k}$ for the rate $\\alpha$
+    Exponential(f64),
+    /// $T_k = \\frac{T_0}{k + 1}$, as for fast annealing with Cauchy proposals
+    Fast,
+    /// $T_k = \\frac{T_0 \\ln 2}{\\ln(k + 2)}$, slow enough to find the global minimum in the limit
+    Logarithmic,
+    /// $T_k = T_0 \\max(0, 1 - \\frac{k}{K})$ over $K$ steps
+    Linear(usize),
+}
+
+impl Cooling {
+    /// the temperature at `step` starting from `initial`
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_wrap,
+        clippy::cast_possible_truncation
+    )]
+    pub fn temperature(&self, initial: f64, step: usize) -> f64 {
+        let k = step as f64;
+        match *self {
+            Self::Exponential(rate) => initial * rate.powi(step as i32),
+            Self::Fast => initial / (k + 1.),
+            Self::Logarithmic => initial * 2f64.ln() / (k + 2.).ln(),
+            Self::Linear(steps) => initial * (1. - k / steps as f64).max(0.),
+        }
+    }
+}
+
+/// Parameters for simulated annealing
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAnnealing {
+    /// Initial temperature $T_0$, on the scale of the increases in the loss that should be accepted at the start
+    pub initial_temperature: f64,
+    /// The optimiser has converged once the temperature is below this
+    pub min_temperature: f64,
+    /// Schedule of the temperature
+    pub cooling: Cooling,
+    /// Distribution of the perturbations
+    pub proposal: Proposal,
+    /// Number of moves made at each temperature, so each step evaluates the loss this many times
+    pub moves: usize,
+}
+
+impl Default for ParamsAnnealing {
+    fn default() -> Self {
+        Self {
+            initial_temperature: 1.,
+            min_temperature: 1e-8,
+            cooling: Cooling::Exponential(0.99),
+            proposal: Proposal::Gaussian { std: 0.1 },
+            moves: 1,
+        }
+    }
+}
+
+/// Simulated annealing optimiser
+///
+/// A derivative-free method accepting random moves by the Metropolis criterion at a falling temperature
+#[derive(Debug)]
+pub struct Annealing<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsAnnealing,
+    step: usize,
+    /// the current point of the chain and its loss, unset before the first step
+    current: Option<(Tensor, f64)>,
+    /// the best point found, its loss, and the loss as a scalar
+    best: Option<(Tensor, Tensor, f64)>,
+    proposed: usize,
+    accepted: usize,
+}
+
+impl<M: Model> LossOptimizer<M> for Annealing<M> {
+    type Config = ParamsAnnealing;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.initial_temperature <= 0. {
+            candle_core::bail!(
+                "the initial temperature must be positive, got {}",
+                params.initial_temperature
+            );
+        }
+        if params.moves == 0 {
+            candle_core::bail!("simulated annealing needs at least one move per step");
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            step: 0,
+            current: None,
+            best: None,
+            proposed: 0,
+            accepted: 0,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        if self.current.is_none() {
+            let x = flatten_vars(&self.vars)?.copy()?;
+            let fx = scalar(loss)?;
+            self.best = Some((x.clone(), loss.detach(), fx));
+            self.current = Some((x, fx));
+        }
+        let (mut x, mut fx) = self.current.take().expect("the chain is started above");
+        let temperature = self.temperature();
+        for _ in 0..self.params.moves {
+            let candidate = (&x + self.perturbation(&x)?)?;
+            self.set_vars(&candidate)?;
+            let candidate_loss = self.model.loss()?.detach();
+            let f_candidate = scalar(&candidate_loss)?;
+            self.proposed += 1;
+            let accept = f_candidate <= fx
+                || (f_candidate.is_finite()
+                    && uniform()? < (-(f_candidate - fx) / temperature).exp());
+            if accept {
+                self.accepted += 1;
+                if self.best.as_ref().map_or(f64::INFINITY, |(_, _, f)| *f) > f_candidate {
+                    self.best = Some((candidate.clone(), candidate_loss, f_candidate));
+                }
+                x = candidate;
+                fx = f_candidate;
+            }
+        }
+        self.current = Some((x, fx));
+        self.step += 1;
+
+        let (best, best_loss, _) = self.best.clone().expect("best is set on the first step");
+        self.set_vars(&best)?;
+        if self.temperature() < self.params.min_temperature {
+            info!("temperature below the minimum");
+            return Ok(ModelOutcome::Converged(best_loss, self.params.moves));
+        }
+        Ok(ModelOutcome::Stepped(best_loss, self.params.moves))
+    }
+
+    /// the initial temperature $T_0$
+    fn learning_rate(&self) -> f64 {
+        self.params.initial_temperature
+    }
+
+    /// set the initial temperature $T_0$, which rescales the whole schedule
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.initial_temperature = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for Annealing<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> Annealing<M> {
+    /// The temperature of the next step
+    #[must_use]
+    pub fn temperature(&self) -> f64 {
+        self.params
+            .cooling
+            .temperature(self.params.initial_temperature, self.step)
+    }
+
+    /// The fraction of the proposed moves that have been accepted, or 0 before the first step
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn acceptance_rate(&self) -> f64 {
+        if self.proposed == 0 {
+            0.
+        } else {
+            self.accepted as f64 / self.proposed as f64
+        }
+    }
+
+    /// Restart the schedule from the initial temperature, continuing the chain from the best point found
+    pub fn reset(&mut self) {
+        self.step = 0;
+        self.current = self.best.as_ref().map(|(x, _, f)| (x.clone(), *f));
+        self.proposed = 0;
+        self.accepted = 0;
+    }
+
+    fn perturbation(&self, x: &Tensor) -> CResult<Tensor> {
+        match self.params.proposal {
+            Proposal::Gaussian { std } => x.randn_like(0., std),
+            Proposal::Uniform { half_width } => x.rand_like(-half_width, half_width),
+            Proposal::Cauchy { scale } => {
+                let angle = ((x.rand_like(0., 1.)? - 0.5)? * std::f64::consts::PI)?;
+                (angle.sin()? / angle.cos()?)? * scale
+            }
+        }
+    }
+
+    fn set_vars(&self, x: &Tensor) -> CResult<()> {
+        for (var, value) in self.vars.iter().zip(unflatten(x, &self.vars)?) {
+            var.set(&value)?;
+        }
+        Ok(())
+    }
+}
+
+fn scalar(loss: &Tensor) -> CResult<f64> {
+    let f = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+    Ok(if f.is_nan() { f64::INFINITY } else { f })
+}
+
+/// a sample from the uniform distribution on $[0, 1)$
+fn uniform() -> CResult<f64> {
+    Tensor::rand(0f64, 1f64, (), &Device::Cpu)?.to_scalar::<f64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    /// $||x - 1||_{2}^{2}$
+    struct Sphere {
+        x: Var,
+    }
+
+    impl Model for Sphere {
+        fn loss(&self) -> CResult<Tensor> {
+            (self.x.as_tensor() - 1.)?.sqr()?.sum_all()
+        }
+    }
+
+    fn setup(params: ParamsAnnealing) -> Result<(Var, Annealing<Sphere>)> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let optim = Annealing::new(vec![x.clone()], params, Sphere { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsAnnealing::default())?;
+        assert_approx_eq!(1., optim.learning_rate());
+        optim.set_learning_rate(2.);
+        assert_approx_eq!(2., optim.temperature());
+        assert!(setup(ParamsAnnealing {
+            moves: 0,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cooling_test() {
+        assert_approx_eq!(Cooling::Exponential(0.5).temperature(2., 3), 0.25);
+        assert_approx_eq!(Cooling::Fast.temperature(2., 3), 0.5);
+        assert_approx_eq!(Cooling::Logarithmic.temperature(2., 0), 2.);
+        assert_approx_eq!(Cooling::Logarithmic.temperature(2., 2), 1.);
+        assert_approx_eq!(Cooling::Linear(4).temperature(2., 1), 1.5);
+        assert_approx_eq!(Cooling::Linear(4).temperature(2., 6), 0.);
+    }
+
+    #[test]
+    fn greedy_test() -> Result<()> {
+        // at a negligible temperature only downhill moves are accepted, so the chain is the best point
+        let (x, mut optim) = setup(ParamsAnnealing {
+            initial_temperature: 1e-300,
+            min_temperature: 0.,
+            proposal: Proposal::Uniform { half_width: 0.2 },
+            moves: 5,
+            ..Default::default()
+        })?;
+        let mut loss = optim.model.loss()?;
+        for _ in 0..20 {
+            if let ModelOutcome::Stepped(next, evals) = optim.backward_step(&loss)? {
+                assert_eq!(evals, 5);
+                assert!(next.to_scalar::<f64>()? <= loss.to_scalar::<f64>()?);
+                loss = next;
+            }
+            let (current, _) = optim.current.as_ref().expect("set by the step");
+            assert_eq!(current.to_vec1::<f64>()?, x.to_vec1::<f64>()?);
+        }
+        assert!(optim.acceptance_rate() < 1.);
+        optim.reset();
+        assert_approx_eq!(optim.acceptance_rate(), 0.);
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+/*!
+Averaged SGD optimiser
+
+Described in [Acceleration of stochastic approximation by averaging](https://doi.org/10.1137/0330046)
+
+SGD with a decaying step size, keeping a running average of the iterates once a trigger step $t_0$ has passed. The
+working variables are updated as in SGD (with an extra decay of the variables by $\\lambda \\eta_t$), while the average
+is kept separately and can be retrieved with [`Asgd::averages`]. Before step $t_0$ the average simply follows the
+variables. This follows `torch.optim.ASGD`.
+
+Pseudocode (including decoupling of weight decay):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\theta_0 \\text{ (params)}, \\: f(\\theta)
+        \\text{ (objective)}, \\: \\lambda \\text{ (lambd)}, \\: \\alpha \\text{ (alpha)},     \\\\
+    &\\hspace{13mm} t_0 \\text{ (trigger point)}, \\: \\lambda_{wd} \\text{ (weight decay)}    \\\\
+    &\\textbf{initialize} :  \\eta_0 \\leftarrow \\gamma, \\: \\mu_0 \\leftarrow 1, \\: a_0 \\leftarrow 0 \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\lambda_{wd} \\textbf{ is } \\text{Some}                   \\\\
+    &\\hspace{10mm}\\textbf{if} \\: \\textit{decoupled}                                      \\\\
+    &\\hspace{15mm} \\theta_{t-1} \\leftarrow \\theta_{t-1} - \\eta_{t-1} \\lambda_{wd} \\theta_{t-1} \\\\
+    &\\hspace{10mm}\\textbf{else}                                                          \\\\
+    &\\hspace{15mm} g_t \\leftarrow g_t + \\lambda_{wd} \\theta_{t-1}                        \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow (1 - \\lambda \\eta_{t-1}) \\theta_{t-1} - \\eta_{t-1} g_t \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\mu_{t-1} \\neq 1                                         \\\\
+    &\\hspace{10mm} a_t \\leftarrow a_{t-1} + \\mu_{t-1} (\\theta_t - a_{t-1})               \\\\
+    &\\hspace{5mm}\\textbf{else}                                                           \\\\
+    &\\hspace{10mm} a_t \\leftarrow \\theta_t                                                \\\\
+    &\\hspace{5mm}\\eta_t \\leftarrow \\gamma / (1 + \\lambda \\gamma t)^{\\alpha}               \\\\
+    &\\hspace{5mm}\\mu_t \\leftarrow 1 / \\max(1, t - t_0)                                    \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  a_t                                                          \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+The step size $\\eta_t$ and averaging weight $\\mu_t$ are computed from the step count and the current parameters,
+so changes to the learning rate take effect on the next step.
+*/
+
+use candle_core::{Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot, OptimParams,
+    OptimVars, UnusedConfig,
+};
+
+/// Averaged SGD optimiser
+///
+/// Described in [Acceleration of stochastic approximation by averaging](https://doi.org/10.1137/0330046)
+#[derive(Debug)]
+pub struct Asgd {
+    vars: Vec<VarAsgd>,
+    params: ParamsAsgd,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarAsgd {
+    theta: Var,
+    /// running average of the iterates
+    ax: Var,
+}
+
+/// Parameters for the averaged SGD optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsAsgd {
+    /// Learning rate
+    pub lr: f64,
+    /// Decay term $\\lambda$, both of the step size and of the variables
+    pub lambd: f64,
+    /// Power of the step size decay
+    pub alpha: f64,
+    /// Step at which averaging starts
+    pub t0: f64,
+    /// Weight decay
+    pub weight_decay: Option<Decay>,
+}
+
+impl Default for ParamsAsgd {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            lambd: 1e-4,
+            alpha: 0.75,
+            t0: 1e6,
+            weight_decay: None,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsAsgd {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Optimizer for Asgd {
+    type Config = ParamsAsgd;
+
+    fn new(vars: Vec<Var>, params: ParamsAsgd) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let ax = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                Ok(VarAsgd { theta: var, ax })
+            })
+            .collect::<Result<Vec<VarAsgd>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 0.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let eta = self.eta();
+        let mu = (self.t - self.params.t0).max(1.).recip();
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let grad = match self.params.weight_decay {
+                    Some(Decay::WeightDecay(wd)) => (grad + (wd * theta.as_tensor())?)?,
+                    Some(Decay::DecoupledWeightDecay(decay)) => {
+                        // decoupled weight decay step
+                        theta.set(&(theta.as_tensor() * eta.mul_add(-decay, 1.))?)?;
+                        grad.clone()
+                    }
+                    None => grad.clone(),
+                };
+                let next =
+                    ((theta.as_tensor() * eta.mul_add(-self.params.lambd, 1.))? - (grad * eta)?)?;
+                theta.set(&next)?;
+                if mu < 1. {
+                    var.ax
+                        .set(&(var.ax.as_tensor() + ((&next - var.ax.as_tensor())? * mu)?)?)?;
+                } else {
+                    var.ax.set(&next)?;
+                }
+            }
+        }
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Asgd {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Asgd {
+    /// the learning rate reported is the decayed step size of the next step
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.eta(),
+            betas: None,
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Asgd {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Asgd {
+    /// the step size of the next step, decayed from the learning rate
+    fn eta(&self) -> f64 {
+        let lr = self.params.lr;
+        lr / (self.params.lambd * lr)
+            .mul_add(self.t, 1.)
+            .powf(self.params.alpha)
+    }
+
+    /// the averaged variables, in the same order as the vars
+    ///
+    /// these follow the working variables until the trigger step `t0`, and are zero before the first step
+    #[must_use]
+    pub fn averages(&self) -> Vec<&Tensor> {
+        self.vars.iter().map(|v| v.ax.as_tensor()).collect()
+    }
+
+    /// Return the averaged variables, rather than the working variables being optimised
+    #[must_use]
+    pub fn into_averages(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.ax).collect()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsAsgd {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Asgd::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsAsgd {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Asgd::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsAsgd {
+            lr: 0.002,
+            t0: 10.,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn eta_test() -> Result<()> {
+        let params = ParamsAsgd {
+            lr: 0.1,
+            lambd: 1.,
+            alpha: 0.5,
+            ..Default::default()
+        };
+        let x = Var::new(0f64, &Device::Cpu)?;
+        let mut optim = Asgd::new(vec![x.clone()], params)?;
+        // the first step is taken with the learning rate, then eta = lr / sqrt(1 + lr t)
+        assert_approx_eq!(optim.current_hyperparams().lr, 0.1);
+        optim.backward_step(&x.as_tensor().clone())?;
+        assert_approx_eq!(x.to_scalar::<f64>()?, -0.1);
+        assert_approx_eq!(optim.current_hyperparams().lr, 0.1 / 1.1f64.sqrt());
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+/*!
+Automatic checkpointing
+
+[`Autosave`] wraps any optimiser in this crate and calls a user supplied callback with the wrapped optimiser every `every` steps,
+for example to save its state with [`OptimState`](crate::OptimState) and the variables with safetensors.
+Any error returned by the callback is returned from the step that triggered it (after the step itself has been taken).
+*/
+
+use std::fmt::Debug;
+
+use candle_core::{backprop::GradStore, Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::OptimVars;
+
+/// callback invoked with the wrapped optimiser
+type AutosaveFn<O> = Box<dyn FnMut(&O) -> Result<()>>;
+
+/// Optimiser wrapper calling a save callback every fixed number of steps
+pub struct Autosave<O: Optimizer + OptimVars> {
+    inner: O,
+    step_count: usize,
+    since_save: usize,
+    every: usize,
+    callback: Option<AutosaveFn<O>>,
+}
+
+impl<O: Optimizer + OptimVars + Debug> Debug for Autosave<O> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Autosave")
+            .field("inner", &self.inner)
+            .field("step_count", &self.step_count)
+            .field("since_save", &self.since_save)
+            .field("every", &self.every)
+            .field("callback", &self.callback.as_ref().map(|_| "FnMut"))
+            .finish()
+    }
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for Autosave<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        self.inner.step(grads)?;
+        self.step_count += 1;
+        self.since_save += 1;
+        if let Some(callback) = &mut self.callback {
+            if self.since_save == self.every {
+                self.since_save = 0;
+                callback(&self.inner)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for Autosave<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> Autosave<O> {
+    /// wrap an optimiser, with no callback set
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self {
+            inner,
+            step_count: 0,
+            since_save: 0,
+            every: 0,
+            callback: None,
+        }
+    }
+
+    /// call `f` with the wrapped optimiser after every `every` steps, counted from when the callback is set
+    ///
+    /// this replaces any existing callback; an `every` of 0 disables the callback
+    pub fn set_autosave(&mut self, every: usize, f: AutosaveFn<O>) {
+        self.every = every;
+        self.since_save = 0;
+        self.callback = Some(f);
+    }
+
+    /// remove the callback
+    pub fn clear_autosave(&mut self) {
+        self.callback = None;
+    }
+
+    /// the number of steps taken since the wrapper was created
+    #[must_use]
+    pub fn step_count(&self) -> usize {
+        self.step_count
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser, dropping the callback
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    fn setup() -> Result<(Var, Autosave<SGD>)> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let optim = Autosave::new(SGD::new(vec![w.clone()], ParamsSGD::default())?);
+        Ok((w, optim))
+    }
+
+    #[test]
+    fn autosave_test() -> Result<()> {
+        let (w, mut optim) = setup()?;
+        let saved = Rc::new(RefCell::new(Vec::new()));
+        let record = Rc::clone(&saved);
+        optim.set_autosave(
+            3,
+            Box::new(move |sgd: &SGD| {
+                record.borrow_mut().push(sgd.vars()[0].to_vec1::<f32>()?);
+                Ok(())
+            }),
+        );
+        let loss = w.as_tensor().sum_all()?;
+        for _ in 0..7 {
+            optim.backward_step(&loss)?;
+        }
+        // the callback sees the variables after steps 3 and 6
+        let saved = saved.borrow();
+        assert_eq!(saved.len(), 2);
+        for (w, expected) in saved.iter().flatten().zip([0.7, 1.7, 0.4, 1.4]) {
+            assert_approx_eq!(w, expected, 1e-6);
+        }
+        assert_eq!(optim.step_count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn autosave_error_test() -> Result<()> {
+        let (w, mut optim) = setup()?;
+        optim.set_autosave(
+            2,
+            Box::new(|_: &SGD| candle_core::bail!("checkpoint failed")),
+        );
+        let loss = w.as_tensor().sum_all()?;
+        optim.backward_step(&loss)?;
+        let err = optim.backward_step(&loss).unwrap_err().to_string();
+        assert!(err.contains("checkpoint failed"));
+        // the step itself was still taken
+        assert_eq!(optim.step_count(), 2);
+        optim.clear_autosave();
+        optim.backward_step(&loss)?;
+        optim.backward_step(&loss)?;
+        Ok(())
+    }
+}
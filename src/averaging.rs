@@ -0,0 +1,224 @@
+/*!
+Weight averaging
+
+[`WeightAverager`] keeps an average of the variables of a model over the course of training, which often generalises better
+than the final weights. This can be either
+
+* a uniform running mean of the snapshots passed to [`WeightAverager::update`], as in Stochastic Weight Averaging described in
+  [Averaging Weights Leads to Wider Optima and Better Generalization](https://arxiv.org/abs/1803.05407), typically updated
+  every few steps over the tail of training, or
+* an exponential moving average with decay $\\beta$, commonly used for diffusion models and updated every step:
+
+$$ \\bar{\\theta}_{t} \\gets \\beta \\bar{\\theta}_{t-1} + (1 - \\beta) \\theta_t $$
+
+The averaging buffers are allocated on the first update, with the dtype, shape and device of each variable.
+Once training is finished the average can be written back into the variables with [`WeightAverager::finalize_into`].
+*/
+
+use candle_core::{Result, Var};
+
+/// How the snapshots of the weights are averaged
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Averaging {
+    /// the mean of all the snapshots, each weighted equally
+    Uniform,
+    /// an exponential moving average with the given decay, in $[0, 1)$
+    Ema(f64),
+}
+
+/// Running average of a set of variables
+#[derive(Debug)]
+pub struct WeightAverager {
+    averaging: Averaging,
+    averages: Vec<Var>,
+    count: usize,
+}
+
+impl Default for WeightAverager {
+    fn default() -> Self {
+        Self {
+            averaging: Averaging::Uniform,
+            averages: Vec::new(),
+            count: 0,
+        }
+    }
+}
+
+impl WeightAverager {
+    /// create an averager with no snapshots
+    ///
+    /// # Errors
+    ///
+    /// Errors if the decay of an exponential moving average is not in $[0, 1)$
+    pub fn new(averaging: Averaging) -> Result<Self> {
+        if let Averaging::Ema(decay) = averaging {
+            if !(0. ..1.).contains(&decay) {
+                candle_core::bail!("EMA decay must be in [0, 1), got {decay}");
+            }
+        }
+        Ok(Self {
+            averaging,
+            ..Default::default()
+        })
+    }
+
+    /// add a snapshot of `vars` to the average
+    ///
+    /// the first snapshot initialises the average; later snapshots must be of the same variables in the same order
+    ///
+    /// # Errors
+    ///
+    /// Errors if the number of variables or their shapes differ from the first snapshot
+    #[allow(clippy::cast_precision_loss)]
+    pub fn update(&mut self, vars: &[Var]) -> Result<()> {
+        if self.count == 0 {
+            // `Var::from_tensor` would share the storage of a var, so copy first
+            self.averages = vars
+                .iter()
+                .map(|var| Var::from_tensor(&var.as_tensor().copy()?))
+                .collect::<Result<Vec<Var>>>()?;
+            self.count = 1;
+            return Ok(());
+        }
+        self.check_vars(vars)?;
+        let count = (self.count + 1) as f64;
+        for (average, var) in self.averages.iter().zip(vars) {
+            let next = match self.averaging {
+                Averaging::Uniform => {
+                    (average.as_tensor() + ((var.as_tensor() - average.as_tensor())? / count)?)?
+                }
+                Averaging::Ema(decay) => {
+                    ((average.as_tensor() * decay)? + (var.as_tensor() * (1. - decay))?)?
+                }
+            };
+            average.set(&next)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// write the averaged weights into `vars`, which should be the variables passed to [`WeightAverager::update`]
+    ///
+    /// # Errors
+    ///
+    /// Errors if no snapshots have been taken, or the variables do not match those averaged
+    pub fn finalize_into(&self, vars: &[Var]) -> Result<()> {
+        if self.count == 0 {
+            candle_core::bail!("no weights have been averaged");
+        }
+        self.check_vars(vars)?;
+        for (average, var) in self.averages.iter().zip(vars) {
+            var.set(average.as_tensor())?;
+        }
+        Ok(())
+    }
+
+    /// the averaged weights, empty before the first update
+    #[must_use]
+    pub fn averages(&self) -> &[Var] {
+        &self.averages
+    }
+
+    /// the number of snapshots averaged
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// discard the average, so the next update starts afresh
+    pub fn reset(&mut self) {
+        self.averages.clear();
+        self.count = 0;
+    }
+
+    /// check `vars` match the averaged variables before any are updated
+    fn check_vars(&self, vars: &[Var]) -> Result<()> {
+        if vars.len() != self.averages.len() {
+            candle_core::bail!(
+                "expected {} variables to average, got {}",
+                self.averages.len(),
+                vars.len()
+            );
+        }
+        for (i, (average, var)) in self.averages.iter().zip(vars).enumerate() {
+            if average.shape() != var.shape() {
+                candle_core::bail!(
+                    "expected variable {i} to have shape {:?}, got {:?}",
+                    average.dims(),
+                    var.dims()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{DType, Device};
+
+    #[test]
+    fn uniform_test() -> Result<()> {
+        let w = Var::new(&[1f64, 2.], &Device::Cpu)?;
+        let vars = vec![w.clone()];
+        let mut averager = WeightAverager::default();
+        for step in 0..4 {
+            w.set(&(w.as_tensor() + f64::from(step))?)?;
+            averager.update(&vars)?;
+        }
+        // snapshots [1, 2], [2, 3], [4, 5], [7, 8]
+        assert_eq!(averager.count(), 4);
+        assert_eq!(averager.averages()[0].to_vec1::<f64>()?, [3.5, 4.5]);
+        assert_eq!(w.to_vec1::<f64>()?, [7., 8.]);
+        averager.finalize_into(&vars)?;
+        assert_eq!(w.to_vec1::<f64>()?, [3.5, 4.5]);
+        Ok(())
+    }
+
+    #[test]
+    fn ema_test() -> Result<()> {
+        let w = Var::new(&[0f32], &Device::Cpu)?;
+        let vars = vec![w.clone()];
+        let mut averager = WeightAverager::new(Averaging::Ema(0.9))?;
+        averager.update(&vars)?;
+        // the buffer is allocated to match the var
+        assert_eq!(averager.averages()[0].dtype(), DType::F32);
+        w.set(&w.ones_like()?)?;
+        averager.update(&vars)?;
+        averager.update(&vars)?;
+        // 0.9 * (0.9 * 0 + 0.1) + 0.1
+        assert_approx_eq!(averager.averages()[0].to_vec1::<f32>()?[0], 0.19);
+        assert!(WeightAverager::new(Averaging::Ema(1.)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mismatch_test() -> Result<()> {
+        let w = Var::new(&[0f32], &Device::Cpu)?;
+        let vars = vec![w.clone()];
+        let mut averager = WeightAverager::default();
+        assert!(averager.finalize_into(&vars).is_err());
+        averager.update(&vars)?;
+        let two = vec![w.clone(), w];
+        assert!(averager.update(&two).is_err());
+        averager.reset();
+        averager.update(&two)?;
+        assert_eq!(averager.count(), 1);
+
+        // a misshapen second variable is caught before the first average is updated
+        let misshapen = vec![
+            Var::new(&[1f32], &Device::Cpu)?,
+            Var::new(&[1f32, 1.], &Device::Cpu)?,
+        ];
+        assert!(averager.update(&misshapen).is_err());
+        assert_eq!(averager.count(), 1);
+        averager.finalize_into(&two)?;
+        assert_eq!(two[0].to_vec1::<f32>()?, [0.]);
+        assert!(averager.finalize_into(&misshapen).is_err());
+        assert_eq!(misshapen[0].to_vec1::<f32>()?, [1.]);
+        Ok(())
+    }
+}
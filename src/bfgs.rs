@@ -0,0 +1,359 @@
+/*!
+Broyden–Fletcher–Goldfarb–Shanno algorithm
+
+A quasi-Newton method keeping a dense approximation $H_k$ of the inverse Hessian, as described in
+[Numerical Optimization](https://doi.org/10.1007/978-0-387-40065-5) (algorithm 6.1). The search direction is
+$d_k = -H_k g_k$, and after each step $s_k = x_{k+1} - x_k$ with change in gradient $y_k = g_{k+1} - g_k$ the
+approximation is updated as
+
+$$ H_{k+1} = (I - \\rho_k s_k y_k^{\\top}) H_k (I - \\rho_k y_k s_k^{\\top}) + \\rho_k s_k s_k^{\\top},
+    \\quad \\rho_k = \\frac{1}{y_k^{\\top} s_k} $$
+
+Before the first update $H_0$ is scaled to $\\frac{y_0^{\\top} s_0}{y_0^{\\top} y_0} I$. Updates that do not satisfy
+the curvature condition $y_k^{\\top} s_k > 0$ are skipped, so that $H_k$ stays positive definite.
+
+The matrix holds $n^2$ elements for $n$ parameters, so this is only suited to small problems, such as curve fitting,
+where it converges in fewer iterations than [LBFGS](crate::lbfgs), whose history holds only the latest updates. The
+same line searches as LBFGS are used.
+*/
+
+use crate::{
+    lbfgs::{
+        add_grad, directional_evaluate, flat_grads, l2_reg, satisfies_curvature, Backtracking,
+        GradConv, LineSearch, LineSearchTarget, StepConv, StrongWolfe,
+    },
+    LossOptimizer, Model, ModelOutcome, OptimVars,
+};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for the BFGS optimiser
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsBfgs {
+    /// 'Learning rate': used for the initial step size guess of the line search, and as the step size when no line
+    /// search is used
+    pub lr: f64,
+    /// linesearch method to use
+    pub line_search: Option<LineSearch>,
+    /// convergence criteria for gradient
+    pub grad_conv: GradConv,
+    /// convergence criteria for step size
+    pub step_conv: StepConv,
+    /// weight decay
+    pub weight_decay: Option<f64>,
+    /// maximum number of loss evaluations made by the line search in each step, as for LBFGS
+    pub max_eval: Option<usize>,
+}
+
+impl Default for ParamsBfgs {
+    fn default() -> Self {
+        Self {
+            lr: 1.,
+            line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+            grad_conv: GradConv::MinForce(1e-7),
+            step_conv: StepConv::MinStep(1e-9),
+            weight_decay: None,
+            max_eval: None,
+        }
+    }
+}
+
+/// BFGS optimiser
+///
+/// A quasi-Newton method keeping a dense approximation of the inverse Hessian
+#[derive(Debug)]
+pub struct Bfgs<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsBfgs,
+    /// approximation of the inverse Hessian, `None` until the first update (as the identity)
+    h_inv: Option<Tensor>,
+    last_grad: Option<Tensor>,
+    next_grad: Option<Tensor>,
+    last_step: Option<Tensor>,
+}
+
+impl<M: Model> LossOptimizer<M> for Bfgs<M> {
+    type Config = ParamsBfgs;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if let Some(line_search) = params.line_search {
+            line_search.validate()?;
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            h_inv: None,
+            last_grad: None,
+            next_grad: None,
+            last_step: None,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let mut evals = 1;
+        let grad = if let Some(grad) = self.next_grad.take() {
+            grad
+        } else {
+            flat_grads(&self.vars, loss, self.params.weight_decay)?
+        };
+        if self.params.grad_conv.converged(&grad)? == Some(true) {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+
+        if let (Some(last_grad), Some(s)) = (self.last_grad.take(), self.last_step.take()) {
+            let y = (&grad - last_grad)?;
+            if satisfies_curvature(&s, &y)? {
+                self.update(&s, &y)?;
+            } else {
+                info!("curvature condition not met: skipping update");
+            }
+        }
+        self.last_grad = Some(grad.clone());
+
+        // as in LBFGS the direction is H g, which is stepped along with a negative step size
+        let mut q = match &self.h_inv {
+            Some(h_inv) => h_inv.matmul(&grad.unsqueeze(1)?)?.squeeze(1)?,
+            None => grad.clone(),
+        };
+        let mut dd = dot(&grad, &q)?;
+        if dd <= 0. {
+            info!("inverse Hessian approximation lost positive definiteness: resetting");
+            self.h_inv = None;
+            q = grad.clone();
+            dd = dot(&grad, &grad)?;
+        }
+        let lr = if self.h_inv.is_none() {
+            // with no curvature information, scale the first step as LBFGS does
+            -(1_f64.min(
+                1. / grad
+                    .abs()?
+                    .sum_all()?
+                    .to_dtype(DType::F64)?
+                    .to_scalar::<f64>()?,
+            )) * self.params.lr
+        } else {
+            -self.params.lr
+        };
+
+        let mut stalled = false;
+        let (next_loss, step, stepped): (Tensor, Tensor, fn(Tensor, usize) -> ModelOutcome) =
+            if let Some(ls) = self.params.line_search {
+                let budget = self.params.max_eval;
+                let (next_loss, next_grad, t, steps) = match ls {
+                    LineSearch::StrongWolfe(c1, c2, tol) => {
+                        let max_ls =
+                            budget.map_or(25, |max_eval| max_eval.saturating_sub(1).min(25));
+                        self.strong_wolfe(lr, &q, loss, &grad, dd, c1, c2, tol, max_ls)?
+                    }
+                    LineSearch::Backtracking { c1, rho, max_steps } => {
+                        let max_steps =
+                            budget.map_or(max_steps, |max_eval| max_eval.min(max_steps));
+                        self.backtracking(lr, &q, loss, &grad, dd, c1, rho, max_steps)?
+                    }
+                };
+                evals += steps;
+                self.next_grad = Some(next_grad);
+                let step = (q * t)?;
+                add_grad(&mut self.vars, &step)?;
+                if t == 0. {
+                    // a step size of zero means the line search found no acceptable step
+                    stalled = true;
+                    (next_loss, step, ModelOutcome::Truncated)
+                } else if budget.is_some_and(|max_eval| steps >= max_eval) {
+                    info!("line search truncated by max_eval");
+                    (next_loss, step, ModelOutcome::Truncated)
+                } else {
+                    (next_loss, step, ModelOutcome::Stepped)
+                }
+            } else {
+                let step = (q * lr)?;
+                add_grad(&mut self.vars, &step)?;
+                evals += 1;
+                (self.model.loss()?, step, ModelOutcome::Stepped)
+            };
+
+        // a step that was not taken cannot have converged
+        let converged = !stalled && self.params.step_conv.converged(&step)? == Some(true);
+        self.last_step = Some(step);
+        if converged {
+            info!("step converged");
+            Ok(ModelOutcome::Converged(next_loss, evals))
+        } else {
+            Ok(stepped(next_loss, evals))
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> LineSearchTarget for Bfgs<M> {
+    fn directional_evaluate(
+        &mut self,
+        mag: f64,
+        direction: &Tensor,
+    ) -> CResult<(Tensor, Tensor, f64)> {
+        directional_evaluate(
+            &mut self.vars,
+            &self.model,
+            self.params.weight_decay,
+            mag,
+            direction,
+        )
+    }
+
+    fn l2_reg(&self) -> CResult<f64> {
+        l2_reg(&self.vars, self.params.weight_decay)
+    }
+}
+
+impl<M: Model> OptimVars for Bfgs<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> Bfgs<M> {
+    /// The current approximation of the inverse Hessian, as an $n \\times n$ matrix over the flattened variables, or
+    /// `None` before the first update (when the identity is used)
+    #[must_use]
+    pub fn inverse_hessian(&self) -> Option<&Tensor> {
+        self.h_inv.as_ref()
+    }
+
+    /// Discard the inverse Hessian approximation and the last step, so the next step is along the gradient
+    pub fn reset(&mut self) {
+        self.h_inv = None;
+        self.last_grad = None;
+        self.next_grad = None;
+        self.last_step = None;
+    }
+
+    /// apply the BFGS update for the step `s` and change in gradient `y`, which satisfy the curvature condition
+    fn update(&mut self, s: &Tensor, y: &Tensor) -> CResult<()> {
+        let ys = dot(y, s)?;
+        let h_inv = if let Some(h_inv) = &self.h_inv {
+            h_inv.clone()
+        } else {
+            let n = s.elem_count();
+            (Tensor::eye(n, s.dtype(), s.device())? * (ys / dot(y, y)?))?
+        };
+        let rho = ys.recip();
+        // with H symmetric the update expands to H + (rho^2 y^T H y + rho) s s^T - rho (H y s^T + s y^T H)
+        let hy = h_inv.matmul(&y.unsqueeze(1)?)?;
+        let yhy = dot(y, &hy.squeeze(1)?)?;
+        let s_col = s.unsqueeze(1)?;
+        let ss = s_col.matmul(&s_col.t()?)?;
+        let hys = hy.matmul(&s_col.t()?)?;
+        let cross = (&hys + hys.t()?)?;
+        let h_inv = ((h_inv + (ss * rho.mul_add(rho * yhy, rho))?)? - (cross * rho)?)?;
+        self.h_inv = Some(h_inv);
+        Ok(())
+    }
+}
+
+fn dot(a: &Tensor, b: &Tensor) -> CResult<f64> {
+    (a * b)?.sum_all()?.to_dtype(DType::F64)?.to_scalar::<f64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+
+    /// $\\frac{1}{2} x^{\\top} A x$ with $A = [[4, 1], [1, 3]]$
+    struct Quadratic {
+        x: Var,
+    }
+
+    impl Model for Quadratic {
+        fn loss(&self) -> CResult<Tensor> {
+            let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+            let x = self.x.as_tensor();
+            let ax = a.matmul(&x.unsqueeze(1)?)?.squeeze(1)?;
+            (x * ax)?.sum_all()? * 0.5
+        }
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let x = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let mut optim = Bfgs::new(vec![x.clone()], ParamsBfgs::default(), Quadratic { x })?;
+        assert_approx_eq!(1., optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn update_test() -> Result<()> {
+        // the updated matrix satisfies the secant condition H y = s, and is symmetric
+        let x = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let mut optim = Bfgs::new(vec![x.clone()], ParamsBfgs::default(), Quadratic { x })?;
+        let s = Tensor::new(&[1f64, -0.5], &Device::Cpu)?;
+        let y = Tensor::new(&[3.5f64, -0.5], &Device::Cpu)?;
+        optim.update(&s, &y)?;
+        let s2 = Tensor::new(&[0.2f64, 0.7], &Device::Cpu)?;
+        let y2 = Tensor::new(&[1.5f64, 2.3], &Device::Cpu)?;
+        optim.update(&s2, &y2)?;
+        let h_inv = optim.inverse_hessian().cloned().unwrap_or(s);
+        let hy = h_inv
+            .matmul(&y2.unsqueeze(1)?)?
+            .squeeze(1)?
+            .to_vec1::<f64>()?;
+        assert_approx_eq!(hy[0], 0.2);
+        assert_approx_eq!(hy[1], 0.7);
+        let h = h_inv.to_vec2::<f64>()?;
+        assert_approx_eq!(h[0][1], h[1][0]);
+        optim.reset();
+        assert!(optim.inverse_hessian().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn quadratic_test() -> Result<()> {
+        // on a quadratic the approximation converges to the inverse Hessian as the minimum is approached
+        let x = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let model = Quadratic { x: x.clone() };
+        let mut loss = model.loss()?;
+        let mut optim = Bfgs::new(vec![x.clone()], ParamsBfgs::default(), model)?;
+        let mut steps = 0;
+        while steps < 20 {
+            match optim.backward_step(&loss)? {
+                ModelOutcome::Converged(_, _) => break,
+                ModelOutcome::Stepped(next, _) | ModelOutcome::Truncated(next, _) => loss = next,
+            }
+            steps += 1;
+        }
+        assert!(steps < 10, "{steps} steps");
+        for x in x.to_vec1::<f64>()? {
+            assert_approx_eq!(x, 0., 1e-6);
+        }
+        // the inverse of [[4, 1], [1, 3]]
+        let h = optim
+            .inverse_hessian()
+            .map(Tensor::to_vec2::<f64>)
+            .transpose()?
+            .unwrap_or_default();
+        assert_approx_eq!(h[0][0], 3. / 11., 1e-2);
+        assert_approx_eq!(h[0][1], -1. / 11., 1e-2);
+        assert_approx_eq!(h[1][1], 4. / 11., 1e-2);
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+/*!
+Block diagonal preconditioning over user specified groups of variables
+
+Variables that interact strongly, such as the weight and bias of a layer, can be declared as a group to share a
+preconditioner block. For each group the flattened gradient $g_t$ of all its variables is used to track a moving average
+of the (empirical Fisher) gradient second moment
+
+$$ F_t \\gets \\beta F_{t-1} + (1 - \\beta) g_t g_t^{\\top}, \\qquad \\widehat{F_t} \\gets F_t / \\big(1 - \\beta^t\\big)$$
+
+and the group is updated by solving the damped system with [conjugate gradients](crate::cg)
+
+$$ \\left(\\widehat{F_t} + \\lambda I\\right) \\delta_t = g_t, \\qquad \\theta_t \\gets \\theta_{t-1} - \\gamma \\delta_t $$
+
+Variables not in any group fall back to the diagonal of the same preconditioner, so each element is updated as
+$\\gamma g_t / \\big(\\widehat{v_t} + \\lambda\\big)$ with $v_t$ the moving average of $g_t^2$.
+
+Each block holds a dense matrix with the square of the number of elements in its group, so this is suited to small groups.
+It is the basis for Kronecker factored approximations such as [K-FAC](https://arxiv.org/abs/1503.05671), which replace
+the dense block of a layer by the Kronecker product of two much smaller factors.
+*/
+
+use std::collections::HashSet;
+
+use candle_core::{backprop::GradStore, DType, Result, Tensor, TensorId, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, cg::conjugate_gradient, flatten_grads, unflatten, OptimParams, OptimVars,
+};
+
+/// Optimiser preconditioning user specified groups of variables with a shared block
+#[derive(Debug)]
+pub struct BlockPreconditioner {
+    /// all the variables, in construction order
+    vars: Vec<Var>,
+    blocks: Vec<Block>,
+    diagonal: Vec<VarDiagonal>,
+    params: ParamsBlockPreconditioner,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct Block {
+    vars: Vec<Var>,
+    /// moving average of the outer product of the flattened group gradient, in f64
+    stats: Var,
+}
+
+#[derive(Debug)]
+struct VarDiagonal {
+    theta: Var,
+    /// moving average of the squared gradient
+    v: Var,
+}
+
+/// Parameters for the block preconditioner
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsBlockPreconditioner {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for the moving average of the gradient second moment
+    pub beta: f64,
+    /// Damping added to the diagonal of the preconditioner
+    pub damping: f64,
+    /// Maximum number of conjugate gradient iterations per block per step
+    pub cg_max_iter: usize,
+    /// Tolerance on the residual norm for conjugate gradient convergence, relative to the norm of the group gradient
+    pub cg_tol: f64,
+}
+
+impl Default for ParamsBlockPreconditioner {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            beta: 0.95,
+            damping: 1e-3,
+            cg_max_iter: 100,
+            cg_tol: 1e-10,
+        }
+    }
+}
+
+impl Optimizer for BlockPreconditioner {
+    type Config = ParamsBlockPreconditioner;
+
+    /// create the optimiser with no groups, so every variable is preconditioned diagonally
+    fn new(vars: Vec<Var>, params: ParamsBlockPreconditioner) -> Result<Self> {
+        Self::new_with_groups(vars, Vec::new(), params)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        let beta = self.params.beta;
+        let correction = bias_correction(beta, self.t);
+        let damping = self.params.damping;
+        for block in &self.blocks {
+            if block.vars.iter().all(|var| grads.get(var).is_none()) {
+                continue;
+            }
+            let grad = flatten_grads(grads, &block.vars)?.to_dtype(DType::F64)?;
+            let outer = grad.unsqueeze(1)?.matmul(&grad.unsqueeze(0)?)?;
+            let stats = ((block.stats.as_tensor() * beta)? + (outer * (1. - beta))?)?;
+            block.stats.set(&stats)?;
+            let stats = (stats / correction)?;
+            let preconditioned_vp =
+                |v: &Tensor| stats.matmul(&v.unsqueeze(1)?)?.squeeze(1)? + (v * damping)?;
+            let delta = conjugate_gradient(
+                preconditioned_vp,
+                &grad,
+                self.params.cg_max_iter,
+                self.params.cg_tol,
+            )?;
+            for (var, update) in block.vars.iter().zip(unflatten(&delta, &block.vars)?) {
+                var.set(&var.sub(&(update.to_dtype(var.dtype())? * self.params.lr)?)?)?;
+            }
+        }
+        for var in &self.diagonal {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let v_next = ((beta * var.v.as_tensor())? + ((1. - beta) * grad.sqr()?)?)?;
+                let delta = grad.div(&((&v_next / correction)? + damping)?)?;
+                theta.set(&theta.sub(&(delta * self.params.lr)?)?)?;
+                var.v.set(&v_next)?;
+            }
+        }
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for BlockPreconditioner {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl OptimVars for BlockPreconditioner {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl BlockPreconditioner {
+    /// Create an optimiser where the variables in each of `groups` share a preconditioner block
+    ///
+    /// Variables of `vars` not in any group are preconditioned diagonally
+    ///
+    /// # Errors
+    ///
+    /// Errors if a group is empty, contains a variable not in `vars`, or contains a variable already in another group
+    pub fn new_with_groups(
+        vars: Vec<Var>,
+        groups: Vec<Vec<Var>>,
+        params: ParamsBlockPreconditioner,
+    ) -> Result<Self> {
+        let vars: Vec<Var> = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect();
+        let optimised: HashSet<TensorId> = vars.iter().map(|var| var.id()).collect();
+        let mut grouped = HashSet::new();
+        let mut blocks = Vec::with_capacity(groups.len());
+        for group in groups {
+            if group.is_empty() {
+                candle_core::bail!("preconditioning groups must not be empty");
+            }
+            for var in &group {
+                if !optimised.contains(&var.id()) {
+                    candle_core::bail!("variable {:?} in a group is not being optimised", var.id());
+                }
+                if !grouped.insert(var.id()) {
+                    candle_core::bail!("variable {:?} is in more than one group", var.id());
+                }
+            }
+            let n_elems = group.iter().map(|var| var.elem_count()).sum::<usize>();
+            let stats = Var::zeros((n_elems, n_elems), DType::F64, group[0].device())?;
+            blocks.push(Block { vars: group, stats });
+        }
+        let diagonal = vars
+            .iter()
+            .filter(|var| !grouped.contains(&var.id()))
+            .map(|var| {
+                let v = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                Ok(VarDiagonal {
+                    theta: var.clone(),
+                    v,
+                })
+            })
+            .collect::<Result<Vec<VarDiagonal>>>()?;
+        Ok(Self {
+            vars,
+            blocks,
+            diagonal,
+            params,
+            t: 1.,
+        })
+    }
+
+    /// the number of preconditioner blocks
+    #[must_use]
+    pub fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    fn setup() -> Result<(Var, Var, Tensor)> {
+        let w = Var::new(&[0f64], &Device::Cpu)?;
+        let b = Var::new(&[0f64], &Device::Cpu)?;
+        // gradients of 1 for w and 2 for b
+        let loss = (w.as_tensor() + (b.as_tensor() * 2.)?)?.sum_all()?;
+        Ok((w, b, loss))
+    }
+
+    #[test]
+    fn shared_block_test() -> Result<()> {
+        let params = ParamsBlockPreconditioner {
+            lr: 1.,
+            damping: 1.,
+            ..Default::default()
+        };
+        let (w, b, loss) = setup()?;
+        let mut optim = BlockPreconditioner::new_with_groups(
+            vec![w.clone(), b.clone()],
+            vec![vec![w.clone(), b.clone()]],
+            params.clone(),
+        )?;
+        assert_eq!(optim.num_blocks(), 1);
+        optim.backward_step(&loss)?;
+        // after bias correction the block is g g^T, so the step is g / (damping + |g|^2) = g / 6
+        assert_approx_eq!(w.to_vec1::<f64>()?[0], -1. / 6.);
+        assert_approx_eq!(b.to_vec1::<f64>()?[0], -2. / 6.);
+
+        // ungrouped, each variable only sees its own gradient
+        let (w, b, loss) = setup()?;
+        let mut optim = BlockPreconditioner::new(vec![w.clone(), b.clone()], params)?;
+        assert_eq!(optim.num_blocks(), 0);
+        optim.backward_step(&loss)?;
+        assert_approx_eq!(w.to_vec1::<f64>()?[0], -1. / 2.);
+        assert_approx_eq!(b.to_vec1::<f64>()?[0], -2. / 5.);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_groups_test() -> Result<()> {
+        let (w, b, _) = setup()?;
+        let other = Var::new(&[0f64], &Device::Cpu)?;
+        let params = ParamsBlockPreconditioner::default();
+        assert!(BlockPreconditioner::new_with_groups(
+            vec![w.clone(), b.clone()],
+            vec![vec![other]],
+            params.clone()
+        )
+        .is_err());
+        assert!(BlockPreconditioner::new_with_groups(
+            vec![w.clone(), b.clone()],
+            vec![vec![w.clone()], vec![w.clone(), b.clone()]],
+            params.clone()
+        )
+        .is_err());
+        assert!(BlockPreconditioner::new_with_groups(vec![w, b], vec![vec![]], params).is_err());
+        Ok(())
+    }
+}
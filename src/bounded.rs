@@ -0,0 +1,165 @@
+/*!
+Box constraints on parameters
+
+[`Bounded`] wraps any optimiser in this crate and, after each step, clamps every variable it manages to a global
+interval $[\\text{lo}, \\text{hi}]$, for example to keep physically meaningful parameters such as rates positive.
+The clamp is applied last, after the wrapped optimiser's update (including any weight decay), so the bounds always hold
+after a step. Optimiser state such as momentum is not changed by the clamp.
+*/
+
+use candle_core::{backprop::GradStore, Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::OptimVars;
+
+/// Optimiser wrapper clamping all variables to fixed bounds after each step
+#[derive(Debug)]
+pub struct Bounded<O: Optimizer + OptimVars> {
+    inner: O,
+    bounds: Option<(f64, f64)>,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for Bounded<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        self.inner.step(grads)?;
+        if let Some((lo, hi)) = self.bounds {
+            for var in self.inner.vars() {
+                var.set(&var.clamp(lo, hi)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for Bounded<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> Bounded<O> {
+    /// wrap an optimiser, with no bounds initially set
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self {
+            inner,
+            bounds: None,
+        }
+    }
+
+    /// clamp every variable to $[\\text{lo}, \\text{hi}]$ after each step
+    ///
+    /// variables are only clamped when a step is taken, so may be out of bounds until then
+    ///
+    /// # Errors
+    ///
+    /// Errors if `lo` is greater than `hi`, or either is NaN
+    pub fn set_param_bounds(&mut self, lo: f64, hi: f64) -> Result<()> {
+        if lo.is_nan() || hi.is_nan() || lo > hi {
+            candle_core::bail!("invalid parameter bounds [{lo}, {hi}]");
+        }
+        self.bounds = Some((lo, hi));
+        Ok(())
+    }
+
+    /// remove the bounds
+    pub fn clear_param_bounds(&mut self) {
+        self.bounds = None;
+    }
+
+    /// the current bounds, if set
+    #[must_use]
+    pub fn param_bounds(&self) -> Option<(f64, f64)> {
+        self.bounds
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use crate::{Decay, Momentum};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    #[test]
+    fn bounds_test() -> Result<()> {
+        let w = Var::new(&[1.5f32, 1.5], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            momentum: Some(Momentum::Classical(0.9)),
+            ..Default::default()
+        };
+        let mut optim = Bounded::new(SGD::new(vec![w.clone()], params)?);
+        optim.set_param_bounds(1., 2.)?;
+        // minima at 5 and -3, both outside the bounds
+        let target = candle_core::Tensor::new(&[5f32, -3.], &Device::Cpu)?;
+        let loss = w.as_tensor().sub(&target)?.sqr()?.sum_all()?;
+        for _ in 0..50 {
+            optim.backward_step(&loss)?;
+            for x in w.to_vec1::<f32>()? {
+                assert!((1. ..=2.).contains(&x), "{x} out of bounds");
+            }
+        }
+        let w = w.to_vec1::<f32>()?;
+        assert_approx_eq!(w[0], 2.);
+        assert_approx_eq!(w[1], 1.);
+        Ok(())
+    }
+
+    #[test]
+    fn weight_decay_test() -> Result<()> {
+        // decoupled weight decay alone pulls the variable towards zero, but the clamp is applied after it
+        let w = Var::new(&[1f32], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            weight_decay: Some(Decay::DecoupledWeightDecay(1.)),
+            ..Default::default()
+        };
+        let mut optim = Bounded::new(SGD::new(vec![w.clone()], params)?);
+        optim.set_param_bounds(0.5, 10.)?;
+        let loss = w.as_tensor().sum_all()?;
+        for _ in 0..20 {
+            optim.backward_step(&loss)?;
+        }
+        assert_approx_eq!(w.to_vec1::<f32>()?[0], 0.5);
+        assert_eq!(optim.param_bounds(), Some((0.5, 10.)));
+        assert!(optim.set_param_bounds(1., 0.).is_err());
+        optim.clear_param_bounds();
+        optim.backward_step(&loss)?;
+        assert!(w.to_vec1::<f32>()?[0] < 0.5);
+        Ok(())
+    }
+}
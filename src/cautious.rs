@@ -0,0 +1,183 @@
+/*!
+Cautious updates
+
+Described in [Cautious Optimizers: Improving Training with One Line of Code](https://arxiv.org/abs/2411.16085)
+
+[`Cautious`] wraps any optimiser in this crate and masks out the elements of its update whose sign disagrees with
+the current gradient, rescaling the rest by the fraction of elements kept:
+
+$$
+\\begin{aligned}
+    &u_t \\gets \\theta_{t-1} - \\text{inner}(\\theta_{t-1}, g_t) \\\\
+    &\\phi_t \\gets \\mathbb{1}\\left[u_t \\circ g_t > 0\\right] \\\\
+    &\\theta_t \\gets \\theta_{t-1} - \\frac{\\phi_t \\circ u_t}{\\max\\left(\\text{mean}(\\phi_t), \\epsilon\\right)}
+\\end{aligned}
+$$
+
+where the mean is over the elements of each variable. For SGD without momentum every element is kept, while for
+momentum based methods such as Adam (C-Adam) or Lion (C-Lion) the elements whose momentum points uphill are skipped.
+
+The `Optimizer` trait does not expose the update an optimiser would make, so it is recovered by comparing the
+variables before and after the inner step: this costs a copy of every variable with a gradient each step. The update
+includes any weight decay applied by the inner optimiser, which is masked in the same way. The inner optimiser's state
+(such as its moment estimates) is updated as usual.
+*/
+
+use candle_core::{backprop::GradStore, DType, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::OptimVars;
+
+/// smallest fraction of kept elements the update is rescaled by
+const MIN_MASK_MEAN: f64 = 1e-3;
+
+/// Optimiser wrapper masking update elements that disagree in sign with the gradient
+#[derive(Debug)]
+pub struct Cautious<O: Optimizer + OptimVars> {
+    inner: O,
+    last_mask_fraction: Option<f64>,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for Cautious<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        // the vars are updated in place so must be copied
+        let before = self
+            .inner
+            .vars()
+            .into_iter()
+            .map(|var| match grads.get(var) {
+                Some(_) => Ok(Some(var.as_tensor().copy()?)),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<Option<Tensor>>>>()?;
+        self.inner.step(grads)?;
+
+        let mut kept = 0.;
+        let mut total = 0;
+        for (var, before) in self.inner.vars().into_iter().zip(before) {
+            let (Some(before), Some(grad)) = (before, grads.get(var)) else {
+                continue;
+            };
+            let update = (&before - var.as_tensor())?;
+            let mask = update.mul(grad)?.gt(0.)?.to_dtype(update.dtype())?;
+            let mask_sum = mask.sum_all()?.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+            #[allow(clippy::cast_precision_loss)]
+            let mask_mean = mask_sum / var.elem_count() as f64;
+            kept += mask_sum;
+            total += var.elem_count();
+            let masked = (update.mul(&mask)? / mask_mean.max(MIN_MASK_MEAN))?;
+            var.set(&(before - masked)?)?;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let fraction = (total > 0).then(|| kept / total as f64);
+        self.last_mask_fraction = fraction;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for Cautious<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> Cautious<O> {
+    /// wrap an optimiser
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self {
+            inner,
+            last_mask_fraction: None,
+        }
+    }
+
+    /// the fraction of update elements kept on the last step, across all variables with a gradient
+    ///
+    /// `None` before the first step, or if no variable had a gradient
+    #[must_use]
+    pub fn last_mask_fraction(&self) -> Option<f64> {
+        self.last_mask_fraction
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use crate::Momentum;
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    #[test]
+    fn sgd_unchanged_test() -> Result<()> {
+        // without momentum the update is always along the gradient, so nothing is masked
+        let w = Var::new(&[1f64, -2.], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let mut optim: Cautious<SGD> = Cautious::new(SGD::new(vec![w.clone()], params)?);
+        optim.backward_step(&w.as_tensor().sqr()?.sum_all()?)?;
+        let w = w.to_vec1::<f64>()?;
+        assert_approx_eq!(w[0], 0.8);
+        assert_approx_eq!(w[1], -1.6);
+        assert_eq!(optim.last_mask_fraction(), Some(1.));
+        Ok(())
+    }
+
+    #[test]
+    fn mask_test() -> Result<()> {
+        let w = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            momentum: Some(Momentum::Classical(0.9)),
+            ..Default::default()
+        };
+        let mut optim: Cautious<SGD> = Cautious::new(SGD::new(vec![w.clone()], params)?);
+        // build momentum of [1, 1]
+        optim.backward_step(&w.as_tensor().sum_all()?)?;
+        assert_eq!(optim.last_mask_fraction(), Some(1.));
+        let after_first = w.to_vec1::<f64>()?;
+        // gradient [1, -0.5]: the momentum is now [1.9, 0.4], so the second element's update of 0.04 would
+        // move uphill against the gradient so is masked, and the first's update of 0.19 is doubled
+        let coeffs = Tensor::new(&[1f64, -0.5], &Device::Cpu)?;
+        optim.backward_step(&w.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        assert_eq!(optim.last_mask_fraction(), Some(0.5));
+        let w = w.to_vec1::<f64>()?;
+        assert_approx_eq!(w[0], after_first[0] - 0.38);
+        assert_approx_eq!(w[1], after_first[1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+/*!
+Gradient centralization
+
+Described in [Gradient Centralization: A New Optimization Technique for Deep Neural Networks](https://arxiv.org/abs/2004.01461)
+
+For weights of rank two or more (such as those of linear and convolutional layers), the gradient is centralized by
+subtracting its mean over every dimension except the first (output) dimension:
+
+$$ g_{i} \\gets g_{i} - \\frac{1}{M} \\sum_{j=1}^{M} g_{i, j}$$
+
+where $g_{i}$ is the gradient for output $i$, flattened to $M$ elements. Gradients of rank zero or one (such as those
+of biases) are left unchanged.
+
+This can be applied to a `GradStore` with [`centralize_grads`] before it is passed to an optimiser's `step`,
+or inside `step` by wrapping any optimiser in this crate with [`GradCentralization`].
+*/
+
+use candle_core::{backprop::GradStore, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{empty_grad_store, OptimVars};
+
+/// Subtract the mean over all dimensions except the first from a gradient of rank two or more
+///
+/// Gradients of rank zero or one are returned unchanged.
+///
+/// # Errors
+///
+/// Errors if the mean cannot be calculated
+pub fn centralize_grad(grad: &Tensor) -> Result<Tensor> {
+    let rank = grad.rank();
+    if rank < 2 {
+        return Ok(grad.clone());
+    }
+    let mut mean_dims = vec![1; rank];
+    mean_dims[0] = grad.dim(0)?;
+    let mean = grad.flatten_from(1)?.mean_keepdim(1)?.reshape(mean_dims)?;
+    grad.broadcast_sub(&mean)
+}
+
+/// Centralize the gradients of `vars`
+///
+/// # Errors
+///
+/// Errors if the gradients cannot be centralized
+pub fn centralize_grads(grads: &mut GradStore, vars: &[&Var]) -> Result<()> {
+    for var in vars {
+        if let Some(grad) = grads.get(var) {
+            let centralized = centralize_grad(grad)?;
+            grads.insert(var, centralized);
+        }
+    }
+    Ok(())
+}
+
+/// Optimiser wrapper centralizing the gradients before each step
+#[derive(Debug)]
+pub struct GradCentralization<O: Optimizer + OptimVars> {
+    inner: O,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for GradCentralization<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        // only the gradients of the managed variables are copied into the new store
+        let mut centralized = empty_grad_store()?;
+        for var in self.inner.vars() {
+            if let Some(grad) = grads.get(var) {
+                centralized.insert(var, centralize_grad(grad)?);
+            }
+        }
+        self.inner.step(&centralized)
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for GradCentralization<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> GradCentralization<O> {
+    /// wrap an optimiser
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self { inner }
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{DType, Device, D};
+
+    #[test]
+    fn centralize_test() -> Result<()> {
+        let grad = Tensor::arange(0f64, 24., &Device::Cpu)?
+            .sqr()?
+            .reshape((2, 3, 4))?;
+        let centralized = centralize_grad(&grad)?;
+        // zero mean over every dimension but the first
+        for mean in centralized
+            .flatten_from(1)?
+            .mean(D::Minus1)?
+            .to_vec1::<f64>()?
+        {
+            assert_approx_eq!(mean, 0.);
+        }
+        // which is the only change: the differences within each output are kept
+        let diff = (grad - &centralized)?.flatten_from(1)?;
+        for row in diff.to_vec2::<f64>()? {
+            assert!(row.iter().all(|x| (x - row[0]).abs() < 1e-9));
+        }
+
+        // biases and scalars are unchanged
+        let bias = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+        assert_eq!(centralize_grad(&bias)?.to_vec1::<f32>()?, [1., 2., 3.]);
+        let scalar = Tensor::new(2f32, &Device::Cpu)?;
+        assert_eq!(centralize_grad(&scalar)?.to_scalar::<f32>()?, 2.);
+        Ok(())
+    }
+
+    #[test]
+    fn wrapper_test() -> Result<()> {
+        let w = Var::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+        let b = Var::new(&[1f32, 1.], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 1.,
+            ..Default::default()
+        };
+        let mut optim: GradCentralization<SGD> =
+            GradCentralization::new(SGD::new(vec![w.clone(), b.clone()], params)?);
+        // gradient [[1, 2], [3, 4]] for w and [1, 1] for b
+        let loss = ((w.as_tensor().sqr()? * 0.5)?.sum_all()? + b.as_tensor().sum_all()?)?;
+        let mut grads = loss.backward()?;
+        optim.step(&grads)?;
+        // each row of the gradient is centralized to [-0.5, 0.5]
+        assert_eq!(w.to_vec2::<f32>()?, [[1.5, 1.5], [3.5, 3.5]]);
+        assert_eq!(b.to_vec1::<f32>()?, [0., 0.]);
+
+        // the same as centralizing the store directly
+        centralize_grads(&mut grads, &[&w, &b])?;
+        let g = grads.get(&w).unwrap().to_dtype(DType::F32)?;
+        assert_eq!(g.to_vec2::<f32>()?, [[-0.5, 0.5], [-0.5, 0.5]]);
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+/*!
+Matrix free conjugate gradient solver
+
+For second order methods that need to solve $A x = b$ for a symmetric positive definite $A$ (a damped Fisher, Hessian
+or Gauss-Newton matrix) available only through matrix-vector products, as in
+[Methods of Conjugate Gradients for Solving Linear Systems](https://doi.org/10.6028/jres.049.044).
+
+$$
+\\begin{aligned}
+    &x_0 \\gets 0, \\: r_0 \\gets b, \\: p_0 \\gets r_0 \\\\
+    &\\alpha_k \\gets \\frac{r_k^{\\top} r_k}{p_k^{\\top} A p_k} \\\\
+    &x_{k+1} \\gets x_k + \\alpha_k p_k, \\: r_{k+1} \\gets r_k - \\alpha_k A p_k \\\\
+    &p_{k+1} \\gets r_{k+1} + \\frac{r_{k+1}^{\\top} r_{k+1}}{r_k^{\\top} r_k} p_k
+\\end{aligned}
+$$
+
+The iteration stops once $||r_k||_{2} \\leq \\text{tol} \\, ||b||_{2}$.
+
+[`truncated_conjugate_gradient`] is for matrices that may be indefinite, such as the Hessian of a non-convex loss in
+a truncated Newton method: rather than erroring, it stops at the first direction of non-positive curvature.
+
+[`steihaug`] solves the trust region subproblem of minimising the quadratic model $g^{\\top} p + \\frac{1}{2} p^{\\top} A p$
+subject to $||p||_{2} \\leq \\Delta$, as described in
+[The Conjugate Gradient Method and Trust Regions in Large Scale Optimization](https://doi.org/10.1137/0720042).
+It follows the conjugate gradient iterates (of $A p = -g$) until they leave the trust region or a direction of
+non-positive curvature is found, and then steps along the last direction to the boundary.
+*/
+
+use candle_core::{DType, Result, Tensor};
+
+/// inner product of two tensors of the same shape, as an `f64`
+pub(crate) fn dot(a: &Tensor, b: &Tensor) -> Result<f64> {
+    (a * b)?.sum_all()?.to_dtype(DType::F64)?.to_scalar::<f64>()
+}
+
+/// solve $A x = b$ for symmetric positive definite $A$, given only the product `matvec(v)` $= Av$ on flat vectors
+///
+/// Starting from $x_0 = 0$, iterates until the residual norm is at most `tol` times the norm of `b`.
+/// If this is not reached within `max_iter` iterations, the last iterate is returned: this truncated solution is
+/// still a descent direction, as used by truncated Newton methods.
+///
+/// # Errors
+///
+/// Errors if `b` is not a vector, if `matvec` fails, or if a search direction of non-positive curvature
+/// $p^{\\top} A p \\leq 0$ is found, as then $A$ is not positive definite
+pub fn conjugate_gradient(
+    mut matvec: impl FnMut(&Tensor) -> Result<Tensor>,
+    b: &Tensor,
+    max_iter: usize,
+    tol: f64,
+) -> Result<Tensor> {
+    if b.rank() != 1 {
+        candle_core::bail!(
+            "conjugate gradient expects a flat vector, got shape {:?}",
+            b.shape()
+        );
+    }
+    let mut x = b.zeros_like()?;
+    let mut r = b.clone();
+    let mut p = r.clone();
+    let mut rr = dot(&r, &r)?;
+    let threshold = tol * rr.sqrt();
+    for _ in 0..max_iter {
+        if rr.sqrt() <= threshold {
+            break;
+        }
+        let ap = matvec(&p)?;
+        let curvature = dot(&p, &ap)?;
+        if curvature <= 0. {
+            candle_core::bail!(
+                "conjugate gradient found non-positive curvature {curvature}: the matrix is not positive definite"
+            );
+        }
+        let alpha = rr / curvature;
+        x = (x + (&p * alpha)?)?;
+        r = (r - (ap * alpha)?)?;
+        let rr_next = dot(&r, &r)?;
+        p = (&r + (p * (rr_next / rr))?)?;
+        rr = rr_next;
+    }
+    Ok(x)
+}
+
+/// Why [`truncated_conjugate_gradient`] or [`steihaug`] stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CgStop {
+    /// the residual met the tolerance
+    Converged,
+    /// the iteration limit was reached first
+    MaxIter,
+    /// a search direction of non-positive curvature was found
+    NegativeCurvature,
+    /// the iterates left the trust region, so the solution is on its boundary
+    Boundary,
+}
+
+/// solve $A x = b$ as [`conjugate_gradient`], but stopping at a direction of non-positive curvature rather than
+/// erroring, returning the solution with the reason the iteration stopped
+///
+/// On non-positive curvature the last iterate is returned, or `b` itself if this is found on the first iteration (as
+/// then there is no iterate yet). For $b = -g$, with $g$ a gradient and $A$ a Hessian, both are descent directions.
+///
+/// # Errors
+///
+/// Errors if `b` is not a vector, or if `matvec` fails
+pub fn truncated_conjugate_gradient(
+    mut matvec: impl FnMut(&Tensor) -> Result<Tensor>,
+    b: &Tensor,
+    max_iter: usize,
+    tol: f64,
+) -> Result<(Tensor, CgStop)> {
+    if b.rank() != 1 {
+        candle_core::bail!(
+            "conjugate gradient expects a flat vector, got shape {:?}",
+            b.shape()
+        );
+    }
+    let mut x = b.zeros_like()?;
+    let mut r = b.clone();
+    let mut p = r.clone();
+    let mut rr = dot(&r, &r)?;
+    let threshold = tol * rr.sqrt();
+    for iter in 0..max_iter {
+        if rr.sqrt() <= threshold {
+            return Ok((x, CgStop::Converged));
+        }
+        let ap = matvec(&p)?;
+        let curvature = dot(&p, &ap)?;
+        if curvature <= 0. {
+            let x = if iter == 0 { b.clone() } else { x };
+            return Ok((x, CgStop::NegativeCurvature));
+        }
+        let alpha = rr / curvature;
+        x = (x + (&p * alpha)?)?;
+        r = (r - (ap * alpha)?)?;
+        let rr_next = dot(&r, &r)?;
+        p = (&r + (p * (rr_next / rr))?)?;
+        rr = rr_next;
+    }
+    let stop = if rr.sqrt() <= threshold {
+        CgStop::Converged
+    } else {
+        CgStop::MaxIter
+    };
+    Ok((x, stop))
+}
+
+/// the positive step $\\tau$ such that $||p + \\tau d||_{2} = \\Delta$, for $p$ inside the trust region
+fn to_boundary(p: &Tensor, d: &Tensor, radius: f64) -> Result<f64> {
+    let dd = dot(d, d)?;
+    let pd = dot(p, d)?;
+    let pp = dot(p, p)?;
+    let discriminant = pd.mul_add(pd, dd * radius.mul_add(radius, -pp)).max(0.);
+    Ok((discriminant.sqrt() - pd) / dd)
+}
+
+/// Steihaug's truncated conjugate gradient solution of the trust region subproblem: minimise
+/// $g^{\\top} p + \\frac{1}{2} p^{\\top} A p$ subject to $||p||_{2} \\leq$ `radius`, given only the product
+/// `matvec(v)` $= Av$ on flat vectors
+///
+/// The iteration stops when the residual norm is at most `tol` times the norm of `g`, after `max_iter` iterations,
+/// or on reaching the boundary (on leaving the trust region or finding a direction of non-positive curvature).
+///
+/// # Returns
+///
+/// (p, $Ap$, the reason the iteration stopped), with $Ap$ returned so the reduction predicted by the model can be
+/// found without another product
+///
+/// # Errors
+///
+/// Errors if `g` is not a vector, if `radius` is not positive, or if `matvec` fails
+pub fn steihaug(
+    mut matvec: impl FnMut(&Tensor) -> Result<Tensor>,
+    g: &Tensor,
+    radius: f64,
+    max_iter: usize,
+    tol: f64,
+) -> Result<(Tensor, Tensor, CgStop)> {
+    if g.rank() != 1 {
+        candle_core::bail!(
+            "conjugate gradient expects a flat vector, got shape {:?}",
+            g.shape()
+        );
+    }
+    if radius <= 0. {
+        candle_core::bail!("the trust region radius must be positive, got {radius}");
+    }
+    let mut p = g.zeros_like()?;
+    let mut ap = g.zeros_like()?;
+    let mut r = g.clone();
+    let mut d = g.neg()?;
+    let mut rr = dot(&r, &r)?;
+    let threshold = tol * rr.sqrt();
+    for _ in 0..max_iter {
+        if rr.sqrt() <= threshold {
+            return Ok((p, ap, CgStop::Converged));
+        }
+        let ad = matvec(&d)?;
+        let curvature = dot(&d, &ad)?;
+        if curvature <= 0. {
+            let tau = to_boundary(&p, &d, radius)?;
+            return Ok((
+                (p + (d * tau)?)?,
+                (ap + (ad * tau)?)?,
+                CgStop::NegativeCurvature,
+            ));
+        }
+        let alpha = rr / curvature;
+        let p_next = (&p + (&d * alpha)?)?;
+        if dot(&p_next, &p_next)?.sqrt() >= radius {
+            let tau = to_boundary(&p, &d, radius)?;
+            return Ok(((p + (d * tau)?)?, (ap + (ad * tau)?)?, CgStop::Boundary));
+        }
+        p = p_next;
+        ap = (ap + (&ad * alpha)?)?;
+        r = (r + (ad * alpha)?)?;
+        let rr_next = dot(&r, &r)?;
+        d = ((d * (rr_next / rr))? - &r)?;
+        rr = rr_next;
+    }
+    let stop = if rr.sqrt() <= threshold {
+        CgStop::Converged
+    } else {
+        CgStop::MaxIter
+    };
+    Ok((p, ap, stop))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+
+    fn matvec(a: &Tensor) -> impl FnMut(&Tensor) -> Result<Tensor, candle_core::Error> + '_ {
+        |v: &Tensor| a.matmul(&v.unsqueeze(1)?)?.squeeze(1)
+    }
+
+    #[test]
+    fn cg_test() -> Result<()> {
+        // A = [[4, 1], [1, 3]], b = [1, 2] has solution [1/11, 7/11]
+        let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+        let b = Tensor::new(&[1f64, 2.], &Device::Cpu)?;
+        let x = conjugate_gradient(matvec(&a), &b, 2, 1e-12)?.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 1. / 11.);
+        assert_approx_eq!(x[1], 7. / 11.);
+        Ok(())
+    }
+
+    #[test]
+    fn relative_tol_test() -> Result<()> {
+        // A = diag(1, 2, 4, 8) needs 4 iterations, and each is counted
+        let a = Tensor::new(
+            &[
+                [1f64, 0., 0., 0.],
+                [0., 2., 0., 0.],
+                [0., 0., 4., 0.],
+                [0., 0., 0., 8.],
+            ],
+            &Device::Cpu,
+        )?;
+        let count = |b: &Tensor, tol: f64| -> Result<(usize, Vec<f64>)> {
+            let mut calls = 0;
+            let mut mv = matvec(&a);
+            let x = conjugate_gradient(
+                |v| {
+                    calls += 1;
+                    mv(v)
+                },
+                b,
+                10,
+                tol,
+            )?;
+            Ok((calls, x.to_vec1::<f64>()?))
+        };
+        let b = Tensor::new(&[1f64, 1., 1., 1.], &Device::Cpu)?;
+        let (calls, x) = count(&b, 1e-12)?;
+        assert_eq!(calls, 4);
+        for (x, e) in x.iter().zip([1., 0.5, 0.25, 0.125]) {
+            assert_approx_eq!(x, e);
+        }
+        // the tolerance is relative, so scaling b does not change the number of iterations
+        let (scaled_calls, _) = count(&(b * 1e-8)?, 1e-12)?;
+        assert_eq!(scaled_calls, 4);
+        // a zero right hand side is solved immediately
+        let (zero_calls, x) = count(&Tensor::zeros(4, DType::F64, &Device::Cpu)?, 1e-12)?;
+        assert_eq!(zero_calls, 0);
+        assert_eq!(x, [0., 0., 0., 0.]);
+        Ok(())
+    }
+
+    #[test]
+    fn non_convergence_test() -> Result<()> {
+        // truncated after one iteration the residual is reduced but not solved
+        let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+        let b = Tensor::new(&[1f64, 2.], &Device::Cpu)?;
+        let x = conjugate_gradient(matvec(&a), &b, 1, 1e-12)?;
+        let residual = (&b - matvec(&a)(&x)?)?
+            .sqr()?
+            .sum_all()?
+            .to_scalar::<f64>()?;
+        assert!(residual > 1e-6 && residual < 5.);
+        // an indefinite matrix errors
+        let indefinite = Tensor::new(&[[1f64, 0.], [0., -1.]], &Device::Cpu)?;
+        let b = Tensor::new(&[0f64, 1.], &Device::Cpu)?;
+        assert!(conjugate_gradient(matvec(&indefinite), &b, 2, 1e-12).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_test() -> Result<()> {
+        // a positive definite system is solved as by conjugate_gradient
+        let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+        let b = Tensor::new(&[1f64, 2.], &Device::Cpu)?;
+        let (x, stop) = truncated_conjugate_gradient(matvec(&a), &b, 2, 1e-12)?;
+        assert_eq!(stop, CgStop::Converged);
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 1. / 11.);
+        assert_approx_eq!(x[1], 7. / 11.);
+        let (_, stop) = truncated_conjugate_gradient(matvec(&a), &b, 1, 1e-12)?;
+        assert_eq!(stop, CgStop::MaxIter);
+        // negative curvature on the first direction returns b
+        let indefinite = Tensor::new(&[[1f64, 0.], [0., -1.]], &Device::Cpu)?;
+        let b = Tensor::new(&[0f64, 1.], &Device::Cpu)?;
+        let (x, stop) = truncated_conjugate_gradient(matvec(&indefinite), &b, 2, 1e-12)?;
+        assert_eq!(stop, CgStop::NegativeCurvature);
+        assert_eq!(x.to_vec1::<f64>()?, [0., 1.]);
+        // and on a later direction returns the last iterate: the first step along b = [1, 1] has curvature 1, so goes to
+        // 2 b, then the second direction [6, 12] has curvature -72
+        let indefinite = Tensor::new(&[[2f64, 0.], [0., -1.]], &Device::Cpu)?;
+        let b = Tensor::new(&[1f64, 1.], &Device::Cpu)?;
+        let (x, stop) = truncated_conjugate_gradient(matvec(&indefinite), &b, 2, 1e-12)?;
+        assert_eq!(stop, CgStop::NegativeCurvature);
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 2.);
+        assert_approx_eq!(x[1], 2.);
+        Ok(())
+    }
+
+    #[test]
+    fn steihaug_test() -> Result<()> {
+        // inside a large trust region this is the Newton step solving A p = -g
+        let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+        let g = Tensor::new(&[-1f64, -2.], &Device::Cpu)?;
+        let (p, ap, stop) = steihaug(matvec(&a), &g, 10., 10, 1e-12)?;
+        assert_eq!(stop, CgStop::Converged);
+        let p = p.to_vec1::<f64>()?;
+        assert_approx_eq!(p[0], 1. / 11.);
+        assert_approx_eq!(p[1], 7. / 11.);
+        let ap = ap.to_vec1::<f64>()?;
+        assert_approx_eq!(ap[0], 1.);
+        assert_approx_eq!(ap[1], 2.);
+        // with a small radius the first direction, steepest descent, reaches the boundary
+        let (p, _, stop) = steihaug(matvec(&a), &g, 0.1, 10, 1e-12)?;
+        assert_eq!(stop, CgStop::Boundary);
+        let p = p.to_vec1::<f64>()?;
+        assert_approx_eq!(p[0], 0.1 / 5f64.sqrt());
+        assert_approx_eq!(p[1], 0.2 / 5f64.sqrt());
+        // with negative curvature the step goes to the boundary along it, and Ap matches the step
+        let indefinite = Tensor::new(&[[1f64, 0.], [0., -1.]], &Device::Cpu)?;
+        let g = Tensor::new(&[0f64, 1.], &Device::Cpu)?;
+        let (p, ap, stop) = steihaug(matvec(&indefinite), &g, 2., 10, 1e-12)?;
+        assert_eq!(stop, CgStop::NegativeCurvature);
+        assert_eq!(p.to_vec1::<f64>()?, [0., -2.]);
+        assert_eq!(ap.to_vec1::<f64>()?, [0., 2.]);
+        assert!(steihaug(matvec(&a), &g, 0., 10, 1e-12).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+/*!
+Gradient clipping
+
+Clipping rescales gradients in a `GradStore` before they are passed to an optimiser's `step`,
+so the $L_2$ norm of the gradient of the clipped variables is at most `max_norm`. Gradients with a norm over
+`max_norm` are rescaled as
+
+$$ g \\gets \\frac{\\text{max\\_norm}}{\\|g\\|_2 + 10^{-6}} g$$
+
+Gradients can also be clipped element-wise to lie within $[-\\text{clip\\_value}, \\text{clip\\_value}]$ with [`clip_grad_value`].
+
+Variables can be split into groups (e.g. a backbone and a head) with each group clipped separately;
+if a global clip is also given it is applied across all the groups first.
+*/
+
+use std::borrow::Borrow;
+
+use candle_core::{backprop::GradStore, DType, Result, Var};
+
+use crate::flatten_grads;
+
+/// A group of variables whose gradients are clipped together
+#[derive(Clone, Debug)]
+pub struct ClipGroup {
+    /// the variables in the group
+    pub vars: Vec<Var>,
+    /// max $L_2$ norm of the gradient of the group: if `None` the group is not clipped
+    pub clip_grad: Option<f64>,
+}
+
+/// the $L_2$ norm of the gradients of `vars`, treating variables without a gradient as having zero gradient
+///
+/// # Errors
+///
+/// Errors if the norm cannot be calculated, including if the gradients have different dtypes
+pub fn grad_norm<V: Borrow<Var>>(vars: &[V], grads: &GradStore) -> Result<f64> {
+    Ok(flatten_grads(grads, vars)?
+        .sqr()?
+        .sum_all()?
+        .to_dtype(DType::F64)?
+        .to_scalar::<f64>()?
+        .sqrt())
+}
+
+/// Clip the gradients of `vars` so their combined $L_2$ norm is at most `max_norm`
+///
+/// Gradients over the max norm are scaled by $\frac{\text{max\_norm}}{\|g\|_2 + 10^{-6}}$, so the clipped norm is
+/// just below `max_norm`. Returns the norm before clipping.
+///
+/// # Errors
+///
+/// Errors if the norm cannot be calculated or the gradients cannot be rescaled
+pub fn clip_grad_norm<V: Borrow<Var>>(
+    vars: &[V],
+    grads: &mut GradStore,
+    max_norm: f64,
+) -> Result<f64> {
+    let norm = grad_norm(vars, grads)?;
+    if norm > max_norm {
+        let scale = max_norm / (norm + 1e-6);
+        for var in vars {
+            let var = var.borrow();
+            if let Some(grad) = grads.get(var) {
+                let clipped = (grad * scale)?;
+                grads.insert(var, clipped);
+            }
+        }
+    }
+    Ok(norm)
+}
+
+/// Clamp each element of the gradients of `vars` to lie within `[-clip_value, clip_value]`
+///
+/// # Errors
+///
+/// Errors if the gradients cannot be clamped
+pub fn clip_grad_value<V: Borrow<Var>>(
+    vars: &[V],
+    grads: &mut GradStore,
+    clip_value: f64,
+) -> Result<()> {
+    for var in vars {
+        let var = var.borrow();
+        if let Some(grad) = grads.get(var) {
+            let clipped = grad.clamp(-clip_value, clip_value)?;
+            grads.insert(var, clipped);
+        }
+    }
+    Ok(())
+}
+
+/// Clip the gradients of each group separately, after first clipping across all groups if `global_clip` is set
+///
+/// Groups without a `clip_grad` are only affected by the global clip.
+///
+/// # Errors
+///
+/// Errors if the gradients cannot be rescaled
+pub fn clip_grad_norm_grouped(
+    grads: &mut GradStore,
+    groups: &[ClipGroup],
+    global_clip: Option<f64>,
+) -> Result<()> {
+    if let Some(max_norm) = global_clip {
+        let all: Vec<&Var> = groups.iter().flat_map(|g| g.vars.iter()).collect();
+        clip_grad_norm(&all, grads, max_norm)?;
+    }
+    for group in groups {
+        if let Some(max_norm) = group.clip_grad {
+            clip_grad_norm(&group.vars, grads, max_norm)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Tensor};
+
+    // gradient of the loss is [3, 4] for `a` and [6, 8] for `b`
+    fn setup() -> Result<(Var, Var, GradStore)> {
+        let a = Var::new(&[0f32, 0.], &Device::Cpu)?;
+        let b = Var::new(&[0f32, 0.], &Device::Cpu)?;
+        let coeffs = Tensor::new(&[3f32, 4.], &Device::Cpu)?;
+        let loss = (a.as_tensor().mul(&coeffs)?.sum_all()?
+            + (b.as_tensor().mul(&coeffs)? * 2.)?.sum_all()?)?;
+        let grads = loss.backward()?;
+        Ok((a, b, grads))
+    }
+
+    #[test]
+    fn clip_norm_test() -> Result<()> {
+        let (a, b, mut grads) = setup()?;
+        let norm = clip_grad_norm(&[&a], &mut grads, 1.)?;
+        assert_approx_eq!(norm, 5.);
+        let ga = grads.get(&a).unwrap().to_vec1::<f32>()?;
+        for (g, e) in ga.iter().zip([0.6, 0.8]) {
+            assert_approx_eq!(g, e, 1e-6);
+        }
+        assert_eq!(grads.get(&b).unwrap().to_vec1::<f32>()?, [6., 8.]);
+        // under the max norm so unchanged
+        let norm = clip_grad_norm(&[&b], &mut grads, 20.)?;
+        assert_approx_eq!(norm, 10.);
+        assert_eq!(grads.get(&b).unwrap().to_vec1::<f32>()?, [6., 8.]);
+        Ok(())
+    }
+
+    #[test]
+    fn clip_value_test() -> Result<()> {
+        let (a, b, mut grads) = setup()?;
+        clip_grad_value(&[&b], &mut grads, 7.)?;
+        assert_eq!(grads.get(&a).unwrap().to_vec1::<f32>()?, [3., 4.]);
+        assert_eq!(grads.get(&b).unwrap().to_vec1::<f32>()?, [6., 7.]);
+        Ok(())
+    }
+
+    #[test]
+    fn grouped_clip_test() -> Result<()> {
+        let (a, b, mut grads) = setup()?;
+        let groups = [
+            ClipGroup {
+                vars: vec![a.clone()],
+                clip_grad: Some(1.),
+            },
+            ClipGroup {
+                vars: vec![b.clone()],
+                clip_grad: None,
+            },
+        ];
+        clip_grad_norm_grouped(&mut grads, &groups, None)?;
+        let ga = grads.get(&a).unwrap().to_vec1::<f32>()?;
+        for (g, e) in ga.iter().zip([0.6, 0.8]) {
+            assert_approx_eq!(g, e, 1e-6);
+        }
+        assert_eq!(grads.get(&b).unwrap().to_vec1::<f32>()?, [6., 8.]);
+        Ok(())
+    }
+
+    #[test]
+    fn grouped_global_first_test() -> Result<()> {
+        let (a, b, mut grads) = setup()?;
+        let groups = [
+            ClipGroup {
+                vars: vec![a.clone()],
+                clip_grad: Some(1.),
+            },
+            ClipGroup {
+                vars: vec![b.clone()],
+                clip_grad: None,
+            },
+        ];
+        // total norm is sqrt(125): global clipping halves both gradients, then a is clipped to norm 1
+        // (clipping a first would leave a larger share of the global norm to b)
+        clip_grad_norm_grouped(&mut grads, &groups, Some(125_f64.sqrt() / 2.))?;
+        let ga = grads.get(&a).unwrap().to_vec1::<f32>()?;
+        let gb = grads.get(&b).unwrap().to_vec1::<f32>()?;
+        for (g, e) in ga.iter().chain(gb.iter()).zip([0.6, 0.8, 3., 4.]) {
+            assert_approx_eq!(g, e, 1e-6);
+        }
+        Ok(())
+    }
+}
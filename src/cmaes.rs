@@ -0,0 +1,386 @@
+/*!
+Covariance matrix adaptation evolution strategy (CMA-ES)
+
+A derivative-free, population based method, described in [The CMA Evolution Strategy: A Tutorial](https://arxiv.org/abs/1604.00772),
+which only evaluates [`Model::loss`], so can be used for black-box problems such as hyperparameter or policy
+search. Each step samples a population of $\\lambda$ candidates for the flattened variables from a multivariate normal
+distribution
+
+$$ x_i = m + \\sigma y_i, \\qquad y_i \\sim \\mathcal{N}(0, C) $$
+
+and moves the mean $m$ to the weighted mean of the best $\\mu = \\lfloor \\lambda / 2 \\rfloor$. The covariance $C$ is
+adapted by the rank one update from the evolution path $p_c$ of the mean and the rank $\\mu$ update from the selected
+steps, and the step size $\\sigma$ by comparing the length of the conjugate evolution path $p_{\\sigma}$ with its
+expected length under random selection, with the default constants of the tutorial.
+
+The samples are taken as $y_i = A z_i$ with $z_i \\sim \\mathcal{N}(0, I)$, where $A$ is the Cholesky factor of $C$,
+as candle has no eigendecomposition. Then $A^{-1} y_i = z_i$ takes the place of $C^{-\\frac{1}{2}} y_i$ in the
+conjugate evolution path, which has the same distribution under random selection.
+
+After each step the variables hold the best candidate found so far, and the optimiser has converged when
+$\\sigma \\max_i \\sqrt{C_{ii}}$, the largest standard deviation of the sampling distribution, is below `sigma_tol`.
+Loss evaluations that are NaN are treated as infinite.
+*/
+
+use crate::{flatten_vars, unflatten, LossOptimizer, Model, ModelOutcome, OptimVars};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for CMA-ES
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsCmaes {
+    /// Initial step size $\\sigma$: the initial standard deviation of the candidates about the variables
+    pub sigma: f64,
+    /// Population size $\\lambda$, or `None` for the default $4 + \\lfloor 3 \\ln n \\rfloor$ for $n$ parameters
+    ///
+    /// Larger populations search more globally, at the cost of more evaluations per step.
+    pub population: Option<usize>,
+    /// The optimiser has converged when the largest standard deviation of the sampling distribution is below this
+    pub sigma_tol: f64,
+}
+
+impl Default for ParamsCmaes {
+    fn default() -> Self {
+        Self {
+            sigma: 0.5,
+            population: None,
+            sigma_tol: 1e-11,
+        }
+    }
+}
+
+/// CMA-ES optimiser
+///
+/// A derivative-free evolution strategy adapting the covariance of a normal distribution of candidates
+#[derive(Debug)]
+pub struct Cmaes<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsCmaes,
+    /// recombination weights of the best $\\mu$ candidates, which sum to 1
+    weights: Tensor,
+    mu_eff: f64,
+    mean: Tensor,
+    sigma: f64,
+    cov: Tensor,
+    /// Cholesky factor of `cov`
+    chol: Tensor,
+    p_sigma: Tensor,
+    p_c: Tensor,
+    generation: i32,
+    /// the best candidate so far, its loss, and the loss as a scalar
+    best: Option<(Tensor, Tensor, f64)>,
+}
+
+impl<M: Model> LossOptimizer<M> for Cmaes<M> {
+    type Config = ParamsCmaes;
+
+    #[allow(clippy::cast_precision_loss)]
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.sigma <= 0. {
+            candle_core::bail!(
+                "the CMA-ES step size must be positive, got {}",
+                params.sigma
+            );
+        }
+        let mean = flatten_vars(&vs)?.to_dtype(DType::F64)?.copy()?;
+        let n = mean.elem_count();
+        if n == 0 {
+            candle_core::bail!("CMA-ES needs at least one variable to optimise");
+        }
+        let lambda = params.population.unwrap_or_else(|| default_population(n));
+        if lambda < 2 {
+            candle_core::bail!("the CMA-ES population must be at least 2, got {lambda}");
+        }
+        let mu = lambda / 2;
+        let raw = (1..=mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln())
+            .collect::<Vec<f64>>();
+        let total = raw.iter().sum::<f64>();
+        let weights = raw.iter().map(|w| w / total).collect::<Vec<f64>>();
+        let mu_eff = 1. / weights.iter().map(|w| w * w).sum::<f64>();
+        let device = mean.device().clone();
+        Ok(Self {
+            vars: vs,
+            model,
+            weights: Tensor::new(weights, &device)?,
+            mu_eff,
+            cov: Tensor::eye(n, DType::F64, &device)?,
+            chol: Tensor::eye(n, DType::F64, &device)?,
+            p_sigma: Tensor::zeros(n, DType::F64, &device)?,
+            p_c: Tensor::zeros(n, DType::F64, &device)?,
+            mean,
+            sigma: params.sigma,
+            params,
+            generation: 0,
+            best: None,
+        })
+    }
+
+    #[allow(clippy::cast_precision_loss, clippy::many_single_char_names)]
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        if self.best.is_none() {
+            let x = flatten_vars(&self.vars)?.to_dtype(DType::F64)?.copy()?;
+            self.best = Some((x, loss.detach(), scalar(loss)?));
+        }
+        let n = self.mean.elem_count();
+        let nf = n as f64;
+        let mu = self.weights.elem_count();
+        let lambda = self
+            .params
+            .population
+            .unwrap_or_else(|| default_population(n));
+
+        // sample and rank the population
+        let z = Tensor::randn(0f64, 1f64, (lambda, n), self.mean.device())?;
+        let y = z.matmul(&self.chol.t()?)?;
+        let xs = self.mean.unsqueeze(0)?.broadcast_add(&(&y * self.sigma)?)?;
+        let mut ranked = Vec::with_capacity(lambda);
+        for i in 0..lambda {
+            let x = xs.get(i)?;
+            self.set_vars(&x)?;
+            let loss = self.model.loss()?.detach();
+            let f = scalar(&loss)?;
+            ranked.push((i, x, loss, f));
+        }
+        ranked.sort_by(|(_, _, _, a), (_, _, _, b)| a.total_cmp(b));
+        let evals = lambda;
+        let best_f = self.best.as_ref().map_or(f64::INFINITY, |(_, _, f)| *f);
+        if let Some((_, x, loss, f)) = ranked.first() {
+            if *f < best_f {
+                self.best = Some((x.clone(), loss.clone(), *f));
+            }
+        }
+        let selected = ranked[..mu]
+            .iter()
+            .map(|(i, _, _, _)| u32::try_from(*i).expect("population fits in u32"))
+            .collect::<Vec<u32>>();
+        let selected = Tensor::new(selected, self.mean.device())?;
+        let z_sel = z.index_select(&selected, 0)?;
+        let y_sel = y.index_select(&selected, 0)?;
+        let w = self.weights.unsqueeze(0)?;
+        let z_w = w.matmul(&z_sel)?.squeeze(0)?;
+        let y_w = w.matmul(&y_sel)?.squeeze(0)?;
+
+        // constants of the adaptation
+        let mu_eff = self.mu_eff;
+        let c_sigma = (mu_eff + 2.) / (nf + mu_eff + 5.);
+        let d_sigma = 2f64.mul_add((((mu_eff - 1.) / (nf + 1.)).sqrt() - 1.).max(0.), 1.) + c_sigma;
+        let c_c = (4. + mu_eff / nf) / 2f64.mul_add(mu_eff / nf, nf + 4.);
+        let c_1 = 2. / ((nf + 1.3).powi(2) + mu_eff);
+        let c_mu = (1. - c_1).min(2. * (mu_eff - 2. + 1. / mu_eff) / ((nf + 2.).powi(2) + mu_eff));
+        // the expected length of a standard normal vector
+        let chi_n = nf.sqrt() * (1. - 1. / (4. * nf) + 1. / (21. * nf * nf));
+
+        self.mean = (&self.mean + (&y_w * self.sigma)?)?;
+        self.p_sigma = ((&self.p_sigma * (1. - c_sigma))?
+            + (z_w * (c_sigma * (2. - c_sigma) * mu_eff).sqrt())?)?;
+        let ps_norm = self.p_sigma.sqr()?.sum_all()?.to_scalar::<f64>()?.sqrt();
+        self.generation += 1;
+        // stall the rank one update while the step size grows quickly, so the covariance does not grow with it
+        let h_sigma = ps_norm / (1. - (1. - c_sigma).powi(2 * self.generation)).sqrt()
+            < (1.4 + 2. / (nf + 1.)) * chi_n;
+        let h = if h_sigma { 1. } else { 0. };
+        self.p_c = ((&self.p_c * (1. - c_c))? + (y_w * (h * (c_c * (2. - c_c) * mu_eff).sqrt()))?)?;
+
+        let p_c = self.p_c.unsqueeze(1)?;
+        let rank_one = p_c.matmul(&p_c.t()?)?;
+        let rank_mu = y_sel
+            .broadcast_mul(&self.weights.unsqueeze(1)?)?
+            .t()?
+            .matmul(&y_sel)?;
+        let decay = ((1. - h) * c_1 * c_c).mul_add(2. - c_c, 1. - c_1 - c_mu);
+        let cov = ((&self.cov * decay)? + (rank_one * c_1)? + (rank_mu * c_mu)?)?;
+        self.cov = ((&cov + cov.t()?)? * 0.5)?;
+        self.chol = cholesky(&self.cov)?;
+        self.sigma *= ((c_sigma / d_sigma) * (ps_norm / chi_n - 1.)).exp();
+
+        let (best, best_loss, _) = self
+            .best
+            .clone()
+            .expect("best is set at the start of the step");
+        self.set_vars(&best)?;
+        let max_var = self
+            .cov
+            .mul(&Tensor::eye(n, DType::F64, self.mean.device())?)?
+            .sum(1)?
+            .max(0)?
+            .to_scalar::<f64>()?;
+        let spread = self.sigma * max_var.sqrt();
+        if spread < self.params.sigma_tol {
+            info!("sampling distribution converged");
+            return Ok(ModelOutcome::Converged(best_loss, evals));
+        }
+        Ok(ModelOutcome::Stepped(best_loss, evals))
+    }
+
+    /// the current step size $\\sigma$
+    fn learning_rate(&self) -> f64 {
+        self.sigma
+    }
+
+    /// set the current step size $\\sigma$
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.sigma = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for Cmaes<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> Cmaes<M> {
+    /// The current step size $\\sigma$
+    #[must_use]
+    pub fn sigma(&self) -> f64 {
+        self.sigma
+    }
+
+    /// The mean of the sampling distribution over the flattened variables, in double precision
+    #[must_use]
+    pub fn mean(&self) -> &Tensor {
+        &self.mean
+    }
+
+    /// The covariance $C$ of the sampling distribution, which is scaled by $\\sigma^2$
+    #[must_use]
+    pub fn covariance(&self) -> &Tensor {
+        &self.cov
+    }
+
+    fn set_vars(&self, x: &Tensor) -> CResult<()> {
+        for (var, value) in self.vars.iter().zip(unflatten(x, &self.vars)?) {
+            var.set(&value.to_dtype(var.dtype())?)?;
+        }
+        Ok(())
+    }
+}
+
+/// the default population $4 + \\lfloor 3 \\ln n \\rfloor$ for `n` parameters
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn default_population(n: usize) -> usize {
+    4 + (3. * (n as f64).ln()).floor() as usize
+}
+
+/// the lower triangular Cholesky factor $A$ of the symmetric positive definite matrix $C = A A^{\\top}$
+fn cholesky(c: &Tensor) -> CResult<Tensor> {
+    let device = c.device().clone();
+    let c = c.to_vec2::<f64>()?;
+    let n = c.len();
+    let mut a = vec![vec![0f64; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum = (0..j).map(|k| a[i][k] * a[j][k]).sum::<f64>();
+            if i == j {
+                let pivot = c[i][i] - sum;
+                if pivot <= 0. || !pivot.is_finite() {
+                    candle_core::bail!(
+                        "the CMA-ES covariance is no longer positive definite, with pivot {pivot}"
+                    );
+                }
+                a[i][j] = pivot.sqrt();
+            } else {
+                a[i][j] = (c[i][j] - sum) / a[j][j];
+            }
+        }
+    }
+    Tensor::new(a, &device)
+}
+
+fn scalar(loss: &Tensor) -> CResult<f64> {
+    let f = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+    Ok(if f.is_nan() { f64::INFINITY } else { f })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+
+    /// $||x - 1||_{2}^{2}$
+    struct Sphere {
+        x: Var,
+    }
+
+    impl Model for Sphere {
+        fn loss(&self) -> CResult<Tensor> {
+            (self.x.as_tensor() - 1.)?.sqr()?.sum_all()
+        }
+    }
+
+    fn setup(params: ParamsCmaes) -> Result<(Var, Cmaes<Sphere>)> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let optim = Cmaes::new(vec![x.clone()], params, Sphere { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsCmaes::default())?;
+        assert_approx_eq!(0.5, optim.learning_rate());
+        optim.set_learning_rate(0.1);
+        assert_approx_eq!(0.1, optim.sigma());
+        assert!(setup(ParamsCmaes {
+            population: Some(1),
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn weights_test() -> Result<()> {
+        // two parameters give a population of 6, of which the best 3 are recombined
+        assert_eq!(default_population(2), 6);
+        let (_, optim) = setup(ParamsCmaes::default())?;
+        let weights = optim.weights.to_vec1::<f64>()?;
+        assert_eq!(weights.len(), 3);
+        assert_approx_eq!(weights.iter().sum::<f64>(), 1.);
+        assert!(weights[0] > weights[1] && weights[1] > weights[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn cholesky_test() -> Result<()> {
+        let c = Tensor::new(&[[4f64, 2.], [2., 5.]], &Device::Cpu)?;
+        let a = cholesky(&c)?;
+        assert_eq!(a.to_vec2::<f64>()?, [[2., 0.], [1., 2.]]);
+        let not_pd = Tensor::new(&[[1f64, 2.], [2., 1.]], &Device::Cpu)?;
+        assert!(cholesky(&not_pd).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn step_test() -> Result<()> {
+        // the variables hold the best candidate, so the reported loss never increases
+        let (x, mut optim) = setup(ParamsCmaes::default())?;
+        let mut loss = optim.model.loss()?;
+        for _ in 0..20 {
+            if let ModelOutcome::Stepped(next, evals) = optim.backward_step(&loss)? {
+                assert_eq!(evals, 6);
+                assert!(next.to_scalar::<f64>()? <= loss.to_scalar::<f64>()?);
+                loss = next;
+            }
+        }
+        assert_approx_eq!(
+            loss.to_scalar::<f64>()?,
+            optim.model.loss()?.to_scalar::<f64>()?
+        );
+        assert!(x.to_vec1::<f64>()? != [0., 0.]);
+        Ok(())
+    }
+}
@@ -0,0 +1,427 @@
+/*!
+Convergence checking for element-wise optimisers
+
+[`ConvergentAdapter`] wraps any optimiser in this crate implementing candle's `Optimizer` trait so it can be driven
+through [`LossOptimizer::backward_step`] like LBFGS, returning a [`ModelOutcome`]. Convergence is checked with the same
+[`GradConv`] and [`StepConv`] criteria and [`ConvergencePolicy`] as LBFGS: the gradient before each step, and the change
+in the variables made by the step.
+
+By default both criteria have a tolerance of zero so are disabled, and the adapter never reports convergence.
+
+For deterministic full batch problems, `reject_on_increase` gives a crude backtracking: if a step increases the loss the
+variables are rolled back, the learning rate is halved and the step is retried with the same gradient, up to
+[`MAX_REJECTIONS`] times. The reduced learning rate is kept for later steps, unless every retry is rejected, in
+which case the variables and learning rate are left as they were and the step is reported as `Stepped`. Only the variables are rolled back, so any
+internal state of the wrapped optimiser (such as moment estimates) includes the rejected steps.
+*/
+
+use candle_core::{Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+use log::{info, warn};
+
+use crate::lbfgs::{ConvergencePolicy, GradConv, StepConv};
+use crate::{flatten_grads, flatten_vars, LossOptimizer, Model, ModelOutcome, OptimVars};
+
+/// Maximum number of times a step is rejected and retried with a halved learning rate
+pub const MAX_REJECTIONS: usize = 10;
+
+/// Parameters for the convergence adapter: those of the wrapped optimiser, with the convergence criteria
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsConvergent<C> {
+    /// parameters of the wrapped optimiser
+    pub inner: C,
+    /// convergence criteria for gradient
+    pub grad_conv: GradConv,
+    /// convergence criteria for step size
+    pub step_conv: StepConv,
+    /// whether any or all of the convergence criteria must be met
+    pub convergence_policy: ConvergencePolicy,
+    /// roll back steps that increase the loss, and retry them with a halved learning rate
+    pub reject_on_increase: bool,
+}
+
+impl<C: Default> Default for ParamsConvergent<C> {
+    fn default() -> Self {
+        Self {
+            inner: C::default(),
+            grad_conv: GradConv::MinForce(0.),
+            step_conv: StepConv::MinStep(0.),
+            convergence_policy: ConvergencePolicy::Any,
+            reject_on_increase: false,
+        }
+    }
+}
+
+fn to_f64(loss: &Tensor) -> Result<f64> {
+    loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()
+}
+
+/// Adapter driving an `Optimizer` through the [`LossOptimizer`] interface, with convergence checks
+#[derive(Debug)]
+pub struct ConvergentAdapter<O: Optimizer + OptimVars, M: Model> {
+    inner: O,
+    model: M,
+    grad_conv: GradConv,
+    step_conv: StepConv,
+    convergence_policy: ConvergencePolicy,
+    reject_on_increase: bool,
+}
+
+impl<O: Optimizer + OptimVars, M: Model> LossOptimizer<M> for ConvergentAdapter<O, M> {
+    type Config = ParamsConvergent<O::Config>;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> Result<Self> {
+        Ok(Self {
+            inner: O::new(vs, params.inner)?,
+            model,
+            grad_conv: params.grad_conv,
+            step_conv: params.step_conv,
+            convergence_policy: params.convergence_policy,
+            reject_on_increase: params.reject_on_increase,
+        })
+    }
+
+    /// take a step of the wrapped optimiser, then evaluate the model at the new point
+    ///
+    /// if the gradient of `loss` has already converged no step is taken, and `loss` is returned
+    fn backward_step(&mut self, loss: &Tensor) -> Result<ModelOutcome> {
+        let grads = loss.backward()?;
+        let grad_converged = self
+            .grad_conv
+            .converged(&flatten_grads(&grads, &self.inner.vars())?)?;
+        // with no step criterion there is nothing else to wait for
+        let step_enabled = self.step_conv.tolerance() > 0.;
+        if grad_converged == Some(true)
+            && (self.convergence_policy == ConvergencePolicy::Any || !step_enabled)
+        {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), 1));
+        }
+
+        // the variables are updated in place, so copy them before the step
+        let before = self
+            .inner
+            .vars()
+            .iter()
+            .map(|v| v.as_tensor().copy())
+            .collect::<Result<Vec<Tensor>>>()?;
+        let f_init = to_f64(loss)?;
+        let lr_init = self.inner.learning_rate();
+        let mut evals = 1;
+        let mut rejections = 0;
+        let next_loss = loop {
+            self.inner.step(&grads)?;
+            let next_loss = self.model.loss()?;
+            evals += 1;
+            // a NaN loss is rejected too
+            if !self.reject_on_increase || to_f64(&next_loss)? <= f_init {
+                break next_loss;
+            }
+            for (var, prev) in self.inner.vars().iter().zip(&before) {
+                var.set(prev)?;
+            }
+            if rejections == MAX_REJECTIONS {
+                // no step was taken, so this must not be reported as converged by the step criterion
+                warn!("loss still increased after {MAX_REJECTIONS} rejections, not stepping");
+                self.inner.set_learning_rate(lr_init);
+                return Ok(ModelOutcome::Stepped(loss.clone(), evals));
+            }
+            rejections += 1;
+            let lr = self.inner.learning_rate() / 2.;
+            info!("loss increased, retrying with learning rate {lr}");
+            self.inner.set_learning_rate(lr);
+        };
+        let before = Tensor::cat(
+            &before
+                .iter()
+                .map(Tensor::flatten_all)
+                .collect::<Result<Vec<Tensor>>>()?,
+            0,
+        )?;
+        let step = (flatten_vars(&self.inner.vars())? - before)?;
+        let converged = self.step_conv.converged(&step)? == Some(true)
+            && (self.convergence_policy == ConvergencePolicy::Any
+                || grad_converged.unwrap_or(true));
+
+        if converged {
+            info!("step converged");
+            Ok(ModelOutcome::Converged(next_loss, evals))
+        } else {
+            Ok(ModelOutcome::Stepped(next_loss, evals))
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.inner.vars().into_iter().cloned().collect()
+    }
+}
+
+impl<O: Optimizer + OptimVars, M: Model> OptimVars for ConvergentAdapter<O, M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars, M: Model> ConvergentAdapter<O, M> {
+    /// wrap an existing optimiser, never reporting convergence until criteria are set
+    #[must_use]
+    pub fn from_optimiser(inner: O, model: M) -> Self {
+        Self {
+            inner,
+            model,
+            grad_conv: GradConv::MinForce(0.),
+            step_conv: StepConv::MinStep(0.),
+            convergence_policy: ConvergencePolicy::Any,
+            reject_on_increase: false,
+        }
+    }
+
+    /// roll back steps that increase the loss, and retry them with a halved learning rate
+    pub fn set_reject_on_increase(&mut self, reject_on_increase: bool) {
+        self.reject_on_increase = reject_on_increase;
+    }
+
+    /// set the convergence criteria
+    pub fn set_convergence(
+        &mut self,
+        grad_conv: GradConv,
+        step_conv: StepConv,
+        convergence_policy: ConvergencePolicy,
+    ) {
+        self.grad_conv = grad_conv;
+        self.step_conv = step_conv;
+        self.convergence_policy = convergence_policy;
+    }
+
+    /// get a reference to the model
+    #[must_use]
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser and the model
+    #[must_use]
+    pub fn into_parts(self) -> (O, M) {
+        (self.inner, self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+    use crate::adamax::{Adamax, ParamsAdaMax};
+    use crate::esgd::{ParamsSGD, SGD};
+
+    /// $(x - 1)^2$ summed over the components
+    struct Parabola {
+        x: Var,
+    }
+
+    impl Model for Parabola {
+        fn loss(&self) -> Result<Tensor, candle_core::Error> {
+            (self.x.as_tensor() - 1.)?.sqr()?.sum_all()
+        }
+    }
+
+    fn setup(
+        params: ParamsConvergent<ParamsSGD>,
+    ) -> Result<(Var, ConvergentAdapter<SGD, Parabola>)> {
+        let x = Var::new(&[0f64, 3.], &Device::Cpu)?;
+        let optim = ConvergentAdapter::new(vec![x.clone()], params, Parabola { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    fn run(optim: &mut ConvergentAdapter<SGD, Parabola>, max_steps: usize) -> Result<usize> {
+        let mut loss = optim.model().loss()?;
+        for step in 0..max_steps {
+            match optim.backward_step(&loss)? {
+                ModelOutcome::Converged(_, _) => return Ok(step),
+                ModelOutcome::Stepped(next, _) | ModelOutcome::Truncated(next, _) => loss = next,
+            }
+        }
+        Ok(max_steps)
+    }
+
+    #[test]
+    fn never_converge_test() -> Result<()> {
+        let params = ParamsConvergent {
+            inner: ParamsSGD {
+                lr: 0.5,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (x, mut optim) = setup(params)?;
+        // lr 0.5 solves the parabola in one step, but with no criteria set convergence is never reported
+        assert_eq!(run(&mut optim, 10)?, 10);
+        for x in x.to_vec1::<f64>()? {
+            assert_approx_eq!(x, 1.);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn grad_conv_test() -> Result<()> {
+        let params = ParamsConvergent {
+            inner: ParamsSGD {
+                lr: 0.5,
+                ..Default::default()
+            },
+            grad_conv: GradConv::MinForce(1e-6),
+            ..Default::default()
+        };
+        let (_, mut optim) = setup(params)?;
+        // the first step solves the problem, and the second sees a zero gradient
+        assert_eq!(run(&mut optim, 10)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn step_conv_test() -> Result<()> {
+        let params = ParamsConvergent {
+            inner: ParamsSGD {
+                lr: 0.25,
+                ..Default::default()
+            },
+            step_conv: StepConv::MinStep(1e-3),
+            ..Default::default()
+        };
+        let (x, mut optim) = setup(params)?;
+        // the largest error starts at 2 and halves each step, with the step being half the error:
+        // the step first falls below 1e-3 on the eleventh, 2^-10
+        let steps = run(&mut optim, 100)?;
+        assert_eq!(steps, 10);
+        for x in x.to_vec1::<f64>()? {
+            assert_approx_eq!(x, 1., 1e-3);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_on_increase_test() -> Result<()> {
+        // lr 1.5 overshoots the parabola: x = 0 -> 3, tripling the loss
+        let params = ParamsConvergent {
+            inner: ParamsSGD {
+                lr: 1.5,
+                ..Default::default()
+            },
+            reject_on_increase: true,
+            ..Default::default()
+        };
+        let x = Var::new(&[0f64], &Device::Cpu)?;
+        let mut optim: ConvergentAdapter<SGD, Parabola> =
+            ConvergentAdapter::new(vec![x.clone()], params, Parabola { x: x.clone() })?;
+        let loss = optim.model().loss()?;
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Stepped(next, evals) => {
+                // rejected once, then lr 0.75 steps to 1.5 with a loss of 0.25
+                assert_eq!(evals, 3);
+                assert_approx_eq!(next.to_scalar::<f64>()?, 0.25);
+            }
+            outcome => panic!("unexpected outcome {outcome:?}"),
+        }
+        assert_approx_eq!(x.to_vec1::<f64>()?[0], 1.5);
+        assert_approx_eq!(optim.learning_rate(), 0.75);
+
+        // without the guard the overshoot is kept
+        let x = Var::new(&[0f64], &Device::Cpu)?;
+        let mut optim: ConvergentAdapter<SGD, Parabola> = ConvergentAdapter::new(
+            vec![x.clone()],
+            ParamsConvergent {
+                inner: ParamsSGD {
+                    lr: 1.5,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            Parabola { x: x.clone() },
+        )?;
+        optim.backward_step(&optim.model().loss()?)?;
+        assert_approx_eq!(x.to_vec1::<f64>()?[0], 3.);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_exhausted_test() -> Result<()> {
+        // the gradient of the negated loss points uphill, so every retry is rejected
+        let params = ParamsConvergent {
+            inner: ParamsSGD {
+                lr: 0.5,
+                ..Default::default()
+            },
+            step_conv: StepConv::MinStep(1e-6),
+            reject_on_increase: true,
+            ..Default::default()
+        };
+        let (x, mut optim) = setup(params)?;
+        let loss = optim.model().loss()?.neg()?;
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Stepped(next, evals) => {
+                assert_eq!(evals, MAX_REJECTIONS + 2);
+                assert_approx_eq!(next.to_scalar::<f64>()?, -5.);
+            }
+            outcome => panic!("unexpected outcome {outcome:?}"),
+        }
+        assert_eq!(x.to_vec1::<f64>()?, [0., 3.]);
+        assert_approx_eq!(optim.learning_rate(), 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn adamax_test() -> Result<()> {
+        let x = Var::new(&[0f64, 3.], &Device::Cpu)?;
+        let adamax = Adamax::new(
+            vec![x.clone()],
+            ParamsAdaMax {
+                lr: 0.1,
+                ..Default::default()
+            },
+        )?;
+        let mut optim = ConvergentAdapter::from_optimiser(adamax, Parabola { x: x.clone() });
+        optim.set_convergence(
+            GradConv::RMSForce(1e-2),
+            StepConv::MinStep(0.),
+            ConvergencePolicy::Any,
+        );
+        let mut loss = optim.model().loss()?;
+        let mut converged = false;
+        for _ in 0..1000 {
+            match optim.backward_step(&loss)? {
+                ModelOutcome::Converged(_, _) => {
+                    converged = true;
+                    break;
+                }
+                ModelOutcome::Stepped(next, _) | ModelOutcome::Truncated(next, _) => loss = next,
+            }
+        }
+        assert!(converged);
+        for x in x.to_vec1::<f64>()? {
+            assert_approx_eq!(x, 1., 1e-2);
+        }
+        assert_eq!(optim.into_inner().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+/*!
+Block coordinate descent
+
+Each step updates a single block of the parameters, one [`Var`] per block, leaving the others fixed. The block is
+chosen by cycling through the variables in order or by sampling one at random, and is updated either by a gradient
+step
+
+$$ x_j \\leftarrow x_j - \\gamma \\nabla_{x_j} f $$
+
+or by minimising the loss along $-\\nabla_{x_j} f$ with the [scalar line search](crate::scalar_search::line_minimise),
+which is an exact minimisation over the block when it has a single element. Blocks that the loss is (close to)
+quadratic in, as in alternating least squares for matrix factorisation $f(U, V) = \\|U V - M\\|^{2}$, are minimised
+along their gradient in one step.
+
+The optimiser has converged when the gradient of every block meets `grad_conv`, checked for each block when it is
+visited: a block that meets it is not updated, and updating any block requires the others to be checked again.
+*/
+
+use crate::{
+    lbfgs::GradConv, scalar_search::line_minimise, subset::SplitMix64, LossOptimizer, Model,
+    ModelOutcome, OptimVars,
+};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// How the block to update is chosen on each step
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum BlockSelection {
+    /// cycle through the variables in the order they were passed
+    Cyclic,
+    /// sample a variable uniformly at random, with a seed so runs are reproducible
+    Random {
+        /// the seed of the pseudo random number generator
+        seed: u64,
+    },
+}
+
+/// How the chosen block is updated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum BlockStep {
+    /// a gradient step with the learning rate
+    Gradient,
+    /// minimise the loss along the negative gradient of the block, with the learning rate as the first step of
+    /// the line search
+    Exact,
+}
+
+/// Parameters for block coordinate descent
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsCoordinateDescent {
+    /// Learning rate of gradient steps, or the first step of the line search for exact steps
+    pub lr: f64,
+    /// How the block to update is chosen
+    pub selection: BlockSelection,
+    /// How the block is updated
+    pub step: BlockStep,
+    /// Convergence criterion for the gradient of each block
+    pub grad_conv: GradConv,
+    /// Relative tolerance of the line search for exact steps
+    pub line_tol: f64,
+    /// Maximum number of iterations of each of the bracketing and locating the minimum in the line search
+    pub max_line_iter: usize,
+}
+
+impl Default for ParamsCoordinateDescent {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            selection: BlockSelection::Cyclic,
+            step: BlockStep::Gradient,
+            grad_conv: GradConv::MinForce(1e-7),
+            line_tol: 1e-8,
+            max_line_iter: 100,
+        }
+    }
+}
+
+/// Block coordinate descent optimiser
+///
+/// Updates one variable at a time, by a gradient step or a line search along its gradient
+#[derive(Debug)]
+pub struct CoordinateDescent<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsCoordinateDescent,
+    rng: SplitMix64,
+    /// the block to visit next when cycling
+    next: usize,
+    /// whether each block met the gradient criterion when last visited, with no block updated since
+    settled: Vec<bool>,
+    last_block: Option<usize>,
+}
+
+impl<M: Model> LossOptimizer<M> for CoordinateDescent<M> {
+    type Config = ParamsCoordinateDescent;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if vs.is_empty() {
+            candle_core::bail!("coordinate descent needs at least one variable to optimise");
+        }
+        let seed = match params.selection {
+            BlockSelection::Random { seed } => seed,
+            BlockSelection::Cyclic => 0,
+        };
+        Ok(Self {
+            settled: vec![false; vs.len()],
+            vars: vs,
+            model,
+            params,
+            rng: SplitMix64(seed),
+            next: 0,
+            last_block: None,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let block = match self.params.selection {
+            BlockSelection::Cyclic => {
+                let block = self.next;
+                self.next = (self.next + 1) % self.vars.len();
+                block
+            }
+            BlockSelection::Random { .. } => self.rng.below(self.vars.len()),
+        };
+        self.last_block = Some(block);
+        let var = self.vars[block].clone();
+        let grads = loss.backward()?;
+        let grad = match grads.get(&var) {
+            Some(grad) => grad.flatten_all()?,
+            None => var.zeros_like()?.flatten_all()?,
+        };
+
+        // a disabled criterion never settles a block
+        self.settled[block] = self.params.grad_conv.converged(&grad)?.unwrap_or(false);
+        if self.settled.iter().all(|settled| *settled) {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), 0));
+        }
+        if self.settled[block] {
+            return Ok(ModelOutcome::Stepped(loss.clone(), 0));
+        }
+
+        let grad = grad.reshape(var.shape())?;
+        let mut evals = 0;
+        match self.params.step {
+            BlockStep::Gradient => {
+                var.set(&var.sub(&(&grad * self.params.lr)?)?)?;
+            }
+            BlockStep::Exact => {
+                let start = var.as_tensor().copy()?;
+                let mut evaluate = |t: f64| -> CResult<f64> {
+                    var.set(&(&start - (&grad * t)?)?)?;
+                    scalar(&self.model.loss()?)
+                };
+                let minimum = line_minimise(
+                    &mut evaluate,
+                    scalar(loss)?,
+                    self.params.lr,
+                    self.params.line_tol,
+                    self.params.max_line_iter,
+                )?;
+                evals += minimum.evals;
+                let t = if minimum.fx < scalar(loss)? {
+                    minimum.x
+                } else {
+                    0.
+                };
+                var.set(&(&start - (&grad * t)?)?)?;
+            }
+        }
+        for (i, settled) in self.settled.iter_mut().enumerate() {
+            *settled &= i == block;
+        }
+        let next_loss = self.model.loss()?;
+        evals += 1;
+        Ok(ModelOutcome::Stepped(next_loss, evals))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for CoordinateDescent<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> CoordinateDescent<M> {
+    /// The index of the block chosen on the last step, or `None` before the first step
+    #[must_use]
+    pub fn last_block(&self) -> Option<usize> {
+        self.last_block
+    }
+}
+
+fn scalar(loss: &Tensor) -> CResult<f64> {
+    let f = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+    Ok(if f.is_nan() { f64::INFINITY } else { f })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+
+    /// $(x - 1)^2 + 2 (y + 2)^2 + 3 (z - 3)^2$, with one block per coordinate
+    struct Separable {
+        vars: Vec<Var>,
+    }
+
+    impl Model for Separable {
+        fn loss(&self) -> CResult<Tensor> {
+            let terms = [(1., 1.), (2., -2.), (3., 3.)];
+            let mut loss = Tensor::new(0f64, &Device::Cpu)?;
+            for (var, (scale, centre)) in self.vars.iter().zip(terms) {
+                loss = (loss + ((var.as_tensor() - centre)?.sqr()? * scale)?)?;
+            }
+            Ok(loss)
+        }
+    }
+
+    fn setup(params: ParamsCoordinateDescent) -> Result<(Vec<Var>, CoordinateDescent<Separable>)> {
+        let vars = (0..3)
+            .map(|_| Var::new(0f64, &Device::Cpu))
+            .collect::<CResult<Vec<_>>>()?;
+        let model = Separable { vars: vars.clone() };
+        let optim = CoordinateDescent::new(vars.clone(), params, model)?;
+        Ok((vars, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsCoordinateDescent::default())?;
+        assert_approx_eq!(0.01, optim.learning_rate());
+        optim.set_learning_rate(0.1);
+        assert_approx_eq!(0.1, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn cyclic_test() -> Result<()> {
+        // only the chosen block moves, and the blocks are visited in order
+        let (vars, mut optim) = setup(ParamsCoordinateDescent::default())?;
+        let mut loss = optim.model.loss()?;
+        for block in [0, 1, 2, 0] {
+            let before = vars
+                .iter()
+                .map(|v| v.to_scalar::<f64>())
+                .collect::<CResult<Vec<_>>>()?;
+            if let ModelOutcome::Stepped(next, _) = optim.backward_step(&loss)? {
+                loss = next;
+            }
+            assert_eq!(optim.last_block(), Some(block));
+            for (i, (var, before)) in vars.iter().zip(before).enumerate() {
+                assert_eq!(var.to_scalar::<f64>()? != before, i == block);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn exact_test() -> Result<()> {
+        // each coordinate is minimised on its first visit, and the second sweep finds every block settled
+        let (vars, mut optim) = setup(ParamsCoordinateDescent {
+            step: BlockStep::Exact,
+            lr: 1.,
+            ..Default::default()
+        })?;
+        let mut loss = optim.model.loss()?;
+        let mut steps = 0;
+        while let ModelOutcome::Stepped(next, _) = optim.backward_step(&loss)? {
+            loss = next;
+            steps += 1;
+            assert!(steps < 6);
+        }
+        for (var, centre) in vars.iter().zip([1., -2., 3.]) {
+            assert_approx_eq!(var.to_scalar::<f64>()?, centre, 1e-7);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn random_test() -> Result<()> {
+        // the same seed chooses the same blocks
+        let params = ParamsCoordinateDescent {
+            selection: BlockSelection::Random { seed: 7 },
+            ..Default::default()
+        };
+        let mut chosen = vec![];
+        for _ in 0..2 {
+            let (_, mut optim) = setup(params)?;
+            let mut loss = optim.model.loss()?;
+            let mut blocks = vec![];
+            for _ in 0..20 {
+                if let ModelOutcome::Stepped(next, _) = optim.backward_step(&loss)? {
+                    loss = next;
+                }
+                blocks.extend(optim.last_block());
+            }
+            chosen.push(blocks);
+        }
+        assert_eq!(chosen[0], chosen[1]);
+        assert!((0..3).all(|block| chosen[0].contains(&block)));
+        Ok(())
+    }
+}
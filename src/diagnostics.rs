@@ -0,0 +1,338 @@
+/*!
+Training diagnostics
+
+[`Diagnostics`] wraps any optimiser in this crate and records information about each step to help diagnose training pathologies.
+All diagnostics are off by default, in which case stepping is passed straight through to the wrapped optimiser.
+
+Currently tracked:
+
+* dead parameters: variables whose cumulative update norm over a window of steps is below a threshold, see [`Diagnostics::track_dead_params`]
+* effective step size: the ratio of the update norm to the gradient norm over all variables, see [`Diagnostics::track_effective_step_size`]
+
+The free function [`grad_diff`] compares the gradients from two backward passes, for example to validate a custom backward
+implementation against a reference.
+*/
+
+use std::collections::{HashMap, VecDeque};
+
+use candle_core::{backprop::GradStore, DType, Result, Tensor, TensorId, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::OptimVars;
+
+/// Optimiser wrapper recording diagnostics about each step
+#[derive(Debug)]
+pub struct Diagnostics<O: Optimizer + OptimVars> {
+    inner: O,
+    dead: Option<DeadParamTracker>,
+    track_step_size: bool,
+    last_step_size: Option<f64>,
+}
+
+/// tracks the update norm of each variable over a window of steps
+#[derive(Debug)]
+struct DeadParamTracker {
+    window: usize,
+    threshold: f64,
+    history: VecDeque<HashMap<TensorId, f64>>,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for Diagnostics<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        if !self.enabled() {
+            return self.inner.step(grads);
+        }
+        // `Var::set` updates in place, so a copy is needed to keep the previous values
+        let before = self
+            .inner
+            .vars()
+            .iter()
+            .map(|v| Ok((v.id(), v.as_tensor().copy()?)))
+            .collect::<Result<Vec<(TensorId, Tensor)>>>()?;
+        self.inner.step(grads)?;
+        let mut update_norms = HashMap::with_capacity(before.len());
+        for (var, (id, prev)) in self.inner.vars().iter().zip(before) {
+            update_norms.insert(id, l2_norm(&var.as_tensor().sub(&prev)?)?);
+        }
+        if self.track_step_size {
+            self.last_step_size = self.effective_step_size(grads, &update_norms)?;
+        }
+        if let Some(dead) = &mut self.dead {
+            if dead.history.len() == dead.window {
+                dead.history.pop_front();
+            }
+            dead.history.push_back(update_norms);
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for Diagnostics<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> Diagnostics<O> {
+    /// wrap an optimiser, with all diagnostics disabled
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self {
+            inner,
+            dead: None,
+            track_step_size: false,
+            last_step_size: None,
+        }
+    }
+
+    /// track variables whose summed update norm over the last `window` steps is below `threshold`
+    ///
+    /// this resets any previously tracked history
+    ///
+    /// # Errors
+    ///
+    /// Errors if `window` is zero
+    pub fn track_dead_params(&mut self, window: usize, threshold: f64) -> Result<()> {
+        if window == 0 {
+            candle_core::bail!("the dead parameter window must be at least one step");
+        }
+        self.dead = Some(DeadParamTracker {
+            window,
+            threshold,
+            history: VecDeque::with_capacity(window),
+        });
+        Ok(())
+    }
+
+    /// the ids of variables that have effectively not moved over the tracking window, in construction order
+    ///
+    /// empty if dead parameter tracking is disabled or fewer than `window` steps have been taken since it was enabled
+    #[must_use]
+    pub fn dead_params(&self) -> Vec<TensorId> {
+        let Some(dead) = &self.dead else {
+            return Vec::new();
+        };
+        if dead.history.len() < dead.window {
+            return Vec::new();
+        }
+        self.inner
+            .vars()
+            .iter()
+            .map(|v| v.id())
+            .filter(|id| {
+                let total: f64 = dead
+                    .history
+                    .iter()
+                    .map(|norms| norms.get(id).copied().unwrap_or(0.))
+                    .sum();
+                total < dead.threshold
+            })
+            .collect()
+    }
+
+    /// track the effective step size of each step, see [`Diagnostics::last_effective_step_size`]
+    pub fn track_effective_step_size(&mut self) {
+        self.track_step_size = true;
+        self.last_step_size = None;
+    }
+
+    /// the effective step size of the last step: the L2 norm of the update over all variables divided by the
+    /// L2 norm of the gradient over all variables
+    ///
+    /// `None` if tracking is disabled, no step has been taken since it was enabled, or the gradient was zero
+    #[must_use]
+    pub fn last_effective_step_size(&self) -> Option<f64> {
+        self.last_step_size
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+
+    /// whether any diagnostics need the updates to be recorded
+    fn enabled(&self) -> bool {
+        self.dead.is_some() || self.track_step_size
+    }
+
+    /// combine the per variable update norms with the gradient norms into the overall effective step size
+    fn effective_step_size(
+        &self,
+        grads: &GradStore,
+        update_norms: &HashMap<TensorId, f64>,
+    ) -> Result<Option<f64>> {
+        let mut update_sq = 0.;
+        let mut grad_sq = 0.;
+        for var in self.inner.vars() {
+            update_sq += update_norms.get(&var.id()).copied().unwrap_or(0.).powi(2);
+            if let Some(grad) = grads.get(var) {
+                grad_sq += l2_norm(grad)?.powi(2);
+            }
+        }
+        if grad_sq > 0. {
+            Ok(Some((update_sq / grad_sq).sqrt()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// per variable L2 norm of the difference between the gradients in two stores
+///
+/// a variable with a gradient in only one of the stores reports the norm of that gradient;
+/// variables with a gradient in neither store are omitted
+///
+/// # Errors
+///
+/// Errors if the gradients of a variable have different shapes
+pub fn grad_diff(a: &GradStore, b: &GradStore, vars: &[Var]) -> Result<HashMap<TensorId, f64>> {
+    let mut diffs = HashMap::with_capacity(vars.len());
+    for var in vars {
+        let diff = match (a.get(var), b.get(var)) {
+            (Some(ga), Some(gb)) => l2_norm(&ga.sub(gb)?)?,
+            (Some(g), None) | (None, Some(g)) => l2_norm(g)?,
+            (None, None) => continue,
+        };
+        diffs.insert(var.id(), diff);
+    }
+    Ok(diffs)
+}
+
+/// L2 norm of a tensor as an f64
+fn l2_norm(tensor: &Tensor) -> Result<f64> {
+    Ok(tensor
+        .to_dtype(DType::F64)?
+        .sqr()?
+        .sum_all()?
+        .to_scalar::<f64>()?
+        .sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adam::{Adam, ParamsAdam};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    #[test]
+    fn dead_params_test() -> Result<()> {
+        let live = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let dead = Var::new(&[3f32, 4.], &Device::Cpu)?;
+        let inner = Adam::new(vec![live.clone(), dead.clone()], ParamsAdam::default())?;
+        let mut optim = Diagnostics::new(inner);
+        assert!(optim.track_dead_params(0, 1e-6).is_err());
+        optim.track_dead_params(3, 1e-6)?;
+        // the dead var only contributes a zero gradient
+        let zeros = Tensor::zeros(2, DType::F32, &Device::Cpu)?;
+        let loss =
+            (live.as_tensor().sqr()?.sum_all()? + dead.as_tensor().mul(&zeros)?.sum_all()?)?;
+        for step in 0..3 {
+            assert!(optim.dead_params().is_empty(), "window not full at {step}");
+            optim.backward_step(&loss)?;
+        }
+        assert_eq!(optim.dead_params(), [dead.id()]);
+        Ok(())
+    }
+
+    #[test]
+    fn grad_diff_test() -> Result<()> {
+        let x = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let y = Var::new(&[3f32, 4.], &Device::Cpu)?;
+        let z = Var::new(&[5f32, 6.], &Device::Cpu)?;
+        let vars = [x.clone(), y.clone(), z.clone()];
+        // gradients [1, 1] and [3, 4] for x and y
+        let first = (x.as_tensor().sum_all()?
+            + y.as_tensor()
+                .mul(&Tensor::new(&[3f32, 4.], &Device::Cpu)?)?
+                .sum_all()?)?
+        .backward()?;
+        // gradient [1, 1.5] for x only
+        let second = x
+            .as_tensor()
+            .mul(&Tensor::new(&[1f32, 1.5], &Device::Cpu)?)?
+            .sum_all()?
+            .backward()?;
+        let diffs = grad_diff(&first, &second, &vars)?;
+        assert_eq!(diffs.len(), 2);
+        assert_approx_eq!(diffs[&x.id()], 0.5);
+        // only present in the first store
+        assert_approx_eq!(diffs[&y.id()], 5.);
+        assert!(!diffs.contains_key(&z.id()));
+        assert!(grad_diff(&first, &first, &vars)?.values().all(|&d| d == 0.));
+        Ok(())
+    }
+
+    #[test]
+    fn effective_step_size_test() -> Result<()> {
+        let x = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let y = Var::new(&[3f32, 4.], &Device::Cpu)?;
+        let params = ParamsAdam {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let inner = Adam::new(vec![x.clone(), y.clone()], params)?;
+        let mut optim = Diagnostics::new(inner);
+        optim.track_effective_step_size();
+        assert!(optim.last_effective_step_size().is_none());
+        // gradients [3, 4] and [0, 0]
+        let loss = (x
+            .as_tensor()
+            .mul(&Tensor::new(&[3f32, 4.], &Device::Cpu)?)?
+            .sum_all()?
+            + y.as_tensor()
+                .mul(&Tensor::zeros(2, DType::F32, &Device::Cpu)?)?
+                .sum_all()?)?;
+        optim.backward_step(&loss)?;
+        // the first Adam step moves each element of x with a non-zero gradient by (almost exactly) lr,
+        // so the update norm is 0.1 * sqrt(2) against a gradient norm of 5
+        assert_approx_eq!(
+            optim.last_effective_step_size().unwrap(),
+            0.1 * 2_f64.sqrt() / 5.,
+            1e-6
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let inner = Adam::new(vec![w.clone()], ParamsAdam::default())?;
+        let mut optim = Diagnostics::new(inner);
+        let loss = (w.as_tensor() * 0.)?.sum_all()?;
+        for _ in 0..3 {
+            optim.backward_step(&loss)?;
+        }
+        assert!(optim.dead_params().is_empty());
+        assert!(optim.dead.is_none());
+        assert!(optim.last_effective_step_size().is_none());
+        Ok(())
+    }
+}
@@ -36,16 +36,22 @@ $$
 
 */
 
-use candle_core::{Result, Var};
+use candle_core::{DType, Result, Tensor, Var};
 use candle_nn::optim::Optimizer;
+use log::warn;
 
-use crate::{Decay, Momentum, OptimParams};
+use crate::{
+    empty_grad_store, flatten_vars, is_low_precision, warn_unused_config, CurrentHyperparams,
+    Decay, HyperSnapshot, Momentum, OptimParams, OptimVars, UnusedConfig,
+};
 
 /// Optimizer for Stochastic Gradient Descent with momentum.
 #[derive(Debug)]
 pub struct SGD {
     vars: Vec<VarSGD>,
     params: ParamsSGD,
+    t: usize,
+    trajectory: Vec<Tensor>,
 }
 
 #[derive(Debug)]
@@ -64,7 +70,26 @@ pub struct ParamsSGD {
     /// Momentum
     pub momentum: Option<Momentum>,
     /// Dampening
+    ///
+    /// As in PyTorch this must be zero when using Nesterov momentum
     pub dampening: f64,
+    /// Perform the update arithmetic in f32 for f16 and bf16 variables
+    ///
+    /// Small updates $\\gamma g_t$ underflow to zero in f16, stalling training:
+    /// with this set the variable, gradient and momentum are upcast to f32 for the update and
+    /// the result cast back to the variable's dtype (the momentum buffer is kept in f32).
+    /// This has no effect on f32 and f64 variables.
+    pub upcast: bool,
+    /// Number of steps over which the momentum is linearly ramped up from 0
+    ///
+    /// At step $t$ the momentum used is $\\mu \\min(t - 1, N) / N$ for $N$ warmup steps,
+    /// so with the default of 0 the full momentum is used immediately
+    pub momentum_warmup_steps: usize,
+    /// Record the flattened values of all the variables after every step, see [`SGD::trajectory`]
+    ///
+    /// This keeps a copy of every parameter for every step, so is only suitable for small problems
+    /// (for example to plot the optimisation path of a 2D test function)
+    pub record_trajectory: bool,
 }
 
 impl Default for ParamsSGD {
@@ -75,14 +100,56 @@ impl Default for ParamsSGD {
             momentum: None, //Momentum::Classical(0.1)
             dampening: 0.0,
             // nesterov: false,
+            upcast: false,
+            momentum_warmup_steps: 0,
+            record_trajectory: false,
         }
     }
 }
 
+impl ParamsSGD {
+    /// Check the parameters are consistent: as in PyTorch, Nesterov momentum requires a non-zero momentum and no dampening
+    ///
+    /// # Errors
+    ///
+    /// Errors if Nesterov momentum is used with a momentum of zero or with dampening
+    pub fn validate(&self) -> Result<()> {
+        if let Some(Momentum::Nesterov(mu)) = self.momentum {
+            if mu == 0. {
+                candle_core::bail!("Nesterov momentum requires a non-zero momentum");
+            }
+            if self.dampening != 0. {
+                candle_core::bail!(
+                    "Nesterov momentum requires zero dampening, got {}",
+                    self.dampening
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UnusedConfig for ParamsSGD {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused = Vec::new();
+        if self.momentum.is_none() {
+            if self.dampening != 0. {
+                unused.push("dampening has no effect without momentum".to_string());
+            }
+            if self.momentum_warmup_steps != 0 {
+                unused.push("momentum_warmup_steps has no effect without momentum".to_string());
+            }
+        }
+        unused
+    }
+}
+
 impl Optimizer for SGD {
     type Config = ParamsSGD;
 
     fn new(vars: Vec<Var>, params: ParamsSGD) -> Result<Self> {
+        params.validate()?;
+        warn_unused_config(&params);
         let vars = vars
             .into_iter()
             .filter(|var| var.dtype().is_float())
@@ -92,16 +159,131 @@ impl Optimizer for SGD {
             })
             .collect::<Vec<VarSGD>>();
         // Err(SGDError::NoMomentum)?;
-        Ok(Self { vars, params })
+        Ok(Self {
+            vars,
+            params,
+            t: 0,
+            trajectory: Vec::new(),
+        })
     }
 
     fn learning_rate(&self) -> f64 {
         self.params.lr
     }
 
-    #[allow(clippy::too_many_lines)]
     fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
-        if let Some(momentum) = self.params.momentum {
+        if self.params.upcast {
+            self.upcast_step(grads)?;
+        } else {
+            self.sgd_step(grads)?;
+        }
+        if self.params.record_trajectory {
+            self.trajectory.push(flatten_vars(&self.vars())?.copy()?);
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl SGD {
+    /// step with any low precision variables upcast to f32
+    fn upcast_step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        // swap any low precision vars for f32 working copies, and step those
+        let mut working_grads = empty_grad_store()?;
+        let mut originals = Vec::new();
+        for (i, var) in self.vars.iter_mut().enumerate() {
+            if let Some(grad) = grads.get(&var.theta) {
+                if is_low_precision(var.theta.dtype()) {
+                    let working = Var::from_tensor(&var.theta.to_dtype(DType::F32)?)?;
+                    working_grads.insert(&working, grad.to_dtype(DType::F32)?);
+                    originals.push((i, std::mem::replace(&mut var.theta, working)));
+                } else {
+                    working_grads.insert(&var.theta, grad.clone());
+                }
+            }
+        }
+        let res = self.sgd_step(&working_grads);
+        // always restore the original vars, even if the step failed
+        for (i, original) in originals {
+            let working = std::mem::replace(&mut self.vars[i].theta, original);
+            let theta = &self.vars[i].theta;
+            if res.is_ok() {
+                theta.set(&working.to_dtype(theta.dtype())?)?;
+            }
+        }
+        res
+    }
+}
+
+impl OptimParams for SGD {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// Set the parameters for the optimiser
+    ///
+    /// # Warning
+    ///
+    /// Parameters failing [`ParamsSGD::validate`] are ignored with a warning, keeping the current parameters
+    fn set_params(&mut self, config: Self::Config) {
+        if let Err(e) = config.validate() {
+            warn!("ignoring invalid SGD parameters: {e}");
+        } else {
+            self.params = config;
+        }
+    }
+}
+
+impl CurrentHyperparams for SGD {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: None,
+            weight_decay: self.params.weight_decay,
+            momentum: self.params.momentum,
+        }
+    }
+}
+
+impl OptimVars for SGD {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl SGD {
+    /// the momentum to use for the current step, accounting for any warmup
+    #[allow(clippy::cast_precision_loss)]
+    fn warmed_up_momentum(&self) -> Option<Momentum> {
+        let warmup = self.params.momentum_warmup_steps;
+        if warmup == 0 {
+            return self.params.momentum;
+        }
+        let factor = self.t.min(warmup) as f64 / warmup as f64;
+        self.params.momentum.map(|momentum| match momentum {
+            Momentum::Classical(mu) => Momentum::Classical(mu * factor),
+            Momentum::Nesterov(mu) => Momentum::Nesterov(mu * factor),
+        })
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn sgd_step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        // keep any momentum buffer in the same dtype as the variable it is applied to
+        // (this can change if `upcast` is toggled between steps)
+        for var in &mut self.vars {
+            if let Some(b) = &var.b {
+                if b.dtype() != var.theta.dtype() {
+                    var.b = Some(Var::from_tensor(&b.to_dtype(var.theta.dtype())?)?);
+                }
+            }
+        }
+
+        let momentum = self.warmed_up_momentum();
+        self.t += 1;
+        if let Some(momentum) = momentum {
             match momentum {
                 Momentum::Classical(momentum) => {
                     if let Some(decay) = self.params.weight_decay {
@@ -339,22 +521,12 @@ impl Optimizer for SGD {
         Ok(())
     }
 
-    fn set_learning_rate(&mut self, lr: f64) {
-        self.params.lr = lr;
-    }
-}
-
-impl OptimParams for SGD {
-    fn params(&self) -> &Self::Config {
-        &self.params
-    }
-
-    fn set_params(&mut self, config: Self::Config) {
-        self.params = config;
+    /// The flattened values of the variables after each step, if `record_trajectory` is set
+    #[must_use]
+    pub fn trajectory(&self) -> &[Tensor] {
+        &self.trajectory
     }
-}
 
-impl SGD {
     /// Return the vars being optimised
     #[must_use]
     pub fn into_inner(self) -> Vec<Var> {
@@ -372,10 +544,50 @@ mod tests {
 
     use anyhow::Result;
     use assert_approx_eq::assert_approx_eq;
-    use candle_core::{Device, Var};
+    use candle_core::{Device, Tensor, Var};
     use candle_nn::Optimizer;
 
     use super::*;
+    #[test]
+    fn trajectory_test() -> Result<()> {
+        let params = ParamsSGD {
+            lr: 0.5,
+            record_trajectory: true,
+            ..Default::default()
+        };
+        let x = Var::new(&[2f64], &Device::Cpu)?;
+        let y = Var::new(&[[-4f64]], &Device::Cpu)?;
+        let mut optim = SGD::new(vec![x.clone(), y.clone()], params.clone())?;
+        for _ in 0..3 {
+            // gradient of (x^2 + y^2) / 2 is (x, y), so each step halves both
+            let loss =
+                ((x.as_tensor().sqr()?.sum_all()? + y.as_tensor().sqr()?.sum_all()?)? * 0.5)?;
+            optim.backward_step(&loss)?;
+        }
+        let trajectory = optim
+            .trajectory()
+            .iter()
+            .map(Tensor::to_vec1::<f64>)
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        assert_eq!(trajectory, [[1., -2.], [0.5, -1.], [0.25, -0.5]]);
+        // a single var is flattened without concatenating, so must still be copied
+        let mut optim = SGD::new(vec![x.clone()], params)?;
+        for _ in 0..2 {
+            optim.backward_step(&(x.as_tensor().sqr()?.sum_all()? * 0.5)?)?;
+        }
+        let trajectory = optim
+            .trajectory()
+            .iter()
+            .map(Tensor::to_vec1::<f64>)
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        assert_eq!(trajectory, [[0.125], [0.0625]]);
+        // off by default
+        let mut optim = SGD::new(vec![x.clone()], ParamsSGD::default())?;
+        optim.backward_step(&x.as_tensor().sum_all()?)?;
+        assert!(optim.trajectory().is_empty());
+        Ok(())
+    }
+
     #[test]
     fn lr_test() -> Result<()> {
         let params = ParamsSGD {
@@ -423,4 +635,149 @@ mod tests {
         assert_eq!(new_params, optim.params().clone());
         Ok(())
     }
+
+    fn f16_step(params: ParamsSGD) -> Result<Vec<f32>> {
+        let theta = Var::from_tensor(&Tensor::zeros(2, DType::F16, &Device::Cpu)?)?;
+        let mut optim = SGD::new(vec![theta.clone()], params)?;
+        for _ in 0..2 {
+            let loss = (theta.as_tensor() * 4.)?.sum_all()?;
+            optim.backward_step(&loss)?;
+        }
+        assert_eq!(theta.dtype(), DType::F16);
+        Ok(theta.to_dtype(DType::F32)?.to_vec1::<f32>()?)
+    }
+
+    #[test]
+    fn momentum_warmup_test() -> Result<()> {
+        let params = ParamsSGD {
+            lr: 0.1,
+            momentum: Some(Momentum::Classical(0.9)),
+            momentum_warmup_steps: 2,
+            ..Default::default()
+        };
+        let w = Var::new(&[0f64], &Device::Cpu)?;
+        let mut optim = SGD::new(vec![w.clone()], params)?;
+        // constant gradient of 1, so b_t = mu_t b_{t-1} + 1 with mu_t ramping 0, 0.45, 0.9
+        let loss = w.as_tensor().sum_all()?;
+        for expected in [1., 1.45, 2.305, 3.0745] {
+            optim.backward_step(&loss)?;
+            let b = optim.vars[0].b.as_ref().unwrap().to_vec1::<f64>()?;
+            assert_approx_eq!(b[0], expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn upcast_test() -> Result<()> {
+        let params = ParamsSGD {
+            lr: 1e-8,
+            ..Default::default()
+        };
+        // lr * grad underflows to zero in f16
+        assert_eq!(f16_step(params.clone())?, [0., 0.]);
+        let upcast = f16_step(ParamsSGD {
+            upcast: true,
+            ..params.clone()
+        })?;
+        assert!(upcast.iter().all(|x| *x < 0.));
+        let momentum = f16_step(ParamsSGD {
+            upcast: true,
+            momentum: Some(Momentum::Classical(0.9)),
+            ..params
+        })?;
+        assert!(momentum.iter().all(|x| *x < 0.));
+        Ok(())
+    }
+
+    #[test]
+    fn upcast_f32_unchanged_test() -> Result<()> {
+        let run = |upcast: bool| -> Result<Vec<f32>> {
+            let params = ParamsSGD {
+                lr: 0.1,
+                momentum: Some(Momentum::Nesterov(0.5)),
+                upcast,
+                ..Default::default()
+            };
+            let theta = Var::new(&[1f32, -2.], &Device::Cpu)?;
+            let mut optim = SGD::new(vec![theta.clone()], params)?;
+            for _ in 0..3 {
+                optim.backward_step(&theta.as_tensor().sqr()?.sum_all()?)?;
+            }
+            Ok(theta.to_vec1::<f32>()?)
+        };
+        assert_eq!(run(false)?, run(true)?);
+        Ok(())
+    }
+
+    #[test]
+    fn nesterov_test() -> Result<()> {
+        let run = |momentum: Momentum| -> Result<f64> {
+            let params = ParamsSGD {
+                lr: 0.1,
+                momentum: Some(momentum),
+                ..Default::default()
+            };
+            let theta = Var::new(&[1f64], &Device::Cpu)?;
+            let mut optim = SGD::new(vec![theta.clone()], params)?;
+            for _ in 0..2 {
+                optim.backward_step(&theta.as_tensor().sum_all()?)?;
+            }
+            Ok(theta.to_vec1::<f64>()?[0])
+        };
+        // with a unit gradient b_1 = 1 and b_2 = 1.5, classical momentum steps by b_t
+        assert_approx_eq!(run(Momentum::Classical(0.5))?, 0.75);
+        // and nesterov by g_t + mu b_t
+        assert_approx_eq!(run(Momentum::Nesterov(0.5))?, 0.675);
+        Ok(())
+    }
+
+    #[test]
+    fn nesterov_validation_test() -> Result<()> {
+        let theta = Var::new(&[1f64], &Device::Cpu)?;
+        let dampened = ParamsSGD {
+            momentum: Some(Momentum::Nesterov(0.5)),
+            dampening: 0.1,
+            ..Default::default()
+        };
+        assert!(SGD::new(vec![theta.clone()], dampened.clone()).is_err());
+        let zero = ParamsSGD {
+            momentum: Some(Momentum::Nesterov(0.)),
+            ..Default::default()
+        };
+        assert!(SGD::new(vec![theta.clone()], zero).is_err());
+        // dampening is fine with classical momentum
+        let classical = ParamsSGD {
+            momentum: Some(Momentum::Classical(0.5)),
+            dampening: 0.1,
+            ..Default::default()
+        };
+        let mut optim = SGD::new(vec![theta], classical.clone())?;
+        // invalid parameters are not applied
+        optim.set_params(dampened);
+        assert_eq!(optim.params(), &classical);
+        Ok(())
+    }
+
+    #[test]
+    fn upcast_toggle_test() -> Result<()> {
+        let params = ParamsSGD {
+            lr: 0.1,
+            momentum: Some(Momentum::Classical(0.9)),
+            upcast: true,
+            ..Default::default()
+        };
+        let theta = Var::from_tensor(&Tensor::ones(2, DType::F16, &Device::Cpu)?)?;
+        let mut optim = SGD::new(vec![theta.clone()], params.clone())?;
+        optim.backward_step(&theta.as_tensor().sum_all()?)?;
+        // the f32 momentum buffer is cast back when upcasting is switched off
+        optim.set_params(ParamsSGD {
+            upcast: false,
+            ..params
+        });
+        optim.backward_step(&theta.as_tensor().sum_all()?)?;
+        for x in theta.to_dtype(DType::F32)?.to_vec1::<f32>()? {
+            assert_approx_eq!(x, 0.71, 1e-3);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,306 @@
+/*!
+Frank–Wolfe (conditional gradient) method
+
+For minimising a smooth loss over a compact convex set $\\mathcal{C}$ where projection is expensive but linear
+functions are cheap to minimise, as described in [An algorithm for quadratic programming](https://doi.org/10.1002/nav.3800030109).
+Each step calls the [`LinearMinimisationOracle`] of the set for the vertex minimising the linearised loss, and moves
+towards it by a convex combination, so the variables remain in $\\mathcal{C}$ without any projection:
+
+$$
+\\begin{aligned}
+    &s_t \\gets \\arg\\min_{s \\in \\mathcal{C}} \\langle \\nabla f(\\theta_{t-1}), s \\rangle \\\\
+    &\\theta_t \\gets (1 - \\gamma_t) \\theta_{t-1} + \\gamma_t s_t
+\\end{aligned}
+$$
+
+with the classic step size $\\gamma_t = \\frac{2}{t + 2}$ for $t$ from 0, or a constant step size. The variables must
+start in the set. Each variable is constrained separately, with the oracles for the [`L1Ball`], [`L2Ball`] and
+[`Simplex`] built in, and others can be added by implementing the trait.
+
+The Frank–Wolfe gap $\\langle \\nabla f(\\theta_{t-1}), \\theta_{t-1} - s_t \\rangle$, which bounds the suboptimality of
+the loss for convex losses, is available from [`FrankWolfe::gap`].
+*/
+
+use std::fmt::Debug;
+
+use candle_core::{DType, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+use log::warn;
+
+use crate::{sign, OptimParams, OptimVars};
+
+/// Trait for constraint sets that can minimise a linear function over themselves
+pub trait LinearMinimisationOracle: Debug {
+    /// a point $s$ of the set minimising $\\langle g, s \\rangle$ for the gradient $g$, of the same shape as it
+    ///
+    /// # Errors
+    ///
+    /// Errors if the tensor operations fail
+    fn lmo(&self, grad: &Tensor) -> Result<Tensor>;
+}
+
+/// The L1 ball $\\{\\theta : ||\\theta||_{1} \\leq r\\}$, whose vertices are sparse
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct L1Ball {
+    /// the radius $r$
+    pub radius: f64,
+}
+
+impl LinearMinimisationOracle for L1Ball {
+    /// the vertex $-r \\, \\text{sign}(g_i) e_i$ for the largest $|g_i|$, averaged over ties
+    fn lmo(&self, grad: &Tensor) -> Result<Tensor> {
+        let flat = grad.flatten_all()?;
+        let abs = flat.abs()?;
+        let largest = abs.max_keepdim(0)?.broadcast_as(abs.shape())?;
+        let mask = abs.eq(&largest)?.to_dtype(grad.dtype())?;
+        let vertex =
+            ((sign(&flat)? * &mask)? * -self.radius)?.broadcast_div(&mask.sum_keepdim(0)?)?;
+        vertex.reshape(grad.shape())
+    }
+}
+
+/// The L2 ball $\\{\\theta : ||\\theta||_{2} \\leq r\\}$
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct L2Ball {
+    /// the radius $r$
+    pub radius: f64,
+}
+
+impl LinearMinimisationOracle for L2Ball {
+    /// the point $-r \\frac{g}{||g||_{2}}$, or the centre for a zero gradient
+    fn lmo(&self, grad: &Tensor) -> Result<Tensor> {
+        let norm = grad
+            .sqr()?
+            .sum_all()?
+            .to_dtype(DType::F64)?
+            .to_scalar::<f64>()?
+            .sqrt();
+        if norm == 0. {
+            return grad.zeros_like();
+        }
+        grad * (-self.radius / norm)
+    }
+}
+
+/// The scaled probability simplex $\\{\\theta : \\theta \\geq 0, \\sum_i \\theta_i = r\\}$
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Simplex {
+    /// the sum $r$ of the elements, 1 for probability distributions
+    pub scale: f64,
+}
+
+impl LinearMinimisationOracle for Simplex {
+    /// the vertex $r e_i$ for the smallest $g_i$, averaged over ties
+    fn lmo(&self, grad: &Tensor) -> Result<Tensor> {
+        let flat = grad.flatten_all()?;
+        let smallest = flat.min_keepdim(0)?.broadcast_as(flat.shape())?;
+        let mask = flat.eq(&smallest)?.to_dtype(grad.dtype())?;
+        let vertex = (mask.broadcast_div(&mask.sum_keepdim(0)?)? * self.scale)?;
+        vertex.reshape(grad.shape())
+    }
+}
+
+/// Parameters for the Frank–Wolfe method
+#[derive(Clone, Debug)]
+pub struct ParamsFrankWolfe<C: LinearMinimisationOracle> {
+    /// The constraint set, applied to each variable separately
+    pub set: C,
+    /// Constant step size, or `None` for the classic step size $\\frac{2}{t + 2}$
+    pub lr: Option<f64>,
+}
+
+/// Frank–Wolfe optimiser
+///
+/// Conditional gradient steps towards the minimiser of the linearised loss over a constraint set
+#[derive(Debug)]
+pub struct FrankWolfe<C: LinearMinimisationOracle> {
+    vars: Vec<Var>,
+    params: ParamsFrankWolfe<C>,
+    t: usize,
+    gap: Option<f64>,
+}
+
+impl<C: LinearMinimisationOracle> Optimizer for FrankWolfe<C> {
+    type Config = ParamsFrankWolfe<C>;
+
+    fn new(vars: Vec<Var>, params: ParamsFrankWolfe<C>) -> Result<Self> {
+        if let Some(lr) = params.lr {
+            check_lr(lr)?;
+        }
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect();
+        Ok(Self {
+            vars,
+            params,
+            t: 0,
+            gap: None,
+        })
+    }
+
+    /// the step size $\\gamma_t$ of the next step
+    #[allow(clippy::cast_precision_loss)]
+    fn learning_rate(&self) -> f64 {
+        self.params.lr.unwrap_or(2. / (self.t as f64 + 2.))
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let lr = self.learning_rate();
+        let mut gap = 0.;
+        for var in &self.vars {
+            if let Some(grad) = grads.get(var) {
+                let vertex = self.params.set.lmo(grad)?;
+                gap += (grad * (var.as_tensor() - &vertex)?)?
+                    .sum_all()?
+                    .to_dtype(DType::F64)?
+                    .to_scalar::<f64>()?;
+                var.set(&((var.as_tensor() * (1. - lr))? + (vertex * lr)?)?)?;
+            }
+        }
+        self.gap = Some(gap);
+        self.t += 1;
+        Ok(())
+    }
+
+    /// use a constant step size
+    /// Set a constant step size
+    ///
+    /// # Warning
+    ///
+    /// Step sizes outside $(0, 1]$ are ignored with a warning, keeping the current step size
+    fn set_learning_rate(&mut self, lr: f64) {
+        if let Err(e) = check_lr(lr) {
+            warn!("ignoring invalid Frank-Wolfe step size: {e}");
+        } else {
+            self.params.lr = Some(lr);
+        }
+    }
+}
+
+impl<C: LinearMinimisationOracle> OptimParams for FrankWolfe<C> {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// Set the parameters for the optimiser
+    ///
+    /// # Warning
+    ///
+    /// Parameters with a step size outside $(0, 1]$ are ignored with a warning, keeping the current parameters
+    fn set_params(&mut self, config: Self::Config) {
+        match config.lr.map(check_lr) {
+            Some(Err(e)) => warn!("ignoring invalid Frank-Wolfe parameters: {e}"),
+            _ => self.params = config,
+        }
+    }
+}
+
+/// the step size must be in $(0, 1]$ for the iterates to stay in the constraint set
+fn check_lr(lr: f64) -> Result<()> {
+    if !(lr > 0. && lr <= 1.) {
+        candle_core::bail!("the Frank-Wolfe step size must be in (0, 1], got {lr}");
+    }
+    Ok(())
+}
+
+impl<C: LinearMinimisationOracle> OptimVars for FrankWolfe<C> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<C: LinearMinimisationOracle> FrankWolfe<C> {
+    /// The Frank–Wolfe gap of the last step, summed over the variables, or `None` before the first step
+    ///
+    /// For convex losses this is an upper bound on how far the loss before the step was above the minimum
+    #[must_use]
+    pub fn gap(&self) -> Option<f64> {
+        self.gap
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lmo_test() -> Result<()> {
+        let g = Tensor::new(&[[0.5f64, -2.], [1., 0.]], &Device::Cpu)?;
+        let l1 = L1Ball { radius: 3. }.lmo(&g)?.to_vec2::<f64>()?;
+        assert_eq!(l1, [[0., 3.], [0., 0.]]);
+        let l2 = L2Ball { radius: 1. }
+            .lmo(&g)?
+            .flatten_all()?
+            .to_vec1::<f64>()?;
+        let norm = 5.25f64.sqrt();
+        for (s, g) in l2.iter().zip([0.5, -2., 1., 0.]) {
+            assert_approx_eq!(*s, -g / norm);
+        }
+        let simplex = Simplex { scale: 2. }.lmo(&g)?.to_vec2::<f64>()?;
+        assert_eq!(simplex, [[0., 2.], [0., 0.]]);
+        // ties share the vertex, staying in the set
+        let tied = Tensor::new(&[1f64, -1., 1.], &Device::Cpu)?;
+        let s = L1Ball { radius: 1. }.lmo(&tied)?.to_vec1::<f64>()?;
+        assert_eq!(s, [-1. / 3., 1. / 3., -1. / 3.]);
+        let zero = Tensor::zeros(2, DType::F64, &Device::Cpu)?;
+        assert_eq!(
+            L2Ball { radius: 1. }.lmo(&zero)?.to_vec1::<f64>()?,
+            [0., 0.]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let w = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let params = ParamsFrankWolfe {
+            set: L2Ball { radius: 1. },
+            lr: None,
+        };
+        let mut optim = FrankWolfe::new(vec![w.clone()], params)?;
+        assert_approx_eq!(optim.learning_rate(), 1.);
+        let loss = w.as_tensor().sum_all()?;
+        optim.backward_step(&loss)?;
+        assert_approx_eq!(optim.learning_rate(), 2. / 3.);
+        optim.set_learning_rate(0.1);
+        assert_approx_eq!(optim.learning_rate(), 0.1);
+        // step sizes outside (0, 1] would leave the constraint set, so are ignored
+        optim.set_learning_rate(1.5);
+        assert_approx_eq!(optim.learning_rate(), 0.1);
+        optim.set_params(ParamsFrankWolfe {
+            set: L2Ball { radius: 1. },
+            lr: Some(0.),
+        });
+        assert_approx_eq!(optim.learning_rate(), 0.1);
+        Ok(())
+    }
+
+    #[test]
+    fn first_step_test() -> Result<()> {
+        // with the classic step size the first step lands on the vertex, and the gap is <g, -s>
+        let w = Var::new(&[0.25f64, 0.25, 0.5], &Device::Cpu)?;
+        let params = ParamsFrankWolfe {
+            set: Simplex { scale: 1. },
+            lr: None,
+        };
+        let mut optim = FrankWolfe::new(vec![w.clone()], params)?;
+        assert!(optim.gap().is_none());
+        let c = Tensor::new(&[3f64, 1., 2.], &Device::Cpu)?;
+        optim.backward_step(&(w.as_tensor() * c)?.sum_all()?)?;
+        assert_eq!(w.to_vec1::<f64>()?, [0., 1., 0.]);
+        assert_approx_eq!(optim.gap().unwrap_or_default(), 1.);
+        Ok(())
+    }
+}
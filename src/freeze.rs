@@ -0,0 +1,254 @@
+/*!
+Freezing of individual parameters
+
+[`Freezable`] wraps any optimiser in this crate and allows specific variables to be frozen: they are kept in the
+optimiser (so any state such as momentum buffers is preserved, and they can later be unfrozen), but are skipped entirely
+when stepping. A frozen variable is not changed and its optimiser state is not updated.
+
+This is different from scaling the gradient of a variable to zero with [`Freezable::scale_grad_for`]: a zero gradient is still
+a gradient, so optimisers with momentum (or decoupled weight decay) will continue to move the variable and update its state.
+
+Neither of these stop gradients from flowing *through* the variable to earlier parts of the model;
+to do this the variable should be detached in the model itself (e.g. using `Tensor::detach`).
+As computing gradients for frozen variables is wasted work, [`Freezable::set_warn_on_frozen_grads`] can be used to log a
+warning when a frozen variable is still receiving a gradient.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use candle_core::{backprop::GradStore, Result, TensorId, Var};
+use candle_nn::optim::Optimizer;
+use log::warn;
+
+use crate::{empty_grad_store, OptimVars};
+
+/// Optimiser wrapper allowing variables to be frozen or have their gradients scaled
+#[derive(Debug)]
+pub struct Freezable<O: Optimizer + OptimVars> {
+    inner: O,
+    frozen: HashSet<TensorId>,
+    grad_scales: HashMap<TensorId, f64>,
+    warn_on_frozen_grads: bool,
+    /// frozen variables already warned about, so each is only reported once while frozen
+    warned: HashSet<TensorId>,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for Freezable<O> {
+    type Config = O::Config;
+
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Ok(Self::new(O::new(vars, config)?))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        let mut masked = empty_grad_store()?;
+        for var in self.inner.vars() {
+            if self.frozen.contains(&var.id()) {
+                if self.warn_on_frozen_grads
+                    && grads.get(var).is_some()
+                    && self.warned.insert(var.id())
+                {
+                    warn!(
+                        "frozen variable {:?} received a gradient: detach it in the model to avoid computing it",
+                        var.id()
+                    );
+                }
+                continue;
+            }
+            if let Some(grad) = grads.get(var) {
+                let grad = match self.grad_scales.get(&var.id()) {
+                    Some(scale) => (grad * *scale)?,
+                    None => grad.clone(),
+                };
+                masked.insert(var, grad);
+            }
+        }
+        self.inner.step(&masked)
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for Freezable<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> Freezable<O> {
+    /// wrap an optimiser, with no variables initially frozen
+    #[must_use]
+    pub fn new(inner: O) -> Self {
+        Self {
+            inner,
+            frozen: HashSet::new(),
+            grad_scales: HashMap::new(),
+            warn_on_frozen_grads: false,
+            warned: HashSet::new(),
+        }
+    }
+
+    /// freeze a variable: it will be skipped when stepping, leaving it and its state unchanged
+    pub fn freeze(&mut self, var: &Var) {
+        self.frozen.insert(var.id());
+    }
+
+    /// unfreeze a previously frozen variable
+    pub fn unfreeze(&mut self, var: &Var) {
+        self.frozen.remove(&var.id());
+        self.warned.remove(&var.id());
+    }
+
+    /// whether a variable is currently frozen
+    #[must_use]
+    pub fn is_frozen(&self, var: &Var) -> bool {
+        self.frozen.contains(&var.id())
+    }
+
+    /// scale the gradient of a variable by `scale` before it is passed to the optimiser
+    ///
+    /// a scale of `1.` removes any scaling
+    #[allow(clippy::float_cmp)]
+    pub fn scale_grad_for(&mut self, var: &Var, scale: f64) {
+        if scale == 1. {
+            self.grad_scales.remove(&var.id());
+        } else {
+            self.grad_scales.insert(var.id(), scale);
+        }
+    }
+
+    /// log a warning when a frozen variable has a gradient in the `GradStore` passed to [`Optimizer::step`]
+    ///
+    /// this usually means the variable was not detached in the model, so its gradient is computed and then discarded;
+    /// each frozen variable is reported once until it is unfrozen
+    pub fn set_warn_on_frozen_grads(&mut self, warn: bool) {
+        self.warn_on_frozen_grads = warn;
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use crate::Momentum;
+    use anyhow::Result;
+    use candle_core::Device;
+
+    fn momentum_sgd(vars: Vec<Var>) -> Result<Freezable<SGD>> {
+        let params = ParamsSGD {
+            lr: 0.1,
+            momentum: Some(Momentum::Classical(0.9)),
+            ..Default::default()
+        };
+        Ok(Freezable::new(SGD::new(vars, params)?))
+    }
+
+    #[test]
+    fn freeze_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let b = Var::new(3f32, &Device::Cpu)?;
+        let mut optim = momentum_sgd(vec![w.clone(), b.clone()])?;
+        let loss = w.as_tensor().sum_all()?.add(b.as_tensor())?;
+        optim.backward_step(&loss)?;
+        optim.freeze(&b);
+        assert!(optim.is_frozen(&b));
+        assert!(!optim.is_frozen(&w));
+        for _ in 0..3 {
+            optim.backward_step(&loss)?;
+        }
+        // b only took the first step
+        assert_eq!(b.to_scalar::<f32>()?, 2.9);
+        assert_ne!(w.to_vec1::<f32>()?, [0.9, 1.9]);
+        optim.unfreeze(&b);
+        optim.backward_step(&loss)?;
+        assert!(b.to_scalar::<f32>()? < 2.9);
+        Ok(())
+    }
+
+    #[test]
+    fn zero_scale_test() -> Result<()> {
+        let frozen = Var::new(1f32, &Device::Cpu)?;
+        let scaled = Var::new(1f32, &Device::Cpu)?;
+        let mut optim = momentum_sgd(vec![frozen.clone(), scaled.clone()])?;
+        let loss = frozen.as_tensor().add(scaled.as_tensor())?;
+        optim.backward_step(&loss)?;
+        optim.freeze(&frozen);
+        optim.scale_grad_for(&scaled, 0.);
+        optim.backward_step(&loss)?;
+        assert_eq!(frozen.to_scalar::<f32>()?, 0.9);
+        // momentum keeps moving a variable with a zero gradient
+        assert!(scaled.to_scalar::<f32>()? < 0.9);
+        Ok(())
+    }
+
+    fn warnings_for(var: &Var) -> usize {
+        let id = format!("{:?}", var.id());
+        crate::test_logger::warnings()
+            .iter()
+            .filter(|msg| msg.contains("frozen variable") && msg.contains(&id))
+            .count()
+    }
+
+    #[test]
+    fn warn_on_frozen_grads_test() -> Result<()> {
+        crate::test_logger::install();
+        let frozen = Var::new(1f32, &Device::Cpu)?;
+        let w = Var::new(1f32, &Device::Cpu)?;
+        let mut optim = momentum_sgd(vec![frozen.clone(), w.clone()])?;
+        let loss = frozen.as_tensor().add(w.as_tensor())?;
+        optim.freeze(&frozen);
+        optim.backward_step(&loss)?;
+        assert_eq!(warnings_for(&frozen), 0);
+        optim.set_warn_on_frozen_grads(true);
+        optim.backward_step(&loss)?;
+        optim.backward_step(&loss)?;
+        // reported once while frozen, and only for the frozen var
+        assert_eq!(warnings_for(&frozen), 1);
+        assert_eq!(warnings_for(&w), 0);
+        // still just a warning: the frozen var is untouched and the step succeeds
+        assert_eq!(frozen.to_scalar::<f32>()?, 1.);
+        // a detached frozen var has no gradient so is not reported
+        optim.unfreeze(&frozen);
+        optim.freeze(&frozen);
+        let detached = frozen.as_tensor().detach().add(w.as_tensor())?;
+        optim.backward_step(&detached)?;
+        assert_eq!(warnings_for(&frozen), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn into_inner_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut optim = momentum_sgd(vec![w.clone()])?;
+        optim.freeze(&w);
+        optim.set_learning_rate(0.5);
+        assert_eq!(optim.inner().learning_rate(), 0.5);
+        assert_eq!(optim.vars().len(), 1);
+        let inner = optim.into_inner();
+        assert_eq!(inner.into_inner()[0].id(), w.id());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+/*!
+Gauss-Newton optimiser for least squares problems
+
+For a [`ResidualModel`] with residuals $r(\\theta)$ and loss $F = ||r||_{2}^{2}$, the Hessian of the loss is
+approximated by $2 J^{\\top} J$, dropping the second derivatives of the residuals, so each step solves
+
+$$ J^{\\top} J \\delta = -J^{\\top} r $$
+
+and steps to $\\theta + t \\delta$. Without a line search $t$ is the learning rate, so a learning rate of 1 takes the full
+Gauss-Newton step, which solves linear least squares problems in one step and converges quadratically on problems
+whose residuals are small at the minimum. Optionally $t$ is chosen by one of the [LBFGS](crate::lbfgs) line searches,
+which makes the method globally convergent when $J$ has full rank.
+
+Unlike [Levenberg–Marquardt](crate::levenberg_marquardt) there is no damping, so this is suited to well conditioned
+problems such as model calibration: if $J$ is rank deficient the conjugate gradient solution of the system may fail.
+The Jacobian is built by backpropagating each residual, as for Levenberg–Marquardt.
+*/
+
+use crate::{
+    cg::{conjugate_gradient, dot},
+    lbfgs::{
+        add_grad, directional_evaluate, flat_grads, l2_reg, Backtracking, GradConv, LineSearch,
+        LineSearchTarget, StepConv, StrongWolfe,
+    },
+    levenberg_marquardt::{residual_jacobian, ResidualConv},
+    LossOptimizer, ModelOutcome, OptimVars, ResidualModel,
+};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for the Gauss-Newton optimiser
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsGaussNewton {
+    /// Step size along the Gauss-Newton step without a line search, or the initial step size of the line search
+    pub lr: f64,
+    /// linesearch method to use, or `None` to take steps of size `lr`
+    pub line_search: Option<LineSearch>,
+    /// Convergence criteria for the residuals
+    pub residual_conv: ResidualConv,
+    /// Convergence criteria for the gradient $J^{\\top} r$
+    pub grad_conv: GradConv,
+    /// Convergence criteria for the step
+    pub step_conv: StepConv,
+    /// Relative tolerance of the conjugate gradient solution of the Gauss-Newton system
+    pub cg_tol: f64,
+}
+
+impl Default for ParamsGaussNewton {
+    fn default() -> Self {
+        Self {
+            lr: 1.,
+            line_search: None,
+            residual_conv: ResidualConv::Norm(1e-10),
+            grad_conv: GradConv::MinForce(1e-10),
+            step_conv: StepConv::MinStep(1e-12),
+            cg_tol: 1e-12,
+        }
+    }
+}
+
+/// Gauss-Newton optimiser
+///
+/// Steps by solving the Gauss-Newton system of a model whose loss is a sum of squared residuals, optionally with a
+/// line search
+#[derive(Debug)]
+pub struct GaussNewton<M: ResidualModel> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsGaussNewton,
+}
+
+impl<M: ResidualModel> LossOptimizer<M> for GaussNewton<M> {
+    type Config = ParamsGaussNewton;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if let Some(line_search) = params.line_search {
+            line_search.validate()?;
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let mut evals = 1;
+        let (residuals, jacobian) = residual_jacobian(&self.model, &self.vars)?;
+        let f_init = dot(&residuals, &residuals)?;
+        if let ResidualConv::Norm(tol) = self.params.residual_conv {
+            if f_init.sqrt() < tol {
+                info!("residual norm converged");
+                return Ok(ModelOutcome::Converged(loss.clone(), evals));
+            }
+        }
+        let jt = jacobian.t()?;
+        let jtr = jt.matmul(&residuals.unsqueeze(1)?)?.squeeze(1)?;
+        if self.params.grad_conv.converged(&jtr)? == Some(true) {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+
+        let jtj = jt.matmul(&jacobian)?;
+        let n = jtr.elem_count();
+        // as in LBFGS the direction q is stepped along with a negative step size, so the Gauss-Newton step is -q
+        let q = {
+            let jtj = &jtj;
+            let matvec =
+                |v: &Tensor| -> CResult<Tensor> { jtj.matmul(&v.unsqueeze(1)?)?.squeeze(1) };
+            conjugate_gradient(matvec, &jtr, 2 * n, self.params.cg_tol)?
+        };
+
+        let (next_loss, step) = if let Some(ls) = self.params.line_search {
+            // the gradient of the loss itself, in case it is scaled differently to the sum of squared residuals
+            let grad = flat_grads(&self.vars, loss, None)?;
+            let dd = dot(&grad, &q)?;
+            let lr = -self.params.lr;
+            let (next_loss, _, t, steps) = match ls {
+                LineSearch::StrongWolfe(c1, c2, tol) => {
+                    self.strong_wolfe(lr, &q, loss, &grad, dd, c1, c2, tol, 25)?
+                }
+                LineSearch::Backtracking { c1, rho, max_steps } => {
+                    self.backtracking(lr, &q, loss, &grad, dd, c1, rho, max_steps)?
+                }
+            };
+            evals += steps;
+            if t == 0. {
+                // no acceptable step was found, which must not be taken for convergence
+                return Ok(ModelOutcome::Truncated(next_loss, evals));
+            }
+            let step = (q * t)?;
+            add_grad(&mut self.vars, &step)?;
+            (next_loss, step)
+        } else {
+            let step = (q * -self.params.lr)?;
+            add_grad(&mut self.vars, &step)?;
+            evals += 1;
+            (self.model.loss()?, step)
+        };
+
+        if self.params.step_conv.converged(&step)? == Some(true) {
+            info!("step converged");
+            return Ok(ModelOutcome::Converged(next_loss, evals));
+        }
+        if let ResidualConv::RelativeReduction(tol) = self.params.residual_conv {
+            let f_new = next_loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+            if (f_init - f_new).abs() < tol * f_init {
+                info!("relative reduction converged");
+                return Ok(ModelOutcome::Converged(next_loss, evals));
+            }
+        }
+        Ok(ModelOutcome::Stepped(next_loss, evals))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: ResidualModel> LineSearchTarget for GaussNewton<M> {
+    fn directional_evaluate(
+        &mut self,
+        mag: f64,
+        direction: &Tensor,
+    ) -> CResult<(Tensor, Tensor, f64)> {
+        directional_evaluate(&mut self.vars, &self.model, None, mag, direction)
+    }
+
+    fn l2_reg(&self) -> CResult<f64> {
+        l2_reg(&self.vars, None)
+    }
+}
+
+impl<M: ResidualModel> OptimVars for GaussNewton<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+    use crate::Model;
+
+    /// the linear residuals $A x - b$ with $A = [[1, 0], [1, 1], [1, 2]]$ and $b = [1, 2, 2]$
+    struct Linear {
+        x: Var,
+    }
+
+    impl Model for Linear {
+        fn loss(&self) -> CResult<Tensor> {
+            self.residuals()?.sqr()?.sum_all()
+        }
+    }
+
+    impl ResidualModel for Linear {
+        fn residuals(&self) -> CResult<Tensor> {
+            let a = Tensor::new(&[[1f64, 0.], [1., 1.], [1., 2.]], &Device::Cpu)?;
+            let b = Tensor::new(&[1f64, 2., 2.], &Device::Cpu)?;
+            a.matmul(&self.x.as_tensor().unsqueeze(1)?)?.squeeze(1)? - b
+        }
+    }
+
+    fn setup(params: ParamsGaussNewton) -> Result<(Var, GaussNewton<Linear>)> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let optim = GaussNewton::new(vec![x.clone()], params, Linear { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsGaussNewton::default())?;
+        assert_approx_eq!(1., optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn linear_step_test() -> Result<()> {
+        // a linear least squares problem is solved by one full step, to [7/6, 1/2]
+        for line_search in [None, Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9))] {
+            let (x, mut optim) = setup(ParamsGaussNewton {
+                line_search,
+                ..Default::default()
+            })?;
+            let loss = optim.model.loss()?;
+            optim.backward_step(&loss)?;
+            let x = x.to_vec1::<f64>()?;
+            assert_approx_eq!(x[0], 7. / 6.);
+            assert_approx_eq!(x[1], 0.5);
+        }
+        Ok(())
+    }
+}
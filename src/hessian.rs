@@ -0,0 +1,237 @@
+/*!
+Estimates of the diagonal of the Hessian
+
+These estimate the diagonal of the Hessian of a loss with respect to a set of variables, for use in preconditioning
+such as by [Sophia](crate::sophia). Each takes a closure that evaluates the loss at the current value of the variables.
+
+Candle does not differentiate through the backward pass, so Hessian-vector products are taken by central differences
+of the gradient:
+
+$$ H v \\approx \\frac{\\nabla f(\\theta + \\epsilon v) - \\nabla f(\\theta - \\epsilon v)}{2 \\epsilon} $$
+
+which is exact for quadratic losses. The variables are restored to their original values afterwards.
+
+[`hutchinson`] gives an unbiased estimate of the diagonal as the average of $u \\odot (H u)$ over random vectors $u$
+with independent entries of $\\pm 1$, as described in
+[An estimator for the diagonal of a matrix](https://doi.org/10.1016/j.apnum.2007.01.003).
+
+[`gauss_newton_bartlett`] instead estimates the diagonal of the Gauss-Newton matrix from a single gradient of the loss
+on labels sampled from the model, as described in
+[Sophia: A Scalable Stochastic Second-order Optimizer for Language Model Pre-training](https://arxiv.org/abs/2305.14342).
+*/
+
+use candle_core::{Result, Tensor, Var};
+
+use crate::{cg::dot, unflatten};
+
+/// gradients of the loss returned by `loss_fn` with respect to `vars`, with zeros for variables it does not depend on
+fn loss_grads<F: FnMut() -> Result<Tensor>>(vars: &[&Var], loss_fn: &mut F) -> Result<Vec<Tensor>> {
+    let grads = loss_fn()?.backward()?;
+    vars.iter()
+        .map(|var| match grads.get(var) {
+            Some(grad) => Ok(grad.clone()),
+            None => var.zeros_like(),
+        })
+        .collect()
+}
+
+/// Hessian-vector product of the loss returned by `loss_fn` with the vector `v`, by central differences of step `eps`
+///
+/// `v` holds a tensor for each of `vars`, of the same shape
+///
+/// # Errors
+///
+/// Errors if `v` does not match `vars`, or the loss or its gradient cannot be evaluated
+pub fn hessian_vector_product<F: FnMut() -> Result<Tensor>>(
+    vars: &[&Var],
+    v: &[Tensor],
+    mut loss_fn: F,
+    eps: f64,
+) -> Result<Vec<Tensor>> {
+    if v.len() != vars.len() {
+        candle_core::bail!(
+            "{} tensors given for the product with the Hessian of {} variables",
+            v.len(),
+            vars.len()
+        );
+    }
+    let originals = vars
+        .iter()
+        .zip(v)
+        .map(|(var, v)| {
+            if v.shape() != var.shape() {
+                candle_core::bail!(
+                    "tensor of shape {:?} given for variable of shape {:?}",
+                    v.shape(),
+                    var.shape()
+                );
+            }
+            var.as_tensor().copy()
+        })
+        .collect::<Result<Vec<Tensor>>>()?;
+    let perturbed_grads = |sign: f64, loss_fn: &mut F| -> Result<Vec<Tensor>> {
+        for ((var, v), original) in vars.iter().zip(v).zip(&originals) {
+            var.set(&(original + (v * (sign * eps))?)?)?;
+        }
+        loss_grads(vars, loss_fn)
+    };
+    let plus = perturbed_grads(1., &mut loss_fn);
+    let minus = perturbed_grads(-1., &mut loss_fn);
+    // restore the variables even if a gradient could not be evaluated
+    for (var, original) in vars.iter().zip(&originals) {
+        var.set(original)?;
+    }
+    plus?
+        .iter()
+        .zip(minus?)
+        .map(|(plus, minus)| (plus - minus)? / (2. * eps))
+        .collect()
+}
+
+/// Hessian-vector product as [`hessian_vector_product`], with `v` and the product flattened over all of `vars`
+///
+/// `v` is scaled to unit norm before the difference is taken, so that `eps` is the distance moved in parameter space
+/// whatever the length of `v`
+pub(crate) fn flat_hessian_vector_product<F: FnMut() -> Result<Tensor>>(
+    vars: &[&Var],
+    v: &Tensor,
+    loss_fn: F,
+    eps: f64,
+) -> Result<Tensor> {
+    let norm = dot(v, v)?.sqrt();
+    if norm == 0. {
+        return v.zeros_like();
+    }
+    let unit = unflatten(&(v / norm)?, vars)?;
+    let hv = hessian_vector_product(vars, &unit, loss_fn, eps)?
+        .iter()
+        .map(Tensor::flatten_all)
+        .collect::<Result<Vec<Tensor>>>()?;
+    Tensor::cat(&hv, 0)? * norm
+}
+
+/// Hutchinson estimate of the diagonal of the Hessian of the loss returned by `loss_fn`, averaged over `samples`
+/// random vectors, with Hessian-vector products by central differences of step `eps`
+///
+/// # Errors
+///
+/// Errors if `samples` is 0, or the loss or its gradient cannot be evaluated
+#[allow(clippy::cast_precision_loss)]
+pub fn hutchinson<F: FnMut() -> Result<Tensor>>(
+    vars: &[&Var],
+    mut loss_fn: F,
+    samples: usize,
+    eps: f64,
+) -> Result<Vec<Tensor>> {
+    if samples == 0 {
+        candle_core::bail!("the Hutchinson estimate needs at least one sample");
+    }
+    let mut estimate = vars
+        .iter()
+        .map(|var| var.zeros_like())
+        .collect::<Result<Vec<Tensor>>>()?;
+    for _ in 0..samples {
+        // Rademacher vectors, with entries of +1 or -1 with equal probability
+        let u = vars
+            .iter()
+            .map(|var| {
+                let uniform = Tensor::rand(0f32, 1f32, var.shape(), var.device())?;
+                (uniform.ge(0.5)?.to_dtype(var.dtype())? * 2.)? - 1.
+            })
+            .collect::<Result<Vec<Tensor>>>()?;
+        let hu = hessian_vector_product(vars, &u, &mut loss_fn, eps)?;
+        for ((estimate, u), hu) in estimate.iter_mut().zip(&u).zip(hu) {
+            *estimate = (&*estimate + u.mul(&hu)?)?;
+        }
+    }
+    estimate
+        .into_iter()
+        .map(|estimate| estimate / samples as f64)
+        .collect()
+}
+
+/// Gauss-Newton-Bartlett estimate of the diagonal of the Gauss-Newton matrix, as `batch_size` times the square of
+/// the gradient of the loss returned by `loss_fn`
+///
+/// The loss should be the mean over a batch of `batch_size` inputs of the loss on labels sampled from the model's
+/// predicted distribution for each input, rather than the true labels.
+///
+/// # Errors
+///
+/// Errors if the loss or its gradient cannot be evaluated
+#[allow(clippy::cast_precision_loss)]
+pub fn gauss_newton_bartlett<F: FnMut() -> Result<Tensor>>(
+    vars: &[&Var],
+    mut loss_fn: F,
+    batch_size: usize,
+) -> Result<Vec<Tensor>> {
+    loss_grads(vars, &mut loss_fn)?
+        .into_iter()
+        .map(|grad| grad.sqr()? * batch_size as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+
+    use super::*;
+
+    /// loss of 0.5 * (1 x0^2 + 4 x1^2) + 3 x0 x1 + 2 y^2, with y a scalar
+    fn quadratic(x: &Var, y: &Var) -> Result<Tensor, candle_core::Error> {
+        let coeffs = Tensor::new(&[0.5f64, 2.], &Device::Cpu)?;
+        let x_sq = x.as_tensor().sqr()?.mul(&coeffs)?.sum_all()?;
+        let cross = ((x.as_tensor().get(0)? * x.as_tensor().get(1)?)? * 3.)?;
+        let y_sq = (y.as_tensor().sqr()? * 2.)?;
+        x_sq + cross + y_sq
+    }
+
+    #[test]
+    fn hessian_vector_product_test() -> Result<()> {
+        let x = Var::new(&[1f64, -2.], &Device::Cpu)?;
+        let y = Var::new(3f64, &Device::Cpu)?;
+        let v = [
+            Tensor::new(&[1f64, 1.], &Device::Cpu)?,
+            Tensor::new(2f64, &Device::Cpu)?,
+        ];
+        let hv = hessian_vector_product(&[&x, &y], &v, || quadratic(&x, &y), 1e-3)?;
+        // the Hessian of x is [[1, 3], [3, 4]] and of y is 4
+        let hv_x = hv[0].to_vec1::<f64>()?;
+        assert_approx_eq!(hv_x[0], 4.);
+        assert_approx_eq!(hv_x[1], 7.);
+        assert_approx_eq!(hv[1].to_scalar::<f64>()?, 8.);
+        // the variables are restored
+        assert_eq!(x.to_vec1::<f64>()?, [1., -2.]);
+        assert_approx_eq!(y.to_scalar::<f64>()?, 3.);
+        assert!(hessian_vector_product(&[&x, &y], &v[..1], || quadratic(&x, &y), 1e-3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hutchinson_test() -> Result<()> {
+        let x = Var::new(&[1f64, -2.], &Device::Cpu)?;
+        let y = Var::new(3f64, &Device::Cpu)?;
+        // each sample is the diagonal plus the off diagonal term 3 times +-1, so many samples average it away
+        let estimate = hutchinson(&[&x, &y], || quadratic(&x, &y), 2000, 1e-3)?;
+        let diag = estimate[0].to_vec1::<f64>()?;
+        assert_approx_eq!(diag[0], 1., 0.3);
+        assert_approx_eq!(diag[1], 4., 0.3);
+        // a scalar has no off diagonal terms, so every sample is exact
+        assert_approx_eq!(estimate[1].to_scalar::<f64>()?, 4.);
+        assert!(hutchinson(&[&x, &y], || quadratic(&x, &y), 0, 1e-3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn gauss_newton_bartlett_test() -> Result<()> {
+        let x = Var::new(&[1f64, -2.], &Device::Cpu)?;
+        let y = Var::new(3f64, &Device::Cpu)?;
+        let estimate = gauss_newton_bartlett(&[&x, &y], || quadratic(&x, &y), 4)?;
+        // the gradient of x is [1 - 6, -8 + 3] and of y is 12
+        assert_eq!(estimate[0].to_vec1::<f64>()?, [100., 100.]);
+        assert_approx_eq!(estimate[1].to_scalar::<f64>()?, 576.);
+        Ok(())
+    }
+}
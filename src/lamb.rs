@@ -0,0 +1,327 @@
+/*!
+LAMB optimiser
+
+Described in [Large Batch Optimization for Deep Learning: Training BERT in 76 minutes](https://arxiv.org/abs/1904.00962)
+
+LAMB takes Adam style moments, adds decoupled weight decay to the resulting update, and then rescales the update of
+each variable (layer) by a trust ratio so its size is proportional to the size of the variable.
+This lets the learning rate be scaled up for large batch training.
+
+Pseudocode:
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\beta_1, \\beta_2
+    \\text{ (betas)},\\theta_0 \\text{ (params)},f(\\theta) \\text{ (objective)}, \\: \\lambda \\text{ (weight decay)}  \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+                v_0\\leftarrow 0 \\text{ (second moment)}                                      \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}v_t           \\leftarrow   \\beta_2 v_{t-1} + (1-\\beta_2) g^2_t          \\\\
+    &\\hspace{5mm}\\widehat{m_t} \\leftarrow   m_t/\\big(1-\\beta_1^t \\big)                   \\\\
+    &\\hspace{5mm}\\widehat{v_t} \\leftarrow   v_t/\\big(1-\\beta_2^t \\big)                   \\\\
+    &\\hspace{5mm}r_t \\leftarrow \\widehat{m_t}/\\big(\\sqrt{\\widehat{v_t}} + \\epsilon \\big) + \\lambda \\theta_{t-1} \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\frac{||\\theta_{t-1}||_2}{||r_t||_2} r_t     \\\\
+    &\\rule{110mm}{0.4pt}                                                                 \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+The trust ratio $||\\theta_{t-1}||_2/||r_t||_2$ is taken to be 1 if either norm is zero, so variables initialised to zero
+still move. However a small variable only takes small steps, so as in BERT variables such as biases and layer norm
+parameters can be excluded from both weight decay and the trust ratio (taking plain Adam steps) with
+[`Lamb::new_with_exclusions`].
+*/
+
+use std::collections::HashSet;
+
+use candle_core::{Result, TensorId, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, trust_ratio, warn_unused_config, zero_decay, CurrentHyperparams, Decay,
+    HyperSnapshot, OptimParams, OptimVars, UnusedConfig,
+};
+
+/// LAMB optimiser
+///
+/// Described in [Large Batch Optimization for Deep Learning: Training BERT in 76 minutes](https://arxiv.org/abs/1904.00962)
+#[derive(Debug)]
+pub struct Lamb {
+    vars: Vec<VarLamb>,
+    params: ParamsLamb,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarLamb {
+    theta: Var,
+    m: Var,
+    v: Var,
+    /// whether weight decay and the trust ratio are applied to this variable
+    adapt: bool,
+    /// the trust ratio used on the last step
+    trust_ratio: Option<f64>,
+}
+
+/// Parameters for the LAMB optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsLamb {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of second moment
+    pub beta_2: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Decoupled weight decay, added to the update before the trust ratio is computed
+    pub weight_decay: Option<f64>,
+}
+
+impl Default for ParamsLamb {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.9,
+            beta_2: 0.999,
+            eps: 1e-6,
+            weight_decay: None,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsLamb {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay).into_iter().collect()
+    }
+}
+
+impl Optimizer for Lamb {
+    type Config = ParamsLamb;
+
+    fn new(vars: Vec<Var>, params: ParamsLamb) -> Result<Self> {
+        Self::new_with_exclusions(vars, params, &[])
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let params = &self.params;
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            let m = &var.m;
+            let v = &var.v;
+            if let Some(grad) = grads.get(theta) {
+                let m_next = ((params.beta_1 * m.as_tensor())? + ((1. - params.beta_1) * grad)?)?;
+                let v_next =
+                    ((params.beta_2 * v.as_tensor())? + ((1. - params.beta_2) * grad.sqr()?)?)?;
+                let m_hat = (&m_next / bias_correction(params.beta_1, self.t))?;
+                let v_hat = (&v_next / bias_correction(params.beta_2, self.t))?;
+                let mut update = m_hat.div(&(v_hat.sqrt()? + params.eps)?)?;
+                let ratio = if var.adapt {
+                    if let Some(decay) = params.weight_decay {
+                        update = (update + (decay * theta.as_tensor())?)?;
+                    }
+                    trust_ratio(theta, &update)?
+                } else {
+                    1.
+                };
+                theta.set(&theta.sub(&(update * (params.lr * ratio))?)?)?;
+                m.set(&m_next)?;
+                v.set(&v_next)?;
+                var.trust_ratio = Some(ratio);
+            }
+        }
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Lamb {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Lamb {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay.map(Decay::DecoupledWeightDecay),
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Lamb {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Lamb {
+    /// Create an optimiser that does not apply weight decay or the trust ratio to the variables in `excluded`,
+    /// such as biases and layer norm parameters
+    ///
+    /// # Errors
+    ///
+    /// Errors if a variable in `excluded` is not being optimised
+    pub fn new_with_exclusions(
+        vars: Vec<Var>,
+        params: ParamsLamb,
+        excluded: &[&Var],
+    ) -> Result<Self> {
+        warn_unused_config(&params);
+        let mut excluded: HashSet<TensorId> = excluded.iter().map(|var| var.id()).collect();
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let adapt = !excluded.remove(&var.id());
+                let m = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                let v = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                Ok(VarLamb {
+                    theta: var,
+                    m,
+                    v,
+                    adapt,
+                    trust_ratio: None,
+                })
+            })
+            .collect::<Result<Vec<VarLamb>>>()?;
+        if let Some(id) = excluded.iter().next() {
+            candle_core::bail!("excluded variable {id:?} is not being optimised");
+        }
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+        })
+    }
+
+    /// the trust ratio used for each variable on the last step, in the same order as the vars
+    ///
+    /// this is `None` for variables that have not yet been stepped
+    #[must_use]
+    pub fn trust_ratios(&self) -> Vec<Option<f64>> {
+        self.vars.iter().map(|v| v.trust_ratio).collect()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{DType, Device, Tensor, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsLamb {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Lamb::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsLamb {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Lamb::new(vec![w], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsLamb {
+            lr: 0.002,
+            weight_decay: Some(0.01),
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn trust_ratio_test() -> Result<()> {
+        let params = ParamsLamb {
+            lr: 0.1,
+            eps: 0.,
+            ..Default::default()
+        };
+        // on the first step the Adam update is the sign of the gradient, with norm sqrt(2)
+        let w = Var::new(&[3f64, 4.], &Device::Cpu)?;
+        let b = Var::new(&[0f64], &Device::Cpu)?;
+        let mut optim = Lamb::new(vec![w.clone(), b.clone()], params)?;
+        assert_eq!(optim.trust_ratios(), [None, None]);
+        let loss = (w.as_tensor().sum_all()? - (b.as_tensor() * 2.)?.sum_all()?)?;
+        optim.backward_step(&loss)?;
+        let ratio = 5. / 2_f64.sqrt();
+        let ratios = optim.trust_ratios();
+        assert_approx_eq!(ratios[0].unwrap_or_default(), ratio);
+        // the bias is zero so its trust ratio falls back to 1
+        assert_eq!(ratios[1], Some(1.));
+        let w = w.to_vec1::<f64>()?;
+        assert_approx_eq!(w[0], 3. - 0.1 * ratio);
+        assert_approx_eq!(w[1], 4. - 0.1 * ratio);
+        assert_approx_eq!(b.to_vec1::<f64>()?[0], 0.1);
+        Ok(())
+    }
+
+    #[test]
+    fn exclusion_test() -> Result<()> {
+        let params = ParamsLamb {
+            lr: 0.1,
+            weight_decay: Some(0.5),
+            ..Default::default()
+        };
+        // with no gradient signal the update is the weight decay alone, so only the decayed variable moves
+        let w = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let b = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let mut optim =
+            Lamb::new_with_exclusions(vec![w.clone(), b.clone()], params.clone(), &[&b])?;
+        let zeros = Tensor::zeros(2, DType::F64, &Device::Cpu)?;
+        let loss = (w.as_tensor() + b.as_tensor())?.mul(&zeros)?.sum_all()?;
+        optim.backward_step(&loss)?;
+        // the trust ratio rescales the decay to a step of lr times the norm of the variable
+        for w in w.to_vec1::<f64>()? {
+            assert_approx_eq!(w, 0.9);
+        }
+        assert_eq!(b.to_vec1::<f64>()?, [1., 1.]);
+        assert_eq!(optim.trust_ratios()[1], Some(1.));
+        let other = Var::new(&[1f64], &Device::Cpu)?;
+        assert!(Lamb::new_with_exclusions(vec![w], params, &[&other]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+/*!
+LARS optimiser
+
+Described in [Large Batch Training of Convolutional Networks](https://arxiv.org/abs/1708.03888)
+
+LARS (layer-wise adaptive rate scaling) is SGD with momentum where the learning rate of each variable (layer) is scaled
+by a trust ratio, so the size of its step is proportional to the size of the variable. This lets the learning rate be
+scaled up for large batch training.
+
+Pseudocode:
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\eta \\text{ (trust coefficient)}, \\: \\mu \\text{ (momentum)},
+    \\: \\lambda \\text{ (weight decay)}, \\: \\theta_0 \\text{ (params)},f(\\theta) \\text{ (objective)}  \\\\
+    &\\textbf{initialize} :  b_0 \\leftarrow 0 \\text{ (momentum buffer)}                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}u_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1}) + \\lambda \\theta_{t-1}   \\\\
+    &\\hspace{5mm}b_t \\leftarrow \\mu b_{t-1} + \\gamma \\eta \\frac{||\\theta_{t-1}||_2}{||u_t||_2} u_t      \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - b_t                                     \\\\
+    &\\rule{110mm}{0.4pt}                                                                 \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+The trust ratio is shared with [LAMB](crate::lamb), and is taken to be 1 if either norm is zero. As for LAMB,
+variables such as biases and batch norm parameters can be excluded from both weight decay and the trust ratio
+(taking plain SGD with momentum steps) with [`Lars::new_with_exclusions`].
+*/
+
+use std::collections::HashSet;
+
+use candle_core::{Result, TensorId, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    trust_ratio, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot,
+    Momentum, OptimParams, OptimVars, UnusedConfig,
+};
+
+/// LARS optimiser
+///
+/// Described in [Large Batch Training of Convolutional Networks](https://arxiv.org/abs/1708.03888)
+#[derive(Debug)]
+pub struct Lars {
+    vars: Vec<VarLars>,
+    params: ParamsLars,
+}
+
+#[derive(Debug)]
+struct VarLars {
+    theta: Var,
+    /// momentum buffer
+    b: Var,
+    /// whether weight decay and the trust ratio are applied to this variable
+    adapt: bool,
+    /// the trust ratio used on the last step
+    trust_ratio: Option<f64>,
+}
+
+/// Parameters for the LARS optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsLars {
+    /// Learning rate
+    pub lr: f64,
+    /// Momentum
+    pub momentum: Option<f64>,
+    /// Trust coefficient $\\eta$ scaling the trust ratio
+    pub trust_coefficient: f64,
+    /// Weight decay, added to the gradient before the trust ratio is computed
+    pub weight_decay: Option<f64>,
+}
+
+impl Default for ParamsLars {
+    fn default() -> Self {
+        Self {
+            lr: 0.1,
+            momentum: Some(0.9),
+            trust_coefficient: 0.001,
+            weight_decay: None,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsLars {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused: Vec<String> = zero_decay(self.weight_decay).into_iter().collect();
+        if self.momentum == Some(0.) {
+            unused.push("momentum has no effect with a coefficient of zero".to_string());
+        }
+        unused
+    }
+}
+
+impl Optimizer for Lars {
+    type Config = ParamsLars;
+
+    fn new(vars: Vec<Var>, params: ParamsLars) -> Result<Self> {
+        Self::new_with_exclusions(vars, params, &[])
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let params = &self.params;
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let mut update = grad.clone();
+                let ratio = if var.adapt {
+                    if let Some(decay) = params.weight_decay {
+                        update = (update + (decay * theta.as_tensor())?)?;
+                    }
+                    params.trust_coefficient * trust_ratio(theta, &update)?
+                } else {
+                    1.
+                };
+                let mut step = (update * (params.lr * ratio))?;
+                if let Some(mu) = params.momentum {
+                    step = ((mu * var.b.as_tensor())? + step)?;
+                    var.b.set(&step)?;
+                }
+                theta.set(&theta.sub(&step)?)?;
+                var.trust_ratio = Some(ratio);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Lars {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Lars {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: None,
+            weight_decay: self.params.weight_decay.map(Decay::WeightDecay),
+            momentum: self.params.momentum.map(Momentum::Classical),
+        }
+    }
+}
+
+impl OptimVars for Lars {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Lars {
+    /// Create an optimiser that does not apply weight decay or the trust ratio to the variables in `excluded`,
+    /// such as biases and batch norm parameters
+    ///
+    /// # Errors
+    ///
+    /// Errors if a variable in `excluded` is not being optimised
+    pub fn new_with_exclusions(
+        vars: Vec<Var>,
+        params: ParamsLars,
+        excluded: &[&Var],
+    ) -> Result<Self> {
+        warn_unused_config(&params);
+        let mut excluded: HashSet<TensorId> = excluded.iter().map(|var| var.id()).collect();
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let adapt = !excluded.remove(&var.id());
+                let b = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                Ok(VarLars {
+                    theta: var,
+                    b,
+                    adapt,
+                    trust_ratio: None,
+                })
+            })
+            .collect::<Result<Vec<VarLars>>>()?;
+        if let Some(id) = excluded.iter().next() {
+            candle_core::bail!("excluded variable {id:?} is not being optimised");
+        }
+        Ok(Self { vars, params })
+    }
+
+    /// the trust ratio, including the trust coefficient, used for each variable on the last step,
+    /// in the same order as the vars
+    ///
+    /// this is `None` for variables that have not yet been stepped
+    #[must_use]
+    pub fn trust_ratios(&self) -> Vec<Option<f64>> {
+        self.vars.iter().map(|v| v.trust_ratio).collect()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Tensor, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsLars {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Lars::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn trust_ratio_test() -> Result<()> {
+        let params = ParamsLars {
+            lr: 1.,
+            momentum: Some(0.5),
+            trust_coefficient: 0.1,
+            weight_decay: None,
+        };
+        // the gradient [6, 8] has norm 10 and the weight [3, 4] has norm 5
+        let w = Var::new(&[3f64, 4.], &Device::Cpu)?;
+        let b = Var::new(&[1f64], &Device::Cpu)?;
+        let mut optim = Lars::new_with_exclusions(vec![w.clone(), b.clone()], params, &[&b])?;
+        assert_eq!(optim.trust_ratios(), [None, None]);
+        let coeffs = Tensor::new(&[6f64, 8.], &Device::Cpu)?;
+        let loss = (w.as_tensor().mul(&coeffs)?.sum_all()? + b.as_tensor().sum_all()?)?;
+        optim.backward_step(&loss)?;
+        // the step is lr * 0.1 * 5 / 10 * g = 0.05 g, while the excluded bias takes a plain step
+        assert_approx_eq!(optim.trust_ratios()[0].unwrap_or_default(), 0.05);
+        assert_eq!(optim.trust_ratios()[1], Some(1.));
+        let stepped = w.to_vec1::<f64>()?;
+        assert_approx_eq!(stepped[0], 2.7);
+        assert_approx_eq!(stepped[1], 3.6);
+        assert_approx_eq!(b.to_vec1::<f64>()?[0], 0.);
+        // the next step adds half the last one through momentum, with the trust ratio of the new weight of norm 4.5
+        optim.backward_step(&loss)?;
+        let stepped = w.to_vec1::<f64>()?;
+        assert_approx_eq!(stepped[0], 2.7 - 0.15 - 0.045 * 6.);
+        assert_approx_eq!(stepped[1], 3.6 - 0.2 - 0.045 * 8.);
+        Ok(())
+    }
+}
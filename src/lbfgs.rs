@@ -30,17 +30,43 @@ $$
 
 //<https://sagecal.sourceforge.net/pytorch/index.html> possible extensions
 
-use crate::{LossOptimizer, Model, ModelOutcome};
+use crate::{
+    flatten_grads, flatten_vars, missing_vars, take_state, unflatten, varmap_vars,
+    warn_unused_config, LossOptimizer, Model, ModelOutcome, OptimState, OptimVars, UnusedConfig,
+};
 use candle_core::Result as CResult;
-use candle_core::{Tensor, Var};
-use log::info;
-use std::collections::VecDeque;
+use candle_core::{DType, Device, Tensor, TensorId, Var};
+use candle_nn::VarMap;
+use log::{info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
 // use candle_nn::optim::Optimizer;
 
+mod backtracking;
 mod strong_wolfe;
 
+pub(crate) use backtracking::Backtracking;
+pub(crate) use strong_wolfe::{directional_evaluate, l2_reg, StrongWolfe};
+
+/// Variables and a loss that the line searches can evaluate along a search direction
+pub(crate) trait LineSearchTarget {
+    /// the loss, flat gradient and L2 regularisation after a step of `mag` along `direction`, leaving the variables
+    /// unchanged
+    fn directional_evaluate(
+        &mut self,
+        mag: f64,
+        direction: &Tensor,
+    ) -> CResult<(Tensor, Tensor, f64)>;
+    /// the L2 regularisation of the current variables
+    fn l2_reg(&self) -> CResult<f64>;
+}
+
+/// number of past losses retained for estimating the convergence rate
+const LOSS_HISTORY: usize = 10;
+
+/// relative tolerance for the curvature condition on update pairs added to the history
+const CURVATURE_EPS: f64 = 1e-10;
+
 /// Line search method
-/// Only Strong Wolfe is currently implemented
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 #[non_exhaustive]
 pub enum LineSearch {
@@ -57,6 +83,40 @@ pub enum LineSearch {
     ///  Strong Curvature Condition:
     /// $$ |\\bm{d}^{T} \\nabla f(x + t \\bm{d})| \\leq c_{2} |\\bm{d}^{T} \\nabla f(x)| $$
     StrongWolfe(f64, f64, f64),
+    /// backtracking line search, starting from the full step and shrinking it by a factor of `rho` until the Armijo rule
+    /// $$ f(x + t \\bm{d}) \\leq f(x) + c_1 t \\bm{d}^T \\nabla f(x)  $$
+    /// is met. If it is not met within `max_steps` trial steps no step is taken, and the step is reported as
+    /// [`ModelOutcome::Truncated`]
+    ///
+    /// This only evaluates the loss and gradient once per trial step, so is often cheaper than the strong Wolfe line search.
+    /// Suggested values are 1e-4 for `c1` and 0.5 for `rho`, both of which must lie in $(0, 1)$
+    Backtracking {
+        /// coefficient for the Armijo rule
+        c1: f64,
+        /// factor the step is shrunk by after each failed trial
+        rho: f64,
+        /// maximum number of trial steps (each requiring a loss evaluation)
+        max_steps: usize,
+    },
+}
+
+impl LineSearch {
+    /// Check the coefficients of the line search
+    ///
+    /// # Errors
+    ///
+    /// Errors if the coefficients of a backtracking line search are not in $(0, 1)$
+    pub fn validate(&self) -> CResult<()> {
+        if let Self::Backtracking { c1, rho, .. } = *self {
+            if !(c1 > 0. && c1 < 1.) {
+                candle_core::bail!("the Armijo coefficient c1 must be in (0, 1), got {c1}");
+            }
+            if !(rho > 0. && rho < 1.) {
+                candle_core::bail!("the backtracking factor rho must be in (0, 1), got {rho}");
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Conditions for terminsation based on gradient
@@ -69,6 +129,37 @@ pub enum GradConv {
     RMSForce(f64),
 }
 
+impl GradConv {
+    /// the tolerance of the criterion: a tolerance of zero (or below) can never be met, so disables it
+    #[must_use]
+    pub fn tolerance(&self) -> f64 {
+        match self {
+            Self::MinForce(tol) | Self::RMSForce(tol) => *tol,
+        }
+    }
+
+    /// whether the gradient meets the criterion, or `None` if it is disabled
+    pub(crate) fn converged(&self, grad: &Tensor) -> CResult<Option<bool>> {
+        if self.tolerance() <= 0. {
+            return Ok(None);
+        }
+        let size = match self {
+            Self::MinForce(_) => grad
+                .abs()?
+                .max(0)?
+                .to_dtype(candle_core::DType::F64)?
+                .to_scalar::<f64>()?,
+            Self::RMSForce(_) => grad
+                .sqr()?
+                .mean_all()?
+                .to_dtype(candle_core::DType::F64)?
+                .to_scalar::<f64>()?
+                .sqrt(),
+        };
+        Ok(Some(size < self.tolerance()))
+    }
+}
+
 /// Conditions for termination based on step size
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 #[non_exhaustive]
@@ -79,6 +170,58 @@ pub enum StepConv {
     RMSStep(f64),
 }
 
+impl StepConv {
+    /// the tolerance of the criterion: a tolerance of zero (or below) can never be met, so disables it
+    #[must_use]
+    pub fn tolerance(&self) -> f64 {
+        match self {
+            Self::MinStep(tol) | Self::RMSStep(tol) => *tol,
+        }
+    }
+
+    /// whether the step meets the criterion, or `None` if it is disabled
+    pub(crate) fn converged(&self, step: &Tensor) -> CResult<Option<bool>> {
+        if self.tolerance() <= 0. {
+            return Ok(None);
+        }
+        let size = match self {
+            Self::MinStep(_) => step
+                .abs()?
+                .max(0)?
+                .to_dtype(candle_core::DType::F64)?
+                .to_scalar::<f64>()?,
+            Self::RMSStep(_) => step
+                .sqr()?
+                .mean_all()?
+                .to_dtype(candle_core::DType::F64)?
+                .to_scalar::<f64>()?
+                .sqrt(),
+        };
+        Ok(Some(size < self.tolerance()))
+    }
+}
+
+/// How the gradient and step convergence criteria are combined
+///
+/// Only criteria that are enabled (have a positive tolerance) are considered, so with a single criterion the policy has no effect
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd)]
+pub enum ConvergencePolicy {
+    /// converged when any criterion is met: the gradient is checked before the step, and the step after it is taken
+    Any,
+    /// converged only when all criteria are met, with the gradient at the start of a step and the step itself both converged
+    All,
+}
+
+/// Heuristic for the length of the first step, when there is no history to scale the direction by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd)]
+pub enum InitialStep {
+    /// step by `lr` along the negative gradient
+    Unit,
+    /// step by $\min(1, 1 / ||g||_{1}) \gamma$ along the negative gradient, as in PyTorch,
+    /// so a steep initial gradient does not give a huge first step
+    ScaledByGradNorm,
+}
+
 /// Parameters for LBFGS optimiser
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct ParamsLBFGS {
@@ -93,8 +236,30 @@ pub struct ParamsLBFGS {
     pub grad_conv: GradConv,
     /// convergence criteria for step size
     pub step_conv: StepConv,
+    /// whether any or all of the convergence criteria must be met
+    pub convergence_policy: ConvergencePolicy,
+    /// heuristic for the length of the first step
+    pub initial_step: InitialStep,
     /// weight decay
     pub weight_decay: Option<f64>,
+    /// log the history size, gamma and number of line search evaluations of each step at the info level
+    pub verbose: bool,
+    /// maximum number of loss evaluations made by the line search in each step
+    ///
+    /// If the line search uses its full budget the step is still taken, but reported as [`ModelOutcome::Truncated`].
+    /// At least one evaluation is always made; this has no effect when no line search is used.
+    pub max_eval: Option<usize>,
+    /// Record the flattened values of all the variables after every step, see [`Lbfgs::trajectory`]
+    ///
+    /// This keeps a copy of every parameter for every step, so is only suitable for small problems
+    /// (for example to plot the optimisation path of a 2D test function)
+    pub record_trajectory: bool,
+    /// Maximum norm of a step taken without a line search
+    ///
+    /// If the step (the search direction scaled by `lr`) is longer than this it is rescaled to this length,
+    /// as a cheap trust region to stop the first steps of a stiff problem from overshooting.
+    /// This has no effect when a line search is used, as that already controls the step length.
+    pub max_step_norm: Option<f64>,
 }
 
 impl Default for ParamsLBFGS {
@@ -102,16 +267,62 @@ impl Default for ParamsLBFGS {
         Self {
             lr: 1.,
             // max_iter: 20,
-            // max_eval: None,
             history_size: 100,
             line_search: None,
             grad_conv: GradConv::MinForce(1e-7),
             step_conv: StepConv::MinStep(1e-9),
+            convergence_policy: ConvergencePolicy::Any,
+            initial_step: InitialStep::ScaledByGradNorm,
             weight_decay: None,
+            verbose: false,
+            max_eval: None,
+            record_trajectory: false,
+            max_step_norm: None,
         }
     }
 }
 
+impl ParamsLBFGS {
+    /// Default parameters with the largest `history_size` whose stored update pairs fit in `bytes`
+    ///
+    /// Each entry in the history holds two vectors ($s_k$ and $y_k$) of `n_params` elements of `dtype`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if there are no parameters, as then any budget would allow an unbounded history, or if not even a
+    /// single pair fits in the budget
+    pub fn with_memory_budget(bytes: usize, n_params: usize, dtype: DType) -> CResult<Self> {
+        let pair_bytes = n_params.saturating_mul(2 * dtype.size_in_bytes());
+        if pair_bytes == 0 {
+            candle_core::bail!("cannot size the history from a memory budget with no parameters");
+        }
+        let history_size = bytes / pair_bytes;
+        if history_size == 0 {
+            candle_core::bail!(
+                "memory budget of {bytes} bytes cannot fit a single history pair of {pair_bytes} bytes"
+            );
+        }
+        Ok(Self {
+            history_size,
+            ..Default::default()
+        })
+    }
+}
+
+impl UnusedConfig for ParamsLBFGS {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused = Vec::new();
+        if self.line_search.is_none() {
+            if self.max_eval.is_some() {
+                unused.push("max_eval has no effect without a line search".to_string());
+            }
+        } else if self.max_step_norm.is_some() {
+            unused.push("max_step_norm has no effect with a line search".to_string());
+        }
+        unused
+    }
+}
+
 /// LBFGS optimiser
 ///
 /// A pseudo second order optimiser based on the BFGS method.
@@ -129,61 +340,79 @@ pub struct Lbfgs<M: Model> {
     last_step: Option<Var>,
     params: ParamsLBFGS,
     first: bool,
+    loss_hist: VecDeque<f64>,
+    trajectory: Vec<Tensor>,
 }
 
 impl<M: Model> LossOptimizer<M> for Lbfgs<M> {
     type Config = ParamsLBFGS;
 
     fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
-        let hist_size = params.history_size;
+        if let Some(line_search) = params.line_search {
+            line_search.validate()?;
+        }
+        warn_unused_config(&params);
         Ok(Lbfgs {
             vars: vs,
             model,
-            s_hist: VecDeque::with_capacity(hist_size),
+            s_hist: VecDeque::new(),
             last_step: None,
             last_grad: None,
             next_grad: None,
             params,
             first: true,
+            loss_hist: VecDeque::with_capacity(LOSS_HISTORY),
+            trajectory: Vec::new(),
         })
     }
 
-    #[allow(clippy::too_many_lines)]
     fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let outcome = self.lbfgs_step(loss)?;
+        if self.params.record_trajectory {
+            self.trajectory.push(flatten_vars(&self.vars)?.copy()?);
+        }
+        Ok(outcome)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+
+    #[must_use]
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> Lbfgs<M> {
+    #[allow(clippy::too_many_lines)]
+    fn lbfgs_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
         let mut evals = 1;
 
+        if self.loss_hist.len() == LOSS_HISTORY {
+            self.loss_hist.pop_front();
+        }
+        self.loss_hist
+            .push_back(loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()?);
+
         let grad = if let Some(this_grad) = &self.next_grad {
             this_grad.as_tensor().copy()?
         } else {
             flat_grads(&self.vars, loss, self.params.weight_decay)?
         };
 
-        match self.params.grad_conv {
-            GradConv::MinForce(tol) => {
-                if grad
-                    .abs()?
-                    .max(0)?
-                    .to_dtype(candle_core::DType::F64)?
-                    .to_scalar::<f64>()?
-                    < tol
-                {
-                    info!("grad converged");
-                    return Ok(ModelOutcome::Converged(loss.clone(), evals));
-                }
-            }
-            GradConv::RMSForce(tol) => {
-                if grad
-                    .sqr()?
-                    .mean_all()?
-                    .to_dtype(candle_core::DType::F64)?
-                    .to_scalar::<f64>()?
-                    .sqrt()
-                    < tol
-                {
-                    info!("grad converged");
-                    return Ok(ModelOutcome::Converged(loss.clone(), evals));
-                }
-            }
+        let grad_converged = self.params.grad_conv.converged(&grad)?;
+        // with no step criterion there is nothing else to wait for
+        let step_enabled = self.params.step_conv.tolerance() > 0.;
+        if grad_converged == Some(true)
+            && (self.params.convergence_policy == ConvergencePolicy::Any || !step_enabled)
+        {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
         }
 
         let mut yk = None;
@@ -199,12 +428,19 @@ impl<M: Model> LossOptimizer<M> for Lbfgs<M> {
 
         let hist_size = self.s_hist.len();
 
-        if hist_size == self.params.history_size {
-            self.s_hist.pop_front();
-        }
         if let Some(yk) = yk {
             if let Some(step) = &self.last_step {
-                self.s_hist.push_back((step.as_tensor().clone(), yk));
+                // the last step is updated in place, so must be copied into the history
+                let sk = step.as_tensor().copy()?;
+                // the two loop recursion requires y^T s > 0, which is not guaranteed without a line search
+                if satisfies_curvature(&sk, &yk)? {
+                    if hist_size == self.params.history_size {
+                        self.s_hist.pop_front();
+                    }
+                    self.s_hist.push_back((sk, yk));
+                } else {
+                    info!("curvature condition not met: skipping history update");
+                }
             }
         }
 
@@ -230,9 +466,12 @@ impl<M: Model> LossOptimizer<M> for Lbfgs<M> {
         } else {
             1.
         };
+        if self.params.verbose {
+            info!("hist_size {}, gamma {gamma}", self.s_hist.len());
+        }
 
-        let mut rhos = VecDeque::with_capacity(hist_size);
-        let mut alphas = VecDeque::with_capacity(hist_size);
+        let mut rhos = VecDeque::with_capacity(self.s_hist.len());
+        let mut alphas = VecDeque::with_capacity(self.s_hist.len());
         for (s, y) in self.s_hist.iter().rev() {
             let rho = (y
                 .unsqueeze(0)?
@@ -286,8 +525,7 @@ impl<M: Model> LossOptimizer<M> for Lbfgs<M> {
             .squeeze(0)?
             .to_scalar::<f64>()?;
 
-        let mut lr = if self.first {
-            self.first = false;
+        let mut lr = if self.first && self.params.initial_step == InitialStep::ScaledByGradNorm {
             -(1_f64.min(
                 1. / grad
                     .abs()?
@@ -298,65 +536,73 @@ impl<M: Model> LossOptimizer<M> for Lbfgs<M> {
         } else {
             -self.params.lr
         };
+        self.first = false;
 
-        if let Some(ls) = &self.params.line_search {
-            match ls {
+        if let Some(ls) = self.params.line_search {
+            let budget = self.params.max_eval;
+            let (loss, grad, t, steps) = match ls {
                 LineSearch::StrongWolfe(c1, c2, tol) => {
-                    let (loss, grad, t, steps) =
-                        self.strong_wolfe(lr, &q, loss, &grad, dd, *c1, *c2, *tol, 25)?;
-                    if let Some(next_grad) = &self.next_grad {
-                        next_grad.set(&grad)?;
-                    } else {
-                        self.next_grad = Some(Var::from_tensor(&grad)?);
-                    }
+                    // the strong wolfe search evaluates once, then up to once per iteration
+                    let max_ls = budget.map_or(25, |max_eval| max_eval.saturating_sub(1).min(25));
+                    self.strong_wolfe(lr, &q, loss, &grad, dd, c1, c2, tol, max_ls)?
+                }
+                LineSearch::Backtracking { c1, rho, max_steps } => {
+                    let max_steps = budget.map_or(max_steps, |max_eval| max_eval.min(max_steps));
+                    self.backtracking(lr, &q, loss, &grad, dd, c1, rho, max_steps)?
+                }
+            };
+            // a step size of zero means the line search found no acceptable step
+            let stepped: fn(Tensor, usize) -> ModelOutcome = if t == 0. {
+                ModelOutcome::Truncated
+            } else if budget.is_some_and(|max_eval| steps >= max_eval) {
+                info!("line search truncated by max_eval");
+                ModelOutcome::Truncated
+            } else {
+                ModelOutcome::Stepped
+            };
+            if let Some(next_grad) = &self.next_grad {
+                next_grad.set(&grad)?;
+            } else {
+                self.next_grad = Some(Var::from_tensor(&grad)?);
+            }
 
-                    evals += steps;
-                    lr = t;
-                    q.set(&(q.as_tensor() * lr)?)?;
+            if self.params.verbose {
+                info!("line search took {steps} evaluations, step size {t}");
+            }
+            evals += steps;
+            lr = t;
+            q.set(&(q.as_tensor() * lr)?)?;
 
-                    if let Some(step) = &self.last_step {
-                        step.set(&q)?;
-                    } else {
-                        self.last_step = Some(Var::from_tensor(&q)?);
-                    }
+            if let Some(step) = &self.last_step {
+                step.set(&q)?;
+            } else {
+                self.last_step = Some(Var::from_tensor(&q)?);
+            }
 
-                    match self.params.step_conv {
-                        StepConv::MinStep(tol) => {
-                            if q.abs()?
-                                .max(0)?
-                                .to_dtype(candle_core::DType::F64)?
-                                .to_scalar::<f64>()?
-                                < tol
-                            {
-                                add_grad(&mut self.vars, q.as_tensor())?;
-                                info!("step converged");
-                                Ok(ModelOutcome::Converged(loss, evals))
-                            } else {
-                                add_grad(&mut self.vars, q.as_tensor())?;
-                                Ok(ModelOutcome::Stepped(loss, evals))
-                            }
-                        }
-                        StepConv::RMSStep(tol) => {
-                            if q.sqr()?
-                                .mean_all()?
-                                .to_dtype(candle_core::DType::F64)?
-                                .to_scalar::<f64>()?
-                                .sqrt()
-                                < tol
-                            {
-                                add_grad(&mut self.vars, q.as_tensor())?;
-                                info!("step converged");
-                                Ok(ModelOutcome::Converged(loss, evals))
-                            } else {
-                                add_grad(&mut self.vars, q.as_tensor())?;
-                                Ok(ModelOutcome::Stepped(loss, evals))
-                            }
-                        }
-                    }
-                }
+            let converged = t != 0. && self.step_converged(q.as_tensor(), grad_converged)?;
+            add_grad(&mut self.vars, q.as_tensor())?;
+            if converged {
+                info!("step converged");
+                Ok(ModelOutcome::Converged(loss, evals))
+            } else {
+                Ok(stepped(loss, evals))
             }
         } else {
             q.set(&(q.as_tensor() * lr)?)?;
+            if let Some(max_norm) = self.params.max_step_norm {
+                let norm = q
+                    .sqr()?
+                    .sum_all()?
+                    .to_dtype(candle_core::DType::F64)?
+                    .to_scalar::<f64>()?
+                    .sqrt();
+                if norm > max_norm {
+                    if self.params.verbose {
+                        info!("step norm {norm} capped to {max_norm}");
+                    }
+                    q.set(&(q.as_tensor() * (max_norm / norm))?)?;
+                }
+            }
 
             if let Some(step) = &self.last_step {
                 step.set(&q)?;
@@ -364,105 +610,297 @@ impl<M: Model> LossOptimizer<M> for Lbfgs<M> {
                 self.last_step = Some(Var::from_tensor(&q)?);
             }
 
-            match self.params.step_conv {
-                StepConv::MinStep(tol) => {
-                    if q.abs()?
-                        .max(0)?
-                        .to_dtype(candle_core::DType::F64)?
-                        .to_scalar::<f64>()?
-                        < tol
-                    {
-                        add_grad(&mut self.vars, q.as_tensor())?;
-
-                        let next_loss = self.model.loss()?;
-                        evals += 1;
-                        info!("step converged");
-                        Ok(ModelOutcome::Converged(next_loss, evals))
-                    } else {
-                        add_grad(&mut self.vars, q.as_tensor())?;
-
-                        let next_loss = self.model.loss()?;
-                        evals += 1;
-                        Ok(ModelOutcome::Stepped(next_loss, evals))
-                    }
-                }
-                StepConv::RMSStep(tol) => {
-                    if q.sqr()?
-                        .mean_all()?
-                        .to_dtype(candle_core::DType::F64)?
-                        .to_scalar::<f64>()?
-                        .sqrt()
-                        < tol
-                    {
-                        add_grad(&mut self.vars, q.as_tensor())?;
-
-                        let next_loss = self.model.loss()?;
-                        evals += 1;
-                        info!("step converged");
-                        Ok(ModelOutcome::Converged(next_loss, evals))
-                    } else {
-                        add_grad(&mut self.vars, q.as_tensor())?;
-
-                        let next_loss = self.model.loss()?;
-                        evals += 1;
-                        Ok(ModelOutcome::Stepped(next_loss, evals))
-                    }
-                }
+            let converged = self.step_converged(q.as_tensor(), grad_converged)?;
+            add_grad(&mut self.vars, q.as_tensor())?;
+
+            let next_loss = self.model.loss()?;
+            evals += 1;
+            if converged {
+                info!("step converged");
+                Ok(ModelOutcome::Converged(next_loss, evals))
+            } else {
+                Ok(ModelOutcome::Stepped(next_loss, evals))
             }
         }
     }
+}
 
-    fn learning_rate(&self) -> f64 {
-        self.params.lr
+impl<M: Model> OptimVars for Lbfgs<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
     }
+}
 
-    fn set_learning_rate(&mut self, lr: f64) {
-        self.params.lr = lr;
+impl<M: Model> OptimState for Lbfgs<M> {
+    fn state_dict(&self) -> CResult<HashMap<String, Tensor>> {
+        let mut state = HashMap::with_capacity(2 * self.s_hist.len() + 5);
+        state.insert(
+            "first".to_string(),
+            Tensor::new(u8::from(self.first), &Device::Cpu)?,
+        );
+        for (i, (s, y)) in self.s_hist.iter().enumerate() {
+            state.insert(format!("s_hist.{i}.s"), s.copy()?);
+            state.insert(format!("s_hist.{i}.y"), y.copy()?);
+        }
+        for (key, var) in [
+            ("last_grad", &self.last_grad),
+            ("next_grad", &self.next_grad),
+            ("last_step", &self.last_step),
+        ] {
+            if let Some(var) = var {
+                state.insert(key.to_string(), var.as_tensor().copy()?);
+            }
+        }
+        if !self.loss_hist.is_empty() {
+            let losses: Vec<f64> = self.loss_hist.iter().copied().collect();
+            state.insert(
+                "loss_hist".to_string(),
+                Tensor::new(losses.as_slice(), &Device::Cpu)?,
+            );
+        }
+        Ok(state)
+    }
+
+    fn load_state_dict(&mut self, mut state: HashMap<String, Tensor>) -> CResult<()> {
+        let n_params: usize = self.vars.iter().map(|v| v.elem_count()).sum();
+        let device = self
+            .vars
+            .first()
+            .map_or(Device::Cpu, |v| v.device().clone());
+        // all the stored vectors are flattened over every variable
+        let flat = |tensor: Tensor| -> CResult<Tensor> {
+            if tensor.dims() != [n_params] {
+                candle_core::bail!(
+                    "optimiser state has shape {:?}, but the optimiser has {n_params} parameters",
+                    tensor.dims()
+                );
+            }
+            tensor.to_device(&device)
+        };
+        let first = take_state(&mut state, "first")?
+            .to_dtype(DType::U8)?
+            .to_scalar::<u8>()?
+            != 0;
+        let mut s_hist = VecDeque::new();
+        let mut i = 0;
+        while let Some(s) = state.remove(&format!("s_hist.{i}.s")) {
+            let y = take_state(&mut state, &format!("s_hist.{i}.y"))?;
+            s_hist.push_back((flat(s)?, flat(y)?));
+            i += 1;
+        }
+        // keep only the most recent pairs if the history size is smaller than when saved
+        while s_hist.len() > self.params.history_size {
+            s_hist.pop_front();
+        }
+        let mut optional_var = |key: &str| -> CResult<Option<Var>> {
+            state
+                .remove(key)
+                .map(|tensor| Var::from_tensor(&flat(tensor)?))
+                .transpose()
+        };
+        let last_grad = optional_var("last_grad")?;
+        let next_grad = optional_var("next_grad")?;
+        let last_step = optional_var("last_step")?;
+        let loss_hist = match state.remove("loss_hist") {
+            Some(losses) => losses.to_dtype(DType::F64)?.to_vec1::<f64>()?.into(),
+            None => VecDeque::with_capacity(LOSS_HISTORY),
+        };
+        self.first = first;
+        self.s_hist = s_hist;
+        self.last_grad = last_grad;
+        self.next_grad = next_grad;
+        self.last_step = last_step;
+        self.loss_hist = loss_hist;
+        Ok(())
     }
+}
 
+impl<M: Model> Lbfgs<M> {
+    /// get a reference to the model being optimised
     #[must_use]
-    fn into_inner(self) -> Vec<Var> {
-        self.vars
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Return the model being optimised
+    ///
+    /// This shares its variables with the optimiser, so reflects the optimised parameters
+    #[must_use]
+    pub fn into_model(self) -> M {
+        self.model
+    }
+
+    /// Create an optimiser for all the variables held in `varmap`, so no trainable parameter of the model can be missed
+    ///
+    /// The variables are ordered by name. If the model lists its variables with [`Model::listed_vars`], a warning is logged
+    /// for each variable in the `VarMap` that is not listed, and if any listed variables are not in the `VarMap`
+    ///
+    /// # Errors
+    ///
+    /// Errors if the optimiser cannot be created
+    pub fn from_varmap_and_model(varmap: &VarMap, params: ParamsLBFGS, model: M) -> CResult<Self> {
+        let vars = varmap_vars(varmap);
+        if let Some(listed) = model.listed_vars() {
+            for name in missing_vars(varmap, &listed) {
+                warn!("variable {name} is in the VarMap but not listed by the model");
+            }
+            let held: HashSet<TensorId> = vars.iter().map(|v| v.id()).collect();
+            let extra = listed.iter().filter(|v| !held.contains(&v.id())).count();
+            if extra > 0 {
+                warn!("{extra} variables listed by the model are not in the VarMap and will not be optimised");
+            }
+        }
+        Self::new(vars, params, model)
+    }
+
+    /// Swap in a new model and its variables, clearing the history so the optimiser starts afresh
+    ///
+    /// The parameters (including `history_size`, which may have been sized for the number of parameters
+    /// with [`ParamsLBFGS::with_memory_budget`]) are retained, so the new variables must have the same total number of elements.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the total number of elements in `vars` differs from that of the current variables
+    pub fn set_model(&mut self, model: M, vars: Vec<Var>) -> CResult<()> {
+        let current: usize = self.vars.iter().map(|v| v.elem_count()).sum();
+        let new: usize = vars.iter().map(|v| v.elem_count()).sum();
+        if current != new {
+            candle_core::bail!(
+                "new variables have {new} elements, but the optimiser was set up for {current}"
+            );
+        }
+        self.model = model;
+        self.vars = vars;
+        self.reset();
+        self.loss_hist.clear();
+        self.trajectory.clear();
+        Ok(())
+    }
+
+    /// Clear the curvature history and cached gradients, for a warm restart that keeps the model and variables
+    ///
+    /// The next step is taken as if it were the first. The loss history used by
+    /// [`Lbfgs::estimated_iters_remaining`] and any recorded trajectory are kept.
+    pub fn reset(&mut self) {
+        self.s_hist.clear();
+        self.last_grad = None;
+        self.next_grad = None;
+        self.last_step = None;
+        self.first = true;
     }
+
+    /// The number of curvature pairs $(s_k, y_k)$ currently in the history
+    #[must_use]
+    pub fn history_len(&self) -> usize {
+        self.s_hist.len()
+    }
+
+    /// The flattened values of the variables after each step, if `record_trajectory` is set
+    ///
+    /// This is cleared by [`Lbfgs::set_model`]
+    #[must_use]
+    pub fn trajectory(&self) -> &[Tensor] {
+        &self.trajectory
+    }
+
+    /// Estimate the number of further iterations until the decrease in loss per iteration falls below `tol`
+    ///
+    /// This fits a geometric rate of decrease to the (up to 10) most recent losses passed to `backward_step`,
+    /// so is only a rough guide.
+    /// Returns `None` if there is insufficient history or the loss is not decreasing at a converging rate.
+    #[must_use]
+    pub fn estimated_iters_remaining(&self, tol: f64) -> Option<usize> {
+        geometric_iters_remaining(&self.loss_hist, tol)
+    }
+
+    /// Estimate the condition number of the Hessian from the curvature pairs in the history
+    ///
+    /// Each pair $(s, y)$ gives the Barzilai-Borwein estimates $\frac{s^{\top} y}{s^{\top} s}$ and
+    /// $\frac{y^{\top} y}{s^{\top} y}$ of the Hessian's eigenvalues along the step, both of which lie between
+    /// its smallest and largest eigenvalues for a quadratic. The estimate is the ratio of the largest to the smallest
+    /// over the history, so is a lower bound on the true condition number that improves as the steps explore more
+    /// directions.
+    /// Returns `None` if there are fewer than two pairs in the history.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the tensor operations on the history fail
+    pub fn condition_estimate(&self) -> CResult<Option<f64>> {
+        if self.s_hist.len() < 2 {
+            return Ok(None);
+        }
+        let mut min_eig = f64::INFINITY;
+        let mut max_eig = 0_f64;
+        for (s, y) in &self.s_hist {
+            let dot = |a: &Tensor, b: &Tensor| -> CResult<f64> {
+                (a * b)?
+                    .sum_all()?
+                    .to_dtype(candle_core::DType::F64)?
+                    .to_scalar::<f64>()
+            };
+            let (ss, sy, yy) = (dot(s, s)?, dot(s, y)?, dot(y, y)?);
+            // pairs are only stored if they have positive curvature
+            min_eig = min_eig.min(sy / ss);
+            max_eig = max_eig.max(yy / sy);
+        }
+        Ok(Some(max_eig / min_eig))
+    }
+
+    /// whether the step completes convergence under the convergence policy, given whether the gradient had converged
+    fn step_converged(&self, step: &Tensor, grad_converged: Option<bool>) -> CResult<bool> {
+        let step_converged = self.params.step_conv.converged(step)?;
+        Ok(step_converged == Some(true)
+            && (self.params.convergence_policy == ConvergencePolicy::Any
+                || grad_converged.unwrap_or(true)))
+    }
+}
+
+/// fit a geometric decay $d_{k+1} = r d_k$ to the decreases in loss, and extrapolate until $d < $ `tol`
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn geometric_iters_remaining(losses: &VecDeque<f64>, tol: f64) -> Option<usize> {
+    if losses.len() < 3 {
+        return None;
+    }
+    let decreases: Vec<f64> = losses
+        .iter()
+        .zip(losses.iter().skip(1))
+        .map(|(prev, next)| prev - next)
+        .collect();
+    if decreases.iter().any(|d| *d <= 0. || !d.is_finite()) {
+        return None;
+    }
+    let first = decreases[0];
+    let last = decreases[decreases.len() - 1];
+    if last < tol {
+        return Some(0);
+    }
+    // geometric mean of the ratio of successive decreases
+    let rate = (last / first).powf(1. / (decreases.len() - 1) as f64);
+    if rate >= 1. {
+        return None;
+    }
+    Some(((tol / last).ln() / rate.ln()).ceil() as usize)
 }
 
 #[allow(clippy::inline_always)]
 #[inline(always)]
-fn flat_grads(vs: &Vec<Var>, loss: &Tensor, weight_decay: Option<f64>) -> CResult<Tensor> {
+pub(crate) fn flat_grads(vs: &[Var], loss: &Tensor, weight_decay: Option<f64>) -> CResult<Tensor> {
     let grads = loss.backward()?;
-    let mut flat_grads = Vec::with_capacity(vs.len());
+    let flat_grads = flatten_grads(&grads, vs)?;
     if let Some(wd) = weight_decay {
-        for v in vs {
-            if let Some(grad) = grads.get(v) {
-                let grad = &(grad + (wd * v.as_tensor())?)?;
-                flat_grads.push(grad.flatten_all()?);
-            } else {
-                let grad = (wd * v.as_tensor())?; // treat as if grad were 0
-                flat_grads.push(grad.flatten_all()?);
-            }
-        }
+        flat_grads + (wd * flatten_vars(vs)?)?
     } else {
-        for v in vs {
-            if let Some(grad) = grads.get(v) {
-                flat_grads.push(grad.flatten_all()?);
-            } else {
-                let n_elems = v.elem_count();
-                flat_grads.push(candle_core::Tensor::zeros(n_elems, v.dtype(), v.device())?);
-            }
-        }
+        Ok(flat_grads)
     }
-    candle_core::Tensor::cat(&flat_grads, 0)
 }
 
-fn add_grad(vs: &mut Vec<Var>, flat_tensor: &Tensor) -> CResult<()> {
-    let mut offset = 0;
-    for var in vs {
-        let n_elems = var.elem_count();
-        let tensor = flat_tensor
-            .narrow(0, offset, n_elems)?
-            .reshape(var.shape())?;
+pub(crate) fn add_grad(vs: &mut [Var], flat_tensor: &Tensor) -> CResult<()> {
+    for (var, tensor) in vs.iter().zip(unflatten(flat_tensor, vs)?) {
         var.set(&var.add(&tensor)?)?;
-        offset += n_elems;
     }
     Ok(())
 }
@@ -474,6 +912,20 @@ fn set_vs(vs: &mut [Var], vals: &Vec<Tensor>) -> CResult<()> {
     Ok(())
 }
 
+/// whether the update pair satisfies the curvature condition $y^{\\top} s > \\epsilon y^{\\top} y$
+pub(crate) fn satisfies_curvature(s: &Tensor, y: &Tensor) -> CResult<bool> {
+    let ys = (y * s)?
+        .sum_all()?
+        .to_dtype(candle_core::DType::F64)?
+        .to_scalar::<f64>()?;
+    let yy = y
+        .sqr()?
+        .sum_all()?
+        .to_dtype(candle_core::DType::F64)?
+        .to_scalar::<f64>()?;
+    Ok(ys > CURVATURE_EPS * yy)
+}
+
 #[cfg(test)]
 mod tests {
     // use candle_core::test_utils::{to_vec0_round, to_vec2_round};
@@ -501,7 +953,17 @@ mod tests {
         fn new() -> CResult<(Self, Vec<Var>)> {
             let weight = Var::from_tensor(&Tensor::new(&[3f64, 1.], &Device::Cpu)?)?;
             let bias = Var::from_tensor(&Tensor::new(-2f64, &Device::Cpu)?)?;
+            Self::from_vars(weight, bias)
+        }
+
+        /// model with zero initialised weights of the correct rank for the linear layer
+        fn zeros() -> CResult<(Self, Vec<Var>)> {
+            let weight = Var::from_tensor(&Tensor::new(&[[0f64, 0.]], &Device::Cpu)?)?;
+            let bias = Var::from_tensor(&Tensor::new(0f64, &Device::Cpu)?)?;
+            Self::from_vars(weight, bias)
+        }
 
+        fn from_vars(weight: Var, bias: Var) -> CResult<(Self, Vec<Var>)> {
             let linear =
                 candle_nn::Linear::new(weight.as_tensor().clone(), Some(bias.as_tensor().clone()));
 
@@ -520,6 +982,252 @@ mod tests {
         }
     }
 
+    /// concave loss $-x^2$ for which every update pair has negative curvature
+    struct ConcaveModel {
+        x: Var,
+    }
+
+    impl Model for ConcaveModel {
+        fn loss(&self) -> CResult<Tensor> {
+            self.x.as_tensor().sqr()?.sum_all()?.neg()
+        }
+    }
+
+    /// very flat parabola $10^{-4} (x - 1)^2$, whose gradient is small long before the steps are
+    struct FlatModel {
+        x: Var,
+    }
+
+    impl Model for FlatModel {
+        fn loss(&self) -> CResult<Tensor> {
+            ((self.x.as_tensor() - 1.)?.sqr()? * 1e-4)?.sum_all()
+        }
+    }
+
+    /// run to convergence from 0, returning the outcome of each step and the final value
+    fn flat_outcomes(params: ParamsLBFGS) -> Result<(Vec<&'static str>, f64)> {
+        let x = Var::new(&[0f64], &Device::Cpu)?;
+        let mut lbfgs = Lbfgs::new(vec![x.clone()], params, FlatModel { x: x.clone() })?;
+        let mut loss = lbfgs.model().loss()?;
+        let mut outcomes = Vec::new();
+        for _ in 0..10 {
+            match lbfgs.backward_step(&loss)? {
+                ModelOutcome::Stepped(next, _) | ModelOutcome::Truncated(next, _) => {
+                    outcomes.push("stepped");
+                    loss = next;
+                }
+                ModelOutcome::Converged(_, _) => {
+                    outcomes.push("converged");
+                    break;
+                }
+            }
+        }
+        Ok((outcomes, x.to_vec1::<f64>()?[0]))
+    }
+
+    #[test]
+    fn convergence_policy_test() -> Result<()> {
+        let params = ParamsLBFGS {
+            grad_conv: GradConv::MinForce(1e-3),
+            step_conv: StepConv::MinStep(1e-6),
+            ..Default::default()
+        };
+        // the initial gradient of 2e-4 already meets the gradient criterion
+        let (any, x) = flat_outcomes(params)?;
+        assert_eq!(any, ["converged"]);
+        assert_eq!(x, 0.);
+        // but the steps only become small once the minimum has been found
+        let (all, x) = flat_outcomes(ParamsLBFGS {
+            convergence_policy: ConvergencePolicy::All,
+            ..params
+        })?;
+        assert!(all.len() > 2);
+        assert_eq!(all[..all.len() - 1], vec!["stepped"; all.len() - 1]);
+        assert_eq!(all.last(), Some(&"converged"));
+        assert_approx_eq!(x, 1.);
+
+        // with the step criterion disabled the policy makes no difference
+        for convergence_policy in [ConvergencePolicy::Any, ConvergencePolicy::All] {
+            let (single, _) = flat_outcomes(ParamsLBFGS {
+                step_conv: StepConv::MinStep(0.),
+                convergence_policy,
+                ..params
+            })?;
+            assert_eq!(single, ["converged"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reset_test() -> Result<()> {
+        let (model, vars) = LinearModel::zeros()?;
+        let mut lbfgs = Lbfgs::new(vars.clone(), ParamsLBFGS::default(), model)?;
+        let mut loss = lbfgs.model().loss()?;
+        for _ in 0..4 {
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        assert_eq!(lbfgs.history_len(), 3);
+        let before = flatten_vars(&vars)?.to_vec1::<f64>()?;
+        lbfgs.reset();
+        assert_eq!(lbfgs.history_len(), 0);
+        // the variables are untouched, and the next step starts the history again
+        assert_eq!(flatten_vars(&vars)?.to_vec1::<f64>()?, before);
+        lbfgs.backward_step(&lbfgs.model().loss()?)?;
+        assert_eq!(lbfgs.history_len(), 0);
+        lbfgs.backward_step(&lbfgs.model().loss()?)?;
+        assert_eq!(lbfgs.history_len(), 1);
+        Ok(())
+    }
+
+    /// anisotropic quadratic $\frac{1}{2} \sum_i h_i x_i^2$
+    struct AnisotropicModel {
+        x: Var,
+        h: Tensor,
+    }
+
+    impl Model for AnisotropicModel {
+        fn loss(&self) -> CResult<Tensor> {
+            (self.x.as_tensor().sqr()?.mul(&self.h)? * 0.5)?.sum_all()
+        }
+    }
+
+    #[test]
+    fn condition_estimate_test() -> Result<()> {
+        // eigenvalues 1 and 100 so the condition number is 100
+        let h = vec![1f64, 100.];
+        let x = Var::new(vec![1f64; 2], &Device::Cpu)?;
+        let params = ParamsLBFGS {
+            lr: 1.,
+            grad_conv: GradConv::MinForce(0.),
+            step_conv: StepConv::MinStep(0.),
+            ..Default::default()
+        };
+        let model = AnisotropicModel {
+            x: x.clone(),
+            h: Tensor::new(h, &Device::Cpu)?,
+        };
+        let mut lbfgs = Lbfgs::new(vec![x], params, model)?;
+        let mut loss = lbfgs.model().loss()?;
+        let mut estimates = Vec::new();
+        for _ in 0..10 {
+            estimates.push(lbfgs.condition_estimate()?);
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        // there are fewer than two pairs until the third step, as the first step has no previous gradient
+        assert_eq!(estimates[..3], [None; 3]);
+        // after which the estimate is a lower bound, rising towards the condition number as more directions are explored
+        let estimates: Vec<f64> = estimates[3..].iter().map(|e| e.unwrap()).collect();
+        assert!(estimates.windows(2).all(|w| w[0] <= w[1] && w[1] <= 100.));
+        assert_approx_eq!(estimates[estimates.len() - 1], 100., 0.1);
+        Ok(())
+    }
+
+    /// steep parabola $1000 x^2$
+    struct SteepModel {
+        x: Var,
+    }
+
+    impl Model for SteepModel {
+        fn loss(&self) -> CResult<Tensor> {
+            (self.x.as_tensor().sqr()? * 1000.)?.sum_all()
+        }
+    }
+
+    #[test]
+    fn initial_step_test() -> Result<()> {
+        let first_steps = |initial_step: InitialStep| -> Result<Vec<f64>> {
+            let x = Var::new(&[1f64], &Device::Cpu)?;
+            let params = ParamsLBFGS {
+                initial_step,
+                ..Default::default()
+            };
+            let mut lbfgs = Lbfgs::new(vec![x.clone()], params, SteepModel { x: x.clone() })?;
+            let mut loss = lbfgs.model().loss()?;
+            let mut xs = Vec::new();
+            for _ in 0..2 {
+                if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                    loss = next;
+                }
+                xs.push(x.to_vec1::<f64>()?[0]);
+            }
+            Ok(xs)
+        };
+        // the gradient is 2000: scaled by its norm the first step is exactly to the minimum
+        let scaled = first_steps(InitialStep::ScaledByGradNorm)?;
+        assert_approx_eq!(scaled[0], 0.);
+        assert_approx_eq!(scaled[1], 0.);
+        assert_eq!(
+            ParamsLBFGS::default().initial_step,
+            InitialStep::ScaledByGradNorm
+        );
+        // while a unit step overshoots by the full gradient, before the history corrects the scale
+        let unit = first_steps(InitialStep::Unit)?;
+        assert_approx_eq!(unit[0], -1999.);
+        assert_approx_eq!(unit[1], 0., 1e-6);
+        Ok(())
+    }
+
+    /// quartic $x^4$, whose curvature grows away from the minimum
+    struct QuarticModel {
+        x: Var,
+    }
+
+    impl Model for QuarticModel {
+        fn loss(&self) -> CResult<Tensor> {
+            self.x.as_tensor().powf(4.)?.sum_all()
+        }
+    }
+
+    #[test]
+    fn max_step_norm_test() -> Result<()> {
+        let run = |max_step_norm: Option<f64>| -> Result<Vec<f64>> {
+            let x = Var::new(&[2f64], &Device::Cpu)?;
+            let params = ParamsLBFGS {
+                initial_step: InitialStep::Unit,
+                max_step_norm,
+                ..Default::default()
+            };
+            let mut lbfgs = Lbfgs::new(vec![x.clone()], params, QuarticModel { x: x.clone() })?;
+            let mut loss = lbfgs.model().loss()?;
+            let mut xs = vec![2.];
+            for _ in 0..20 {
+                match lbfgs.backward_step(&loss)? {
+                    ModelOutcome::Converged(_, _) => break,
+                    ModelOutcome::Stepped(next, _) | ModelOutcome::Truncated(next, _) => {
+                        loss = next;
+                    }
+                }
+                xs.push(x.to_vec1::<f64>()?[0]);
+            }
+            Ok(xs)
+        };
+        // the gradient at 2 is 32, so a unit step overshoots to -30 with a loss of 810000
+        let uncapped = run(None)?;
+        assert_approx_eq!(uncapped[1], -30.);
+        // capped, every step is at most 0.5 and the iterates stay bounded as they approach the minimum
+        let capped = run(Some(0.5))?;
+        assert_approx_eq!(capped[1], 1.5);
+        for pair in capped.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= 0.5 + 1e-12);
+            assert!(pair[1].abs() <= 2.);
+        }
+        assert!(capped.last().unwrap_or(&2.).abs() < 0.5);
+        // while with a line search the cap is reported as unused
+        let params = ParamsLBFGS {
+            line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+            max_step_norm: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(params.unused_config().len(), 1);
+        let x = Var::new(&[2f64], &Device::Cpu)?;
+        assert!(Lbfgs::new_strict(vec![x.clone()], params, QuarticModel { x }).is_err());
+        Ok(())
+    }
+
     use super::*;
     #[test]
     fn lr_test() -> Result<()> {
@@ -549,8 +1257,204 @@ mod tests {
         let inner = lbfgs.into_inner();
 
         assert_eq!(inner[0].as_tensor().to_vec1::<f64>()?, &[3f64, 1.]);
-        println!("checked weights");
         assert_approx_eq!(inner[1].as_tensor().to_vec0::<f64>()?, -2_f64);
         Ok(())
     }
+
+    #[test]
+    fn set_model_test() -> Result<()> {
+        let params = ParamsLBFGS {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let (model, vars) = LinearModel::zeros()?;
+        let mut lbfgs = Lbfgs::new(vars, params, model)?;
+        let mut loss = lbfgs.model().loss()?;
+        for _ in 0..5 {
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        assert!(!lbfgs.s_hist.is_empty());
+
+        let (model, vars) = LinearModel::zeros()?;
+        let fresh_vars = vars.clone();
+        lbfgs.set_model(model, vars)?;
+        assert!(lbfgs.s_hist.is_empty());
+        assert!(lbfgs.first);
+        let mut loss = lbfgs.model().loss()?;
+        let start = loss.to_scalar::<f64>()?;
+        for _ in 0..5 {
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        assert!(loss.to_scalar::<f64>()? < start);
+        // the new model's variables are the ones being optimised
+        assert_ne!(fresh_vars[0].to_vec2::<f64>()?, [[0., 0.]]);
+
+        // mismatched number of parameters
+        let (model, _) = LinearModel::zeros()?;
+        let vars = vec![Var::new(&[0f64, 0.], &Device::Cpu)?];
+        assert!(lbfgs.set_model(model, vars).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn memory_budget_test() -> Result<()> {
+        // 1000 f32 params: each pair takes 8000 bytes
+        let params = ParamsLBFGS::with_memory_budget(100_000, 1000, DType::F32)?;
+        assert_eq!(params.history_size, 12);
+        assert_eq!(
+            params,
+            ParamsLBFGS {
+                history_size: 12,
+                ..Default::default()
+            }
+        );
+        // exactly one f64 pair fits
+        let params = ParamsLBFGS::with_memory_budget(16_000, 1000, DType::F64)?;
+        assert_eq!(params.history_size, 1);
+        assert!(ParamsLBFGS::with_memory_budget(15_999, 1000, DType::F64).is_err());
+        // with no parameters the history would be unbounded
+        assert!(ParamsLBFGS::with_memory_budget(16_000, 0, DType::F64).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn into_model_test() -> Result<()> {
+        let params = ParamsLBFGS {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let (model, vars) = LinearModel::zeros()?;
+        let mut lbfgs = Lbfgs::new(vars, params, model)?;
+        let start = lbfgs.model().loss()?.to_scalar::<f64>()?;
+        let mut loss = lbfgs.model().loss()?;
+        for _ in 0..5 {
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        let model = lbfgs.into_model();
+        assert!(model.loss()?.to_scalar::<f64>()? < start);
+        let preds = model.forward(&model.xs)?;
+        assert_eq!(
+            preds.to_vec2::<f64>()?,
+            model.linear.forward(&model.xs)?.to_vec2::<f64>()?
+        );
+        assert_approx_eq!(
+            candle_nn::loss::mse(&preds, &model.ys)?.to_scalar::<f64>()?,
+            loss.to_scalar::<f64>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn curvature_test() -> Result<()> {
+        let s = Tensor::new(&[1f64, 0.], &Device::Cpu)?;
+        assert!(satisfies_curvature(
+            &s,
+            &Tensor::new(&[2f64, 1.], &Device::Cpu)?
+        )?);
+        assert!(!satisfies_curvature(
+            &s,
+            &Tensor::new(&[-2f64, 1.], &Device::Cpu)?
+        )?);
+        assert!(!satisfies_curvature(
+            &s,
+            &Tensor::new(&[0f64, 1.], &Device::Cpu)?
+        )?);
+
+        let x = Var::new(&[1f64], &Device::Cpu)?;
+        let model = ConcaveModel { x: x.clone() };
+        let params = ParamsLBFGS {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let mut lbfgs = Lbfgs::new(vec![x], params, model)?;
+        let mut loss = lbfgs.model().loss()?;
+        for _ in 0..3 {
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        // every pair was skipped, so the steps are plain gradient descent
+        // (with the first step scaled by 1 / |g|_1 = 1 / 2)
+        assert!(lbfgs.s_hist.is_empty());
+        assert_approx_eq!(
+            lbfgs.model.x.as_tensor().to_vec1::<f64>()?[0],
+            1.1 * 1.2_f64.powi(2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn history_test() -> Result<()> {
+        // the last step is updated in place, so each pair in the history must hold its own copy of it
+        let (model, vars) = LinearModel::zeros()?;
+        let params = ParamsLBFGS {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let mut lbfgs = Lbfgs::new(vars, params, model)?;
+        let mut loss = lbfgs.model().loss()?;
+        for _ in 0..4 {
+            if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        let steps = lbfgs
+            .s_hist
+            .iter()
+            .map(|(s, _)| s.to_vec1::<f64>())
+            .collect::<CResult<Vec<_>>>()?;
+        assert_eq!(steps.len(), 3);
+        assert!(steps.windows(2).all(|w| w[0] != w[1]));
+        Ok(())
+    }
+
+    #[test]
+    fn verbose_test() -> Result<()> {
+        // logging does not change the trajectory
+        let mut weights = Vec::new();
+        for verbose in [false, true] {
+            let params = ParamsLBFGS {
+                lr: 0.1,
+                line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+                verbose,
+                ..Default::default()
+            };
+            let (model, vars) = LinearModel::zeros()?;
+            let mut lbfgs = Lbfgs::new(vars, params, model)?;
+            let mut loss = lbfgs.model().loss()?;
+            for _ in 0..3 {
+                if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+                    loss = next;
+                }
+            }
+            weights.push(lbfgs.into_inner()[0].as_tensor().to_vec2::<f64>()?);
+        }
+        assert_eq!(weights[0], weights[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn geometric_estimate_test() {
+        // loss decreases of 0.5^k
+        let losses: VecDeque<f64> = (0..5).map(|k| 1. + 0.5_f64.powi(k)).collect();
+        // last decrease is 0.0625 so 5 more halvings are needed to drop below 0.003
+        assert_eq!(geometric_iters_remaining(&losses, 3e-3), Some(5));
+        assert_eq!(geometric_iters_remaining(&losses, 0.1), Some(0));
+        // too short
+        let short: VecDeque<f64> = losses.iter().take(2).copied().collect();
+        assert_eq!(geometric_iters_remaining(&short, 3e-3), None);
+        // not decreasing
+        let mut increasing = losses.clone();
+        increasing.push_back(2.);
+        assert_eq!(geometric_iters_remaining(&increasing, 3e-3), None);
+        // decreasing, but not converging
+        let linear: VecDeque<f64> = (0..5).map(|k| 10. - f64::from(k)).collect();
+        assert_eq!(geometric_iters_remaining(&linear, 3e-3), None);
+    }
 }
@@ -0,0 +1,138 @@
+use candle_core::Result as CResult;
+use candle_core::Tensor;
+use log::warn;
+
+use super::LineSearchTarget;
+
+/// Backtracking line search, for any [`LineSearchTarget`]
+pub(crate) trait Backtracking: LineSearchTarget {
+    /// Backtracking line search
+    ///
+    /// Starting from `step_size`, shrink the step by `rho` until the Armijo condition is met. If it is not met
+    /// within `max_steps` trial steps no step is taken: a step size of zero is returned with the initial loss
+    /// and gradient
+    ///
+    /// # Returns
+    ///
+    /// (`f_new`, `g_new`, t, `ls_func_evals`)
+    #[allow(clippy::too_many_arguments)]
+    fn backtracking(
+        &mut self,
+        mut step_size: f64,    // step size
+        direction: &Tensor,    // direction
+        loss: &Tensor,         // initial loss
+        grad: &Tensor,         // initial grad
+        directional_grad: f64, // initial directional grad
+        c1: f64,               // c1 coefficient for armijo condition
+        rho: f64,              // factor to shrink the step by
+        max_steps: usize,      // maximum number of trial steps
+    ) -> CResult<(Tensor, Tensor, f64, usize)> {
+        let f_init = loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()? + self.l2_reg()?;
+        let mut ls_func_evals = 0;
+        while ls_func_evals < max_steps {
+            let (f_new, g_new, l2_new) = self.directional_evaluate(step_size, direction)?;
+            ls_func_evals += 1;
+            let f_trial = f_new
+                .to_dtype(candle_core::DType::F64)?
+                .to_scalar::<f64>()?
+                + l2_new;
+            if f_trial <= c1.mul_add(step_size * directional_grad, f_init) {
+                return Ok((f_new, g_new, step_size, ls_func_evals));
+            }
+            step_size *= rho;
+        }
+        warn!("no step met the Armijo condition after {ls_func_evals} trial steps, not stepping");
+        Ok((loss.clone(), grad.clone(), 0., ls_func_evals))
+    }
+}
+
+impl<T: LineSearchTarget> Backtracking for T {}
+
+#[cfg(test)]
+mod tests {
+    use crate::lbfgs::{Lbfgs, LineSearch, ParamsLBFGS};
+    use crate::{LossOptimizer, Model};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Result as CResult, Tensor, Var};
+
+    use super::*;
+
+    /// $(x - 1)^2$ in one dimension
+    struct Parabola {
+        x: Var,
+    }
+
+    impl Model for Parabola {
+        fn loss(&self) -> CResult<Tensor> {
+            (self.x.as_tensor() - 1.)?.sqr()?.sum_all()
+        }
+    }
+
+    fn setup() -> Result<Lbfgs<Parabola>> {
+        let x = Var::new(&[0f64], &Device::Cpu)?;
+        let params = ParamsLBFGS {
+            line_search: Some(LineSearch::Backtracking {
+                c1: 1e-4,
+                rho: 0.5,
+                max_steps: 10,
+            }),
+            ..Default::default()
+        };
+        Ok(Lbfgs::new(vec![x.clone()], params, Parabola { x })?)
+    }
+
+    #[test]
+    fn backtracking_test() -> Result<()> {
+        let mut lbfgs = setup()?;
+        let loss = lbfgs.model.loss()?;
+        // gradient at 0 is -2: t = 4 and t = 2 overshoot to a loss of at least 1, t = 1 is exact
+        let direction = Tensor::new(&[1f64], &Device::Cpu)?;
+        let grad = Tensor::new(&[-2f64], &Device::Cpu)?;
+        let (f_new, _, t, evals) =
+            lbfgs.backtracking(4., &direction, &loss, &grad, -2., 1e-4, 0.5, 10)?;
+        assert_approx_eq!(t, 1.);
+        assert_eq!(evals, 3);
+        assert_approx_eq!(f_new.to_scalar::<f64>()?, 0.);
+        // the vars are left unchanged
+        assert_approx_eq!(lbfgs.model.x.to_vec1::<f64>()?[0], 0.);
+        Ok(())
+    }
+
+    #[test]
+    fn max_steps_test() -> Result<()> {
+        let mut lbfgs = setup()?;
+        let loss = lbfgs.model.loss()?;
+        // the wrong direction never satisfies the armijo condition
+        let direction = Tensor::new(&[-1f64], &Device::Cpu)?;
+        let grad = Tensor::new(&[-2f64], &Device::Cpu)?;
+        let (f_new, g_new, t, evals) =
+            lbfgs.backtracking(1., &direction, &loss, &grad, -2., 1e-4, 0.5, 3)?;
+        // no step is taken, rather than the last trial step increasing the loss
+        assert_eq!(evals, 3);
+        assert_approx_eq!(t, 0.);
+        assert_approx_eq!(f_new.to_scalar::<f64>()?, 1.);
+        assert_eq!(g_new.to_vec1::<f64>()?, [-2.]);
+        assert_approx_eq!(lbfgs.model.x.to_vec1::<f64>()?[0], 0.);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_test() {
+        for (c1, rho) in [
+            (0., 0.5),
+            (1., 0.5),
+            (1e-4, 0.),
+            (1e-4, 1.),
+            (1e-4, f64::NAN),
+        ] {
+            let line_search = LineSearch::Backtracking {
+                c1,
+                rho,
+                max_steps: 10,
+            };
+            assert!(line_search.validate().is_err());
+        }
+        assert!(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9).validate().is_ok());
+    }
+}
@@ -2,7 +2,7 @@ use crate::Model;
 use candle_core::Result as CResult;
 use candle_core::{Tensor, Var};
 
-use super::{add_grad, flat_grads, set_vs, Lbfgs};
+use super::{add_grad, flat_grads, set_vs, Lbfgs, LineSearchTarget};
 
 /// ported from pytorch torch/optim/lbfgs.py ported from <https://github.com/torch/optim/blob/master/polyinterp.lua>
 fn cubic_interpolate(
@@ -42,7 +42,8 @@ fn cubic_interpolate(
     }
 }
 
-impl<M: Model> Lbfgs<M> {
+/// Strong Wolfe line search, for any [`LineSearchTarget`]
+pub(crate) trait StrongWolfe: LineSearchTarget {
     /// Strong Wolfe line search
     ///
     /// # Arguments
@@ -69,7 +70,7 @@ impl<M: Model> Lbfgs<M> {
     ///
     /// (`f_new`, `g_new`, t, `ls_func_evals`)
     #[allow(clippy::too_many_arguments, clippy::too_many_lines)]
-    pub(super) fn strong_wolfe(
+    fn strong_wolfe(
         &mut self,
         mut step_size: f64,    // step size
         direction: &Tensor,    // direction
@@ -158,6 +159,21 @@ impl<M: Model> Lbfgs<M> {
                 );
             }
 
+            // reached max number of iterations?
+            // (checked before evaluating a new point so the number of evaluations is bounded by `max_ls + 1`)
+            if ls_iter >= max_ls {
+                bracket_gtd = [gtd_prev, gtd_new];
+                bracket_l2 = [l2_prev, l2_new];
+                bracket_f = [scalar_loss, f_new];
+                break (
+                    [0., step_size],
+                    [
+                        Var::from_tensor(grad)?,
+                        Var::from_tensor(g_new.as_tensor())?,
+                    ],
+                );
+            }
+
             // interpolate
             let min_step = step_size + 0.01 * (step_size - t_prev);
             let max_step = step_size * 10.;
@@ -198,20 +214,6 @@ impl<M: Model> Lbfgs<M> {
                 .squeeze(0)?
                 .to_scalar::<f64>()?;
             ls_iter += 1;
-
-            // reached max number of iterations?
-            if ls_iter == max_ls {
-                bracket_gtd = [gtd_prev, gtd_new];
-                bracket_l2 = [l2_prev, l2_new];
-                bracket_f = [scalar_loss, f_new];
-                break (
-                    [0., step_size],
-                    [
-                        Var::from_tensor(grad)?,
-                        Var::from_tensor(g_new.as_tensor())?,
-                    ],
-                );
-            }
         };
 
         // zoom phase: we now have a point satisfying the criteria, or
@@ -348,66 +350,71 @@ impl<M: Model> Lbfgs<M> {
             ))
         }
     }
+}
+
+impl<T: LineSearchTarget> StrongWolfe for T {}
 
+impl<M: Model> LineSearchTarget for Lbfgs<M> {
     fn directional_evaluate(
         &mut self,
         mag: f64,
         direction: &Tensor,
     ) -> CResult<(Tensor, Tensor, f64)> {
-        // need to cache the original result
-        // Otherwise leads to drift over line search evals
-        let original = self
-            .vars
-            .iter()
-            .map(|v| v.as_tensor().copy())
-            .collect::<CResult<Vec<Tensor>>>()?;
-
-        add_grad(&mut self.vars, &(mag * direction)?)?;
-        let loss = self.model.loss()?;
-        let grad = flat_grads(&self.vars, &loss, self.params.weight_decay)?;
-        let l2_reg = if let Some(wd) = self.params.weight_decay {
-            0.5 * wd
-                * self
-                    .vars
-                    .iter()
-                    .map(|v| -> CResult<f64> {
-                        v.as_tensor()
-                            .sqr()?
-                            .sum_all()?
-                            .to_dtype(candle_core::DType::F64)?
-                            .to_scalar::<f64>()
-                    })
-                    .sum::<CResult<f64>>()?
-        } else {
-            0.
-        };
-
-        set_vs(&mut self.vars, &original)?;
-        // add_grad(&mut self.vars, &(-mag * direction)?)?;
-        Ok((
-            loss, //.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()?
-            grad, l2_reg,
-        ))
+        directional_evaluate(
+            &mut self.vars,
+            &self.model,
+            self.params.weight_decay,
+            mag,
+            direction,
+        )
     }
 
     fn l2_reg(&self) -> CResult<f64> {
-        if let Some(wd) = self.params.weight_decay {
-            Ok(0.5
-                * wd
-                * self
-                    .vars
-                    .iter()
-                    .map(|v| -> CResult<f64> {
-                        v.as_tensor()
-                            .sqr()?
-                            .sum_all()?
-                            .to_dtype(candle_core::DType::F64)?
-                            .to_scalar::<f64>()
-                    })
-                    .sum::<CResult<f64>>()?)
-        } else {
-            Ok(0.)
-        }
+        l2_reg(&self.vars, self.params.weight_decay)
+    }
+}
+
+/// the loss, flat gradient and L2 regularisation of `vars` moved by `mag` along `direction`, leaving them unchanged
+pub(crate) fn directional_evaluate<M: Model>(
+    vars: &mut [Var],
+    model: &M,
+    weight_decay: Option<f64>,
+    mag: f64,
+    direction: &Tensor,
+) -> CResult<(Tensor, Tensor, f64)> {
+    // need to cache the original result
+    // Otherwise leads to drift over line search evals
+    let original = vars
+        .iter()
+        .map(|v| v.as_tensor().copy())
+        .collect::<CResult<Vec<Tensor>>>()?;
+
+    add_grad(vars, &(mag * direction)?)?;
+    let loss = model.loss()?;
+    let grad = flat_grads(vars, &loss, weight_decay)?;
+    let l2_reg = l2_reg(vars, weight_decay)?;
+
+    set_vs(vars, &original)?;
+    Ok((loss, grad, l2_reg))
+}
+
+/// the L2 regularisation $\\frac{\\lambda}{2} ||\\theta||_{2}^{2}$ equivalent to a weight decay of $\\lambda$
+pub(crate) fn l2_reg(vars: &[Var], weight_decay: Option<f64>) -> CResult<f64> {
+    if let Some(wd) = weight_decay {
+        Ok(0.5
+            * wd
+            * vars
+                .iter()
+                .map(|v| -> CResult<f64> {
+                    v.as_tensor()
+                        .sqr()?
+                        .sum_all()?
+                        .to_dtype(candle_core::DType::F64)?
+                        .to_scalar::<f64>()
+                })
+                .sum::<CResult<f64>>()?)
+    } else {
+        Ok(0.)
     }
 }
 
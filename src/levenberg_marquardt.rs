@@ -0,0 +1,406 @@
+/*!
+Levenberg–Marquardt optimiser for least squares problems
+
+For a [`ResidualModel`] with residuals $r(\\theta)$ and loss $F = ||r||_{2}^{2}$, each step solves the damped
+Gauss-Newton system
+
+$$ \\left(J^{\\top} J + \\lambda D\\right) \\delta = -J^{\\top} r $$
+
+where $J$ is the Jacobian of the residuals and $D$ is either the identity (Levenberg) or the diagonal of
+$J^{\\top} J$ (Marquardt), which makes the step invariant to the scaling of the parameters. Small damping gives the
+Gauss-Newton step, large damping a short gradient descent step. The step is judged by the gain ratio
+
+$$ \\rho = \\frac{F(\\theta) - F(\\theta + \\delta)}{||r||_{2}^{2} - ||r + J \\delta||_{2}^{2}} $$
+
+and accepted if $\\rho > 0$, as described in
+[Methods for Non-Linear Least Squares Problems](https://www2.imm.dtu.dk/pubdb/edoc/imm3215.pdf) (algorithm 3.16),
+after which the damping is adapted by a [`DampingUpdate`].
+
+The Jacobian is built one row at a time by backpropagating each residual, so a step costs one backward pass per
+residual: this suits curve fitting and other problems with few residuals and parameters. The damped system is small
+and dense, and is solved with [conjugate gradients](crate::cg).
+*/
+
+use crate::{
+    cg::{conjugate_gradient, dot},
+    flatten_grads,
+    lbfgs::GradConv,
+    unflatten, LossOptimizer, ModelOutcome, OptimVars, ResidualModel,
+};
+use candle_core::Result as CResult;
+use candle_core::{Tensor, Var};
+use log::info;
+
+/// The matrix $D$ scaling the damping
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum DampingScaling {
+    /// the identity, as proposed by Levenberg
+    Identity,
+    /// the diagonal of $J^{\\top} J$, as proposed by Marquardt
+    Diagonal,
+}
+
+/// How the damping is adapted after each step
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum DampingUpdate {
+    /// divide the damping by `decrease` after an accepted step and multiply it by `increase` after a rejected one
+    Multiplicative {
+        /// factor by which the damping grows after a rejected step
+        increase: f64,
+        /// factor by which the damping shrinks after an accepted step
+        decrease: f64,
+    },
+    /// Nielsen's update: scale the damping by $\\max(\\frac{1}{3}, 1 - (2 \\rho - 1)^{3})$ after an accepted step,
+    /// and by a factor starting at 2 and doubling with each consecutive rejected step
+    Nielsen,
+}
+
+/// Conditions for termination based on the residuals
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum ResidualConv {
+    /// convergence when the norm of the residuals is below the tolerance, for problems with an exact fit
+    Norm(f64),
+    /// convergence when an accepted step reduces the loss by less than the tolerance relative to the loss
+    RelativeReduction(f64),
+}
+
+/// Parameters for the Levenberg–Marquardt optimiser
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsLevenbergMarquardt {
+    /// Initial damping $\\lambda$
+    pub damping: f64,
+    /// The matrix $D$ scaling the damping
+    pub scaling: DampingScaling,
+    /// How the damping is adapted after each step
+    pub update: DampingUpdate,
+    /// Damping above which no step reducing the loss can be found, so the optimiser is treated as converged
+    pub max_damping: f64,
+    /// Damping is not reduced below this
+    pub min_damping: f64,
+    /// Convergence criteria for the residuals
+    pub residual_conv: ResidualConv,
+    /// Convergence criteria for the gradient $J^{\\top} r$
+    pub grad_conv: GradConv,
+    /// Relative tolerance of the conjugate gradient solution of the damped system
+    pub cg_tol: f64,
+    /// log the gain ratio and damping of each step at the info level
+    pub verbose: bool,
+}
+
+impl Default for ParamsLevenbergMarquardt {
+    fn default() -> Self {
+        Self {
+            damping: 1e-3,
+            scaling: DampingScaling::Diagonal,
+            update: DampingUpdate::Multiplicative {
+                increase: 10.,
+                decrease: 10.,
+            },
+            max_damping: 1e16,
+            min_damping: 1e-16,
+            residual_conv: ResidualConv::Norm(1e-10),
+            grad_conv: GradConv::MinForce(1e-10),
+            cg_tol: 1e-12,
+            verbose: false,
+        }
+    }
+}
+
+/// Levenberg–Marquardt optimiser
+///
+/// A damped Gauss-Newton method for models whose loss is a sum of squared residuals
+#[derive(Debug)]
+pub struct LevenbergMarquardt<M: ResidualModel> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsLevenbergMarquardt,
+    damping: f64,
+    /// growth factor of the damping for consecutive rejected steps with Nielsen's update
+    nu: f64,
+    last_ratio: Option<f64>,
+}
+
+impl<M: ResidualModel> LossOptimizer<M> for LevenbergMarquardt<M> {
+    type Config = ParamsLevenbergMarquardt;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.damping <= 0. {
+            candle_core::bail!("the damping must be positive, got {}", params.damping);
+        }
+        if let DampingUpdate::Multiplicative { increase, decrease } = params.update {
+            if increase <= 1. || decrease <= 1. {
+                candle_core::bail!(
+                    "the damping increase and decrease factors must be above 1, got {increase} and {decrease}"
+                );
+            }
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            damping: params.damping,
+            params,
+            nu: 2.,
+            last_ratio: None,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let mut evals = 1;
+        let (residuals, jacobian) = residual_jacobian(&self.model, &self.vars)?;
+        let f_init = dot(&residuals, &residuals)?;
+        if let ResidualConv::Norm(tol) = self.params.residual_conv {
+            if f_init.sqrt() < tol {
+                info!("residual norm converged");
+                return Ok(ModelOutcome::Converged(loss.clone(), evals));
+            }
+        }
+        let jt = jacobian.t()?;
+        let grad = jt.matmul(&residuals.unsqueeze(1)?)?.squeeze(1)?;
+        if self.params.grad_conv.converged(&grad)? == Some(true) {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+        if self.damping > self.params.max_damping {
+            info!("damping above max_damping");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+
+        let jtj = jt.matmul(&jacobian)?;
+        let scaling = match self.params.scaling {
+            DampingScaling::Identity => grad.ones_like()?,
+            // a zero column of the Jacobian would leave that direction undamped
+            DampingScaling::Diagonal => diagonal(&jtj)?.maximum(1e-12)?,
+        };
+        let n = grad.elem_count();
+        let step = {
+            let scaling = &scaling;
+            let jtj = &jtj;
+            let damping = self.damping;
+            let matvec = |v: &Tensor| -> CResult<Tensor> {
+                jtj.matmul(&v.unsqueeze(1)?)?.squeeze(1)? + ((v * scaling)? * damping)?
+            };
+            conjugate_gradient(matvec, &grad.neg()?, 2 * n, self.params.cg_tol)?
+        };
+        // ||r||^2 - ||r + J step||^2
+        let j_step = jacobian.matmul(&step.unsqueeze(1)?)?.squeeze(1)?;
+        let predicted = -2f64.mul_add(dot(&grad, &step)?, dot(&j_step, &j_step)?);
+
+        let original = self
+            .vars
+            .iter()
+            .map(|v| v.as_tensor().copy())
+            .collect::<CResult<Vec<Tensor>>>()?;
+        for ((var, original), step) in self
+            .vars
+            .iter()
+            .zip(&original)
+            .zip(unflatten(&step, &self.vars)?)
+        {
+            var.set(&(original + step)?)?;
+        }
+        let next_residuals = self.model.residuals()?.flatten_all()?;
+        evals += 1;
+        let f_new = dot(&next_residuals, &next_residuals)?;
+        let ratio = if predicted > 0. {
+            (f_init - f_new) / predicted
+        } else {
+            f64::NEG_INFINITY
+        };
+        self.last_ratio = Some(ratio);
+        let accepted = ratio > 0.;
+        self.adapt_damping(ratio, accepted);
+        if self.params.verbose {
+            info!("gain ratio {ratio}, damping {}", self.damping);
+        }
+
+        if accepted {
+            let next_loss = next_residuals.sqr()?.sum_all()?;
+            if let ResidualConv::RelativeReduction(tol) = self.params.residual_conv {
+                if f_init - f_new < tol * f_init {
+                    info!("relative reduction converged");
+                    return Ok(ModelOutcome::Converged(next_loss, evals));
+                }
+            }
+            Ok(ModelOutcome::Stepped(next_loss, evals))
+        } else {
+            for (var, original) in self.vars.iter().zip(original) {
+                var.set(&original)?;
+            }
+            Ok(ModelOutcome::Stepped(loss.clone(), evals))
+        }
+    }
+
+    /// the current damping
+    fn learning_rate(&self) -> f64 {
+        self.damping
+    }
+
+    /// set the current damping
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.damping = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: ResidualModel> OptimVars for LevenbergMarquardt<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: ResidualModel> LevenbergMarquardt<M> {
+    /// The current damping $\\lambda$
+    #[must_use]
+    pub fn damping(&self) -> f64 {
+        self.damping
+    }
+
+    /// The gain ratio of the last step, or `None` before the first step
+    ///
+    /// The step was accepted if this is positive.
+    #[must_use]
+    pub fn last_ratio(&self) -> Option<f64> {
+        self.last_ratio
+    }
+
+    fn adapt_damping(&mut self, ratio: f64, accepted: bool) {
+        match (self.params.update, accepted) {
+            (DampingUpdate::Multiplicative { decrease, .. }, true) => self.damping /= decrease,
+            (DampingUpdate::Multiplicative { increase, .. }, false) => self.damping *= increase,
+            (DampingUpdate::Nielsen, true) => {
+                self.damping *= (1. - 2f64.mul_add(ratio, -1.).powi(3)).max(1. / 3.);
+                self.nu = 2.;
+            }
+            (DampingUpdate::Nielsen, false) => {
+                self.damping *= self.nu;
+                self.nu *= 2.;
+            }
+        }
+        self.damping = self.damping.max(self.params.min_damping);
+    }
+}
+
+/// The residuals of `model` and their Jacobian with respect to `vars`, with one row per residual
+pub(crate) fn residual_jacobian<M: ResidualModel>(
+    model: &M,
+    vars: &[Var],
+) -> CResult<(Tensor, Tensor)> {
+    let residuals = model.residuals()?.flatten_all()?;
+    let rows = (0..residuals.elem_count())
+        .map(|i| flatten_grads(&residuals.get(i)?.backward()?, vars))
+        .collect::<CResult<Vec<Tensor>>>()?;
+    let jacobian = Tensor::stack(&rows, 0)?;
+    Ok((residuals.detach(), jacobian))
+}
+
+/// the diagonal of a square matrix
+fn diagonal(a: &Tensor) -> CResult<Tensor> {
+    let n = a.dim(0)?;
+    (a * Tensor::eye(n, a.dtype(), a.device())?)?.sum(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+    use crate::Model;
+
+    /// the linear residuals $A x - b$ with $A = [[1, 0], [1, 1], [1, 2]]$ and $b = [1, 2, 2]$
+    struct Linear {
+        x: Var,
+    }
+
+    impl Model for Linear {
+        fn loss(&self) -> CResult<Tensor> {
+            self.residuals()?.sqr()?.sum_all()
+        }
+    }
+
+    impl ResidualModel for Linear {
+        fn residuals(&self) -> CResult<Tensor> {
+            let a = Tensor::new(&[[1f64, 0.], [1., 1.], [1., 2.]], &Device::Cpu)?;
+            let b = Tensor::new(&[1f64, 2., 2.], &Device::Cpu)?;
+            a.matmul(&self.x.as_tensor().unsqueeze(1)?)?.squeeze(1)? - b
+        }
+    }
+
+    fn setup(params: ParamsLevenbergMarquardt) -> Result<(Var, LevenbergMarquardt<Linear>)> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let optim = LevenbergMarquardt::new(vec![x.clone()], params, Linear { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsLevenbergMarquardt::default())?;
+        assert_approx_eq!(1e-3, optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.damping());
+        assert!(setup(ParamsLevenbergMarquardt {
+            damping: 0.,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(setup(ParamsLevenbergMarquardt {
+            update: DampingUpdate::Multiplicative {
+                increase: 10.,
+                decrease: 1.,
+            },
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn jacobian_test() -> Result<()> {
+        let (_, optim) = setup(ParamsLevenbergMarquardt::default())?;
+        let (residuals, jacobian) = residual_jacobian(&optim.model, &optim.vars)?;
+        assert_eq!(residuals.to_vec1::<f64>()?, [-1., -2., -2.]);
+        assert_eq!(jacobian.to_vec2::<f64>()?, [[1., 0.], [1., 1.], [1., 2.]]);
+        Ok(())
+    }
+
+    #[test]
+    fn linear_step_test() -> Result<()> {
+        // with no damping the step is the least squares solution [7/6, 1/2], which the model predicts exactly
+        let (x, mut optim) = setup(ParamsLevenbergMarquardt {
+            damping: 1e-14,
+            ..Default::default()
+        })?;
+        let loss = optim.model.loss()?;
+        optim.backward_step(&loss)?;
+        assert_approx_eq!(optim.last_ratio().unwrap_or_default(), 1., 1e-6);
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 7. / 6.);
+        assert_approx_eq!(x[1], 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn damping_update_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsLevenbergMarquardt {
+            damping: 1.,
+            update: DampingUpdate::Nielsen,
+            ..Default::default()
+        })?;
+        optim.adapt_damping(f64::NEG_INFINITY, false);
+        optim.adapt_damping(f64::NEG_INFINITY, false);
+        assert_approx_eq!(optim.damping(), 8.);
+        // a ratio of 1 shrinks the damping by the largest factor, and resets the growth factor
+        optim.adapt_damping(1., true);
+        assert_approx_eq!(optim.damping(), 8. / 3.);
+        optim.adapt_damping(f64::NEG_INFINITY, false);
+        assert_approx_eq!(optim.damping(), 16. / 3.);
+        Ok(())
+    }
+}
@@ -1,22 +1,82 @@
 /*!
 Optimisers for use with the candle framework for lightweight machine learning.
-Apart from LBFGS, these all implement the [`candle_nn::optim::Optimizer`] trait from candle-nn
+Apart from LBFGS, these all implement the [`candle_nn::optim::Optimizer`] trait from candle-nn.
+They can also be driven through the [`LossOptimizer`] interface used by LBFGS, with the same convergence criteria,
+by wrapping them in a [`convergent::ConvergentAdapter`]
 */
 
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::PoisonError;
 
+use candle_core::backprop::GradStore;
 use candle_core::Result as CResult;
 use candle_core::Tensor;
 use candle_core::Var;
+use candle_core::{DType, Device, TensorId};
+use candle_nn::VarMap;
+pub mod accumulate;
+pub mod adabelief;
+pub mod adabound;
 pub mod adadelta;
+pub mod adafactor;
 pub mod adagrad;
+pub mod adahessian;
 pub mod adam;
 pub mod adamax;
+pub mod adan;
+pub mod annealing;
+pub mod asgd;
+pub mod autosave;
+pub mod averaging;
+pub mod bfgs;
+pub mod block_preconditioner;
+pub mod bounded;
+pub mod cautious;
+pub mod centralize;
+pub mod cg;
+pub mod clip;
+pub mod cmaes;
+pub mod convergent;
+pub mod coordinate;
+pub mod diagnostics;
 pub mod esgd;
+pub mod frank_wolfe;
+pub mod freeze;
+pub mod ftrl;
+pub mod gauss_newton;
+pub mod hessian;
+pub mod lamb;
+pub mod lars;
 pub mod lbfgs;
+pub mod levenberg_marquardt;
+pub mod lion;
+pub mod lookahead;
+pub mod madgrad;
+pub mod meta_lr;
 pub mod nadam;
+pub mod natural_gradient;
+pub mod nelder_mead;
+pub mod newton_cg;
+pub mod novograd;
+pub mod powell;
+pub mod proximal;
+pub mod pso;
 pub mod radam;
+pub mod ranger;
 pub mod rmsprop;
+pub mod rprop;
+pub mod scalar_search;
+pub mod scaler;
+pub mod scheduler;
+pub mod shampoo;
+pub mod sm3;
+pub mod sophia;
+pub mod sparse_adam;
+pub mod subset;
+pub mod trust_region;
+pub mod yogi;
 
 /// Trait for optimisers to expose their parameters
 pub trait OptimParams: candle_nn::optim::Optimizer {
@@ -26,11 +86,164 @@ pub trait OptimParams: candle_nn::optim::Optimizer {
     fn set_params(&mut self, config: Self::Config);
 }
 
+/// Trait for optimisers to expose the variables they manage
+///
+/// The variables are always returned in the order they were passed on construction
+/// (with any non-float variables filtered out), so any per-variable output such as logging
+/// or serialised state is deterministic between runs
+pub trait OptimVars {
+    /// get the variables being optimised, in construction order
+    fn vars(&self) -> Vec<&Var>;
+}
+
+/// Trait for optimisers whose internal state can be saved and restored to resume training
+///
+/// The state is a map of named tensors, so can be round tripped through safetensors with
+/// [`candle_core::safetensors::save`] and [`candle_core::safetensors::load`].
+/// Per variable state is keyed by the position of the variable in [`OptimVars::vars`], so must be loaded
+/// into an optimiser constructed with the same variables in the same order.
+pub trait OptimState {
+    /// get a copy of the internal state of the optimiser
+    fn state_dict(&self) -> CResult<HashMap<String, Tensor>>;
+    /// restore the internal state from the output of [`OptimState::state_dict`]
+    fn load_state_dict(&mut self, state: HashMap<String, Tensor>) -> CResult<()>;
+}
+
+/// Trait for stepping with gradients from outside candle's autograd, such as finite differences
+///
+/// This is implemented for every optimiser that exposes its variables through [`OptimVars`]
+pub trait StepWithGrads {
+    /// take a step with gradients keyed by the id of the variable they are for
+    ///
+    /// Variables without a gradient are treated as they are by `step` when missing from the `GradStore`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a gradient does not match the shape of its variable, if a gradient is given for a variable that
+    /// is not being optimised, or if the step fails
+    fn step_with_grads(&mut self, grads: HashMap<TensorId, Tensor>) -> CResult<()>;
+}
+
+impl<O: candle_nn::optim::Optimizer + OptimVars> StepWithGrads for O {
+    fn step_with_grads(&mut self, mut grads: HashMap<TensorId, Tensor>) -> CResult<()> {
+        let mut store = empty_grad_store()?;
+        for var in self.vars() {
+            if let Some(grad) = grads.remove(&var.id()) {
+                if grad.shape() != var.shape() {
+                    candle_core::bail!(
+                        "gradient of shape {:?} given for variable {:?} of shape {:?}",
+                        grad.shape(),
+                        var.id(),
+                        var.shape()
+                    );
+                }
+                store.insert(var, grad);
+            }
+        }
+        if let Some(id) = grads.keys().next() {
+            candle_core::bail!("gradient given for variable {id:?} that is not being optimised");
+        }
+        self.step(&store)
+    }
+}
+
+/// The hyperparameters an optimiser is currently using, see [`CurrentHyperparams`]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct HyperSnapshot {
+    /// learning rate, including any changes made by a scheduler
+    pub lr: f64,
+    /// coefficients of the moving averages of the first and second moments, for Adam style optimisers
+    pub betas: Option<(f64, f64)>,
+    /// weight decay
+    pub weight_decay: Option<Decay>,
+    /// momentum
+    pub momentum: Option<Momentum>,
+}
+
+/// Trait for optimisers to report the hyperparameters they are currently using, for logging each step
+///
+/// This reflects any changes made since construction, such as by [`candle_nn::optim::Optimizer::set_learning_rate`]
+/// or [`OptimParams::set_params`]
+pub trait CurrentHyperparams {
+    /// a snapshot of the live hyperparameters
+    fn current_hyperparams(&self) -> HyperSnapshot;
+}
+
+/// Trait for optimiser parameters to report settings that have no effect in the mode chosen by the others
+///
+/// Optimisers warn about such settings on construction; to error instead construct them in strict mode with
+/// [`NewStrict::new_strict`] or [`LossOptimizer::new_strict`]
+pub trait UnusedConfig {
+    /// a description of each setting that has no effect
+    fn unused_config(&self) -> Vec<String>;
+
+    /// strict check that every setting has an effect
+    ///
+    /// # Errors
+    ///
+    /// Errors listing the settings that have no effect, if any
+    fn check_unused_config(&self) -> CResult<()> {
+        let unused = self.unused_config();
+        if !unused.is_empty() {
+            candle_core::bail!("unused configuration: {}", unused.join("; "));
+        }
+        Ok(())
+    }
+}
+
+/// Log a warning for each setting of `config` that has no effect
+pub(crate) fn warn_unused_config(config: &impl UnusedConfig) {
+    for unused in config.unused_config() {
+        log::warn!("unused configuration: {unused}");
+    }
+}
+
+/// Describe a weight decay setting with a coefficient of zero, which has no effect
+pub(crate) fn zero_decay(coefficient: Option<f64>) -> Option<String> {
+    (coefficient == Some(0.))
+        .then(|| "weight_decay has no effect with a coefficient of zero".to_string())
+}
+
+/// Trait for constructing optimisers in strict mode
+///
+/// This is implemented for every optimiser whose parameters implement [`UnusedConfig`]
+pub trait NewStrict: candle_nn::optim::Optimizer
+where
+    Self::Config: UnusedConfig,
+{
+    /// create a new optimiser, erroring rather than warning if any setting has no effect
+    ///
+    /// # Errors
+    ///
+    /// Errors listing the settings that have no effect, if any, or if the optimiser cannot be created
+    fn new_strict(vars: Vec<Var>, config: Self::Config) -> CResult<Self> {
+        config.check_unused_config()?;
+        Self::new(vars, config)
+    }
+}
+
+impl<O: candle_nn::optim::Optimizer> NewStrict for O where O::Config: UnusedConfig {}
+
 /// Trait for Models: this is needed for optimisers that require the ability to calculate the loss
 /// such as LBFGS
 pub trait Model: Sized {
     /// get the loss of the model
     fn loss(&self) -> CResult<Tensor>; //, xs: &Tensor, ys: &Tensor
+    /// the variables the model lists as trainable, if it keeps such a list
+    ///
+    /// this is only used to check the list against a `VarMap`, as in [`lbfgs::Lbfgs::from_varmap_and_model`]
+    fn listed_vars(&self) -> Option<Vec<Var>> {
+        None
+    }
+}
+
+/// Trait for models whose loss is a sum of squared residuals, as needed by
+/// [`levenberg_marquardt::LevenbergMarquardt`]
+///
+/// The [`Model::loss`] should be the sum of the squares of the residuals.
+pub trait ResidualModel: Model {
+    /// get the residuals of the model, flattened to a vector if they are not one
+    fn residuals(&self) -> CResult<Tensor>;
 }
 
 /// trait for optimisers like LBFGS that need the ability to calculate the loss
@@ -53,6 +266,18 @@ pub trait LossOptimizer<M: Model>: Sized {
         let vars: Vec<_> = vars.iter().map(|&v| v.clone()).collect();
         Self::new(vars, config, model)
     }
+    /// create a new optimiser, erroring rather than warning if any setting of the parameters has no effect
+    ///
+    /// # Errors
+    ///
+    /// Errors listing the settings that have no effect, if any, or if the optimiser cannot be created
+    fn new_strict(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self>
+    where
+        Self::Config: UnusedConfig,
+    {
+        params.check_unused_config()?;
+        Self::new(vs, params, model)
+    }
 }
 
 /// Outcomes of an optimiser step for methods such as LBFGS
@@ -61,6 +286,11 @@ pub enum ModelOutcome {
     /// The model took a step and the loss decreased
     /// contains next loss and the number of func evals
     Stepped(Tensor, usize),
+    /// The model took a step, but the line search used its full evaluation budget (`max_eval`)
+    /// so may have stopped before its conditions were met, or a backtracking line search found no step
+    /// meeting its condition so the step is zero
+    /// contains next loss and the number of func evals
+    Truncated(Tensor, usize),
     /// The model has converged and the loss has not changed
     /// contains loss and the number of func evals
     Converged(Tensor, usize),
@@ -87,6 +317,152 @@ pub enum Decay {
     DecoupledWeightDecay(f64),
 }
 
+impl Decay {
+    /// the coefficient $\lambda$ of the decay
+    pub(crate) fn coefficient(self) -> f64 {
+        match self {
+            Self::WeightDecay(coefficient) | Self::DecoupledWeightDecay(coefficient) => coefficient,
+        }
+    }
+}
+
+/// The variables held in a `VarMap`, sorted by name so the order is deterministic
+#[must_use]
+pub(crate) fn varmap_vars(varmap: &VarMap) -> Vec<Var> {
+    let data = varmap.data().lock().unwrap_or_else(PoisonError::into_inner);
+    let mut named: Vec<(&String, &Var)> = data.iter().collect();
+    named.sort_by_key(|(name, _)| *name);
+    named.into_iter().map(|(_, var)| var.clone()).collect()
+}
+
+/// The names of the variables in a `VarMap` that are missing from `vars`, sorted by name
+///
+/// This catches variables forgotten when listing the parameters of a model by hand
+#[must_use]
+pub(crate) fn missing_vars(varmap: &VarMap, vars: &[Var]) -> Vec<String> {
+    let listed: HashSet<TensorId> = vars.iter().map(|v| v.id()).collect();
+    let data = varmap.data().lock().unwrap_or_else(PoisonError::into_inner);
+    let mut missing: Vec<String> = data
+        .iter()
+        .filter(|(_, var)| !listed.contains(&var.id()))
+        .map(|(name, _)| name.clone())
+        .collect();
+    missing.sort();
+    missing
+}
+
+/// Create an empty `GradStore`
+///
+/// candle does not expose a constructor for `GradStore`, so this backpropagates through
+/// a constant scalar and removes the gradient of the root
+pub(crate) fn empty_grad_store() -> CResult<GradStore> {
+    let root = Tensor::zeros((), DType::F32, &Device::Cpu)?;
+    let mut grads = root.backward()?;
+    grads.remove(&root);
+    Ok(grads)
+}
+
+/// Flatten the gradients of `vars` into a single vector, treating variables without a gradient as having zero gradient
+pub(crate) fn flatten_grads<V: Borrow<Var>>(grads: &GradStore, vars: &[V]) -> CResult<Tensor> {
+    let flat = vars
+        .iter()
+        .map(|v| {
+            let v = v.borrow();
+            match grads.get(v) {
+                Some(grad) => grad.flatten_all(),
+                None => Tensor::zeros(v.elem_count(), v.dtype(), v.device()),
+            }
+        })
+        .collect::<CResult<Vec<Tensor>>>()?;
+    Tensor::cat(&flat, 0)
+}
+
+/// Flatten the values of `vars` into a single vector
+pub(crate) fn flatten_vars<V: Borrow<Var>>(vars: &[V]) -> CResult<Tensor> {
+    let flat = vars
+        .iter()
+        .map(|v| v.borrow().flatten_all())
+        .collect::<CResult<Vec<Tensor>>>()?;
+    Tensor::cat(&flat, 0)
+}
+
+/// Split the flat vector `flat` into tensors of the shapes of `vars`, the inverse of [`flatten_vars`]
+pub(crate) fn unflatten<V: Borrow<Var>>(flat: &Tensor, vars: &[V]) -> CResult<Vec<Tensor>> {
+    let mut offset = 0;
+    vars.iter()
+        .map(|var| {
+            let var = var.borrow();
+            let n_elems = var.elem_count();
+            let tensor = flat.narrow(0, offset, n_elems)?.reshape(var.shape());
+            offset += n_elems;
+            tensor
+        })
+        .collect()
+}
+
+/// Remove a tensor from a state dict, erroring if it is missing
+pub(crate) fn take_state(state: &mut HashMap<String, Tensor>, key: &str) -> CResult<Tensor> {
+    match state.remove(key) {
+        Some(tensor) => Ok(tensor),
+        None => candle_core::bail!("optimiser state is missing {key}"),
+    }
+}
+
+/// Check a loaded tensor has the shape of the state variable it is for, and convert it to the variable's dtype and
+/// device, so every entry can be checked before any is set
+pub(crate) fn state_for_var(var: &Var, tensor: &Tensor) -> CResult<Tensor> {
+    if tensor.shape() != var.shape() {
+        candle_core::bail!(
+            "optimiser state has shape {:?}, but the variable has shape {:?}",
+            tensor.dims(),
+            var.dims()
+        );
+    }
+    tensor.to_dtype(var.dtype())?.to_device(var.device())
+}
+
+/// Elementwise sign of a tensor, with zero mapped to zero
+pub(crate) fn sign(xs: &Tensor) -> CResult<Tensor> {
+    let dtype = xs.dtype();
+    xs.gt(0.)?.to_dtype(dtype)? - xs.lt(0.)?.to_dtype(dtype)?
+}
+
+/// Layer-wise trust ratio $||\\theta||_2 / ||u||_2$ of a variable and its update, as used by LARS and LAMB
+///
+/// This is 1 if either norm is zero, so variables initialised to zero (or without a gradient) still move
+pub(crate) fn trust_ratio(theta: &Tensor, update: &Tensor) -> CResult<f64> {
+    let norm = |xs: &Tensor| -> CResult<f64> {
+        Ok(xs
+            .sqr()?
+            .sum_all()?
+            .to_dtype(DType::F64)?
+            .to_scalar::<f64>()?
+            .sqrt())
+    };
+    let theta_norm = norm(theta)?;
+    let update_norm = norm(update)?;
+    if theta_norm > 0. && update_norm > 0. {
+        Ok(theta_norm / update_norm)
+    } else {
+        Ok(1.)
+    }
+}
+
+/// Bias correction $1 - \\beta^t$ for an exponential moving average with decay `beta` after `t` steps
+///
+/// This is computed as $-\\text{expm1}(t \\ln \\beta)$ rather than directly, as `1. - beta.powf(t)` suffers catastrophic
+/// cancellation when `beta` is close to 1 and `t` is small (for `beta = 0.999999` at `t = 1` it loses about 6 digits).
+/// The logarithm is taken as `ln_1p(beta - 1)`, where `beta - 1` is exact for `beta` between 0.5 and 1.
+/// For large `t` the result tends smoothly to 1 without underflow issues.
+pub(crate) fn bias_correction(beta: f64, t: f64) -> f64 {
+    -(t * (beta - 1.).ln_1p()).exp_m1()
+}
+
+/// Whether a dtype is a reduced precision float, for which update arithmetic may underflow
+pub(crate) fn is_low_precision(dtype: DType) -> bool {
+    matches!(dtype, DType::F16 | DType::BF16)
+}
+
 /// Type of momentum to use
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub enum Momentum {
@@ -95,3 +471,149 @@ pub enum Momentum {
     /// nesterov momentum
     Nesterov(f64),
 }
+
+/// A logger recording warnings, shared by every test in the crate as only one logger can be set per process
+#[cfg(test)]
+pub(crate) mod test_logger {
+    use std::sync::{Mutex, Once, PoisonError};
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl log::Log for Recorder {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let mut logged = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+                logged.push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+    static INSTALL: Once = Once::new();
+
+    /// install the recorder, which may be called by any number of tests
+    pub(crate) fn install() {
+        INSTALL.call_once(|| {
+            log::set_logger(&RECORDER).expect("the test logger is the only logger set");
+            log::set_max_level(log::LevelFilter::Warn);
+        });
+    }
+
+    /// the warnings logged so far by every test
+    pub(crate) fn warnings() -> Vec<String> {
+        RECORDER
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adahessian::ParamsAdaHessian;
+    use crate::adam::{Adam, ParamsAdam};
+    use crate::adan::ParamsAdan;
+    use crate::esgd::{ParamsSGD, SGD};
+    use candle_nn::Optimizer;
+
+    #[test]
+    fn unused_config_test() -> CResult<()> {
+        test_logger::install();
+        let params = ParamsSGD {
+            dampening: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(params.unused_config().len(), 1);
+        assert!(params.check_unused_config().is_err());
+        assert!(ParamsSGD::default().check_unused_config().is_ok());
+        let w = Var::new(&[1f32], &Device::Cpu)?;
+        // by default this is only a warning
+        SGD::new(vec![w.clone()], params.clone())?;
+        assert!(test_logger::warnings()
+            .iter()
+            .any(|warning| warning.contains("unused configuration: dampening")));
+        assert!(SGD::new_strict(vec![w.clone()], params).is_err());
+        SGD::new_strict(vec![w.clone()], ParamsSGD::default())?;
+
+        let adam = ParamsAdam {
+            weight_decay: Some(Decay::DecoupledWeightDecay(0.)),
+            ..Default::default()
+        };
+        assert!(Adam::new_strict(vec![w.clone()], adam).is_err());
+        let adan = ParamsAdan {
+            no_prox: true,
+            ..Default::default()
+        };
+        assert_eq!(adan.unused_config().len(), 1);
+        let adan = ParamsAdan {
+            weight_decay: Some(0.1),
+            ..adan
+        };
+        assert!(adan.check_unused_config().is_ok());
+        let adahessian = ParamsAdaHessian {
+            hessian_power: 0.,
+            spatial_averaging: true,
+            ..Default::default()
+        };
+        assert_eq!(adahessian.unused_config().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn varmap_test() -> CResult<()> {
+        let varmap = VarMap::new();
+        let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        vb.get_with_hints(1, "b", candle_nn::Init::Const(0.))?;
+        vb.get_with_hints(1, "a", candle_nn::Init::Const(0.))?;
+        let vars = varmap_vars(&varmap);
+        assert_eq!(vars.len(), 2);
+        assert!(missing_vars(&varmap, &vars).is_empty());
+        // the first var is named a, so b is missing
+        assert_eq!(missing_vars(&varmap, &vars[..1]), ["b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn bias_correction_test() {
+        for beta in [0.9, 0.999, 0.999_999] {
+            // at t = 1 the correction is 1 - beta, which is exact in f64
+            let expected: f64 = 1. - beta;
+            assert!((bias_correction(beta, 1.) - expected).abs() <= 1e-15 * expected);
+            // at t = 2 it factors as (1 - beta)(1 + beta) with no cancellation
+            let expected = (1. - beta) * (1. + beta);
+            assert!((bias_correction(beta, 2.) - expected).abs() <= 1e-14 * expected);
+            // at large t, beta^t is far from 1 so the direct form is accurate
+            let expected = 1. - (1e6 * beta.ln()).exp();
+            assert!((bias_correction(beta, 1e6) - expected).abs() <= 1e-12);
+        }
+        // the direct form loses precision when beta is close to 1
+        let (beta, t) = (0.999_999_f64, 2.);
+        let expected = (1. - beta) * (1. + beta);
+        assert!((1. - beta.powf(t) - expected).abs() > 1e-14 * expected);
+    }
+
+    #[test]
+    fn unflatten_test() -> CResult<()> {
+        let a = Var::new(&[[0f32, 0.], [0., 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let flat = Tensor::new(&[1f32, 2., 3., 4., 5.], &Device::Cpu)?;
+        let parts = unflatten(&flat, &[&a, &b])?;
+        assert_eq!(parts[0].to_vec2::<f32>()?, [[1., 2.], [3., 4.]]);
+        assert_eq!(parts[1].to_scalar::<f32>()?, 5.);
+        a.set(&parts[0])?;
+        b.set(&parts[1])?;
+        assert_eq!(
+            flatten_vars(&[a, b])?.to_vec1::<f32>()?,
+            [1., 2., 3., 4., 5.]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+/*!
+Lion optimiser
+
+EvoLved Sign Momentum, described in [Symbolic Discovery of Optimization Algorithms](https://arxiv.org/abs/2302.06675)
+
+Only a single momentum buffer is tracked, and every element is moved by the same magnitude (the sign of the update),
+so the learning rate is typically 3-10x smaller than for Adam, with the weight decay correspondingly larger.
+
+Pseudocode (with decoupled weight decay):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\beta_1, \\beta_2
+        \\text{ (betas)},\\theta_0 \\text{ (params)},f(\\theta) \\text{ (objective)},
+        \\: \\lambda \\text{ (weight decay)}                                                \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ (momentum)}                          \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}c_t      \\leftarrow   \\mathrm{sign}\\left(\\beta_1 m_{t-1} + (1 - \\beta_1) g_t\\right)               \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\left(c_t + \\lambda \\theta_{t-1}\\right)     \\\\
+    &\\hspace{5mm}m_t      \\leftarrow   \\beta_2 m_{t-1} + (1 - \\beta_2) g_t               \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    sign, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot, OptimParams,
+    OptimVars, UnusedConfig,
+};
+
+/// Lion optimiser
+///
+/// EvoLved Sign Momentum, described in [Symbolic Discovery of Optimization Algorithms](https://arxiv.org/abs/2302.06675)
+#[derive(Debug)]
+pub struct Lion {
+    vars: Vec<VarLion>,
+    params: ParamsLion,
+}
+
+#[derive(Debug)]
+struct VarLion {
+    theta: Var,
+    m: Var,
+}
+
+/// Parameters for the Lion optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsLion {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for interpolating the momentum and gradient to get the update direction
+    pub beta_1: f64,
+    /// Coefficient for the moving average of the gradient
+    pub beta_2: f64,
+    /// Decoupled weight decay
+    pub weight_decay: Option<f64>,
+}
+
+impl Default for ParamsLion {
+    fn default() -> Self {
+        Self {
+            lr: 1e-4,
+            beta_1: 0.9,
+            beta_2: 0.99,
+            weight_decay: None,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsLion {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay).into_iter().collect()
+    }
+}
+
+impl Optimizer for Lion {
+    type Config = ParamsLion;
+
+    fn new(vars: Vec<Var>, params: ParamsLion) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let m = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                Ok(VarLion { theta: var, m })
+            })
+            .collect::<Result<Vec<VarLion>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        for var in &self.vars {
+            let theta = &var.theta;
+            let m = &var.m;
+            if let Some(grad) = grads.get(theta) {
+                let update = sign(
+                    &((self.params.beta_1 * m.as_tensor())? + (1. - self.params.beta_1) * grad)?,
+                )?;
+                let update = match self.params.weight_decay {
+                    Some(decay) => (update + (decay * theta.as_tensor())?)?,
+                    None => update,
+                };
+                theta.set(&theta.sub(&(update * self.params.lr)?)?)?;
+                m.set(
+                    &((self.params.beta_2 * m.as_tensor())? + (1. - self.params.beta_2) * grad)?,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Lion {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Lion {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay.map(Decay::DecoupledWeightDecay),
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Lion {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Lion {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Tensor, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsLion {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Lion::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn into_inner_test() -> Result<()> {
+        let params = ParamsLion::default();
+        let w = Var::new(&[[3f32, 1.]], &Device::Cpu)?;
+        let b = Var::new(-2f32, &Device::Cpu)?;
+        let optim = Lion::new(vec![w.clone(), b.clone()], params)?;
+        let inner = optim.into_inner();
+        assert_eq!(inner[0].as_tensor().to_vec2::<f32>()?, &[[3f32, 1.]]);
+        assert_approx_eq!(inner[1].as_tensor().to_vec0::<f32>()?, -2_f32);
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsLion {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Lion::new(vec![w.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsLion {
+            lr: 0.002,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn sign_step_test() -> Result<()> {
+        let params = ParamsLion {
+            lr: 0.1,
+            weight_decay: Some(0.5),
+            ..Default::default()
+        };
+        let w = Var::new(&[2f32, -2., 2.], &Device::Cpu)?;
+        let mut optim = Lion::new(vec![w.clone()], params)?;
+        // gradients of very different magnitudes (and zero) all give unit sized updates
+        let coeffs = Tensor::new(&[100f32, 0.01, 0.], &Device::Cpu)?;
+        optim.backward_step(&w.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        // theta - lr * (sign(g) + wd * theta)
+        let w = w.to_vec1::<f32>()?;
+        for (w, expected) in w.into_iter().zip([1.8, -2.0, 1.9]) {
+            assert_approx_eq!(w, expected, 1e-6);
+        }
+        // the momentum only tracks the gradient
+        let m = optim.vars[0].m.to_vec1::<f32>()?;
+        for (m, expected) in m.into_iter().zip([1., 0.0001, 0.]) {
+            assert_approx_eq!(m, expected, 1e-6);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+/*!
+Lookahead optimiser
+
+As described in [Lookahead Optimizer: k steps forward, 1 step back](https://arxiv.org/abs/1907.08610)
+
+[`Lookahead`] wraps any optimiser in this crate: the wrapped optimiser updates the variables (the fast weights) as normal,
+while a copy of the slow weights is kept. Every $k$ steps the slow weights are moved towards the fast weights and copied back
+into the variables:
+
+$$
+\\begin{aligned}
+    &\\phi_{t} \\gets \\phi_{t-1} + \\alpha (\\theta_{t} - \\phi_{t-1}) \\\\
+    &\\theta_{t} \\gets \\phi_{t}
+\\end{aligned}
+$$
+
+The state of the wrapped optimiser (such as momentum buffers) is not reset when the weights are synchronised.
+
+If the wrapped optimiser implements [`OptimState`], so does the wrapper: its state holds the slow weights and the
+position in the synchronisation cycle alongside the state of the wrapped optimiser, with the keys of the latter
+prefixed by `inner.`.
+*/
+
+use std::collections::HashMap;
+
+use candle_core::{backprop::GradStore, DType, Device, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{take_state, OptimState, OptimVars};
+
+/// Lookahead wrapper around an optimiser
+#[derive(Debug)]
+pub struct Lookahead<O: Optimizer> {
+    inner: O,
+    vars: Vec<VarLookahead>,
+    k: usize,
+    alpha: f64,
+    fast_steps: usize,
+}
+
+#[derive(Debug)]
+struct VarLookahead {
+    theta: Var,
+    slow: Tensor,
+}
+
+impl<O: Optimizer> Optimizer for Lookahead<O> {
+    type Config = O::Config;
+
+    /// create the wrapped optimiser, with the defaults from the paper of $k = 5$ and $\\alpha = 0.5$
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Self::new(O::new(vars.clone(), config)?, vars, 5, 0.5)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        self.inner.step(grads)?;
+        self.fast_steps += 1;
+        if self.fast_steps == self.k {
+            self.fast_steps = 0;
+            for var in &mut self.vars {
+                let slow = (&var.slow + ((var.theta.as_tensor() - &var.slow)? * self.alpha)?)?;
+                var.theta.set(&slow)?;
+                var.slow = slow;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer> OptimVars for Lookahead<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl<O: Optimizer + OptimState> OptimState for Lookahead<O> {
+    #[allow(clippy::cast_precision_loss)]
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        let mut state: HashMap<String, Tensor> = self
+            .inner
+            .state_dict()?
+            .into_iter()
+            .map(|(key, tensor)| (format!("inner.{key}"), tensor))
+            .collect();
+        state.insert(
+            "fast_steps".to_string(),
+            Tensor::new(self.fast_steps as f64, &Device::Cpu)?,
+        );
+        // the slow weights are replaced rather than updated in place, so need not be copied
+        for (i, var) in self.vars.iter().enumerate() {
+            state.insert(format!("slow.{i}"), var.slow.clone());
+        }
+        Ok(state)
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn load_state_dict(&mut self, mut state: HashMap<String, Tensor>) -> Result<()> {
+        let fast_steps = take_state(&mut state, "fast_steps")?
+            .to_dtype(DType::F64)?
+            .to_scalar::<f64>()? as usize;
+        let mut slow = Vec::with_capacity(self.vars.len());
+        for (i, var) in self.vars.iter().enumerate() {
+            let tensor = take_state(&mut state, &format!("slow.{i}"))?;
+            if tensor.shape() != var.theta.shape() {
+                candle_core::bail!(
+                    "slow weights of shape {:?} given for variable of shape {:?}",
+                    tensor.shape(),
+                    var.theta.shape()
+                );
+            }
+            slow.push(
+                tensor
+                    .to_dtype(var.theta.dtype())?
+                    .to_device(var.theta.device())?,
+            );
+        }
+        let inner = state
+            .into_iter()
+            .filter_map(|(key, tensor)| {
+                key.strip_prefix("inner.")
+                    .map(|key| (key.to_string(), tensor))
+            })
+            .collect();
+        self.inner.load_state_dict(inner)?;
+        for (var, slow) in self.vars.iter_mut().zip(slow) {
+            var.slow = slow;
+        }
+        self.fast_steps = fast_steps;
+        Ok(())
+    }
+}
+
+impl<O: Optimizer> Lookahead<O> {
+    /// wrap an optimiser of `vars`, synchronising the slow weights every `k` steps with step size `alpha`
+    ///
+    /// `vars` should be the variables the wrapped optimiser was constructed with
+    ///
+    /// # Errors
+    ///
+    /// Errors if `k` is 0 or `alpha` is not in $(0, 1]$
+    pub fn new(inner: O, vars: Vec<Var>, k: usize, alpha: f64) -> Result<Self> {
+        if k == 0 {
+            candle_core::bail!("lookahead k must be at least 1");
+        }
+        if !(alpha > 0. && alpha <= 1.) {
+            candle_core::bail!("lookahead alpha must be in (0, 1], got {alpha}");
+        }
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                // the var is updated in place so the slow weights must be a copy
+                let slow = var.as_tensor().copy()?;
+                Ok(VarLookahead { theta: var, slow })
+            })
+            .collect::<Result<Vec<VarLookahead>>>()?;
+        Ok(Self {
+            inner,
+            vars,
+            k,
+            alpha,
+            fast_steps: 0,
+        })
+    }
+
+    /// the slow weights, in the same order as the vars
+    #[must_use]
+    pub fn slow_weights(&self) -> Vec<&Tensor> {
+        self.vars.iter().map(|v| &v.slow).collect()
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_optimiser(self) -> O {
+        self.inner
+    }
+
+    /// return the slow weights, in the same order as the vars
+    ///
+    /// these are the weights to keep at the end of training, while the vars hold the fast weights
+    /// unless the last step synchronised them
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Tensor> {
+        self.vars.into_iter().map(|v| v.slow).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adamax::{Adamax, ParamsAdaMax};
+    use crate::esgd::{ParamsSGD, SGD};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    #[test]
+    fn lookahead_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let sgd = SGD::new(vec![w.clone()], params)?;
+        let mut optim = Lookahead::new(sgd, vec![w.clone()], 2, 0.5)?;
+        let loss = w.as_tensor().sum_all()?;
+        optim.backward_step(&loss)?;
+        // a fast step leaves the slow weights unchanged
+        for (w, expected) in w.to_vec1::<f32>()?.into_iter().zip([0.9, 1.9]) {
+            assert_approx_eq!(w, expected, 1e-6);
+        }
+        assert_eq!(optim.slow_weights()[0].to_vec1::<f32>()?, [1., 2.]);
+        optim.backward_step(&loss)?;
+        // fast weights reach [0.8, 1.8], and the slow weights move half way there
+        for (w, expected) in w.to_vec1::<f32>()?.into_iter().zip([0.9, 1.9]) {
+            assert_approx_eq!(w, expected, 1e-6);
+        }
+        assert_eq!(
+            optim.slow_weights()[0].to_vec1::<f32>()?,
+            w.to_vec1::<f32>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn trait_new_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let params = ParamsAdaMax {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let mut optim: Lookahead<Adamax> = Optimizer::new(vec![w.clone()], params)?;
+        optim.set_learning_rate(0.2);
+        assert_approx_eq!(optim.inner().learning_rate(), 0.2);
+        assert_eq!(optim.vars().len(), 1);
+        let loss = w.as_tensor().sqr()?.sum_all()?;
+        for _ in 0..10 {
+            optim.backward_step(&loss)?;
+        }
+        // ten steps is two synchronisations, so the vars hold the slow weights
+        assert_eq!(
+            optim.slow_weights()[0].to_vec1::<f32>()?,
+            w.to_vec1::<f32>()?
+        );
+        assert!(w.to_vec1::<f32>()?[1] < 2.);
+        Ok(())
+    }
+
+    #[test]
+    fn state_test() -> Result<()> {
+        let params = ParamsAdaMax {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut optim = Lookahead::new(
+            Adamax::new(vec![w.clone()], params.clone())?,
+            vec![w.clone()],
+            3,
+            0.5,
+        )?;
+        let loss = w.as_tensor().sqr()?.sum_all()?;
+        for _ in 0..4 {
+            optim.backward_step(&loss)?;
+        }
+        let state = optim.state_dict()?;
+        assert!(state.contains_key("inner.m.0"));
+
+        // a new optimiser loaded with the state takes the same steps from the same weights
+        let w_loaded = Var::from_tensor(&w.as_tensor().copy()?)?;
+        let mut loaded = Lookahead::new(
+            Adamax::new(vec![w_loaded.clone()], params)?,
+            vec![w_loaded.clone()],
+            3,
+            0.5,
+        )?;
+        loaded.load_state_dict(state)?;
+        let loss_loaded = w_loaded.as_tensor().sqr()?.sum_all()?;
+        for _ in 0..5 {
+            optim.backward_step(&loss)?;
+            loaded.backward_step(&loss_loaded)?;
+        }
+        assert_eq!(w.to_vec1::<f32>()?, w_loaded.to_vec1::<f32>()?);
+        let slow = optim.into_inner();
+        assert_eq!(
+            slow[0].to_vec1::<f32>()?,
+            loaded.slow_weights()[0].to_vec1::<f32>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let sgd = SGD::new(vec![w.clone()], ParamsSGD::default())?;
+        assert!(Lookahead::new(sgd, vec![w.clone()], 0, 0.5).is_err());
+        let sgd = SGD::new(vec![w.clone()], ParamsSGD::default())?;
+        assert!(Lookahead::new(sgd, vec![w], 5, 1.5).is_err());
+        Ok(())
+    }
+}
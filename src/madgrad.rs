@@ -0,0 +1,292 @@
+/*!
+MADGRAD optimiser
+
+Described in [Adaptivity without Compromise: A Momentumized, Adaptive, Dual Averaged Gradient Method for Stochastic Optimization](https://arxiv.org/abs/2101.11075)
+
+A dual averaging method: rather than accumulating steps, it keeps weighted sums of the gradients $s$ and of their
+squares $\\nu$, and places the iterate $z$ at the initial point $x_0$ less $s$ scaled by the cube root of $\\nu$. The
+weights $\\lambda_k$ grow as the square root of the step count. Momentum is applied by averaging the variables towards
+$z$. This follows the reference implementation in the `madgrad` package.
+
+Pseudocode (including decoupling of weight decay):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: x_0 \\text{ (params)}, \\: f(x)
+        \\text{ (objective)}, \\: \\beta \\text{ (momentum)},                               \\\\
+    &\\hspace{13mm} \\lambda_{wd} \\text{ (weight decay)}, \\: \\epsilon \\text{ (epsilon)}      \\\\
+    &\\textbf{initialize} :  s_0 \\leftarrow 0, \\: \\nu_0 \\leftarrow 0                        \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: k=0 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_k           \\leftarrow   \\nabla_{x} f_k (x_k)                         \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\lambda_{wd} \\textbf{ is } \\text{Some}                   \\\\
+    &\\hspace{10mm}\\textbf{if} \\: \\textit{decoupled}                                      \\\\
+    &\\hspace{15mm} x_k \\leftarrow x_k - \\gamma \\lambda_{wd} x_k                           \\\\
+    &\\hspace{10mm}\\textbf{else}                                                          \\\\
+    &\\hspace{15mm} g_k \\leftarrow g_k + \\lambda_{wd} x_k                                  \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\beta = 0                                                \\\\
+    &\\hspace{10mm} x_0 \\leftarrow x_k + s_k / (\\nu_k^{1/3} + \\epsilon)                     \\\\
+    &\\hspace{5mm}\\lambda_k \\leftarrow \\gamma \\sqrt{k + 1}                                 \\\\
+    &\\hspace{5mm}s_{k+1} \\leftarrow s_k + \\lambda_k g_k                                    \\\\
+    &\\hspace{5mm}\\nu_{k+1} \\leftarrow \\nu_k + \\lambda_k g_k^2                               \\\\
+    &\\hspace{5mm}z_{k+1} \\leftarrow x_0 - s_{k+1} / (\\nu_{k+1}^{1/3} + \\epsilon)            \\\\
+    &\\hspace{5mm}x_{k+1} \\leftarrow \\beta x_k + (1 - \\beta) z_{k+1}                        \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  x_k                                                          \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+Without momentum the initial point is recomputed from the current variables each step, as in the reference
+implementation, so that weight decay and any changes made to the variables outside the optimiser are kept.
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot, Momentum,
+    OptimParams, OptimVars, UnusedConfig,
+};
+
+/// MADGRAD optimiser
+///
+/// Described in [Adaptivity without Compromise: A Momentumized, Adaptive, Dual Averaged Gradient Method for Stochastic Optimization](https://arxiv.org/abs/2101.11075)
+#[derive(Debug)]
+pub struct Madgrad {
+    vars: Vec<VarMadgrad>,
+    params: ParamsMadgrad,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarMadgrad {
+    theta: Var,
+    /// initial point of the dual averaging
+    x0: Var,
+    /// weighted sum of the gradients
+    s: Var,
+    /// weighted sum of the squared gradients
+    nu: Var,
+}
+
+/// Parameters for the MADGRAD optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsMadgrad {
+    /// Learning rate
+    pub lr: f64,
+    /// Momentum, the weight of the current variables when averaging towards the dual averaged iterate
+    pub momentum: f64,
+    /// Weight decay
+    pub weight_decay: Option<Decay>,
+    /// Term added to the cube root of the squared gradient sum to improve numerical stability
+    pub eps: f64,
+}
+
+impl Default for ParamsMadgrad {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            momentum: 0.9,
+            weight_decay: None,
+            eps: 1e-6,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsMadgrad {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Optimizer for Madgrad {
+    type Config = ParamsMadgrad;
+
+    fn new(vars: Vec<Var>, params: ParamsMadgrad) -> Result<Self> {
+        if !(0. ..1.).contains(&params.momentum) {
+            candle_core::bail!(
+                "MADGRAD momentum must be in [0, 1), got {}",
+                params.momentum
+            );
+        }
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let x0 = Var::from_tensor(&var.as_tensor().copy()?)?;
+                let s = Var::zeros(shape, dtype, device)?;
+                let nu = Var::zeros(shape, dtype, device)?;
+                Ok(VarMadgrad {
+                    theta: var,
+                    x0,
+                    s,
+                    nu,
+                })
+            })
+            .collect::<Result<Vec<VarMadgrad>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 0.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let lr = self.params.lr;
+        let eps = self.params.eps;
+        let momentum = self.params.momentum;
+        let lambda = lr * (self.t + 1.).sqrt();
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let grad = match self.params.weight_decay {
+                    Some(Decay::WeightDecay(wd)) => (grad + (wd * theta.as_tensor())?)?,
+                    Some(Decay::DecoupledWeightDecay(decay)) => {
+                        // decoupled weight decay step
+                        theta.set(&(theta.as_tensor() * lr.mul_add(-decay, 1.))?)?;
+                        grad.clone()
+                    }
+                    None => grad.clone(),
+                };
+                if momentum == 0. {
+                    let rms = (var.nu.as_tensor().powf(1. / 3.)? + eps)?;
+                    var.x0
+                        .set(&(theta.as_tensor() + var.s.as_tensor().div(&rms)?)?)?;
+                }
+                let s = (var.s.as_tensor() + (&grad * lambda)?)?;
+                let nu = (var.nu.as_tensor() + (grad.sqr()? * lambda)?)?;
+                let rms = (nu.powf(1. / 3.)? + eps)?;
+                let z = (var.x0.as_tensor() - s.div(&rms)?)?;
+                if momentum == 0. {
+                    theta.set(&z)?;
+                } else {
+                    theta.set(&((theta.as_tensor() * momentum)? + (z * (1. - momentum))?)?)?;
+                }
+                var.s.set(&s)?;
+                var.nu.set(&nu)?;
+            }
+        }
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Madgrad {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Madgrad {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: None,
+            weight_decay: self.params.weight_decay,
+            momentum: (self.params.momentum != 0.)
+                .then_some(Momentum::Classical(self.params.momentum)),
+        }
+    }
+}
+
+impl OptimVars for Madgrad {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Madgrad {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsMadgrad {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Madgrad::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsMadgrad {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Madgrad::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsMadgrad {
+            lr: 0.002,
+            momentum: 0.,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        assert!(Madgrad::new(
+            vec![w],
+            ParamsMadgrad {
+                momentum: 1.,
+                ..Default::default()
+            }
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn first_step_test() -> Result<()> {
+        // on the first step s = lr g and nu = lr g^2, so z moves from the start by lr^(2/3) g^(1/3), up to eps
+        let x = Var::new(1f64, &Device::Cpu)?;
+        let params = ParamsMadgrad {
+            lr: 0.1,
+            momentum: 0.,
+            ..Default::default()
+        };
+        let mut optim = Madgrad::new(vec![x.clone()], params)?;
+        optim.backward_step(&x.as_tensor().affine(8., 0.)?)?;
+        assert_approx_eq!(x.to_scalar::<f64>()?, 1. - 0.01f64.cbrt() * 2., 1e-5);
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+/*!
+Learnable per-element learning rates
+
+Gradient descent where every element of every variable has its own learning rate, which is itself adapted each step
+based on whether consecutive gradients agree, as in
+[Increased rates of convergence through learning rate adaptation](https://doi.org/10.1016/0893-6080(88)90003-2)
+and [Online Learning Rate Adaptation with Hypergradient Descent](https://arxiv.org/abs/1703.04782).
+
+When consecutive gradients of an element have the same sign the minimum is further along that direction, so its
+learning rate is increased; when they have opposite signs the last step overshot, so it is decreased. The product
+$g_t g_{t-1}$ is (up to sign and scale) the gradient of the loss with respect to the learning rate, so this is gradient
+descent on the learning rates themselves.
+
+$$
+\\begin{aligned}
+    &\\textbf{if} \\: \\textit{sign}: \\: \\eta_t \\leftarrow \\eta_{t-1} \\left(1 + \\beta \\, \\mathrm{sign}(g_t g_{t-1})\\right) \\\\
+    &\\textbf{else}: \\: \\eta_t \\leftarrow \\eta_{t-1} + \\beta g_t g_{t-1} \\\\
+    &\\eta_t \\leftarrow \\mathrm{clamp}(\\eta_t, \\eta_{min}, \\eta_{max}) \\\\
+    &\\theta_t \\leftarrow \\theta_{t-1} - \\eta_t g_t
+\\end{aligned}
+$$
+
+with all operations element-wise, and the learning rates starting at `lr`. The bounds keep every learning rate positive.
+*/
+
+use candle_core::{Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+use log::warn;
+
+use crate::{sign, OptimParams, OptimVars};
+
+/// How the learning rates are updated from the agreement of consecutive gradients
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd)]
+pub enum MetaLrUpdate {
+    /// multiply by $1 \\pm \\beta$ according to the sign of the agreement
+    Sign,
+    /// add $\\beta$ times the agreement (hypergradient descent)
+    Magnitude,
+}
+
+/// Parameters for the learnable learning rate optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsMetaLr {
+    /// Initial learning rate of every element
+    pub lr: f64,
+    /// Learning rate $\\beta$ of the learning rates
+    pub meta_lr: f64,
+    /// How the learning rates are updated
+    pub update: MetaLrUpdate,
+    /// Bounds `(lr_min, lr_max)` the learning rates are clamped to: `lr_min` must be positive
+    pub lr_bounds: (f64, f64),
+}
+
+impl Default for ParamsMetaLr {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            meta_lr: 0.1,
+            update: MetaLrUpdate::Sign,
+            lr_bounds: (1e-6, 1.),
+        }
+    }
+}
+
+impl ParamsMetaLr {
+    /// Check the learning rate bounds are positive and ordered, and contain the initial learning rate
+    ///
+    /// # Errors
+    ///
+    /// Errors if the bounds are invalid
+    pub fn validate(&self) -> Result<()> {
+        let (lr_min, lr_max) = self.lr_bounds;
+        if lr_min.is_nan() || lr_min <= 0. {
+            candle_core::bail!("minimum learning rate must be positive, got {lr_min}");
+        }
+        if lr_max.is_nan() || lr_min > lr_max {
+            candle_core::bail!("learning rate bounds ({lr_min}, {lr_max}) are not ordered");
+        }
+        if self.lr.is_nan() || self.lr < lr_min || self.lr > lr_max {
+            candle_core::bail!(
+                "learning rate {} is outside the bounds ({lr_min}, {lr_max})",
+                self.lr
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Gradient descent with learnable per-element learning rates
+#[derive(Debug)]
+pub struct MetaLr {
+    vars: Vec<VarMetaLr>,
+    params: ParamsMetaLr,
+}
+
+#[derive(Debug)]
+struct VarMetaLr {
+    theta: Var,
+    lr: Var,
+    prev_grad: Option<Tensor>,
+}
+
+impl Optimizer for MetaLr {
+    type Config = ParamsMetaLr;
+
+    fn new(vars: Vec<Var>, params: ParamsMetaLr) -> Result<Self> {
+        params.validate()?;
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let lr = Var::from_tensor(&(var.ones_like()? * params.lr)?)?;
+                Ok(VarMetaLr {
+                    theta: var,
+                    lr,
+                    prev_grad: None,
+                })
+            })
+            .collect::<Result<Vec<VarMetaLr>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    /// the initial learning rate, scaled by any calls to `set_learning_rate`
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let (lr_min, lr_max) = self.params.lr_bounds;
+        let meta_lr = self.params.meta_lr;
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                if let Some(prev_grad) = &var.prev_grad {
+                    let agreement = grad.mul(prev_grad)?;
+                    let lr_next = match self.params.update {
+                        MetaLrUpdate::Sign => {
+                            var.lr.mul(&((sign(&agreement)? * meta_lr)? + 1.)?)?
+                        }
+                        MetaLrUpdate::Magnitude => (var.lr.as_tensor() + (agreement * meta_lr)?)?,
+                    };
+                    var.lr.set(&lr_next.clamp(lr_min, lr_max)?)?;
+                }
+                theta.set(&theta.sub(&var.lr.mul(grad)?)?)?;
+                var.prev_grad = Some(grad.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// set the initial learning rate, scaling the current learning rates proportionally (within the bounds)
+    fn set_learning_rate(&mut self, lr: f64) {
+        let scale = lr / self.params.lr;
+        let (lr_min, lr_max) = self.params.lr_bounds;
+        for var in &self.vars {
+            let scaled = (var.lr.as_tensor() * scale).and_then(|s| s.clamp(lr_min, lr_max));
+            if let Err(e) = scaled.and_then(|scaled| var.lr.set(&scaled)) {
+                warn!("failed to rescale the learning rates: {e}");
+            }
+        }
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for MetaLr {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// Set the parameters for the optimiser
+    ///
+    /// The current learning rates are kept (clamped to any new bounds), so changing `lr` here has no effect on them:
+    /// use `set_learning_rate` to rescale them.
+    ///
+    /// # Warning
+    ///
+    /// Parameters failing [`ParamsMetaLr::validate`] are ignored with a warning, keeping the current parameters
+    fn set_params(&mut self, config: Self::Config) {
+        if let Err(e) = config.validate() {
+            warn!("ignoring invalid MetaLr parameters: {e}");
+            return;
+        }
+        let (lr_min, lr_max) = config.lr_bounds;
+        for var in &self.vars {
+            if let Err(e) = var
+                .lr
+                .clamp(lr_min, lr_max)
+                .and_then(|clamped| var.lr.set(&clamped))
+            {
+                warn!("failed to clamp the learning rates to the new bounds: {e}");
+            }
+        }
+        self.params = config;
+    }
+}
+
+impl OptimVars for MetaLr {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl MetaLr {
+    /// the current learning rates of each variable, in the same order as the vars
+    #[must_use]
+    pub fn learning_rates(&self) -> Vec<&Tensor> {
+        self.vars.iter().map(|v| v.lr.as_tensor()).collect()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let w = Var::new(&[0f32, 0.], &Device::Cpu)?;
+        let mut optim = MetaLr::new(vec![w], ParamsMetaLr::default())?;
+        assert_approx_eq!(0.01, optim.learning_rate());
+        optim.set_learning_rate(0.02);
+        assert_approx_eq!(0.02, optim.learning_rate());
+        assert_eq!(optim.learning_rates()[0].to_vec1::<f32>()?, [0.02, 0.02]);
+        Ok(())
+    }
+
+    #[test]
+    fn diverging_lrs_test() -> Result<()> {
+        // x is far from the minimum of (x - 10)^2 so its gradient keeps its sign, while y starts oscillating
+        // about the minimum of 50 y^2, as its learning rate is too large
+        let params = ParamsMetaLr {
+            lr: 0.03,
+            meta_lr: 0.5,
+            ..Default::default()
+        };
+        let xy = Var::new(&[0f64, 1.], &Device::Cpu)?;
+        let mut optim = MetaLr::new(vec![xy.clone()], params)?;
+        for _ in 0..5 {
+            let scale = Tensor::new(&[1f64, 50.], &Device::Cpu)?;
+            let shift = Tensor::new(&[10f64, 0.], &Device::Cpu)?;
+            let loss = (xy.as_tensor() - shift)?.sqr()?.mul(&scale)?.sum_all()?;
+            optim.backward_step(&loss)?;
+        }
+        let lrs = optim.learning_rates()[0].to_vec1::<f64>()?;
+        // x: every update multiplies by 1.5
+        assert_approx_eq!(lrs[0], 0.03 * 1.5_f64.powi(4));
+        // y: 1 -> -2 -> 1 -> 0.25 -> -0.031_25 so the gradients disagree, disagree, agree then disagree
+        assert_approx_eq!(lrs[1], 0.03 * 0.5 * 0.5 * 1.5 * 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn bounds_test() -> Result<()> {
+        let params = ParamsMetaLr {
+            lr: 0.1,
+            meta_lr: 100.,
+            update: MetaLrUpdate::Magnitude,
+            lr_bounds: (0.01, 0.2),
+        };
+        let w = Var::new(&[1f64, 1.], &Device::Cpu)?;
+        let mut optim = MetaLr::new(vec![w.clone()], params)?;
+        let coeffs = Tensor::new(&[1f64, -1.], &Device::Cpu)?;
+        for _ in 0..3 {
+            optim.backward_step(&w.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        }
+        // constant gradients always agree: the large meta learning rate pushes the learning rates to the maximum
+        assert_eq!(optim.learning_rates()[0].to_vec1::<f64>()?, [0.2, 0.2]);
+
+        for lr_bounds in [(0., 1.), (0.5, 0.1), (0.5, 1.)] {
+            let params = ParamsMetaLr {
+                lr_bounds,
+                ..Default::default()
+            };
+            assert!(MetaLr::new(vec![w.clone()], params.clone()).is_err());
+            // and are ignored when set later
+            optim.set_params(params);
+            assert_eq!(optim.params().lr_bounds, (0.01, 0.2));
+        }
+        Ok(())
+    }
+}
@@ -41,7 +41,10 @@ $$
 use candle_core::{Result, Var};
 use candle_nn::optim::Optimizer;
 
-use crate::{Decay, OptimParams};
+use crate::{
+    bias_correction, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot,
+    OptimParams, OptimVars, UnusedConfig,
+};
 
 /// Adam optimiser with Nesterov momentum
 ///
@@ -77,7 +80,7 @@ pub struct ParamsNAdam {
     pub eps: f64,
     /// Weight decay
     pub weight_decay: Option<Decay>,
-    /// Momentum decay
+    /// Momentum decay $\psi$ in the schedule $\mu_t = \beta_1 \big(1 - \frac{1}{2} 0.96^{t \psi} \big)$
     pub momentum_decay: f64,
 }
 
@@ -94,10 +97,19 @@ impl Default for ParamsNAdam {
     }
 }
 
+impl UnusedConfig for ParamsNAdam {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
 impl Optimizer for NAdam {
     type Config = ParamsNAdam;
 
     fn new(vars: Vec<Var>, params: ParamsNAdam) -> Result<Self> {
+        warn_unused_config(&params);
         let vars = vars
             .into_iter()
             .filter(|var| var.dtype().is_float())
@@ -160,7 +172,7 @@ impl Optimizer for NAdam {
                                 + ((1. - self.params.beta_2) * grad.powf(2.)?)?)?;
                             let m_hat = (((mu_t2 / (1. - prod2)) * &m_next)?
                                 + (((1. - mu_t) / (1. - prod)) * grad)?)?;
-                            let v_hat = (&v_next / (1. - self.params.beta_2.powf(self.t)))?;
+                            let v_hat = (&v_next / bias_correction(self.params.beta_2, self.t))?;
                             let delta = (m_hat * self.params.lr)?
                                 .div(&(v_hat.powf(0.5)? + self.params.eps)?)?;
                             theta.set(&theta.sub(&(delta))?)?;
@@ -183,7 +195,7 @@ impl Optimizer for NAdam {
                                 + ((1. - self.params.beta_2) * grad.powf(2.)?)?)?;
                             let m_hat = (((mu_t2 / (1. - prod2)) * &m_next)?
                                 + (((1. - mu_t) / (1. - prod)) * grad)?)?;
-                            let v_hat = (&v_next / (1. - self.params.beta_2.powf(self.t)))?;
+                            let v_hat = (&v_next / bias_correction(self.params.beta_2, self.t))?;
                             let delta = (m_hat * self.params.lr)?
                                 .div(&(v_hat.powf(0.5)? + self.params.eps)?)?;
                             theta.set(&theta.sub(&(delta))?)?;
@@ -205,7 +217,7 @@ impl Optimizer for NAdam {
                         + ((1. - self.params.beta_2) * grad.powf(2.)?)?)?;
                     let m_hat = (((mu_t2 / (1. - prod2)) * &m_next)?
                         + (((1. - mu_t) / (1. - prod)) * grad)?)?;
-                    let v_hat = (&v_next / (1. - self.params.beta_2.powf(self.t)))?;
+                    let v_hat = (&v_next / bias_correction(self.params.beta_2, self.t))?;
                     let delta =
                         (m_hat * self.params.lr)?.div(&(v_hat.powf(0.5)? + self.params.eps)?)?;
                     theta.set(&theta.sub(&(delta))?)?;
@@ -234,6 +246,23 @@ impl OptimParams for NAdam {
     }
 }
 
+impl CurrentHyperparams for NAdam {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for NAdam {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
 impl NAdam {
     /// Return the vars being optimised
     #[must_use]
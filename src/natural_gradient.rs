@@ -0,0 +1,237 @@
+/*!
+Natural gradient descent using the empirical Fisher information
+
+Described in [Natural Gradient Works Efficiently in Learning](https://doi.org/10.1162/089976698300017746),
+see also [New insights and perspectives on the natural gradient method](https://arxiv.org/abs/1412.1193) for the
+relation to the Gauss-Newton matrix.
+
+Given per-sample gradients $g_1, \\ldots, g_N$, the step is taken in the direction $\\delta$ solving
+
+$$ \\left(F + \\lambda I\\right) \\delta = \\bar{g}, \\qquad F = \\frac{1}{N}\\sum_{i=1}^{N} g_i g_i^{\\top}, \\qquad \\bar{g} = \\frac{1}{N}\\sum_{i=1}^{N} g_i$$
+
+where $\\lambda$ is the damping. This is solved with [conjugate gradients](crate::cg) using Fisher-vector products, so the
+Fisher matrix itself is never formed:
+
+$$ \\theta_t \\gets \\theta_{t-1} - \\gamma \\delta $$
+
+As the Fisher is estimated from the gradients passed to a step, the per-sample gradients should be provided
+through [`NaturalGradient::step_per_sample`] or [`NaturalGradient::backward_step_per_sample`]. The `Optimizer` trait's `step`
+treats the gradient it is given as a single sample.
+*/
+
+use candle_core::{backprop::GradStore, DType, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{cg::conjugate_gradient, flatten_grads, unflatten, OptimParams, OptimVars};
+
+/// Natural gradient optimiser using the damped empirical Fisher information
+#[derive(Debug)]
+pub struct NaturalGradient {
+    vars: Vec<Var>,
+    params: ParamsNaturalGradient,
+}
+
+/// Parameters for the natural gradient optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsNaturalGradient {
+    /// Learning rate
+    pub lr: f64,
+    /// Damping added to the diagonal of the Fisher: larger values move the step towards gradient descent with learning rate `lr / damping`
+    pub damping: f64,
+    /// Maximum number of conjugate gradient iterations per step
+    pub cg_max_iter: usize,
+    /// Tolerance on the residual norm for conjugate gradient convergence, relative to the norm of the mean gradient
+    pub cg_tol: f64,
+}
+
+impl Default for ParamsNaturalGradient {
+    fn default() -> Self {
+        Self {
+            lr: 1.,
+            damping: 1e-3,
+            cg_max_iter: 10,
+            cg_tol: 1e-10,
+        }
+    }
+}
+
+impl Optimizer for NaturalGradient {
+    type Config = ParamsNaturalGradient;
+
+    fn new(vars: Vec<Var>, params: ParamsNaturalGradient) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect();
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        self.step_per_sample(std::slice::from_ref(grads))
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for NaturalGradient {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl OptimVars for NaturalGradient {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl NaturalGradient {
+    /// Take a step using the gradients of each sample in the batch
+    ///
+    /// # Errors
+    ///
+    /// Errors if no gradients are given, or if the variables are of different dtypes
+    #[allow(clippy::cast_precision_loss)]
+    pub fn step_per_sample(&mut self, grads: &[GradStore]) -> Result<()> {
+        if grads.is_empty() {
+            candle_core::bail!("natural gradient step requires at least one sample gradient");
+        }
+        let n = grads.len() as f64;
+        // N x P matrix of per-sample gradients
+        let per_sample = Tensor::stack(
+            &grads
+                .iter()
+                .map(|g| flatten_grads(g, &self.vars))
+                .collect::<Result<Vec<Tensor>>>()?,
+            0,
+        )?
+        .to_dtype(DType::F64)?;
+        let mean_grad = per_sample.mean(0)?;
+        let damping = self.params.damping;
+        let fisher_vp = |v: &Tensor| -> Result<Tensor> {
+            let fv = per_sample
+                .t()?
+                .matmul(&per_sample.matmul(&v.unsqueeze(1)?)?)?
+                .squeeze(1)?;
+            (fv / n)? + (v * damping)?
+        };
+        let delta = conjugate_gradient(
+            fisher_vp,
+            &mean_grad,
+            self.params.cg_max_iter,
+            self.params.cg_tol,
+        )?;
+        self.apply_step(&(delta * -self.params.lr)?)
+    }
+
+    /// Backpropagate the loss of each sample in the batch, then take a step with their gradients
+    ///
+    /// # Errors
+    ///
+    /// Errors if any of the backward passes fail, or if the step fails
+    pub fn backward_step_per_sample(&mut self, losses: &[Tensor]) -> Result<()> {
+        let grads = losses
+            .iter()
+            .map(Tensor::backward)
+            .collect::<Result<Vec<GradStore>>>()?;
+        self.step_per_sample(&grads)
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+
+    /// add a flat step to the vars
+    fn apply_step(&self, step: &Tensor) -> Result<()> {
+        for (var, update) in self.vars.iter().zip(unflatten(step, &self.vars)?) {
+            var.set(&var.add(&update.to_dtype(var.dtype())?)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsNaturalGradient {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = NaturalGradient::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn into_inner_test() -> Result<()> {
+        let params = ParamsNaturalGradient::default();
+        let w = Var::new(&[[3f32, 1.]], &Device::Cpu)?;
+        let b = Var::new(-2f32, &Device::Cpu)?;
+        let optim = NaturalGradient::new(vec![w.clone(), b.clone()], params)?;
+        let inner = optim.into_inner();
+        assert_eq!(inner[0].as_tensor().to_vec2::<f32>()?, &[[3f32, 1.]]);
+        assert_approx_eq!(inner[1].as_tensor().to_vec0::<f32>()?, -2_f32);
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsNaturalGradient {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = NaturalGradient::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsNaturalGradient {
+            lr: 0.002,
+            damping: 0.1,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn damping_test() -> Result<()> {
+        // with very large damping the step approaches gradient descent with lr / damping
+        let params = ParamsNaturalGradient {
+            lr: 1e6,
+            damping: 1e6,
+            ..Default::default()
+        };
+        let w = Var::new(&[1f64, 2.], &Device::Cpu)?;
+        let mut optim = NaturalGradient::new(vec![w.clone()], params)?;
+        let grads = w.as_tensor().sqr()?.sum_all()?.backward()?;
+        optim.step(&grads)?;
+        let w = w.to_vec1::<f64>()?;
+        assert_approx_eq!(w[0], -1., 1e-4);
+        assert_approx_eq!(w[1], -2., 1e-4);
+        Ok(())
+    }
+}
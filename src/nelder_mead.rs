@@ -0,0 +1,349 @@
+/*!
+Nelder–Mead simplex optimiser
+
+A derivative-free method, described in [A Simplex Method for Function Minimization](https://doi.org/10.1093/comjnl/7.4.308),
+which only evaluates [`Model::loss`], so can be used when the loss is not differentiable. The flattened variables are
+the vertices of a simplex of $n + 1$ points $x_0, \\ldots, x_n$, sorted so that $f(x_0) \\leq \\ldots \\leq f(x_n)$.
+Each step moves the worst point $x_n$ through the centroid $c$ of the others:
+
+$$
+\\begin{aligned}
+    &x_r = c + \\alpha (c - x_n) \\\\
+    &\\textbf{if} \\: f(x_r) < f(x_0) \\: \\textbf{then} \\: \\text{try the expansion} \\: x_e = c + \\gamma (x_r - c) \\\\
+    &\\textbf{else if} \\: f(x_r) < f(x_{n-1}) \\: \\textbf{then} \\: x_n \\gets x_r \\\\
+    &\\textbf{else} \\: \\text{try the contraction} \\: x_c = c + \\rho (x_r - c) \\:
+        \\text{or} \\: c + \\rho (x_n - c) \\: \\text{if} \\: f(x_r) \\geq f(x_n)
+\\end{aligned}
+$$
+
+and if the contraction does not improve on the point it was contracted from, the simplex is shrunk towards the best
+point, $x_i \\gets x_0 + \\sigma (x_i - x_0)$.
+
+The initial simplex is made by perturbing each component of the variables in turn by `initial_step` of its value, or
+by `zero_step` if it is zero, as in SciPy. After each step the variables hold the best point, and the optimiser has
+converged when every point is within `simplex_tol` of it in each component. Loss evaluations that are NaN are treated
+as infinite, so a simplex can step away from where the model is undefined.
+
+The number of loss evaluations grows with the number of variables, so this is suited to models with few parameters.
+*/
+
+use crate::{flatten_vars, unflatten, LossOptimizer, Model, ModelOutcome, OptimVars};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for the Nelder–Mead optimiser
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsNelderMead {
+    /// Reflection coefficient $\\alpha$
+    pub alpha: f64,
+    /// Expansion coefficient $\\gamma$
+    pub gamma: f64,
+    /// Contraction coefficient $\\rho$
+    pub rho: f64,
+    /// Shrink coefficient $\\sigma$
+    pub sigma: f64,
+    /// Relative perturbation of each non-zero component for the initial simplex
+    pub initial_step: f64,
+    /// Perturbation of each zero component for the initial simplex
+    pub zero_step: f64,
+    /// The optimiser has converged when every point of the simplex is within this of the best in each component
+    pub simplex_tol: f64,
+}
+
+impl Default for ParamsNelderMead {
+    fn default() -> Self {
+        Self {
+            alpha: 1.,
+            gamma: 2.,
+            rho: 0.5,
+            sigma: 0.5,
+            initial_step: 0.05,
+            zero_step: 0.00025,
+            simplex_tol: 1e-8,
+        }
+    }
+}
+
+/// Nelder–Mead optimiser
+///
+/// A derivative-free simplex method using only evaluations of the loss
+#[derive(Debug)]
+pub struct NelderMead<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsNelderMead,
+    /// the flattened points of the simplex with their losses, sorted from best to worst
+    simplex: Vec<(Tensor, Tensor, f64)>,
+}
+
+impl<M: Model> LossOptimizer<M> for NelderMead<M> {
+    type Config = ParamsNelderMead;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.alpha <= 0.
+            || params.gamma <= 1.
+            || params.gamma <= params.alpha
+            || params.rho <= 0.
+            || params.rho >= 1.
+            || params.sigma <= 0.
+            || params.sigma >= 1.
+        {
+            candle_core::bail!(
+                "Nelder–Mead needs 0 < alpha < gamma, 1 < gamma, 0 < rho < 1 and 0 < sigma < 1, got {params:?}"
+            );
+        }
+        if vs.iter().all(|v| v.elem_count() == 0) {
+            candle_core::bail!("Nelder–Mead needs at least one variable to optimise");
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            simplex: vec![],
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let mut evals = 0;
+        if self.simplex.is_empty() {
+            evals += self.initialise(loss)?;
+        }
+        if self.simplex_size()? < self.params.simplex_tol {
+            info!("simplex converged");
+            self.set_vars(&self.simplex[0].0.clone())?;
+            return Ok(ModelOutcome::Converged(self.simplex[0].1.clone(), evals));
+        }
+
+        let ParamsNelderMead {
+            alpha,
+            gamma,
+            rho,
+            sigma,
+            ..
+        } = self.params;
+        let n = self.simplex.len() - 1;
+        let points = self.simplex[..n]
+            .iter()
+            .map(|(x, _, _)| x.clone())
+            .collect::<Vec<Tensor>>();
+        let centroid = Tensor::stack(&points, 0)?.mean(0)?;
+        let (worst, _, f_worst) = self.simplex[n].clone();
+        let f_best = self.simplex[0].2;
+        let f_second_worst = self.simplex[n - 1].2;
+
+        let reflected = ((&centroid * (1. + alpha))? - (&worst * alpha)?)?;
+        let (reflected_loss, f_reflected) = self.evaluate(&reflected)?;
+        evals += 1;
+        let replacement = if f_reflected < f_best {
+            let expanded = ((&centroid * (1. - gamma))? + (&reflected * gamma)?)?;
+            let (expanded_loss, f_expanded) = self.evaluate(&expanded)?;
+            evals += 1;
+            if f_expanded < f_reflected {
+                Some((expanded, expanded_loss, f_expanded))
+            } else {
+                Some((reflected, reflected_loss, f_reflected))
+            }
+        } else if f_reflected < f_second_worst {
+            Some((reflected, reflected_loss, f_reflected))
+        } else {
+            // contract outside the simplex towards the reflected point if it improved on the worst, else inside
+            let (from, f_from) = if f_reflected < f_worst {
+                (&reflected, f_reflected)
+            } else {
+                (&worst, f_worst)
+            };
+            let contracted = ((&centroid * (1. - rho))? + (from * rho)?)?;
+            let (contracted_loss, f_contracted) = self.evaluate(&contracted)?;
+            evals += 1;
+            if f_contracted < f_from {
+                Some((contracted, contracted_loss, f_contracted))
+            } else {
+                None
+            }
+        };
+
+        if let Some(replacement) = replacement {
+            self.simplex[n] = replacement;
+        } else {
+            let best = self.simplex[0].0.clone();
+            for i in 1..=n {
+                let shrunk = ((&best * (1. - sigma))? + (&self.simplex[i].0 * sigma)?)?;
+                let (shrunk_loss, f_shrunk) = self.evaluate(&shrunk)?;
+                self.simplex[i] = (shrunk, shrunk_loss, f_shrunk);
+            }
+            evals += n;
+        }
+        self.simplex.sort_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+        self.set_vars(&self.simplex[0].0.clone())?;
+        Ok(ModelOutcome::Stepped(self.simplex[0].1.clone(), evals))
+    }
+
+    /// the relative perturbation used for the initial simplex
+    fn learning_rate(&self) -> f64 {
+        self.params.initial_step
+    }
+
+    /// set the relative perturbation used for the initial simplex, which takes effect after a [`NelderMead::reset`]
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.initial_step = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for NelderMead<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> NelderMead<M> {
+    /// The largest difference in any component between a point of the simplex and the best point, or infinity
+    /// before the first step
+    pub fn simplex_size(&self) -> CResult<f64> {
+        let Some((best, _, _)) = self.simplex.first() else {
+            return Ok(f64::INFINITY);
+        };
+        let mut size = 0f64;
+        for (x, _, _) in &self.simplex[1..] {
+            let diff = (x - best)?
+                .abs()?
+                .max(0)?
+                .to_dtype(DType::F64)?
+                .to_scalar::<f64>()?;
+            size = size.max(diff);
+        }
+        Ok(size)
+    }
+
+    /// Discard the simplex, so the next step starts a new one around the current variables
+    pub fn reset(&mut self) {
+        self.simplex.clear();
+    }
+
+    /// build the initial simplex around the current variables, returning the number of loss evaluations
+    fn initialise(&mut self, loss: &Tensor) -> CResult<usize> {
+        let x0 = flatten_vars(&self.vars)?.copy()?;
+        let f0 = scalar(loss)?;
+        let values = x0.to_dtype(DType::F64)?.to_vec1::<f64>()?;
+        let mut simplex = vec![(x0.clone(), loss.detach(), f0)];
+        for (i, value) in values.iter().enumerate() {
+            let step = if *value == 0. {
+                self.params.zero_step
+            } else {
+                self.params.initial_step * value
+            };
+            let mut perturbed = values.clone();
+            perturbed[i] += step;
+            let x = Tensor::new(perturbed, x0.device())?.to_dtype(x0.dtype())?;
+            let (loss, f) = self.evaluate(&x)?;
+            simplex.push((x, loss, f));
+        }
+        simplex.sort_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+        self.simplex = simplex;
+        Ok(values.len())
+    }
+
+    /// the loss at the flattened point `x`, as a tensor and a scalar with NaN mapped to infinity
+    fn evaluate(&self, x: &Tensor) -> CResult<(Tensor, f64)> {
+        self.set_vars(x)?;
+        let loss = self.model.loss()?.detach();
+        let f = scalar(&loss)?;
+        Ok((loss, f))
+    }
+
+    fn set_vars(&self, x: &Tensor) -> CResult<()> {
+        for (var, value) in self.vars.iter().zip(unflatten(x, &self.vars)?) {
+            var.set(&value)?;
+        }
+        Ok(())
+    }
+}
+
+fn scalar(loss: &Tensor) -> CResult<f64> {
+    let f = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+    Ok(if f.is_nan() { f64::INFINITY } else { f })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+    use std::cmp::Ordering;
+
+    use super::*;
+
+    /// $(x - 1)^2 + (y + 2)^2$
+    struct Bowl {
+        xy: Var,
+    }
+
+    impl Model for Bowl {
+        fn loss(&self) -> CResult<Tensor> {
+            let shift = Tensor::new(&[1f64, -2.], &Device::Cpu)?;
+            (self.xy.as_tensor() - shift)?.sqr()?.sum_all()
+        }
+    }
+
+    fn setup(params: ParamsNelderMead) -> Result<(Var, NelderMead<Bowl>)> {
+        let xy = Var::new(&[2f64, 0.], &Device::Cpu)?;
+        let optim = NelderMead::new(vec![xy.clone()], params, Bowl { xy: xy.clone() })?;
+        Ok((xy, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsNelderMead::default())?;
+        assert_approx_eq!(0.05, optim.learning_rate());
+        optim.set_learning_rate(0.1);
+        assert_approx_eq!(0.1, optim.learning_rate());
+        assert!(setup(ParamsNelderMead {
+            rho: 1.,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn initial_simplex_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsNelderMead::default())?;
+        assert!(optim.simplex_size()?.is_infinite());
+        let loss = optim.model.loss()?;
+        optim.initialise(&loss)?;
+        // the first component is perturbed by 5% of 2, the zero second component by the zero step
+        let mut points = optim
+            .simplex
+            .iter()
+            .map(|(x, _, _)| x.to_vec1::<f64>())
+            .collect::<CResult<Vec<Vec<f64>>>>()?;
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        assert_eq!(points, [[2., 0.], [2., 0.00025], [2.1, 0.]]);
+        assert_approx_eq!(optim.simplex_size()?, 0.1);
+        Ok(())
+    }
+
+    #[test]
+    fn step_test() -> Result<()> {
+        // the variables hold the best point after each step, and the loss never increases
+        let (xy, mut optim) = setup(ParamsNelderMead::default())?;
+        let mut loss = optim.model.loss()?;
+        for _ in 0..10 {
+            let ModelOutcome::Stepped(next, _) = optim.backward_step(&loss)? else {
+                panic!("converged early");
+            };
+            assert!(next.to_scalar::<f64>()? <= loss.to_scalar::<f64>()?);
+            assert_approx_eq!(
+                next.to_scalar::<f64>()?,
+                optim.model.loss()?.to_scalar::<f64>()?
+            );
+            loss = next;
+        }
+        assert!(xy.to_vec1::<f64>()? != [2., 0.]);
+        Ok(())
+    }
+}
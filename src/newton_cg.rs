@@ -0,0 +1,295 @@
+/*!
+Newton-CG (truncated Newton) optimiser
+
+Each step approximately solves the Newton system $H d = -g$ by conjugate gradient, using only Hessian-vector products,
+as described in [Numerical Optimization](https://doi.org/10.1007/978-0-387-40065-5) (algorithm 7.1). The conjugate
+gradient iteration is truncated once the residual is small relative to the gradient, with the forcing term
+
+$$ ||H d + g||_{2} \\leq \\min\\big(\\frac{1}{2}, \\sqrt{||g||_{2}}\\big) ||g||_{2} $$
+
+so steps far from a minimum are cheap, while close to it the convergence is superlinear. If a direction of
+non-positive curvature is found the iteration stops: the last iterate is used, or the steepest descent direction if
+this happens on the first iteration. The step along the direction is then chosen by the
+[backtracking line search](crate::lbfgs::LineSearch::Backtracking) of LBFGS.
+
+Candle does not differentiate through the backward pass, so the Hessian-vector products are taken by central
+differences of the gradient, as in [`hessian::hessian_vector_product`](crate::hessian::hessian_vector_product). Each
+product costs two evaluations of the loss and its gradient, and the vector is scaled to unit norm before the
+difference is taken, so the step `hvp_eps` is the distance moved in parameter space.
+
+Like [LBFGS](crate::lbfgs) this needs a [`Model`] to evaluate the loss, and is suited to full batch problems.
+*/
+
+use crate::{
+    cg::{dot, truncated_conjugate_gradient, CgStop},
+    flatten_grads,
+    hessian::flat_hessian_vector_product,
+    lbfgs::{
+        add_grad, directional_evaluate, l2_reg, Backtracking, GradConv, LineSearch,
+        LineSearchTarget,
+    },
+    LossOptimizer, Model, ModelOutcome, OptimVars,
+};
+use candle_core::Result as CResult;
+use candle_core::{Tensor, Var};
+use log::info;
+
+/// Parameters for the Newton-CG optimiser
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsNewtonCg {
+    /// Initial step size of the line search: 1 takes the full Newton step when it is accepted
+    pub lr: f64,
+    /// Maximum number of conjugate gradient iterations, each taking one Hessian-vector product
+    pub max_cg_iter: usize,
+    /// Relative tolerance of the conjugate gradient residual, or `None` for the forcing term
+    /// $\\min(\\frac{1}{2}, \\sqrt{||g||_{2}})$
+    pub cg_tol: Option<f64>,
+    /// Step of the central differences for the Hessian-vector products
+    pub hvp_eps: f64,
+    /// Coefficient of the Armijo condition of the line search, in $(0, 1)$
+    pub c1: f64,
+    /// Factor by which the line search shrinks the step, in $(0, 1)$
+    pub rho: f64,
+    /// Maximum number of loss evaluations made by the line search in each step
+    ///
+    /// If no step meets the Armijo condition within this budget no step is taken, and this is reported as
+    /// [`ModelOutcome::Truncated`]
+    pub max_ls: usize,
+    /// Convergence criteria for the gradient
+    pub grad_conv: GradConv,
+    /// log the conjugate gradient iterations and step size of each step at the info level
+    pub verbose: bool,
+}
+
+impl Default for ParamsNewtonCg {
+    fn default() -> Self {
+        Self {
+            lr: 1.,
+            max_cg_iter: 50,
+            cg_tol: None,
+            hvp_eps: 1e-4,
+            c1: 1e-4,
+            rho: 0.5,
+            max_ls: 20,
+            grad_conv: GradConv::MinForce(1e-7),
+            verbose: false,
+        }
+    }
+}
+
+/// Newton-CG optimiser
+///
+/// A truncated Newton method, solving the Newton system by conjugate gradient with Hessian-vector products
+#[derive(Debug)]
+pub struct NewtonCg<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsNewtonCg,
+    last_cg_stop: Option<CgStop>,
+}
+
+impl<M: Model> LossOptimizer<M> for NewtonCg<M> {
+    type Config = ParamsNewtonCg;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.max_ls == 0 {
+            candle_core::bail!("the Newton-CG line search needs at least one evaluation");
+        }
+        LineSearch::Backtracking {
+            c1: params.c1,
+            rho: params.rho,
+            max_steps: params.max_ls,
+        }
+        .validate()?;
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            last_cg_stop: None,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let mut evals = 1;
+        let grad = flatten_grads(&loss.backward()?, &self.vars)?;
+        if self.params.grad_conv.converged(&grad)? == Some(true) {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+
+        let grad_norm = dot(&grad, &grad)?.sqrt();
+        let tol = self
+            .params
+            .cg_tol
+            .unwrap_or_else(|| grad_norm.sqrt().min(0.5));
+        let mut products = 0;
+        let (direction, stop) = {
+            let vars = self.vars.iter().collect::<Vec<&Var>>();
+            let model = &self.model;
+            let eps = self.params.hvp_eps;
+            let matvec = |v: &Tensor| -> CResult<Tensor> {
+                products += 1;
+                flat_hessian_vector_product(&vars, v, || model.loss(), eps)
+            };
+            truncated_conjugate_gradient(matvec, &grad.neg()?, self.params.max_cg_iter, tol)?
+        };
+        evals += 2 * products;
+        self.last_cg_stop = Some(stop);
+
+        // the truncated solution is a descent direction in exact arithmetic, but fall back to steepest descent if
+        // the finite differences make it otherwise
+        let mut directional_grad = dot(&grad, &direction)?;
+        let direction = if directional_grad < 0. {
+            direction
+        } else {
+            info!("Newton-CG direction is not a descent direction: using steepest descent");
+            directional_grad = -grad_norm * grad_norm;
+            grad.neg()?
+        };
+
+        let (next_loss, _, step_size, ls_evals) = self.backtracking(
+            self.params.lr,
+            &direction,
+            loss,
+            &grad,
+            directional_grad,
+            self.params.c1,
+            self.params.rho,
+            self.params.max_ls,
+        )?;
+        evals += ls_evals;
+        if self.params.verbose {
+            info!("{products} Hessian-vector products ({stop:?}), {ls_evals} line search evaluations, step size {step_size}");
+        }
+        if step_size == 0. {
+            // no step met the Armijo condition, so none was taken
+            return Ok(ModelOutcome::Truncated(next_loss, evals));
+        }
+        add_grad(&mut self.vars, &(direction * step_size)?)?;
+        Ok(ModelOutcome::Stepped(next_loss, evals))
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for NewtonCg<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> NewtonCg<M> {
+    /// Why the conjugate gradient iteration of the last step stopped, or `None` before the first step
+    ///
+    /// [`CgStop::NegativeCurvature`] shows the Hessian was not positive definite along the search directions.
+    #[must_use]
+    pub fn last_cg_stop(&self) -> Option<CgStop> {
+        self.last_cg_stop
+    }
+}
+
+impl<M: Model> LineSearchTarget for NewtonCg<M> {
+    fn directional_evaluate(
+        &mut self,
+        mag: f64,
+        direction: &Tensor,
+    ) -> CResult<(Tensor, Tensor, f64)> {
+        directional_evaluate(&mut self.vars, &self.model, None, mag, direction)
+    }
+
+    fn l2_reg(&self) -> CResult<f64> {
+        l2_reg(&self.vars, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+
+    use super::*;
+
+    /// $\\frac{1}{2} x^{\\top} A x - b^{\\top} x$ with $A = [[4, 1], [1, 3]]$ and $b = [1, 2]$
+    struct Quadratic {
+        x: Var,
+    }
+
+    impl Model for Quadratic {
+        fn loss(&self) -> CResult<Tensor> {
+            let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+            let b = Tensor::new(&[1f64, 2.], &Device::Cpu)?;
+            let x = self.x.as_tensor();
+            let ax = a.matmul(&x.unsqueeze(1)?)?.squeeze(1)?;
+            ((x * ax)?.sum_all()? * 0.5)? - (x * b)?.sum_all()?
+        }
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let mut optim = NewtonCg::new(vec![x.clone()], ParamsNewtonCg::default(), Quadratic { x })?;
+        assert_approx_eq!(1., optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn quadratic_step_test() -> Result<()> {
+        // with a tight tolerance the first step is the full Newton step to the minimum [1/11, 7/11]
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let params = ParamsNewtonCg {
+            cg_tol: Some(1e-10),
+            ..Default::default()
+        };
+        let model = Quadratic { x: x.clone() };
+        let loss = model.loss()?;
+        let mut optim = NewtonCg::new(vec![x.clone()], params, model)?;
+        assert_eq!(optim.last_cg_stop(), None);
+        let outcome = optim.backward_step(&loss)?;
+        // the loss, two products for each of two conjugate gradient iterations, and one line search evaluation
+        assert!(matches!(outcome, ModelOutcome::Stepped(_, 6)));
+        assert_eq!(optim.last_cg_stop(), Some(CgStop::Converged));
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 1. / 11.);
+        assert_approx_eq!(x[1], 7. / 11.);
+        Ok(())
+    }
+    #[test]
+    fn line_search_test() -> Result<()> {
+        // a step 100 times the Newton step never meets the Armijo condition with one evaluation, so is not taken
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let params = ParamsNewtonCg {
+            lr: 100.,
+            max_ls: 1,
+            ..Default::default()
+        };
+        let model = Quadratic { x: x.clone() };
+        let loss = model.loss()?;
+        let mut optim = NewtonCg::new(vec![x.clone()], params, model)?;
+        let outcome = optim.backward_step(&loss)?;
+        assert!(matches!(outcome, ModelOutcome::Truncated(_, _)));
+        assert_eq!(x.to_vec1::<f64>()?, [0., 0.]);
+
+        for (c1, rho) in [(0., 0.5), (1e-4, 1.)] {
+            let params = ParamsNewtonCg {
+                c1,
+                rho,
+                ..Default::default()
+            };
+            assert!(NewtonCg::new(vec![x.clone()], params, Quadratic { x: x.clone() }).is_err());
+        }
+        Ok(())
+    }
+}
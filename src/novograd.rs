@@ -0,0 +1,248 @@
+/*!
+NovoGrad optimiser
+
+Described in [Stochastic Gradient Methods with Layer-wise Adaptive Moments for Training of Deep Networks](https://arxiv.org/abs/1905.11286)
+
+Rather than a second moment for each element, NovoGrad keeps a single second moment for each variable (layer): a
+moving average of the squared norm of its gradient. The gradient is normalised by the root of this before the weight
+decay is added and it enters the first moment, so the weight decay is decoupled from the adaptive scaling.
+
+Pseudocode:
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\epsilon \\text{ (epsilon)}, \\: \\textit{grad averaging}               \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)}                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{if} \\: t = 1                                                      \\\\
+    &\\hspace{10mm} v_t \\leftarrow \\lVert g_t \\rVert^2                                     \\\\
+    &\\hspace{5mm}\\textbf{else}                                                           \\\\
+    &\\hspace{10mm} v_t \\leftarrow \\beta_2 v_{t-1} + (1 - \\beta_2) \\lVert g_t \\rVert^2    \\\\
+    &\\hspace{5mm}d_t \\leftarrow g_t / \\big(\\sqrt{v_t} + \\epsilon \\big) + \\lambda \\theta_{t-1}  \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\textit{grad averaging}                                  \\\\
+    &\\hspace{10mm} d_t \\leftarrow (1 - \\beta_1) d_t                                      \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + d_t                        \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma m_t                           \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+Each variable is treated as a layer, with its second moment starting from the squared norm of its first gradient.
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot, OptimParams,
+    OptimVars, UnusedConfig,
+};
+
+/// NovoGrad optimiser
+///
+/// Described in [Stochastic Gradient Methods with Layer-wise Adaptive Moments for Training of Deep Networks](https://arxiv.org/abs/1905.11286)
+#[derive(Debug)]
+pub struct NovoGrad {
+    vars: Vec<VarNovoGrad>,
+    params: ParamsNovoGrad,
+}
+
+#[derive(Debug)]
+struct VarNovoGrad {
+    theta: Var,
+    /// first moment, of the same shape as the variable
+    m: Var,
+    /// second moment of the whole variable, as a scalar
+    v: Var,
+    /// whether the second moment has been set from a gradient
+    started: bool,
+}
+
+/// Parameters for the NovoGrad optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsNovoGrad {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of the layer-wise second moment
+    pub beta_2: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Weight decay, added to the normalised gradient
+    pub weight_decay: Option<f64>,
+    /// Whether to scale the normalised gradient by `1 - beta_1` before adding it to the first moment
+    pub grad_averaging: bool,
+}
+
+impl Default for ParamsNovoGrad {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.95,
+            beta_2: 0.98,
+            eps: 1e-8,
+            weight_decay: None,
+            grad_averaging: false,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsNovoGrad {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay).into_iter().collect()
+    }
+}
+
+impl Optimizer for NovoGrad {
+    type Config = ParamsNovoGrad;
+
+    fn new(vars: Vec<Var>, params: ParamsNovoGrad) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let m = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                let v = Var::zeros((), var.dtype(), var.device())?;
+                Ok(VarNovoGrad {
+                    theta: var,
+                    m,
+                    v,
+                    started: false,
+                })
+            })
+            .collect::<Result<Vec<VarNovoGrad>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let beta_1 = self.params.beta_1;
+        let beta_2 = self.params.beta_2;
+
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let norm_sq = grad.sqr()?.sum_all()?;
+                let v_next = if var.started {
+                    ((beta_2 * var.v.as_tensor())? + ((1. - beta_2) * norm_sq)?)?
+                } else {
+                    norm_sq
+                };
+                let normalised = grad.broadcast_div(&(v_next.sqrt()? + self.params.eps)?)?;
+                let normalised = match self.params.weight_decay {
+                    Some(wd) => (normalised + (wd * theta.as_tensor())?)?,
+                    None => normalised,
+                };
+                let normalised = if self.params.grad_averaging {
+                    ((1. - beta_1) * normalised)?
+                } else {
+                    normalised
+                };
+                let m_next = ((beta_1 * var.m.as_tensor())? + normalised)?;
+                theta.set(&theta.sub(&(self.params.lr * &m_next)?)?)?;
+                var.m.set(&m_next)?;
+                var.v.set(&v_next)?;
+                var.started = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for NovoGrad {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for NovoGrad {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay.map(Decay::DecoupledWeightDecay),
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for NovoGrad {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl NovoGrad {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsNovoGrad {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = NovoGrad::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsNovoGrad {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = NovoGrad::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsNovoGrad {
+            lr: 0.002,
+            weight_decay: Some(0.001),
+            grad_averaging: true,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+}
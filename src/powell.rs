@@ -0,0 +1,267 @@
+/*!
+Powell's conjugate direction method
+
+A derivative-free method, described in
+[An efficient method for finding the minimum of a function of several variables without calculating derivatives](https://doi.org/10.1093/comjnl/7.2.155),
+which only evaluates [`Model::loss`]. Starting from the coordinate directions of the flattened variables, each step
+minimises the loss along each direction in turn with the [scalar line search](crate::scalar_search::line_minimise).
+The overall displacement $\\Delta$ of the step then replaces the direction along which the loss decreased most,
+following Numerical Recipes (section 10.7): this is skipped if the extrapolated point $x + \\Delta$ does not improve
+on the start of the step, or if the replacement would make the directions close to linearly dependent.
+
+On a quadratic the directions become mutually conjugate, so the minimum is found in $n$ steps for $n$ parameters.
+This suits smooth losses whose gradients are unavailable, while [Nelder–Mead](crate::nelder_mead) also handles
+non-smooth losses. The optimiser has converged when a step reduces the loss by less than `ftol` relative to its size.
+*/
+
+use crate::{
+    flatten_vars, scalar_search::line_minimise, unflatten, LossOptimizer, Model, ModelOutcome,
+    OptimVars,
+};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for Powell's method
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsPowell {
+    /// First step of the line search along each direction, used to bracket the minimum
+    pub initial_step: f64,
+    /// Relative tolerance of the line search
+    pub line_tol: f64,
+    /// Maximum number of iterations of each of the bracketing and locating the minimum in the line search
+    pub max_line_iter: usize,
+    /// The optimiser has converged when a step reduces the loss by less than this relative to its size
+    pub ftol: f64,
+}
+
+impl Default for ParamsPowell {
+    fn default() -> Self {
+        Self {
+            initial_step: 1.,
+            line_tol: 1e-8,
+            max_line_iter: 100,
+            ftol: 1e-12,
+        }
+    }
+}
+
+/// Powell's method
+///
+/// A derivative-free method minimising the loss along a set of directions that become conjugate
+#[derive(Debug)]
+pub struct Powell<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsPowell,
+    /// the search directions over the flattened variables, or empty before the first step
+    directions: Vec<Tensor>,
+}
+
+impl<M: Model> LossOptimizer<M> for Powell<M> {
+    type Config = ParamsPowell;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.initial_step == 0. {
+            candle_core::bail!("the initial step of Powell's method must be non-zero");
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            directions: vec![],
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let x_start = flatten_vars(&self.vars)?.copy()?;
+        if self.directions.is_empty() {
+            let n = x_start.elem_count();
+            let eye = Tensor::eye(n, x_start.dtype(), x_start.device())?;
+            self.directions = (0..n).map(|i| eye.get(i)).collect::<CResult<_>>()?;
+        }
+        let f_start = scalar(loss)?;
+        let mut evals = 0;
+
+        let mut x = x_start.clone();
+        let mut fx = f_start;
+        // the direction along which the loss decreased most, and by how much
+        let (mut biggest, mut biggest_decrease) = (0, 0.);
+        for (i, direction) in self.directions.iter().enumerate() {
+            let f_before = fx;
+            (x, fx) = self.minimise_along(&x, fx, direction, &mut evals)?;
+            if f_before - fx > biggest_decrease {
+                biggest = i;
+                biggest_decrease = f_before - fx;
+            }
+        }
+        self.set_vars(&x)?;
+        let mut next_loss = self.model.loss()?.detach();
+        evals += 1;
+
+        if 2. * (f_start - fx) <= self.params.ftol.mul_add(f_start.abs() + fx.abs(), 1e-25) {
+            info!("loss converged");
+            return Ok(ModelOutcome::Converged(next_loss, evals));
+        }
+
+        let displacement = (&x - &x_start)?;
+        let f_extrapolated = self.evaluate(&(&x + &displacement)?)?;
+        evals += 1;
+        if f_extrapolated < f_start {
+            let t = 2.
+                * 2f64.mul_add(-fx, f_start + f_extrapolated)
+                * (f_start - fx - biggest_decrease).powi(2)
+                - biggest_decrease * (f_start - f_extrapolated).powi(2);
+            if t < 0. {
+                let (x_new, fx_new) = self.minimise_along(&x, fx, &displacement, &mut evals)?;
+                let last = self.directions.len() - 1;
+                self.directions.swap(biggest, last);
+                self.directions[last] = displacement;
+                if fx_new < fx {
+                    x = x_new;
+                    self.set_vars(&x)?;
+                    next_loss = self.model.loss()?.detach();
+                    evals += 1;
+                }
+            }
+        }
+        self.set_vars(&x)?;
+        Ok(ModelOutcome::Stepped(next_loss, evals))
+    }
+
+    /// the first step of the line search along each direction
+    fn learning_rate(&self) -> f64 {
+        self.params.initial_step
+    }
+
+    /// set the first step of the line search along each direction
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.initial_step = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for Powell<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> Powell<M> {
+    /// The current search directions over the flattened variables, or empty before the first step
+    #[must_use]
+    pub fn directions(&self) -> &[Tensor] {
+        &self.directions
+    }
+
+    /// Reset the search directions to the coordinate directions
+    pub fn reset(&mut self) {
+        self.directions.clear();
+    }
+
+    /// minimise the loss along `direction` from `x`, where the loss is `fx`
+    fn minimise_along(
+        &self,
+        x: &Tensor,
+        fx: f64,
+        direction: &Tensor,
+        evals: &mut usize,
+    ) -> CResult<(Tensor, f64)> {
+        let minimum = line_minimise(
+            |t| self.evaluate(&(x + (direction * t)?)?),
+            fx,
+            self.params.initial_step,
+            self.params.line_tol,
+            self.params.max_line_iter,
+        )?;
+        *evals += minimum.evals;
+        if minimum.fx < fx {
+            Ok(((x + (direction * minimum.x)?)?, minimum.fx))
+        } else {
+            Ok((x.clone(), fx))
+        }
+    }
+
+    /// the loss at the flattened point `x`
+    fn evaluate(&self, x: &Tensor) -> CResult<f64> {
+        self.set_vars(x)?;
+        scalar(&self.model.loss()?)
+    }
+
+    fn set_vars(&self, x: &Tensor) -> CResult<()> {
+        for (var, value) in self.vars.iter().zip(unflatten(x, &self.vars)?) {
+            var.set(&value)?;
+        }
+        Ok(())
+    }
+}
+
+fn scalar(loss: &Tensor) -> CResult<f64> {
+    let f = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+    Ok(if f.is_nan() { f64::INFINITY } else { f })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+
+    /// $\\frac{1}{2} x^{\\top} A x - b^{\\top} x$ with $A = [[4, 1], [1, 3]]$ and $b = [1, 2]$
+    struct Quadratic {
+        x: Var,
+    }
+
+    impl Model for Quadratic {
+        fn loss(&self) -> CResult<Tensor> {
+            let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+            let b = Tensor::new(&[1f64, 2.], &Device::Cpu)?;
+            let x = self.x.as_tensor();
+            let ax = a.matmul(&x.unsqueeze(1)?)?.squeeze(1)?;
+            ((x * ax)?.sum_all()? * 0.5)? - (x * b)?.sum_all()?
+        }
+    }
+
+    fn setup() -> Result<(Var, Powell<Quadratic>)> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let optim = Powell::new(
+            vec![x.clone()],
+            ParamsPowell::default(),
+            Quadratic { x: x.clone() },
+        )?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup()?;
+        assert_approx_eq!(1., optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn quadratic_test() -> Result<()> {
+        // the directions become conjugate, so the minimum [1/11, 7/11] is reached in two steps
+        let (x, mut optim) = setup()?;
+        let mut loss = optim.model.loss()?;
+        for _ in 0..2 {
+            if let ModelOutcome::Stepped(next, _) = optim.backward_step(&loss)? {
+                loss = next;
+            }
+        }
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 1. / 11., 1e-6);
+        assert_approx_eq!(x[1], 7. / 11., 1e-6);
+        assert_eq!(optim.directions().len(), 2);
+        optim.reset();
+        assert!(optim.directions().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,554 @@
+/*!
+Proximal gradient methods
+
+For objectives $F(\\theta) = f(\\theta) + h(\\theta)$ with $f$ smooth and $h$ a non-smooth regulariser or constraint
+with a cheap proximal operator
+
+$$ \\text{prox}_{\\gamma h}(x) = \\arg\\min_{\\theta} \\left( h(\\theta) + \\frac{1}{2\\gamma} ||\\theta - x||_{2}^{2} \\right)$$
+
+only the gradient of $f$ is backpropagated and $h$ is handled by the proximal step
+
+$$ \\theta_t \\gets \\text{prox}_{\\gamma h}\\left(\\theta_{t-1} - \\gamma \\nabla f(\\theta_{t-1})\\right)$$
+
+[`ProximalGradient`] takes these steps directly (ISTA for the L1 penalty).
+[`Fista`] adds Nesterov style extrapolation as described in
+[A Fast Iterative Shrinkage-Thresholding Algorithm for Linear Inverse Problems](https://doi.org/10.1137/080716542):
+
+$$
+\\begin{aligned}
+    &x_t \\gets \\text{prox}_{\\gamma h}\\left(y_{t-1} - \\gamma \\nabla f(y_{t-1})\\right) \\\\
+    &t_{t+1} \\gets \\frac{1 + \\sqrt{1 + 4 t_t^2}}{2} \\\\
+    &y_t \\gets x_t + \\frac{t_t - 1}{t_{t+1}} \\left(x_t - x_{t-1}\\right)
+\\end{aligned}
+$$
+
+with $t_1 = 1$. As the gradient is taken at the extrapolated point, the variables hold $y_t$; the proximal iterates $x_t$
+are available from [`Fista::iterates`].
+
+Both need a step size of at most $\frac{1}{L}$ for the Lipschitz constant $L$ of $\nabla f$. When this is unknown,
+[`ProximalBacktracking`] takes the ISTA or FISTA steps through the [`LossOptimizer`] interface, with [`Model::loss`]
+giving $f$ alone, and shrinks the step size until the step $p = \text{prox}_{\gamma h}(y - \gamma \nabla f(y))$
+satisfies the sufficient decrease condition of Beck and Teboulle
+
+$$ f(p) \leq f(y) + \nabla f(y)^{\top} (p - y) + \frac{1}{2 \gamma} ||p - y||_{2}^{2} $$
+
+If no step size meets the condition within the backtracking budget, no step is taken.
+*/
+
+use candle_core::{DType, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+use log::{info, warn};
+
+use crate::lbfgs::GradConv;
+use crate::{
+    flatten_grads, flatten_vars, unflatten, LossOptimizer, Model, ModelOutcome, OptimParams,
+    OptimVars,
+};
+
+/// Proximal operator of the non-smooth part of the objective
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Prox {
+    /// L1 penalty $\\lambda ||\\theta||_{1}$, whose proximal operator is soft thresholding by $\\gamma \\lambda$
+    L1(f64),
+    /// constraint to non-negative values, whose proximal operator is projection onto the non-negative orthant
+    NonNegative,
+}
+
+impl Prox {
+    /// apply the proximal operator with step size `lr`
+    ///
+    /// # Errors
+    ///
+    /// Errors if the tensor operations fail
+    pub fn prox(&self, x: &Tensor, lr: f64) -> Result<Tensor> {
+        match self {
+            Self::L1(lambda) => {
+                // soft thresholding sign(x) max(|x| - threshold, 0) is x - clamp(x, -threshold, threshold)
+                let threshold = lr * lambda;
+                x - x.clamp(-threshold, threshold)?
+            }
+            Self::NonNegative => x.relu(),
+        }
+    }
+
+    /// a proximal gradient step $\\text{prox}_{\\gamma h}(x - \\gamma g)$
+    ///
+    /// # Errors
+    ///
+    /// Errors if the tensor operations fail
+    pub fn prox_step(&self, x: &Tensor, grad: &Tensor, lr: f64) -> Result<Tensor> {
+        self.prox(&(x - (grad * lr)?)?, lr)
+    }
+}
+
+/// Parameters for the proximal gradient optimisers
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsProximal {
+    /// Learning rate: for convergence this should be at most the reciprocal of the Lipschitz constant of $\\nabla f$
+    pub lr: f64,
+    /// Proximal operator of the non-smooth part of the objective
+    pub prox: Prox,
+}
+
+impl Default for ParamsProximal {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            prox: Prox::L1(0.),
+        }
+    }
+}
+
+/// Proximal gradient descent
+#[derive(Debug)]
+pub struct ProximalGradient {
+    vars: Vec<Var>,
+    params: ParamsProximal,
+}
+
+impl Optimizer for ProximalGradient {
+    type Config = ParamsProximal;
+
+    fn new(vars: Vec<Var>, params: ParamsProximal) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect();
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        for var in &self.vars {
+            if let Some(grad) = grads.get(var) {
+                var.set(&self.params.prox.prox_step(var, grad, self.params.lr)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for ProximalGradient {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl OptimVars for ProximalGradient {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl ProximalGradient {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+/// FISTA: accelerated proximal gradient descent
+#[derive(Debug)]
+pub struct Fista {
+    vars: Vec<VarFista>,
+    params: ParamsProximal,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarFista {
+    /// the extrapolated point $y_t$
+    theta: Var,
+    /// the last proximal iterate $x_t$
+    x: Tensor,
+}
+
+impl Optimizer for Fista {
+    type Config = ParamsProximal;
+
+    fn new(vars: Vec<Var>, params: ParamsProximal) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                // the var is updated in place so the iterate must be a copy
+                let x = var.as_tensor().copy()?;
+                Ok(VarFista { theta: var, x })
+            })
+            .collect::<Result<Vec<VarFista>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let (t_next, extrapolation) = fista_momentum(self.t);
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let x = self.params.prox.prox_step(theta, grad, self.params.lr)?;
+                theta.set(&extrapolate(&x, &var.x, extrapolation)?)?;
+                var.x = x;
+            }
+        }
+        self.t = t_next;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Fista {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl OptimVars for Fista {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Fista {
+    /// the proximal iterates $x_t$, in the same order as the vars
+    ///
+    /// these satisfy the proximal operator's constraints (e.g. sparsity for the L1 penalty), unlike the
+    /// extrapolated points held by the vars
+    #[must_use]
+    pub fn iterates(&self) -> Vec<&Tensor> {
+        self.vars.iter().map(|v| &v.x).collect()
+    }
+
+    /// the current value of the momentum sequence $t_t$
+    #[must_use]
+    pub fn momentum_t(&self) -> f64 {
+        self.t
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+/// Parameters for proximal gradient descent with backtracking
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsProximalBacktracking {
+    /// Initial step size, which is only ever decreased by backtracking
+    pub lr: f64,
+    /// Proximal operator of the non-smooth part of the objective
+    pub prox: Prox,
+    /// Factor by which the step size is multiplied when the sufficient decrease condition fails
+    pub shrink: f64,
+    /// Whether to take FISTA steps rather than ISTA steps
+    pub accelerated: bool,
+    /// Convergence criterion on the gradient mapping $\frac{1}{\gamma}(y - p)$, which vanishes at a minimum of
+    /// $f + h$
+    pub grad_conv: GradConv,
+    /// Maximum number of times the step size is shrunk in one step, after which the step is not taken and
+    /// [`ModelOutcome::Truncated`] is returned
+    pub max_backtracks: usize,
+}
+
+impl Default for ParamsProximalBacktracking {
+    fn default() -> Self {
+        Self {
+            lr: 1.,
+            prox: Prox::L1(0.),
+            shrink: 0.5,
+            accelerated: true,
+            grad_conv: GradConv::MinForce(1e-7),
+            max_backtracks: 50,
+        }
+    }
+}
+
+/// Proximal gradient descent (ISTA or FISTA) with a backtracking step size
+///
+/// The loss of the model should be the smooth part $f$ of the objective only
+#[derive(Debug)]
+pub struct ProximalBacktracking<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsProximalBacktracking,
+    /// the last proximal iterate $x_t$ over the flattened variables, unset before the first step
+    iterate: Option<Tensor>,
+    t: f64,
+}
+
+impl<M: Model> LossOptimizer<M> for ProximalBacktracking<M> {
+    type Config = ParamsProximalBacktracking;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> Result<Self> {
+        if !(params.shrink > 0. && params.shrink < 1.) {
+            candle_core::bail!(
+                "the backtracking shrink factor must be in (0, 1), got {}",
+                params.shrink
+            );
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            iterate: None,
+            t: 1.,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> Result<ModelOutcome> {
+        let grad = flatten_grads(&loss.backward()?, &self.vars)?;
+        let y = flatten_vars(&self.vars)?.copy()?;
+        let f_y = scalar(loss)?;
+        let mut evals = 0;
+
+        let mut backtracks = 0;
+        let (p, f_p) = loop {
+            let lr = self.params.lr;
+            let p = self.params.prox.prox_step(&y, &grad, lr)?;
+            let d = (&p - &y)?;
+            self.set_vars(&p)?;
+            let f_p = self.model.loss()?;
+            evals += 1;
+            let bound = f_y
+                + scalar(&(&grad * &d)?.sum_all()?)?
+                + scalar(&d.sqr()?.sum_all()?)? / (2. * lr);
+            if scalar(&f_p)? <= bound {
+                break (p, f_p);
+            }
+            if backtracks >= self.params.max_backtracks {
+                // no step size met the condition, so leave the vars at y rather than accept p
+                warn!(
+                    "sufficient decrease not met after {} backtracks, not stepping",
+                    self.params.max_backtracks
+                );
+                self.set_vars(&y)?;
+                return Ok(ModelOutcome::Truncated(loss.clone(), evals));
+            }
+            self.params.lr *= self.params.shrink;
+            backtracks += 1;
+        };
+
+        let mapping = ((&y - &p)? / self.params.lr)?;
+        if self.params.grad_conv.converged(&mapping)? == Some(true) {
+            info!("gradient mapping converged");
+            self.iterate = Some(p);
+            return Ok(ModelOutcome::Converged(f_p, evals));
+        }
+
+        let y_next = if self.params.accelerated {
+            let (t_next, extrapolation) = fista_momentum(self.t);
+            let previous = self.iterate.as_ref().unwrap_or(&y);
+            self.t = t_next;
+            extrapolate(&p, previous, extrapolation)?
+        } else {
+            p.clone()
+        };
+        self.iterate = Some(p);
+        self.set_vars(&y_next)?;
+        let next_loss = self.model.loss()?;
+        evals += 1;
+        Ok(ModelOutcome::Stepped(next_loss, evals))
+    }
+
+    /// the current step size, after any backtracking
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for ProximalBacktracking<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> ProximalBacktracking<M> {
+    /// the proximal iterates $x_t$, in the same order as the vars, or `None` before the first step
+    ///
+    /// with acceleration the vars hold the extrapolated points, so only these satisfy the proximal operator's
+    /// constraints
+    ///
+    /// # Errors
+    ///
+    /// Errors if the flattened iterate cannot be split into the shapes of the vars
+    pub fn iterates(&self) -> Result<Option<Vec<Tensor>>> {
+        self.iterate
+            .as_ref()
+            .map(|iterate| unflatten(iterate, &self.vars))
+            .transpose()
+    }
+
+    /// the current value of the momentum sequence $t_t$, which stays at 1 without acceleration
+    #[must_use]
+    pub fn momentum_t(&self) -> f64 {
+        self.t
+    }
+
+    fn set_vars(&self, x: &Tensor) -> Result<()> {
+        for (var, value) in self.vars.iter().zip(unflatten(x, &self.vars)?) {
+            var.set(&value)?;
+        }
+        Ok(())
+    }
+}
+
+/// the next term $t_{t+1}$ of the FISTA momentum sequence and the extrapolation weight $\frac{t_t - 1}{t_{t+1}}$
+fn fista_momentum(t: f64) -> (f64, f64) {
+    let t_next = (1. + (4. * t * t + 1.).sqrt()) / 2.;
+    (t_next, (t - 1.) / t_next)
+}
+
+/// the extrapolated point $x_t + w (x_t - x_{t-1})$
+fn extrapolate(x: &Tensor, previous: &Tensor, weight: f64) -> Result<Tensor> {
+    x + ((x - previous)? * weight)?
+}
+
+fn scalar(loss: &Tensor) -> Result<f64> {
+    loss.to_dtype(DType::F64)?.to_scalar::<f64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn prox_test() -> Result<()> {
+        let x = Tensor::new(&[-2f64, -0.5, 0., 0.5, 2.], &Device::Cpu)?;
+        let soft = Prox::L1(1.).prox(&x, 1.)?.to_vec1::<f64>()?;
+        assert_eq!(soft, [-1., 0., 0., 0., 1.]);
+        let nonneg = Prox::NonNegative.prox(&x, 1.)?.to_vec1::<f64>()?;
+        assert_eq!(nonneg, [0., 0., 0., 0.5, 2.]);
+        Ok(())
+    }
+
+    #[test]
+    fn momentum_t_test() -> Result<()> {
+        let w = Var::new(&[1f64, 2.], &Device::Cpu)?;
+        let mut optim = Fista::new(vec![w.clone()], ParamsProximal::default())?;
+        let loss = w.as_tensor().sqr()?.sum_all()?;
+        assert_eq!(optim.momentum_t(), 1.);
+        optim.backward_step(&loss)?;
+        // t_2 is the golden ratio
+        assert_approx_eq!(optim.momentum_t(), (1. + 5_f64.sqrt()) / 2.);
+        let mut t = optim.momentum_t();
+        for _ in 0..5 {
+            optim.backward_step(&loss)?;
+            t = (1. + (1. + 4. * t * t).sqrt()) / 2.;
+            assert_eq!(optim.momentum_t(), t);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn first_step_test() -> Result<()> {
+        // with t_1 = 1 there is no extrapolation on the first step, so it matches proximal gradient descent
+        let params = ParamsProximal {
+            lr: 0.1,
+            prox: Prox::L1(1.),
+        };
+        let w = Var::new(&[1f64, -0.05], &Device::Cpu)?;
+        let mut optim = Fista::new(vec![w.clone()], params)?;
+        optim.backward_step(&w.as_tensor().sum_all()?)?;
+        // gradient step to [0.9, -0.15] then soft thresholding by 0.1
+        let w = w.to_vec1::<f64>()?;
+        assert_approx_eq!(w[0], 0.8);
+        assert_approx_eq!(w[1], -0.05);
+        assert_eq!(optim.iterates()[0].to_vec1::<f64>()?, w);
+        Ok(())
+    }
+
+    #[test]
+    fn backtracking_test() -> Result<()> {
+        // for 5 x^2 the condition holds exactly when the step size is at most 1 / 10, so 1 is halved four times
+        struct Quadratic(Var);
+
+        impl Model for Quadratic {
+            fn loss(&self) -> candle_core::Result<Tensor> {
+                (self.0.as_tensor().sqr()? * 5.)?.sum_all()
+            }
+        }
+
+        let w = Var::new(&[1f64], &Device::Cpu)?;
+        let params = ParamsProximalBacktracking {
+            accelerated: false,
+            ..Default::default()
+        };
+        let mut optim = ProximalBacktracking::new(vec![w.clone()], params, Quadratic(w.clone()))?;
+        assert!(optim.iterates()?.is_none());
+        let loss = optim.model.loss()?;
+        if let ModelOutcome::Stepped(_, evals) = optim.backward_step(&loss)? {
+            assert_eq!(evals, 6);
+        }
+        assert_approx_eq!(optim.learning_rate(), 0.0625);
+        assert_approx_eq!(w.to_vec1::<f64>()?[0], 0.375);
+        assert_eq!(optim.momentum_t(), 1.);
+
+        // the condition needs four backtracks, so with two allowed the step is not taken
+        let w = Var::new(&[1f64], &Device::Cpu)?;
+        let params = ParamsProximalBacktracking {
+            max_backtracks: 2,
+            ..Default::default()
+        };
+        let mut optim = ProximalBacktracking::new(vec![w.clone()], params, Quadratic(w.clone()))?;
+        let loss = optim.model.loss()?;
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Truncated(next_loss, evals) => {
+                assert_eq!(evals, 3);
+                assert_approx_eq!(next_loss.to_scalar::<f64>()?, 5.);
+            }
+            outcome => panic!("expected a truncated step, got {outcome:?}"),
+        }
+        assert_eq!(w.to_vec1::<f64>()?, [1.]);
+        assert!(optim.iterates()?.is_none());
+
+        assert!(ProximalBacktracking::new(
+            vec![w.clone()],
+            ParamsProximalBacktracking {
+                shrink: 1.,
+                ..Default::default()
+            },
+            Quadratic(w)
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+/*!
+Particle swarm optimisation (PSO)
+
+A derivative-free, population based method, described in [Particle swarm optimization](https://doi.org/10.1109/ICNN.1995.488968)
+with the inertia weight of [A modified particle swarm optimizer](https://doi.org/10.1109/ICEC.1998.699146), which only
+evaluates [`Model::loss`]. Each particle $i$ has a position $x_i$ over the flattened variables, a velocity $v_i$, and
+the best position $p_i$ it has found, while $g$ is the best position found by the swarm. Each step updates
+
+$$
+\\begin{aligned}
+    v_i &\\leftarrow \\omega v_i + c_1 r_1 \\odot (p_i - x_i) + c_2 r_2 \\odot (g - x_i) \\\\
+    x_i &\\leftarrow x_i + v_i
+\\end{aligned}
+$$
+
+with $r_1, r_2$ uniform on $[0, 1)$ for each element, before evaluating the loss at each new position. The defaults are
+the constriction coefficients of [The particle swarm - explosion, stability, and convergence in a multidimensional complex space](https://doi.org/10.1109/4235.985692).
+
+The positions, velocities and best positions of the swarm are each kept as a single tensor with one row per particle,
+on the device and in the dtype of the variables, so the updates are batched; the losses are evaluated one particle
+at a time through [`Model::loss`]. The first step evaluates the initial swarm, spread uniformly within
+`initial_spread` of the variables with the first particle at the variables themselves.
+
+After each step the variables hold the best position of the swarm, and the optimiser has converged when every
+particle is within `swarm_tol` of it in each element. Loss evaluations that are NaN are treated as infinite.
+*/
+
+use crate::{flatten_vars, unflatten, LossOptimizer, Model, ModelOutcome, OptimVars};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for PSO
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsPso {
+    /// Number of particles in the swarm
+    pub particles: usize,
+    /// Inertia weight $\\omega$ of the velocity
+    pub inertia: f64,
+    /// Cognitive coefficient $c_1$, the attraction of each particle to its own best position
+    pub cognitive: f64,
+    /// Social coefficient $c_2$, the attraction of each particle to the best position of the swarm
+    pub social: f64,
+    /// The initial positions are uniform within this of the variables in each element
+    pub initial_spread: f64,
+    /// Optional bound on the magnitude of each element of the velocities
+    pub max_velocity: Option<f64>,
+    /// The optimiser has converged when every particle is within this of the best position in each element
+    pub swarm_tol: f64,
+}
+
+impl Default for ParamsPso {
+    fn default() -> Self {
+        Self {
+            particles: 20,
+            inertia: 0.729_844,
+            cognitive: 1.496_180,
+            social: 1.496_180,
+            initial_spread: 1.,
+            max_velocity: None,
+            swarm_tol: 1e-8,
+        }
+    }
+}
+
+/// PSO optimiser
+///
+/// A derivative-free method moving a swarm of particles towards the best positions found
+#[derive(Debug)]
+pub struct Pso<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsPso,
+    positions: Tensor,
+    velocities: Tensor,
+    best_positions: Tensor,
+    /// the loss at the best position of each particle, empty before the first step
+    best_losses: Vec<Tensor>,
+    /// `best_losses` as scalars
+    best_values: Vec<f64>,
+    /// the particle with the best position of the swarm
+    leader: usize,
+}
+
+impl<M: Model> LossOptimizer<M> for Pso<M> {
+    type Config = ParamsPso;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.particles == 0 {
+            candle_core::bail!("the swarm needs at least one particle");
+        }
+        if params.initial_spread < 0. {
+            candle_core::bail!(
+                "the initial spread of the swarm must be non-negative, got {}",
+                params.initial_spread
+            );
+        }
+        if params.max_velocity.is_some_and(|v| v <= 0.) {
+            candle_core::bail!("the maximum velocity must be positive");
+        }
+        let x = flatten_vars(&vs)?;
+        let n = x.elem_count();
+        let x = x.unsqueeze(0)?.broadcast_as((params.particles, n))?;
+        let offsets = (((x.rand_like(0., 1.)? * 2.)? - 1.)? * params.initial_spread)?;
+        // the first particle starts at the variables, so the swarm never starts worse than them
+        let offsets = Tensor::cat(
+            &[
+                offsets.narrow(0, 0, 1)?.zeros_like()?,
+                offsets.narrow(0, 1, params.particles - 1)?,
+            ],
+            0,
+        )?;
+        let positions = (x + offsets)?;
+        Ok(Self {
+            vars: vs,
+            model,
+            params,
+            velocities: positions.zeros_like()?,
+            best_positions: positions.copy()?,
+            positions,
+            best_losses: vec![],
+            best_values: vec![],
+            leader: 0,
+        })
+    }
+
+    fn backward_step(&mut self, _loss: &Tensor) -> CResult<ModelOutcome> {
+        let particles = self.params.particles;
+        if !self.best_losses.is_empty() {
+            let leader = self
+                .best_positions
+                .narrow(0, self.leader, 1)?
+                .broadcast_as(self.positions.shape())?;
+            let r_1 = self.positions.rand_like(0., 1.)?;
+            let r_2 = self.positions.rand_like(0., 1.)?;
+            let cognitive =
+                ((r_1 * self.params.cognitive)? * (&self.best_positions - &self.positions)?)?;
+            let social = ((r_2 * self.params.social)? * (leader - &self.positions)?)?;
+            let mut velocities = ((&self.velocities * self.params.inertia)? + cognitive + social)?;
+            if let Some(max) = self.params.max_velocity {
+                velocities = velocities.clamp(-max, max)?;
+            }
+            self.positions = (&self.positions + &velocities)?;
+            self.velocities = velocities;
+        }
+
+        let mut improved = Vec::with_capacity(particles);
+        for i in 0..particles {
+            self.set_vars(&self.positions.get(i)?)?;
+            let loss = self.model.loss()?.detach();
+            let f = scalar(&loss)?;
+            if self.best_values.len() <= i {
+                self.best_losses.push(loss);
+                self.best_values.push(f);
+                improved.push(1u8);
+            } else if f < self.best_values[i] {
+                self.best_losses[i] = loss;
+                self.best_values[i] = f;
+                improved.push(1u8);
+            } else {
+                improved.push(0u8);
+            }
+        }
+        let improved = Tensor::new(improved, self.positions.device())?
+            .unsqueeze(1)?
+            .broadcast_as(self.positions.shape())?;
+        self.best_positions = improved.where_cond(&self.positions, &self.best_positions)?;
+        for (i, f) in self.best_values.iter().enumerate() {
+            if *f < self.best_values[self.leader] {
+                self.leader = i;
+            }
+        }
+
+        let best = self.best_positions.get(self.leader)?;
+        self.set_vars(&best)?;
+        let best_loss = self.best_losses[self.leader].clone();
+        let distance = self
+            .positions
+            .broadcast_sub(&best.unsqueeze(0)?)?
+            .abs()?
+            .max(1)?
+            .max(0)?
+            .to_dtype(DType::F64)?
+            .to_scalar::<f64>()?;
+        if distance < self.params.swarm_tol {
+            info!("swarm converged");
+            return Ok(ModelOutcome::Converged(best_loss, particles));
+        }
+        Ok(ModelOutcome::Stepped(best_loss, particles))
+    }
+
+    /// the inertia weight $\\omega$
+    fn learning_rate(&self) -> f64 {
+        self.params.inertia
+    }
+
+    /// set the inertia weight $\\omega$
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.inertia = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for Pso<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> Pso<M> {
+    /// The positions of the particles over the flattened variables, with one row per particle
+    #[must_use]
+    pub fn positions(&self) -> &Tensor {
+        &self.positions
+    }
+
+    /// The velocities of the particles, with one row per particle
+    #[must_use]
+    pub fn velocities(&self) -> &Tensor {
+        &self.velocities
+    }
+
+    /// The best position found by each particle, with one row per particle
+    #[must_use]
+    pub fn best_positions(&self) -> &Tensor {
+        &self.best_positions
+    }
+
+    fn set_vars(&self, x: &Tensor) -> CResult<()> {
+        for (var, value) in self.vars.iter().zip(unflatten(x, &self.vars)?) {
+            var.set(&value)?;
+        }
+        Ok(())
+    }
+}
+
+fn scalar(loss: &Tensor) -> CResult<f64> {
+    let f = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+    Ok(if f.is_nan() { f64::INFINITY } else { f })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    use super::*;
+
+    /// $||x - 1||_{2}^{2}$
+    struct Sphere {
+        x: Var,
+    }
+
+    impl Model for Sphere {
+        fn loss(&self) -> CResult<Tensor> {
+            (self.x.as_tensor() - 1.)?.sqr()?.sum_all()
+        }
+    }
+
+    fn setup(params: ParamsPso) -> Result<(Var, Pso<Sphere>)> {
+        let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+        let optim = Pso::new(vec![x.clone()], params, Sphere { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsPso::default())?;
+        assert_approx_eq!(0.729_844, optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.learning_rate());
+        assert!(setup(ParamsPso {
+            particles: 0,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn init_test() -> Result<()> {
+        let (_, optim) = setup(ParamsPso {
+            particles: 5,
+            initial_spread: 0.5,
+            ..Default::default()
+        })?;
+        assert_eq!(optim.positions().dims(), [5, 3]);
+        let positions = optim.positions().to_vec2::<f64>()?;
+        assert_eq!(positions[0], [0., 0., 0.]);
+        assert!(positions.iter().flatten().all(|x| x.abs() <= 0.5));
+        assert_eq!(optim.velocities().sum_all()?.to_scalar::<f64>()?, 0.);
+        Ok(())
+    }
+
+    #[test]
+    fn step_test() -> Result<()> {
+        // the variables hold the best position, so the reported loss never increases
+        let (x, mut optim) = setup(ParamsPso {
+            particles: 8,
+            max_velocity: Some(0.1),
+            ..Default::default()
+        })?;
+        let mut loss = optim.model.loss()?;
+        let first = optim.backward_step(&loss)?;
+        assert!(matches!(first, ModelOutcome::Stepped(_, 8)));
+        for _ in 0..20 {
+            let previous = optim.positions().clone();
+            if let ModelOutcome::Stepped(next, _) = optim.backward_step(&loss)? {
+                assert!(next.to_scalar::<f64>()? <= loss.to_scalar::<f64>()?);
+                loss = next;
+            }
+            let moved = (optim.positions() - previous)?.abs()?.max(1)?.max(0)?;
+            assert!(moved.to_scalar::<f64>()? <= 0.1 + 1e-12);
+        }
+        assert_approx_eq!(
+            loss.to_scalar::<f64>()?,
+            optim.model.loss()?.to_scalar::<f64>()?
+        );
+        assert!(x.to_vec1::<f64>()? != [0., 0., 0.]);
+        Ok(())
+    }
+}
@@ -6,6 +6,10 @@ Described in [On the Variance of the Adaptive Learning Rate and Beyond](https://
 As decoupled weight decay is implemented, this can be used equivalent to the paper (which uses decoupled weight decay),
 or the PyTorch implementation (which does not)
 
+Whilst the variance of the adaptive learning rate is intractable ($\rho_t$ is small) the update falls back to SGD with momentum,
+so no warmup is needed. Following PyTorch the adaptive step is taken once $\rho_t > 5$ rather than the $\rho_t > 4$ of the paper:
+with the default $\beta_2$ this only changes the fifth step, whose rectification term would be close to zero.
+
 Pseudocode (including decoupling of weight decay):
 
 $$
@@ -48,7 +52,10 @@ $$
 use candle_core::{Result, Var};
 use candle_nn::optim::Optimizer;
 
-use crate::{Decay, OptimParams};
+use crate::{
+    bias_correction, warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot,
+    OptimParams, OptimVars, UnusedConfig,
+};
 
 /// R Adam optimiser
 ///
@@ -96,10 +103,19 @@ impl Default for ParamsRAdam {
     }
 }
 
+impl UnusedConfig for ParamsRAdam {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
 impl Optimizer for RAdam {
     type Config = ParamsRAdam;
 
     fn new(vars: Vec<Var>, params: ParamsRAdam) -> Result<Self> {
+        warn_unused_config(&params);
         let vars = vars
             .into_iter()
             .filter(|var| var.dtype().is_float())
@@ -132,7 +148,7 @@ impl Optimizer for RAdam {
         // println!("prod {}", prod);
         let rho_t = self.rho_inf
             - 2. * self.t * self.params.beta_2.powf(self.t)
-                / (1. - self.params.beta_2.powf(self.t));
+                / bias_correction(self.params.beta_2, self.t);
 
         if let Some(wd) = self.params.weight_decay {
             match wd {
@@ -147,10 +163,10 @@ impl Optimizer for RAdam {
                                 + ((1. - self.params.beta_1) * grad)?)?;
                             let v_next = ((self.params.beta_2 * v.as_tensor())?
                                 + ((1. - self.params.beta_2) * grad.powf(2.)?)?)?;
-                            let m_hat = (&m_next / (1. - self.params.beta_1.powf(self.t)))?;
+                            let m_hat = (&m_next / bias_correction(self.params.beta_1, self.t))?;
 
                             let delta = if rho_t > 5. {
-                                let l = ((1. - self.params.beta_2.powf(self.t)).sqrt()
+                                let l = (bias_correction(self.params.beta_2, self.t).sqrt()
                                     / (&v_next.sqrt()? + self.params.eps)?)?;
                                 let r = ((rho_t - 4.) * (rho_t - 2.) * self.rho_inf
                                     / ((self.rho_inf - 4.) * (self.rho_inf - 2.) * rho_t))
@@ -178,10 +194,10 @@ impl Optimizer for RAdam {
                                 + ((1. - self.params.beta_1) * grad)?)?;
                             let v_next = ((self.params.beta_2 * v.as_tensor())?
                                 + ((1. - self.params.beta_2) * grad.powf(2.)?)?)?;
-                            let m_hat = (&m_next / (1. - self.params.beta_1.powf(self.t)))?;
+                            let m_hat = (&m_next / bias_correction(self.params.beta_1, self.t))?;
 
                             let delta = if rho_t > 5. {
-                                let l = ((1. - self.params.beta_2.powf(self.t)).sqrt()
+                                let l = (bias_correction(self.params.beta_2, self.t).sqrt()
                                     / (&v_next.sqrt()? + self.params.eps)?)?;
                                 let r = ((rho_t - 4.) * (rho_t - 2.) * self.rho_inf
                                     / ((self.rho_inf - 4.) * (self.rho_inf - 2.) * rho_t))
@@ -207,10 +223,10 @@ impl Optimizer for RAdam {
                         + ((1. - self.params.beta_1) * grad)?)?;
                     let v_next = ((self.params.beta_2 * v.as_tensor())?
                         + ((1. - self.params.beta_2) * grad.powf(2.)?)?)?;
-                    let m_hat = (&m_next / (1. - self.params.beta_1.powf(self.t)))?;
+                    let m_hat = (&m_next / bias_correction(self.params.beta_1, self.t))?;
 
                     let delta = if rho_t > 5. {
-                        let l = ((1. - self.params.beta_2.powf(self.t)).sqrt()
+                        let l = (bias_correction(self.params.beta_2, self.t).sqrt()
                             / (&v_next.sqrt()? + self.params.eps)?)?;
                         let r = ((rho_t - 4.) * (rho_t - 2.) * self.rho_inf
                             / ((self.rho_inf - 4.) * (self.rho_inf - 2.) * rho_t))
@@ -245,6 +261,23 @@ impl OptimParams for RAdam {
     }
 }
 
+impl CurrentHyperparams for RAdam {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for RAdam {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
 impl RAdam {
     /// Return the vars being optimised
     #[must_use]
@@ -283,6 +316,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn rectification_test() -> Result<()> {
+        let params = ParamsRAdam {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let w = Var::new(&[0f64], &Device::Cpu)?;
+        let mut optim = RAdam::new(vec![w.clone()], params)?;
+        // with a constant unit gradient the bias corrected momentum is exactly 1
+        let loss = w.as_tensor().sum_all()?;
+        for step in 1..=5 {
+            optim.backward_step(&loss)?;
+            // rho_t <= 5: unrectified momentum steps of lr
+            assert_approx_eq!(w.to_vec1::<f64>()?[0], -0.1 * f64::from(step));
+        }
+        optim.backward_step(&loss)?;
+        // rho_6 is just over 5 so the adaptive step is heavily damped by the rectification term
+        let rho_6 = 1999. - 12. * 0.999_f64.powi(6) / (1. - 0.999_f64.powi(6));
+        let r = ((rho_6 - 4.) * (rho_6 - 2.) * 1999. / (1995. * 1997. * rho_6)).sqrt();
+        assert_approx_eq!(w.to_vec1::<f64>()?[0], 0.1f64.mul_add(-r, -0.5));
+        Ok(())
+    }
+
     #[test]
     fn into_inner_test() -> Result<()> {
         let params = ParamsRAdam::default();
@@ -0,0 +1,237 @@
+/*!
+Ranger optimiser
+
+A preset combining [RAdam](crate::radam) with [Lookahead](crate::lookahead) slow weights and
+[gradient centralization](crate::centralize), as popularised by
+[Ranger](https://github.com/lessw2020/Ranger-Deep-Learning-Optimizer).
+
+Each step the gradients are (optionally) centralized and passed to RAdam, which updates the fast weights; every
+$k$ steps the slow weights are moved towards the fast weights by $\\alpha$ and copied back into the variables.
+This is equivalent to wrapping RAdam in [`GradCentralization`](crate::centralize::GradCentralization) and then
+[`Lookahead`], but is configured through a single [`ParamsRanger`], with the defaults of the reference implementation.
+*/
+
+use candle_core::{backprop::GradStore, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+use log::warn;
+
+use crate::{
+    centralize::centralize_grad,
+    empty_grad_store,
+    lookahead::Lookahead,
+    radam::{ParamsRAdam, RAdam},
+    CurrentHyperparams, Decay, HyperSnapshot, OptimParams, OptimVars,
+};
+
+/// Ranger optimiser: RAdam with Lookahead and gradient centralization
+#[derive(Debug)]
+pub struct Ranger {
+    inner: Lookahead<RAdam>,
+    params: ParamsRanger,
+}
+
+/// Parameters for the Ranger optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsRanger {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of second moment
+    pub beta_2: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Weight decay
+    pub weight_decay: Option<Decay>,
+    /// Number of steps between synchronising the slow weights: this cannot be changed once the optimiser is created
+    pub k: usize,
+    /// Step size of the slow weights towards the fast weights: this cannot be changed once the optimiser is created
+    pub alpha: f64,
+    /// Whether to centralize the gradients
+    pub centralize: bool,
+}
+
+impl Default for ParamsRanger {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.95,
+            beta_2: 0.999,
+            eps: 1e-5,
+            weight_decay: None,
+            k: 6,
+            alpha: 0.5,
+            centralize: true,
+        }
+    }
+}
+
+impl ParamsRanger {
+    /// the parameters of the inner RAdam optimiser
+    fn radam(&self) -> ParamsRAdam {
+        ParamsRAdam {
+            lr: self.lr,
+            beta_1: self.beta_1,
+            beta_2: self.beta_2,
+            weight_decay: self.weight_decay,
+            eps: self.eps,
+        }
+    }
+}
+
+impl Optimizer for Ranger {
+    type Config = ParamsRanger;
+
+    fn new(vars: Vec<Var>, params: ParamsRanger) -> Result<Self> {
+        let radam = RAdam::new(vars.clone(), params.radam())?;
+        let inner = Lookahead::new(radam, vars, params.k, params.alpha)?;
+        Ok(Self { inner, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        if !self.params.centralize {
+            return self.inner.step(grads);
+        }
+        let mut centralized = empty_grad_store()?;
+        for var in self.inner.vars() {
+            if let Some(grad) = grads.get(var) {
+                centralized.insert(var, centralize_grad(grad)?);
+            }
+        }
+        self.inner.step(&centralized)
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl OptimParams for Ranger {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// set the parameters
+    ///
+    /// the Lookahead `k` and `alpha` are fixed on creation of the optimiser
+    fn set_params(&mut self, config: Self::Config) {
+        let mut config = config;
+        if self.params.k != config.k || self.params.alpha != config.alpha {
+            warn!("Lookahead k and alpha cannot be changed once set");
+            config.k = self.params.k;
+            config.alpha = self.params.alpha;
+        }
+        self.inner.inner_mut().set_params(config.radam());
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Ranger {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        self.inner.inner().current_hyperparams()
+    }
+}
+
+impl OptimVars for Ranger {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl Ranger {
+    /// the Lookahead slow weights, in the same order as the vars
+    #[must_use]
+    pub fn slow_weights(&self) -> Vec<&Tensor> {
+        self.inner.slow_weights()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.inner.into_optimiser().into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+    use crate::centralize::GradCentralization;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsRanger {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Ranger::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        assert_approx_eq!(0.002, optim.current_hyperparams().lr);
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsRanger::default();
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Ranger::new(vec![w], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsRanger {
+            lr: 0.002,
+            centralize: false,
+            k: 3,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        // k is kept from creation
+        assert_eq!(ParamsRanger { k: 6, ..new_params }, optim.params().clone());
+        assert_approx_eq!(optim.current_hyperparams().lr, 0.002);
+        assert!(Ranger::new(
+            vec![Var::new(0f32, &Device::Cpu)?],
+            ParamsRanger {
+                k: 0,
+                ..Default::default()
+            }
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn composition_test() -> Result<()> {
+        // Ranger takes the same steps as RAdam wrapped in gradient centralization and Lookahead
+        let params = ParamsRanger {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let coeffs = Tensor::new(&[[1f32, 2., 4.], [3., -1., 0.5]], &Device::Cpu)?;
+        let w = Var::new(&[[1f32, 2., 3.], [-1., 0., 1.]], &Device::Cpu)?;
+        let mut ranger = Ranger::new(vec![w.clone()], params.clone())?;
+        let w_ref = Var::new(&[[1f32, 2., 3.], [-1., 0., 1.]], &Device::Cpu)?;
+        let radam = GradCentralization::new(RAdam::new(vec![w_ref.clone()], params.radam())?);
+        let mut reference = Lookahead::new(radam, vec![w_ref.clone()], params.k, params.alpha)?;
+        for _ in 0..13 {
+            ranger.backward_step(&w.as_tensor().mul(&coeffs)?.sqr()?.sum_all()?)?;
+            reference.backward_step(&w_ref.as_tensor().mul(&coeffs)?.sqr()?.sum_all()?)?;
+        }
+        for (row, row_ref) in w.to_vec2::<f32>()?.iter().zip(w_ref.to_vec2::<f32>()?) {
+            for (x, x_ref) in row.iter().zip(row_ref) {
+                assert_approx_eq!(x, x_ref, 1e-6);
+            }
+        }
+        Ok(())
+    }
+}
@@ -43,6 +43,11 @@ $$
 use candle_core::{Result, Var};
 use candle_nn::optim::Optimizer;
 
+use crate::{
+    warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot, Momentum, OptimVars,
+    UnusedConfig,
+};
+
 /// RMS Prop optimiser
 ///
 /// Described in <https://www.cs.toronto.edu/~tijmen/csc321/slides/lecture_slides_lec6.pdf>
@@ -60,6 +65,7 @@ trait RmsInner {
     // where
     //     Self: Sized;
     fn into_inner(self) -> Vec<Var>;
+    fn vars(&self) -> Vec<&Var>;
     fn inner_step(
         &self,
         params: &ParamsRMSprop,
@@ -97,6 +103,10 @@ impl RmsInner for VecRMSProp {
         self.0.into_iter().map(|var| var.theta).collect()
     }
 
+    fn vars(&self) -> Vec<&Var> {
+        self.0.iter().map(|var| &var.theta).collect()
+    }
+
     fn inner_step(
         &self,
         params: &ParamsRMSprop,
@@ -167,6 +177,10 @@ impl RmsInner for VecRmsPropCentered {
         self.0.into_iter().map(|var| var.theta).collect()
     }
 
+    fn vars(&self) -> Vec<&Var> {
+        self.0.iter().map(|var| &var.theta).collect()
+    }
+
     fn inner_step(
         &self,
         params: &ParamsRMSprop,
@@ -257,6 +271,10 @@ impl RmsInner for VecRmsPropMomentum {
         self.vars.into_iter().map(|var| var.theta).collect()
     }
 
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|var| &var.theta).collect()
+    }
+
     fn inner_step(
         &self,
         params: &ParamsRMSprop,
@@ -346,6 +364,10 @@ impl RmsInner for VecRmsPropMomentumCentered {
         self.vars.into_iter().map(|var| var.theta).collect()
     }
 
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|var| &var.theta).collect()
+    }
+
     fn inner_step(
         &self,
         params: &ParamsRMSprop,
@@ -444,10 +466,21 @@ impl Default for ParamsRMSprop {
     }
 }
 
+impl UnusedConfig for ParamsRMSprop {
+    fn unused_config(&self) -> Vec<String> {
+        let mut unused: Vec<String> = zero_decay(self.weight_decay).into_iter().collect();
+        if self.momentum == Some(0.) {
+            unused.push("momentum has no effect with a coefficient of zero".to_string());
+        }
+        unused
+    }
+}
+
 impl Optimizer for RMSprop {
     type Config = ParamsRMSprop;
 
     fn new(vars: Vec<Var>, params: ParamsRMSprop) -> Result<Self> {
+        warn_unused_config(&params);
         if let Some(momentum) = params.momentum {
             if params.centered {
                 Ok(Self {
@@ -510,6 +543,28 @@ impl Optimizer for RMSprop {
     }
 }
 
+impl CurrentHyperparams for RMSprop {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: None,
+            weight_decay: self.params.weight_decay.map(Decay::WeightDecay),
+            momentum: self.params.momentum.map(Momentum::Classical),
+        }
+    }
+}
+
+impl OptimVars for RMSprop {
+    fn vars(&self) -> Vec<&Var> {
+        match &self.vars {
+            VarRMS::RMSProp(vars) => vars.vars(),
+            VarRMS::Centered(vars) => vars.vars(),
+            VarRMS::Momentum(vars) => vars.vars(),
+            VarRMS::MomentumCentered(vars) => vars.vars(),
+        }
+    }
+}
+
 impl RMSprop {
     /// Return the vars being optimised
     #[must_use]
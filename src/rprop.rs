@@ -0,0 +1,240 @@
+/*!
+Rprop optimiser
+
+Resilient backpropagation, described in
+[A Direct Adaptive Method for Faster Backpropagation Learning: The RPROP Algorithm](https://doi.org/10.1109/ICNN.1993.298623)
+
+Each element has its own step size, which grows while the gradient keeps its sign and shrinks when the sign flips;
+only the sign of the gradient is used for the step. This follows the variant in `torch.optim.Rprop`: after a sign
+flip the element is not moved, and its gradient is treated as zero on the next step.
+
+Pseudocode:
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\theta_0 \\in \\mathbf{R}^d \\text{ (params)},f(\\theta)
+        \\text{ (objective)},                                                             \\\\
+    &\\hspace{13mm}      \\eta_{+/-} \\text{ (etaplus, etaminus)}, \\Gamma_{max/min}
+        \\text{ (step sizes)}                                                             \\\\
+    &\\textbf{initialize} :   g^0_{prev} \\leftarrow 0,
+        \\: \\eta_0 \\leftarrow \\text{lr (learning rate)}                                   \\\\
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm} \\textbf{for} \\text{  } i = 0, 1, \\ldots, d-1 \\: \\mathbf{do}            \\\\
+    &\\hspace{10mm}  \\textbf{if} \\:   g^i_{prev} g^i_t  > 0                               \\\\
+    &\\hspace{15mm}  \\eta^i_t \\leftarrow \\mathrm{min}(\\eta^i_{t-1} \\eta_{+},
+        \\Gamma_{max})                                                                    \\\\
+    &\\hspace{10mm}  \\textbf{else if}  \\:  g^i_{prev} g^i_t < 0                           \\\\
+    &\\hspace{15mm}  \\eta^i_t \\leftarrow \\mathrm{max}(\\eta^i_{t-1} \\eta_{-},
+        \\Gamma_{min})                                                                    \\\\
+    &\\hspace{15mm}  g^i_t \\leftarrow 0                                                   \\\\
+    &\\hspace{10mm}  \\textbf{else}  \\:                                                    \\\\
+    &\\hspace{15mm}  \\eta^i_t \\leftarrow \\eta^i_{t-1}                                    \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1}- \\eta_t \\mathrm{sign}(g_t)             \\\\
+    &\\hspace{5mm}g_{prev} \\leftarrow  g_t                                                 \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+As only the sign of the gradient is used, Rprop is suited to full batch problems rather than to noisy minibatch
+gradients.
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{sign, OptimParams, OptimVars};
+
+/// Rprop optimiser
+///
+/// Described in [A Direct Adaptive Method for Faster Backpropagation Learning: The RPROP Algorithm](https://doi.org/10.1109/ICNN.1993.298623)
+#[derive(Debug)]
+pub struct Rprop {
+    vars: Vec<VarRprop>,
+    params: ParamsRprop,
+}
+
+#[derive(Debug)]
+struct VarRprop {
+    theta: Var,
+    /// gradient of the previous step, zeroed where its sign flipped
+    prev: Var,
+    /// step size of each element
+    step_size: Var,
+    /// whether the step sizes have been initialised from the learning rate
+    started: bool,
+}
+
+/// Parameters for the Rprop optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsRprop {
+    /// Learning rate: the initial step size of each element, taken on its first step
+    pub lr: f64,
+    /// Multiplicative factors by which the step size decreases and increases, `(etaminus, etaplus)`
+    pub etas: (f64, f64),
+    /// Minimum and maximum step sizes
+    pub step_sizes: (f64, f64),
+}
+
+impl Default for ParamsRprop {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            etas: (0.5, 1.2),
+            step_sizes: (1e-6, 50.),
+        }
+    }
+}
+
+impl Optimizer for Rprop {
+    type Config = ParamsRprop;
+
+    fn new(vars: Vec<Var>, params: ParamsRprop) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let prev = Var::zeros(shape, dtype, device)?;
+                let step_size = Var::zeros(shape, dtype, device)?;
+                Ok(VarRprop {
+                    theta: var,
+                    prev,
+                    step_size,
+                    started: false,
+                })
+            })
+            .collect::<Result<Vec<VarRprop>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let (eta_minus, eta_plus) = self.params.etas;
+        let (step_min, step_max) = self.params.step_sizes;
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                if !var.started {
+                    var.step_size
+                        .set(&(var.step_size.ones_like()? * self.params.lr)?)?;
+                    var.started = true;
+                }
+                let dtype = grad.dtype();
+                let agreement = sign(&grad.mul(var.prev.as_tensor())?)?;
+                let same = agreement.gt(0.)?.to_dtype(dtype)?;
+                let flipped = agreement.lt(0.)?.to_dtype(dtype)?;
+                let factor = (((same * (eta_plus - 1.))? + (&flipped * (eta_minus - 1.))?)? + 1.)?;
+                let step_size = var.step_size.mul(&factor)?.clamp(step_min, step_max)?;
+                // elements whose gradient flipped sign are not moved
+                let grad = grad.mul(&flipped.affine(-1., 1.)?)?;
+                theta.set(&theta.sub(&sign(&grad)?.mul(&step_size)?)?)?;
+                var.step_size.set(&step_size)?;
+                var.prev.set(&grad)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Rprop {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl OptimVars for Rprop {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Rprop {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Tensor, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsRprop {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Rprop::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsRprop {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Rprop::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsRprop {
+            lr: 0.002,
+            etas: (0.3, 1.5),
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn step_size_test() -> Result<()> {
+        // the first element keeps the sign of its gradient and the second flips it each step
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let mut optim = Rprop::new(vec![x.clone()], ParamsRprop::default())?;
+        for coeffs in [[1f64, 1.], [1., -1.], [1., 1.]] {
+            let coeffs = Tensor::new(&coeffs, &Device::Cpu)?;
+            optim.backward_step(&x.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        }
+        // steps of 0.01, 0.012 and 0.0144 for the first element, and for the second a step of 0.01, none after the
+        // flip with the step size halved, then a step of 0.005 as the previous gradient was zeroed
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], -0.0364);
+        assert_approx_eq!(x[1], -0.015);
+        let step_size = optim.vars[0].step_size.to_vec1::<f64>()?;
+        assert_approx_eq!(step_size[0], 0.0144);
+        assert_approx_eq!(step_size[1], 0.005);
+        Ok(())
+    }
+}
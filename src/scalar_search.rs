@@ -0,0 +1,295 @@
+/*!
+Derivative-free minimisation of scalar functions, for line searches that only evaluate the loss
+
+[`line_minimise`] first brackets a minimum by stepping downhill with golden ratio growth and parabolic extrapolation,
+then locates it by [Brent's method](https://doi.org/10.1093/comjnl/14.4.422), which combines parabolic interpolation
+with golden section steps, as described in Numerical Recipes (sections 10.1 and 10.2). Neither uses derivatives, so
+these are suited to losses whose gradients are unavailable, such as in [Powell's method](crate::powell).
+
+Evaluations that are NaN are treated as infinite, so the search steps away from where the function is undefined.
+*/
+
+use candle_core::Result;
+
+const GOLD: f64 = 1.618_034;
+const CGOLD: f64 = 0.381_966_0;
+const GROWTH_LIMIT: f64 = 100.;
+const TINY: f64 = 1e-20;
+const ZEPS: f64 = 1e-10;
+
+/// The result of [`line_minimise`]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ScalarMinimum {
+    /// the point found
+    pub x: f64,
+    /// the value of the function at `x`
+    pub fx: f64,
+    /// the number of evaluations of the function made
+    pub evals: usize,
+}
+
+/// minimise the scalar function `f`, starting from 0 where its value is `f0` and taking a first step of `step`
+///
+/// The minimum is located to a relative tolerance of `tol`, which should not be below the square root of the machine
+/// precision of `f`. At most `max_iter` iterations are made by each of the bracketing and Brent's method.
+///
+/// # Errors
+///
+/// Errors if `f` fails, or if no minimum is bracketed within `max_iter` iterations, as when `f` decreases without bound
+pub fn line_minimise(
+    mut f: impl FnMut(f64) -> Result<f64>,
+    f0: f64,
+    step: f64,
+    tol: f64,
+    max_iter: usize,
+) -> Result<ScalarMinimum> {
+    let mut f = |x: f64| -> Result<f64> { Ok(not_nan(f(x)?)) };
+    let (bracket, bracket_evals) = bracket(&mut f, 0., not_nan(f0), step, max_iter)?;
+    let mut minimum = brent(&mut f, bracket, tol, max_iter)?;
+    minimum.evals += bracket_evals;
+    Ok(minimum)
+}
+
+fn not_nan(fx: f64) -> f64 {
+    if fx.is_nan() {
+        f64::INFINITY
+    } else {
+        fx
+    }
+}
+
+/// three points $a, b, c$ with $b$ between $a$ and $c$, and $f(b) \\leq f(a), f(c)$
+#[derive(Clone, Copy, Debug)]
+struct Bracket {
+    a: f64,
+    b: f64,
+    c: f64,
+    fb: f64,
+}
+
+/// bracket a minimum of `f` starting from `a` and `b`, returning the bracket and the number of evaluations
+#[allow(clippy::many_single_char_names)]
+fn bracket(
+    f: &mut impl FnMut(f64) -> Result<f64>,
+    a: f64,
+    fa: f64,
+    b: f64,
+    max_iter: usize,
+) -> Result<(Bracket, usize)> {
+    let (mut a, mut b, mut fa) = (a, b, fa);
+    let mut fb = f(b)?;
+    let mut evals = 1;
+    if fb > fa {
+        std::mem::swap(&mut a, &mut b);
+        std::mem::swap(&mut fa, &mut fb);
+    }
+    let mut c = GOLD.mul_add(b - a, b);
+    let mut fc = f(c)?;
+    evals += 1;
+    let mut iter = 0;
+    while fb > fc {
+        if iter >= max_iter {
+            candle_core::bail!(
+                "could not bracket a minimum in {max_iter} iterations: the function may be unbounded below"
+            );
+        }
+        iter += 1;
+        // extrapolate the parabola through a, b, c
+        let r = (b - a) * (fb - fc);
+        let q = (b - c) * (fb - fa);
+        let denom = 2. * (q - r).abs().max(TINY).copysign(q - r);
+        let mut u = b - (b - c).mul_add(q, -(b - a) * r) / denom;
+        let u_lim = GROWTH_LIMIT.mul_add(c - b, b);
+        let mut fu;
+        if !u.is_finite() {
+            u = GOLD.mul_add(c - b, c);
+            fu = f(u)?;
+            evals += 1;
+        } else if (b - u) * (u - c) > 0. {
+            // parabolic u is between b and c
+            fu = f(u)?;
+            evals += 1;
+            if fu < fc {
+                let bracket = Bracket {
+                    a: b,
+                    b: u,
+                    c,
+                    fb: fu,
+                };
+                return Ok((bracket, evals));
+            } else if fu > fb {
+                let bracket = Bracket { a, b, c: u, fb };
+                return Ok((bracket, evals));
+            }
+            u = GOLD.mul_add(c - b, c);
+            fu = f(u)?;
+            evals += 1;
+        } else if (c - u) * (u - u_lim) > 0. {
+            // parabolic u is between c and its allowed limit
+            fu = f(u)?;
+            evals += 1;
+            if fu < fc {
+                b = c;
+                c = u;
+                u = GOLD.mul_add(c - b, c);
+                fb = fc;
+                fc = fu;
+                fu = f(u)?;
+                evals += 1;
+            }
+        } else if (u - u_lim) * (u_lim - c) >= 0. {
+            u = u_lim;
+            fu = f(u)?;
+            evals += 1;
+        } else {
+            u = GOLD.mul_add(c - b, c);
+            fu = f(u)?;
+            evals += 1;
+        }
+        a = b;
+        b = c;
+        c = u;
+        fa = fb;
+        fb = fc;
+        fc = fu;
+    }
+    Ok((Bracket { a, b, c, fb }, evals))
+}
+
+/// locate the minimum within `bracket` by Brent's method, with the names of Numerical Recipes
+#[allow(clippy::many_single_char_names)]
+fn brent(
+    f: &mut impl FnMut(f64) -> Result<f64>,
+    bracket: Bracket,
+    tol: f64,
+    max_iter: usize,
+) -> Result<ScalarMinimum> {
+    let (mut a, mut b) = if bracket.a < bracket.c {
+        (bracket.a, bracket.c)
+    } else {
+        (bracket.c, bracket.a)
+    };
+    // x is the best point, w the second best and v the previous value of w
+    let (mut x, mut w, mut v) = (bracket.b, bracket.b, bracket.b);
+    let (mut fx, mut fw, mut fv) = (bracket.fb, bracket.fb, bracket.fb);
+    // d is the last step and e the step before it
+    let mut d: f64 = 0.;
+    let mut e: f64 = 0.;
+    let mut evals = 0;
+    for _ in 0..max_iter {
+        let xm = 0.5 * (a + b);
+        let tol1 = tol.mul_add(x.abs(), ZEPS);
+        let tol2 = 2. * tol1;
+        if (x - xm).abs() <= 0.5f64.mul_add(-(b - a), tol2) {
+            break;
+        }
+        let mut golden = true;
+        if e.abs() > tol1 {
+            // try a parabolic step through x, w and v
+            let r = (x - w) * (fx - fv);
+            let mut q = (x - v) * (fx - fw);
+            let mut p = (x - v).mul_add(q, -(x - w) * r);
+            q = 2. * (q - r);
+            if q > 0. {
+                p = -p;
+            }
+            q = q.abs();
+            let e_prev = e;
+            if p.abs() < (0.5 * q * e_prev).abs() && p > q * (a - x) && p < q * (b - x) {
+                e = d;
+                d = p / q;
+                let u = x + d;
+                if u - a < tol2 || b - u < tol2 {
+                    d = tol1.copysign(xm - x);
+                }
+                golden = false;
+            }
+        }
+        if golden {
+            e = if x >= xm { a - x } else { b - x };
+            d = CGOLD * e;
+        }
+        let u = if d.abs() >= tol1 {
+            x + d
+        } else {
+            x + tol1.copysign(d)
+        };
+        let fu = f(u)?;
+        evals += 1;
+        if fu <= fx {
+            if u >= x {
+                a = x;
+            } else {
+                b = x;
+            }
+            (v, w, x) = (w, x, u);
+            (fv, fw, fx) = (fw, fx, fu);
+        } else {
+            if u < x {
+                a = u;
+            } else {
+                b = u;
+            }
+            #[allow(clippy::float_cmp)]
+            if fu <= fw || w == x {
+                (v, w) = (w, u);
+                (fv, fw) = (fw, fu);
+            } else if fu <= fv || v == x || v == w {
+                v = u;
+                fv = fu;
+            }
+        }
+    }
+    Ok(ScalarMinimum { x, fx, evals })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn bracket_test() -> Result<()> {
+        // stepping downhill from 0 towards the minimum of (x - 10)^2 at 10
+        let mut f = |x: f64| -> candle_core::Result<f64> { Ok((x - 10.).powi(2)) };
+        let (bracket, _) = bracket(&mut f, 0., 100., 1., 50)?;
+        assert!(bracket.a.min(bracket.c) < 10. && bracket.a.max(bracket.c) > 10.);
+        assert!(bracket.fb <= f(bracket.a)? && bracket.fb <= f(bracket.c)?);
+        Ok(())
+    }
+
+    #[test]
+    fn line_minimise_test() -> Result<()> {
+        // the double well (x^2 - 4)^2 + x slopes up at 0, so the search steps back to the well near -2
+        let f = |x: f64| -> candle_core::Result<f64> { Ok(x.mul_add(x, -4.).powi(2) + x) };
+        let minimum = line_minimise(f, 16., 0.1, 1e-8, 100)?;
+        assert!(minimum.x < 0.);
+        // the derivative 4 x (x^2 - 4) + 1 vanishes at the minimum
+        assert_approx_eq!(
+            4. * minimum.x * minimum.x.mul_add(minimum.x, -4.) + 1.,
+            0.,
+            1e-5
+        );
+        assert_approx_eq!(minimum.fx, f(minimum.x)?);
+        Ok(())
+    }
+
+    #[test]
+    fn nan_test() -> Result<()> {
+        // (x - 1)^2 is only defined for x >= 0 here, and the large first step lands where it is not
+        let f = |x: f64| -> candle_core::Result<f64> {
+            Ok(if x < -0.5 { f64::NAN } else { (x - 1.).powi(2) })
+        };
+        let minimum = line_minimise(f, 1., -2., 1e-8, 100)?;
+        assert_approx_eq!(minimum.x, 1., 1e-7);
+        Ok(())
+    }
+
+    #[test]
+    fn unbounded_test() {
+        let f = |x: f64| -> candle_core::Result<f64> { Ok(-x) };
+        assert!(line_minimise(f, 0., 1., 1e-8, 20).is_err());
+    }
+}
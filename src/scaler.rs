@@ -0,0 +1,253 @@
+/*!
+Dynamic loss scaling for reduced precision training
+
+Small gradients underflow to zero in f16. To avoid this the loss is multiplied by a large scale factor before
+backpropagation, and the gradients divided by it again before the optimiser step.
+If the scaled gradients overflow (contain inf or nan) the step is skipped and the scale reduced by `backoff_factor`;
+after `growth_interval` consecutive successful steps the scale is increased by `growth_factor`.
+
+When used with a [`GradAccumulator`](crate::accumulate::GradAccumulator) the gradients are accumulated still scaled,
+and are unscaled and checked for overflow only once, over the full accumulated gradient, in
+[`GradScaler::step_accumulated`]. If any of the accumulated gradients overflowed the whole accumulated step is skipped.
+*/
+
+use candle_core::{backprop::GradStore, DType, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{accumulate::GradAccumulator, empty_grad_store, OptimVars};
+
+/// Parameters for the gradient scaler
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsGradScaler {
+    /// initial scale factor
+    pub init_scale: f64,
+    /// factor by which the scale grows after `growth_interval` steps without overflow
+    pub growth_factor: f64,
+    /// factor by which the scale is multiplied when an overflow occurs
+    pub backoff_factor: f64,
+    /// number of consecutive steps without overflow before the scale is grown
+    pub growth_interval: usize,
+}
+
+impl Default for ParamsGradScaler {
+    fn default() -> Self {
+        Self {
+            init_scale: 65536.,
+            growth_factor: 2.,
+            backoff_factor: 0.5,
+            growth_interval: 2000,
+        }
+    }
+}
+
+/// Dynamic loss scaler
+#[derive(Clone, Debug)]
+pub struct GradScaler {
+    scale: f64,
+    params: ParamsGradScaler,
+    growth_tracker: usize,
+}
+
+impl GradScaler {
+    /// create a new scaler
+    #[must_use]
+    pub fn new(params: ParamsGradScaler) -> Self {
+        Self {
+            scale: params.init_scale,
+            params,
+            growth_tracker: 0,
+        }
+    }
+
+    /// the current scale factor
+    #[must_use]
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// scale the loss before backpropagation
+    ///
+    /// # Errors
+    ///
+    /// Errors if the loss cannot be scaled
+    pub fn scale_loss(&self, loss: &Tensor) -> Result<Tensor> {
+        loss * self.scale
+    }
+
+    /// divide the gradients of `vars` by the current scale
+    ///
+    /// returns `None` if any of the gradients contain inf or nan
+    ///
+    /// # Errors
+    ///
+    /// Errors if the gradients cannot be unscaled
+    pub fn unscale(&self, grads: &GradStore, vars: &[&Var]) -> Result<Option<GradStore>> {
+        let inv_scale = self.scale.recip();
+        let mut unscaled = empty_grad_store()?;
+        for var in vars {
+            if let Some(grad) = grads.get(var) {
+                if !is_finite(grad)? {
+                    return Ok(None);
+                }
+                unscaled.insert(var, (grad * inv_scale)?);
+            }
+        }
+        Ok(Some(unscaled))
+    }
+
+    /// unscale the gradients and step the optimiser, unless they overflowed, then update the scale
+    ///
+    /// returns whether the step was taken
+    ///
+    /// # Errors
+    ///
+    /// Errors if the gradients cannot be unscaled or the step fails
+    pub fn step<O: Optimizer + OptimVars>(
+        &mut self,
+        optimiser: &mut O,
+        grads: &GradStore,
+    ) -> Result<bool> {
+        let unscaled = self.unscale(grads, &optimiser.vars())?;
+        if let Some(unscaled) = &unscaled {
+            optimiser.step(unscaled)?;
+        }
+        self.update(unscaled.is_none());
+        Ok(unscaled.is_some())
+    }
+
+    /// backpropagate the scaled loss, then [`GradScaler::step`]
+    ///
+    /// # Errors
+    ///
+    /// Errors if the backward pass fails, the gradients cannot be unscaled or the step fails
+    pub fn backward_step<O: Optimizer + OptimVars>(
+        &mut self,
+        optimiser: &mut O,
+        loss: &Tensor,
+    ) -> Result<bool> {
+        let grads = self.scale_loss(loss)?.backward()?;
+        self.step(optimiser, &grads)
+    }
+
+    /// step with the mean of the (scaled) gradients accumulated in `accumulator`, unscaling them once
+    ///
+    /// the accumulation buffer is emptied whether or not the step is taken;
+    /// returns whether the step was taken
+    ///
+    /// # Errors
+    ///
+    /// Errors if the gradients cannot be unscaled or the step fails
+    pub fn step_accumulated<O: Optimizer + OptimVars>(
+        &mut self,
+        accumulator: &mut GradAccumulator<O>,
+    ) -> Result<bool> {
+        let Some(mean) = accumulator.take_mean()? else {
+            return Ok(false);
+        };
+        let optimiser = accumulator.inner_mut();
+        self.step(optimiser, &mean)
+    }
+
+    /// update the scale after a step, given whether the gradients overflowed
+    fn update(&mut self, found_inf: bool) {
+        if found_inf {
+            self.scale *= self.params.backoff_factor;
+            self.growth_tracker = 0;
+        } else {
+            self.growth_tracker += 1;
+            if self.growth_tracker == self.params.growth_interval {
+                self.scale *= self.params.growth_factor;
+                self.growth_tracker = 0;
+            }
+        }
+    }
+}
+
+/// whether all elements of the tensor are finite (any inf or nan makes the sum non finite)
+fn is_finite(tensor: &Tensor) -> Result<bool> {
+    Ok(tensor
+        .to_dtype(DType::F64)?
+        .sum_all()?
+        .to_scalar::<f64>()?
+        .is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::Device;
+
+    fn sgd(w: &Var) -> Result<SGD> {
+        let params = ParamsSGD {
+            lr: 0.1,
+            ..Default::default()
+        };
+        Ok(SGD::new(vec![w.clone()], params)?)
+    }
+
+    #[test]
+    fn scale_update_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut optim = sgd(&w)?;
+        let mut scaler = GradScaler::new(ParamsGradScaler {
+            init_scale: 1024.,
+            growth_interval: 2,
+            ..Default::default()
+        });
+        let loss = w.as_tensor().sum_all()?;
+        assert!(scaler.backward_step(&mut optim, &loss)?);
+        // the update uses the unscaled gradient
+        assert_eq!(w.to_vec1::<f32>()?, [0.9, 1.9]);
+        assert_approx_eq!(scaler.scale(), 1024.);
+        assert!(scaler.backward_step(&mut optim, &loss)?);
+        assert_approx_eq!(scaler.scale(), 2048.);
+        let inf_loss = (w.as_tensor() * f64::INFINITY)?.sum_all()?;
+        assert!(!scaler.backward_step(&mut optim, &inf_loss)?);
+        assert_approx_eq!(scaler.scale(), 1024.);
+        Ok(())
+    }
+
+    #[test]
+    fn accumulated_overflow_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut acc = GradAccumulator::new(sgd(&w)?);
+        let mut scaler = GradScaler::new(ParamsGradScaler {
+            init_scale: 1024.,
+            ..Default::default()
+        });
+        let loss = w.as_tensor().sum_all()?;
+        let inf_loss = (w.as_tensor() * f64::INFINITY)?.sum_all()?;
+        acc.accumulate(&scaler.scale_loss(&loss)?)?;
+        acc.accumulate(&scaler.scale_loss(&inf_loss)?)?;
+        acc.accumulate(&scaler.scale_loss(&loss)?)?;
+        // a single overflowing micro-batch skips the whole accumulated step
+        assert!(!scaler.step_accumulated(&mut acc)?);
+        assert_eq!(w.to_vec1::<f32>()?, [1., 2.]);
+        assert_eq!(acc.accumulated(), 0);
+        assert_approx_eq!(scaler.scale(), 512.);
+        Ok(())
+    }
+
+    #[test]
+    fn accumulated_unscale_once_test() -> Result<()> {
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut acc = GradAccumulator::new(sgd(&w)?);
+        let mut scaler = GradScaler::new(ParamsGradScaler {
+            init_scale: 1024.,
+            ..Default::default()
+        });
+        // gradients of [1, 1] and [3, 3] average to [2, 2] once unscaled
+        acc.accumulate(&scaler.scale_loss(&w.as_tensor().sum_all()?)?)?;
+        acc.accumulate(&scaler.scale_loss(&(w.as_tensor() * 3.)?.sum_all()?)?)?;
+        assert!(scaler.step_accumulated(&mut acc)?);
+        let w = w.to_vec1::<f32>()?;
+        assert_approx_eq!(w[0], 0.8);
+        assert_approx_eq!(w[1], 1.8);
+        // nothing accumulated so no step
+        assert!(!scaler.step_accumulated(&mut acc)?);
+        Ok(())
+    }
+}
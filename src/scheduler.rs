@@ -0,0 +1,619 @@
+/*!
+Learning rate schedulers
+
+Schedulers map the current step and a base learning rate to the learning rate that should be used for that step.
+They are stateless with respect to the optimiser, so the result can be passed to `set_learning_rate`
+on any of the optimisers in this crate, or set directly with [`apply`].
+*/
+
+use std::collections::VecDeque;
+
+use candle_core::Result;
+use candle_nn::optim::Optimizer;
+
+/// Trait for learning rate schedulers
+pub trait LrScheduler {
+    /// get the learning rate to use at `step`, given the base learning rate
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64;
+}
+
+/// Set the learning rate of the optimiser to that given by the scheduler at `step`
+///
+/// The base learning rate is passed explicitly rather than read from the optimiser, as the optimiser's
+/// learning rate is overwritten by the schedule on each call.
+pub fn apply<O: Optimizer>(
+    optimiser: &mut O,
+    scheduler: &impl LrScheduler,
+    base_lr: f64,
+    step: usize,
+) {
+    optimiser.set_learning_rate(scheduler.get_lr(step, base_lr));
+}
+
+/// Warmup that ends once the gradient norm has stabilised
+///
+/// Until the variance of the gradient norm over the last `window` steps drops below `threshold`,
+/// the learning rate is held at `warmup_factor * base_lr`. Once it does the inner schedule takes over,
+/// being passed the number of steps since the handoff.
+///
+/// The gradient norm must be fed in every step via [`AdaptiveWarmup::observe`].
+#[derive(Clone, Debug)]
+pub struct AdaptiveWarmup<S: LrScheduler> {
+    inner: S,
+    warmup_factor: f64,
+    window: usize,
+    threshold: f64,
+    norms: VecDeque<f64>,
+    steps: usize,
+    handoff: Option<usize>,
+}
+
+impl<S: LrScheduler> AdaptiveWarmup<S> {
+    /// create a new adaptive warmup, holding the learning rate at `warmup_factor * base_lr`
+    /// until the variance of the gradient norm over the last `window` steps is below `threshold`
+    #[must_use]
+    pub fn new(inner: S, warmup_factor: f64, window: usize, threshold: f64) -> Self {
+        Self {
+            inner,
+            warmup_factor,
+            window,
+            threshold,
+            norms: VecDeque::with_capacity(window),
+            steps: 0,
+            handoff: None,
+        }
+    }
+
+    /// record the gradient norm for the current step
+    ///
+    /// once the window is full and the variance of the norms in it is below the threshold warmup ends,
+    /// with the inner schedule starting from the step whose norm completed the stable window
+    pub fn observe(&mut self, grad_norm: f64) {
+        self.steps += 1;
+        if self.handoff.is_some() {
+            return;
+        }
+        if self.norms.len() == self.window {
+            self.norms.pop_front();
+        }
+        self.norms.push_back(grad_norm);
+        if self.norms.len() == self.window && self.variance() < self.threshold {
+            self.handoff = Some(self.steps);
+        }
+    }
+
+    /// the step at which warmup ended, if it has
+    #[must_use]
+    pub fn handoff(&self) -> Option<usize> {
+        self.handoff
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn variance(&self) -> f64 {
+        let n = self.norms.len() as f64;
+        let mean = self.norms.iter().sum::<f64>() / n;
+        self.norms.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n
+    }
+}
+
+impl<S: LrScheduler> LrScheduler for AdaptiveWarmup<S> {
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        match self.handoff {
+            Some(handoff) if step >= handoff => self.inner.get_lr(step - handoff, base_lr),
+            _ => self.warmup_factor * base_lr,
+        }
+    }
+}
+
+/// Cosine annealing with warm restarts (SGDR)
+///
+/// Described in [SGDR: Stochastic Gradient Descent with Warm Restarts](https://arxiv.org/abs/1608.03983)
+///
+/// The learning rate follows
+/// $$ \\eta_t = \\eta_{min} + \\frac{1}{2}(\\eta_{base} - \\eta_{min})\\left(1 + \\cos\\left(\\frac{T_{cur}}{T_{i}}\\pi\\right)\\right)$$
+///
+/// where the first cycle has length `t_0` steps and each subsequent cycle is `t_mult` times longer than the last.
+/// A `t_0` of zero has no cycles to anneal over, so the learning rate stays at the base learning rate: use
+/// [`CosineAnnealingWarmRestarts::new`] to reject it instead.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct CosineAnnealingWarmRestarts {
+    /// number of steps in the first cycle
+    pub t_0: usize,
+    /// factor by which the cycle length grows after each restart
+    pub t_mult: usize,
+    /// minimum learning rate
+    pub eta_min: f64,
+}
+
+impl CosineAnnealingWarmRestarts {
+    /// create a new schedule with a first cycle of `t_0` steps, each later cycle `t_mult` times longer than the last
+    ///
+    /// # Errors
+    ///
+    /// Errors if `t_0` is zero
+    pub fn new(t_0: usize, t_mult: usize, eta_min: f64) -> Result<Self> {
+        if t_0 == 0 {
+            candle_core::bail!("the first cycle of warm restarts must have at least one step");
+        }
+        Ok(Self {
+            t_0,
+            t_mult,
+            eta_min,
+        })
+    }
+
+    /// fraction of the way through the current cycle at `step`, in $[0, 1)$, or 0 if `t_0` is zero
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn cycle_fraction(&self, step: usize) -> f64 {
+        if self.t_0 == 0 {
+            return 0.;
+        }
+        let (t_cur, t_i) = if self.t_mult <= 1 {
+            (step % self.t_0, self.t_0)
+        } else {
+            let mut t_cur = step;
+            let mut t_i = self.t_0;
+            while t_cur >= t_i {
+                t_cur -= t_i;
+                t_i = t_i.saturating_mul(self.t_mult);
+            }
+            (t_cur, t_i)
+        };
+        t_cur as f64 / t_i as f64
+    }
+
+    /// cosine factor for `step`: 1 at the start of each cycle, falling to 0 at the end
+    fn cosine_factor(&self, step: usize) -> f64 {
+        0.5 * (1. + (std::f64::consts::PI * self.cycle_fraction(step)).cos())
+    }
+}
+
+impl LrScheduler for CosineAnnealingWarmRestarts {
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        (base_lr - self.eta_min).mul_add(self.cosine_factor(step), self.eta_min)
+    }
+}
+
+/// Cyclical momentum synchronised with a [`CosineAnnealingWarmRestarts`] learning rate schedule
+///
+/// Momentum mirrors the learning rate: it is at `base_momentum` at the start of each cycle when the learning rate is highest,
+/// and rises towards `max_momentum` as the learning rate is annealed, resetting at each restart.
+///
+/// The result can be applied to the optimiser via `set_params` (e.g. for SGD momentum or the betas of Adam)
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct CyclicalMomentum {
+    cycle: CosineAnnealingWarmRestarts,
+    base_momentum: f64,
+    max_momentum: f64,
+}
+
+impl CyclicalMomentum {
+    /// create a momentum schedule sharing the cycle timing of the learning rate schedule
+    #[must_use]
+    pub fn new(
+        lr_schedule: &CosineAnnealingWarmRestarts,
+        base_momentum: f64,
+        max_momentum: f64,
+    ) -> Self {
+        Self {
+            cycle: *lr_schedule,
+            base_momentum,
+            max_momentum,
+        }
+    }
+
+    /// get the momentum to use at `step`
+    #[must_use]
+    pub fn get_momentum(&self, step: usize) -> f64 {
+        (self.base_momentum - self.max_momentum)
+            .mul_add(self.cycle.cosine_factor(step), self.max_momentum)
+    }
+}
+
+/// Constant learning rate of `factor * base_lr`
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ConstantLr {
+    /// factor the base learning rate is multiplied by
+    pub factor: f64,
+}
+
+impl LrScheduler for ConstantLr {
+    fn get_lr(&self, _step: usize, base_lr: f64) -> f64 {
+        self.factor * base_lr
+    }
+}
+
+/// Linear warmup from `warmup_init_lr` to the base learning rate over `warmup_steps`, after which the base learning rate is used
+///
+/// [`LinearWarmup::new`] warms up from 0
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct LinearWarmup {
+    /// number of steps over which to warm up
+    pub warmup_steps: usize,
+    /// learning rate at the first step of warmup
+    pub warmup_init_lr: f64,
+}
+
+impl LinearWarmup {
+    /// create a new linear warmup from 0 over `warmup_steps`
+    #[must_use]
+    pub const fn new(warmup_steps: usize) -> Self {
+        Self {
+            warmup_steps,
+            warmup_init_lr: 0.,
+        }
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    #[allow(clippy::cast_precision_loss)]
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        if step >= self.warmup_steps {
+            base_lr
+        } else {
+            let fraction = step as f64 / self.warmup_steps as f64;
+            (base_lr - self.warmup_init_lr).mul_add(fraction, self.warmup_init_lr)
+        }
+    }
+}
+
+/// Cosine annealing from the base learning rate to `eta_min` over `t_max` steps, after which `eta_min` is used
+///
+/// A `t_max` of zero is already annealed, so always gives `eta_min`: use [`CosineAnnealing::new`] to reject it instead.
+///
+/// Described in [SGDR: Stochastic Gradient Descent with Warm Restarts](https://arxiv.org/abs/1608.03983) (without the restarts)
+///
+/// $$ \\eta_t = \\eta_{min} + \\frac{1}{2}(\\eta_{base} - \\eta_{min})\\left(1 + \\cos\\left(\\frac{t}{T_{max}}\\pi\\right)\\right)$$
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct CosineAnnealing {
+    /// number of steps to anneal over
+    pub t_max: usize,
+    /// minimum learning rate
+    pub eta_min: f64,
+}
+
+impl CosineAnnealing {
+    /// create a new schedule annealing to `eta_min` over `t_max` steps
+    ///
+    /// # Errors
+    ///
+    /// Errors if `t_max` is zero
+    pub fn new(t_max: usize, eta_min: f64) -> Result<Self> {
+        if t_max == 0 {
+            candle_core::bail!("cosine annealing must be over at least one step");
+        }
+        Ok(Self { t_max, eta_min })
+    }
+}
+
+impl LrScheduler for CosineAnnealing {
+    #[allow(clippy::cast_precision_loss)]
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        if self.t_max == 0 {
+            return self.eta_min;
+        }
+        let fraction = step.min(self.t_max) as f64 / self.t_max as f64;
+        let cosine_factor = 0.5 * (1. + (std::f64::consts::PI * fraction).cos());
+        (base_lr - self.eta_min).mul_add(cosine_factor, self.eta_min)
+    }
+}
+
+/// Decay the learning rate by `gamma` every `step_size` steps
+///
+/// $$ \\eta_t = \\eta_{base} \\gamma^{\\lfloor t / \\text{step\\_size} \\rfloor}$$
+///
+/// A `step_size` of zero is treated as 1, decaying every step: use [`StepDecay::new`] to reject it instead.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct StepDecay {
+    /// number of steps between each decay
+    pub step_size: usize,
+    /// multiplicative factor of the decay
+    pub gamma: f64,
+}
+
+impl StepDecay {
+    /// create a new schedule decaying by `gamma` every `step_size` steps
+    ///
+    /// # Errors
+    ///
+    /// Errors if `step_size` is zero
+    pub fn new(step_size: usize, gamma: f64) -> Result<Self> {
+        if step_size == 0 {
+            candle_core::bail!("step decay must have at least one step between decays");
+        }
+        Ok(Self { step_size, gamma })
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        base_lr
+            * self
+                .gamma
+                .powi(i32::try_from(step / self.step_size.max(1)).unwrap_or(i32::MAX))
+    }
+}
+
+/// Decay the learning rate by `gamma` every step
+///
+/// $$ \\eta_t = \\eta_{base} \\gamma^{t}$$
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ExponentialDecay {
+    /// multiplicative factor of the decay
+    pub gamma: f64,
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        base_lr * self.gamma.powi(i32::try_from(step).unwrap_or(i32::MAX))
+    }
+}
+
+/// Chain schedulers, switching from one to the next at each milestone step
+///
+/// Each scheduler is passed the number of steps since the start of its segment,
+/// so for continuity each scheduler should start where the previous one ended.
+pub struct SequentialLR {
+    schedulers: Vec<Box<dyn LrScheduler>>,
+    milestones: Vec<usize>,
+}
+
+impl SequentialLR {
+    /// create a new sequential schedule: `schedulers[i + 1]` takes over from `schedulers[i]` at step `milestones[i]`
+    ///
+    /// # Errors
+    ///
+    /// Errors if there is not exactly one more scheduler than milestones, or the milestones are not strictly increasing
+    pub fn new(schedulers: Vec<Box<dyn LrScheduler>>, milestones: Vec<usize>) -> Result<Self> {
+        if schedulers.len() != milestones.len() + 1 {
+            candle_core::bail!(
+                "expected {} schedulers for {} milestones, got {}",
+                milestones.len() + 1,
+                milestones.len(),
+                schedulers.len()
+            );
+        }
+        if milestones.windows(2).any(|w| w[0] >= w[1]) {
+            candle_core::bail!("milestones must be strictly increasing, got {milestones:?}");
+        }
+        Ok(Self {
+            schedulers,
+            milestones,
+        })
+    }
+}
+
+impl LrScheduler for SequentialLR {
+    fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+        let segment = self.milestones.partition_point(|m| *m <= step);
+        let start = if segment == 0 {
+            0
+        } else {
+            self.milestones[segment - 1]
+        };
+        self.schedulers[segment].get_lr(step - start, base_lr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    struct Halving;
+
+    impl LrScheduler for Halving {
+        fn get_lr(&self, step: usize, base_lr: f64) -> f64 {
+            base_lr * 0.5_f64.powi(i32::try_from(step).unwrap())
+        }
+    }
+
+    #[test]
+    fn adaptive_warmup_test() {
+        let mut sched = AdaptiveWarmup::new(Halving, 0.1, 3, 0.01);
+        let norms = [5.0, 1.0, 8.0, 2.0, 3.0, 3.05, 2.95, 3.0, 3.0];
+        let mut lrs = Vec::new();
+        for (step, norm) in norms.iter().enumerate() {
+            sched.observe(*norm);
+            lrs.push(sched.get_lr(step + 1, 1.0));
+        }
+        // variance first drops below the threshold once the window holds [3.0, 3.05, 2.95]
+        assert_eq!(sched.handoff(), Some(7));
+        for lr in &lrs[..6] {
+            assert_approx_eq!(*lr, 0.1);
+        }
+        assert_approx_eq!(lrs[6], 1.0);
+        assert_approx_eq!(lrs[7], 0.5);
+        assert_approx_eq!(lrs[8], 0.25);
+    }
+
+    #[test]
+    fn adaptive_warmup_noisy_test() {
+        let mut sched = AdaptiveWarmup::new(Halving, 0.5, 4, 1e-3);
+        for (step, norm) in [1.0, 2.0, 1.0, 2.0, 1.0, 2.0].iter().enumerate() {
+            sched.observe(*norm);
+            assert_approx_eq!(sched.get_lr(step + 1, 2.0), 1.0);
+        }
+        assert_eq!(sched.handoff(), None);
+    }
+
+    #[test]
+    fn warm_restarts_test() {
+        let sched = CosineAnnealingWarmRestarts {
+            t_0: 4,
+            t_mult: 2,
+            eta_min: 0.1,
+        };
+        let lrs: Vec<f64> = (0..13).map(|step| sched.get_lr(step, 1.1)).collect();
+        // first cycle of 4 steps, then restart into a cycle of 8
+        let expected = [
+            1.1, 0.953_553, 0.6, 0.246_447, 1.1, 1.061_940, 0.953_553, 0.791_342, 0.6, 0.408_658,
+            0.246_447, 0.138_060, 1.1,
+        ];
+        for (lr, e) in lrs.iter().zip(expected) {
+            assert_approx_eq!(lr, e, 1e-6);
+        }
+    }
+
+    #[test]
+    fn warm_restarts_zero_cycle_test() {
+        assert!(CosineAnnealingWarmRestarts::new(0, 2, 0.1).is_err());
+        // a zero length first cycle neither panics nor loops, and never anneals
+        for t_mult in [1, 2] {
+            let sched = CosineAnnealingWarmRestarts {
+                t_0: 0,
+                t_mult,
+                eta_min: 0.1,
+            };
+            assert_approx_eq!(sched.get_lr(7, 1.1), 1.1);
+            let momentum = CyclicalMomentum::new(&sched, 0.85, 0.95);
+            assert_approx_eq!(momentum.get_momentum(7), 0.85);
+        }
+    }
+
+    #[test]
+    fn cyclical_momentum_test() {
+        let sched = CosineAnnealingWarmRestarts {
+            t_0: 10,
+            t_mult: 1,
+            eta_min: 0.,
+        };
+        let momentum = CyclicalMomentum::new(&sched, 0.85, 0.95);
+        assert_approx_eq!(momentum.get_momentum(0), 0.85);
+        assert_approx_eq!(momentum.get_momentum(5), 0.9);
+        // momentum rises as the lr falls over the cycle
+        for step in 0..9 {
+            assert!(sched.get_lr(step + 1, 1.) < sched.get_lr(step, 1.));
+            assert!(momentum.get_momentum(step + 1) > momentum.get_momentum(step));
+        }
+        // and both reset together at the restart
+        assert_approx_eq!(sched.get_lr(10, 1.), 1.);
+        assert_approx_eq!(momentum.get_momentum(10), 0.85);
+    }
+
+    #[test]
+    fn sequential_test() -> anyhow::Result<()> {
+        let sched = SequentialLR::new(
+            vec![
+                Box::new(LinearWarmup::new(4)),
+                Box::new(CosineAnnealing {
+                    t_max: 4,
+                    eta_min: 0.1,
+                }),
+                Box::new(ConstantLr { factor: 0.1 }),
+            ],
+            vec![4, 8],
+        )?;
+        let lrs: Vec<f64> = (0..11).map(|step| sched.get_lr(step, 1.)).collect();
+        let expected = [
+            0., 0.25, 0.5, 0.75, // warmup
+            1., 0.868_198, 0.55, 0.231_802, // cosine, starting at the base lr
+            0.1, 0.1, 0.1, // constant, continuing from the end of the cosine
+        ];
+        for (lr, e) in lrs.iter().zip(expected) {
+            assert_approx_eq!(lr, e, 1e-6);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn warmup_init_lr_test() {
+        let sched = LinearWarmup {
+            warmup_steps: 4,
+            warmup_init_lr: 0.2,
+        };
+        let lrs: Vec<f64> = (0..6).map(|step| sched.get_lr(step, 1.)).collect();
+        for (lr, e) in lrs.iter().zip([0.2, 0.4, 0.6, 0.8, 1., 1.]) {
+            assert_approx_eq!(lr, e);
+        }
+        // the default starts from 0
+        assert_approx_eq!(LinearWarmup::new(4).get_lr(0, 1.), 0.);
+    }
+
+    #[test]
+    fn sequential_invalid_test() {
+        let make = |n: usize| -> Vec<Box<dyn LrScheduler>> {
+            (0..n)
+                .map(|_| Box::new(ConstantLr { factor: 1. }) as Box<dyn LrScheduler>)
+                .collect()
+        };
+        assert!(SequentialLR::new(make(2), vec![4, 8]).is_err());
+        assert!(SequentialLR::new(make(4), vec![4, 8]).is_err());
+        assert!(SequentialLR::new(make(3), vec![8, 4]).is_err());
+        assert!(SequentialLR::new(make(1), vec![]).is_ok());
+    }
+
+    #[test]
+    fn decay_test() {
+        let step = StepDecay {
+            step_size: 3,
+            gamma: 0.5,
+        };
+        let lrs: Vec<f64> = (0..7).map(|t| step.get_lr(t, 2.)).collect();
+        assert_eq!(lrs, [2., 2., 2., 1., 1., 1., 0.5]);
+        let exp = ExponentialDecay { gamma: 0.5 };
+        let lrs: Vec<f64> = (0..4).map(|t| exp.get_lr(t, 2.)).collect();
+        assert_eq!(lrs, [2., 1., 0.5, 0.25]);
+    }
+
+    #[test]
+    fn zero_length_decay_test() {
+        assert!(StepDecay::new(0, 0.5).is_err());
+        assert!(CosineAnnealing::new(0, 0.1).is_err());
+        let step = StepDecay {
+            step_size: 0,
+            gamma: 0.5,
+        };
+        assert_eq!(step.get_lr(2, 2.), 0.5);
+        let cosine = CosineAnnealing {
+            t_max: 0,
+            eta_min: 0.1,
+        };
+        for t in 0..3 {
+            assert_eq!(cosine.get_lr(t, 2.), 0.1);
+        }
+    }
+
+    #[test]
+    fn cosine_trajectory_test() -> anyhow::Result<()> {
+        use crate::adamax::{Adamax, ParamsAdaMax};
+        use candle_core::{Device, Var};
+
+        let base_lr = 0.01;
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut optim = Adamax::new(
+            vec![w],
+            ParamsAdaMax {
+                lr: base_lr,
+                ..Default::default()
+            },
+        )?;
+        let sched = CosineAnnealing {
+            t_max: 8,
+            eta_min: 0.,
+        };
+        let mut lrs = Vec::new();
+        for step in 0..10 {
+            apply(&mut optim, &sched, base_lr, step);
+            lrs.push(optim.learning_rate());
+        }
+        let expected = [
+            0.01,
+            0.009_619_398,
+            0.008_535_534,
+            0.006_913_417,
+            0.005,
+            0.003_086_583,
+            0.001_464_466,
+            0.000_380_602,
+            0.,
+            // held at eta_min after t_max
+            0.,
+        ];
+        for (lr, e) in lrs.iter().zip(expected) {
+            assert_approx_eq!(lr, e, 1e-9);
+        }
+        Ok(())
+    }
+}
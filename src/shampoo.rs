@@ -0,0 +1,430 @@
+/*!
+Shampoo optimiser
+
+Described in [Shampoo: Preconditioned Stochastic Tensor Optimization](https://arxiv.org/abs/1802.09568)
+
+For a variable of order $k$ with dimensions $n_1, \\ldots, n_k$, Shampoo keeps a statistics matrix $H^{(i)}$ of size
+$n_i \\times n_i$ for each dimension, accumulated from the gradient $G_t$ matricised along that dimension:
+
+$$ H^{(i)}_t \\gets H^{(i)}_{t-1} + G_t^{(i)} {G_t^{(i)}}^{\\top}, \\qquad H^{(i)}_0 = \\epsilon I $$
+
+and preconditions the gradient by multiplying it along each dimension by the inverse $2k$-th root of the statistics
+
+$$ \\theta_t \\gets \\theta_{t-1} - \\gamma \\, G_t \\times_1 \\big(H^{(1)}_t\\big)^{-1/2k} \\times_2 \\cdots \\times_k \\big(H^{(k)}_t\\big)^{-1/2k} $$
+
+For a matrix this is $\\big(L_t\\big)^{-1/4} G_t \\big(R_t\\big)^{-1/4}$ with $L_t$ and $R_t$ the left and right statistics.
+
+The inverse roots are computed by a coupled Newton iteration, which only needs matrix products. As this is the expensive
+part of the step, the roots are only recomputed every `precondition_frequency` steps, with the statistics still
+accumulated on every step. Dimensions larger than `max_preconditioner_dim` are not preconditioned, as the statistics
+for them would be too large, and the exponent is $2k$ with $k$ the number of dimensions that are. Scalars are
+preconditioned as vectors of a single element.
+
+With [grafting](Grafting), the preconditioned gradient only gives the direction of the step for each variable, and its
+size is taken from the step of SGD or Adam, as described in
+[Disentangling Adaptive Gradient Methods from Learning Rates](https://arxiv.org/abs/2002.11803). This makes the learning
+rate transferable from the grafted optimiser.
+*/
+
+use candle_core::{backprop::GradStore, DType, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{bias_correction, trust_ratio, OptimParams, OptimVars};
+
+/// maximum number of coupled Newton iterations when computing an inverse root
+const ROOT_MAX_ITER: usize = 100;
+
+/// tolerance on the largest element of $M - I$ for convergence of the coupled Newton iteration
+const ROOT_TOL: f64 = 1e-6;
+
+/// Shampoo optimiser
+///
+/// Described in [Shampoo: Preconditioned Stochastic Tensor Optimization](https://arxiv.org/abs/1802.09568)
+#[derive(Debug)]
+pub struct Shampoo {
+    vars: Vec<VarShampoo>,
+    params: ParamsShampoo,
+    t: usize,
+}
+
+#[derive(Debug)]
+struct VarShampoo {
+    theta: Var,
+    /// the preconditioner for each dimension, `None` for dimensions that are too large to precondition
+    preconditioners: Vec<Option<Preconditioner>>,
+    /// first and second moments for Adam grafting, allocated on the first grafted step
+    graft: Option<(Var, Var)>,
+}
+
+#[derive(Debug)]
+struct Preconditioner {
+    /// accumulated statistics, in f64
+    stats: Var,
+    /// inverse root of the statistics as of the last refresh, in f64
+    root: Var,
+}
+
+/// Optimiser whose step size is grafted onto the Shampoo direction
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Grafting {
+    /// use the Shampoo step as is
+    None,
+    /// take the size of the step from SGD
+    Sgd,
+    /// take the size of the step from Adam
+    Adam {
+        /// coefficient for the moving average of the first moment
+        beta_1: f64,
+        /// coefficient for the moving average of the second moment
+        beta_2: f64,
+        /// term added to the denominator to improve numerical stability
+        eps: f64,
+    },
+}
+
+/// Parameters for the Shampoo optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsShampoo {
+    /// Learning rate
+    pub lr: f64,
+    /// Initial value of the diagonal of the statistics, ensuring they are invertible
+    pub eps: f64,
+    /// Number of steps between recomputing the inverse roots: must be at least 1
+    pub precondition_frequency: usize,
+    /// Dimensions larger than this are not preconditioned
+    pub max_preconditioner_dim: usize,
+    /// Optimiser to take the size of the step from
+    pub grafting: Grafting,
+}
+
+impl Default for ParamsShampoo {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            eps: 1e-4,
+            precondition_frequency: 1,
+            max_preconditioner_dim: 1024,
+            grafting: Grafting::None,
+        }
+    }
+}
+
+impl Optimizer for Shampoo {
+    type Config = ParamsShampoo;
+
+    fn new(vars: Vec<Var>, params: ParamsShampoo) -> Result<Self> {
+        if params.precondition_frequency == 0 {
+            candle_core::bail!("precondition_frequency must be at least 1");
+        }
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let preconditioners = preconditioned_shape(&var)
+                    .iter()
+                    .map(|&dim| {
+                        if dim > params.max_preconditioner_dim {
+                            return Ok(None);
+                        }
+                        let eye = Tensor::eye(dim, DType::F64, var.device())?;
+                        Ok(Some(Preconditioner {
+                            stats: Var::from_tensor(&(&eye * params.eps)?)?,
+                            root: Var::from_tensor(&eye)?,
+                        }))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(VarShampoo {
+                    theta: var,
+                    preconditioners,
+                    graft: None,
+                })
+            })
+            .collect::<Result<Vec<VarShampoo>>>()?;
+        Ok(Self { vars, params, t: 1 })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        let Some(since_refresh) = (self.t - 1).checked_rem(self.params.precondition_frequency)
+        else {
+            candle_core::bail!("precondition_frequency must be at least 1");
+        };
+        let refresh = since_refresh == 0;
+        for var in &mut self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let grad = grad
+                    .to_dtype(DType::F64)?
+                    .reshape(preconditioned_shape(theta))?;
+                let exponent = 2 * var.preconditioners.iter().flatten().count();
+                let mut direction = grad.clone();
+                for (dim, preconditioner) in var.preconditioners.iter().enumerate() {
+                    if let Some(preconditioner) = preconditioner {
+                        let matricised = grad.transpose(0, dim)?.contiguous()?;
+                        let matricised = matricised.reshape((matricised.dim(0)?, ()))?;
+                        let stats = (preconditioner.stats.as_tensor()
+                            + matricised.matmul(&matricised.t()?)?)?;
+                        preconditioner.stats.set(&stats)?;
+                        if refresh {
+                            preconditioner
+                                .root
+                                .set(&inverse_pth_root(&stats, exponent)?)?;
+                        }
+                        direction = mode_product(&direction, preconditioner.root.as_tensor(), dim)?;
+                    }
+                }
+                let graft = match self.params.grafting {
+                    Grafting::None => None,
+                    Grafting::Sgd => Some(grad),
+                    Grafting::Adam {
+                        beta_1,
+                        beta_2,
+                        eps,
+                    } => {
+                        if var.graft.is_none() {
+                            var.graft = Some((
+                                Var::from_tensor(&grad.zeros_like()?)?,
+                                Var::from_tensor(&grad.zeros_like()?)?,
+                            ));
+                        }
+                        let (m, v) = var.graft.as_ref().expect("allocated above");
+                        let m_next = ((beta_1 * m.as_tensor())? + ((1. - beta_1) * &grad)?)?;
+                        let v_next =
+                            ((beta_2 * v.as_tensor())? + ((1. - beta_2) * grad.sqr()?)?)?;
+                        let m_hat = (&m_next / bias_correction(beta_1, self.t as f64))?;
+                        let v_hat = (&v_next / bias_correction(beta_2, self.t as f64))?;
+                        m.set(&m_next)?;
+                        v.set(&v_next)?;
+                        Some(m_hat.div(&(v_hat.sqrt()? + eps)?)?)
+                    }
+                };
+                if let Some(graft) = graft {
+                    // the ratio of the norms of the grafted step and the direction
+                    direction = (&direction * trust_ratio(&graft, &direction)?)?;
+                }
+                let update = (direction * self.params.lr)?
+                    .to_dtype(theta.dtype())?
+                    .reshape(theta.shape())?;
+                theta.set(&theta.sub(&update)?)?;
+            }
+        }
+        self.t += 1;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Shampoo {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl OptimVars for Shampoo {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Shampoo {
+    /// the number of dimensions preconditioned for each variable, in the same order as the vars
+    #[must_use]
+    pub fn preconditioned_dims(&self) -> Vec<usize> {
+        self.vars
+            .iter()
+            .map(|v| v.preconditioners.iter().flatten().count())
+            .collect()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+/// the shape the gradient of a variable is preconditioned in, treating scalars as a single element vector
+fn preconditioned_shape(var: &Var) -> Vec<usize> {
+    if var.rank() == 0 {
+        vec![1]
+    } else {
+        var.dims().to_vec()
+    }
+}
+
+/// multiply `tensor` along dimension `dim` by the symmetric matrix `mat`
+fn mode_product(tensor: &Tensor, mat: &Tensor, dim: usize) -> Result<Tensor> {
+    let moved = tensor.transpose(0, dim)?.contiguous()?;
+    let shape = moved.dims().to_vec();
+    mat.matmul(&moved.reshape((shape[0], ()))?)?
+        .reshape(shape)?
+        .transpose(0, dim)
+}
+
+/// inverse `p`-th root of a symmetric positive definite matrix by the coupled Newton iteration
+///
+/// $M$ and $H$ start from $zA$ and $z^{1/p} I$, keeping $H^p A = M$ as $M$ is driven towards $I$, so that $H$ tends to $A^{-1/p}$
+#[allow(clippy::cast_precision_loss, clippy::many_single_char_names)]
+fn inverse_pth_root(a: &Tensor, p: usize) -> Result<Tensor> {
+    let max_abs =
+        |xs: &Tensor| -> Result<f64> { xs.abs()?.flatten_all()?.max(0)?.to_scalar::<f64>() };
+    let identity = Tensor::eye(a.dim(0)?, DType::F64, a.device())?;
+    let p_f = p as f64;
+    let alpha = -1. / p_f;
+    // the Frobenius norm bounds the largest eigenvalue, so the eigenvalues of M start in (0, (1 + p) / 2]
+    let norm = a.sqr()?.sum_all()?.sqrt()?.to_scalar::<f64>()?;
+    let z = (1. + p_f) / (2. * norm);
+    let mut m = (a * z)?;
+    let mut h = (&identity * z.powf(1. / p_f))?;
+    let mut error = max_abs(&(&m - &identity)?)?;
+    for _ in 0..ROOT_MAX_ITER {
+        if error < ROOT_TOL {
+            break;
+        }
+        let m_i = ((&identity * (1. - alpha))? + (&m * alpha)?)?;
+        let mut m_next = m.clone();
+        for _ in 0..p {
+            m_next = m_i.matmul(&m_next)?;
+        }
+        let error_next = max_abs(&(&m_next - &identity)?)?;
+        // stop once rounding error makes the iteration diverge
+        if error_next > 1.2 * error {
+            break;
+        }
+        h = h.matmul(&m_i)?;
+        m = m_next;
+        error = error_next;
+    }
+    Ok(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Tensor, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsShampoo {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Shampoo::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        assert_eq!(optim.preconditioned_dims(), [2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let params = ParamsShampoo {
+            precondition_frequency: 0,
+            ..Default::default()
+        };
+        assert!(Shampoo::new(vec![w.clone()], params).is_err());
+        let params = ParamsShampoo {
+            max_preconditioner_dim: 1,
+            ..Default::default()
+        };
+        let optim = Shampoo::new(vec![w], params.clone())?;
+        assert_eq!(optim.preconditioned_dims(), [1]);
+        assert_eq!(optim.params(), &params);
+        Ok(())
+    }
+
+    #[test]
+    fn inverse_root_test() -> Result<()> {
+        let a = Tensor::new(&[[4f64, 1., 0.], [1., 3., 1.], [0., 1., 2.]], &Device::Cpu)?;
+        for p in [2, 4] {
+            let root = inverse_pth_root(&a, p)?;
+            let mut product = a.clone();
+            for _ in 0..p {
+                product = root.matmul(&product)?;
+            }
+            let expected = Tensor::eye(3, DType::F64, &Device::Cpu)?;
+            for (row, expected) in product
+                .to_vec2::<f64>()?
+                .iter()
+                .zip(expected.to_vec2::<f64>()?)
+            {
+                for (x, e) in row.iter().zip(expected) {
+                    assert_approx_eq!(x, e, 1e-6);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn first_step_test() -> Result<()> {
+        // for a vector the statistics are eps I + g g^T, of which g is an eigenvector with eigenvalue eps + |g|^2,
+        // so the first step is lr g / sqrt(eps + |g|^2)
+        let params = ParamsShampoo {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let w = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let mut optim = Shampoo::new(vec![w.clone()], params.clone())?;
+        let coeffs = Tensor::new(&[3f64, 4.], &Device::Cpu)?;
+        let loss = w.as_tensor().mul(&coeffs)?.sum_all()?;
+        optim.backward_step(&loss)?;
+        let scale = 0.1 / (1e-4f64 + 25.).sqrt();
+        let stepped = w.to_vec1::<f64>()?;
+        assert_approx_eq!(stepped[0], -3. * scale, 1e-6);
+        assert_approx_eq!(stepped[1], -4. * scale, 1e-6);
+
+        // grafted onto SGD the step has the size of the gradient step, in the same direction
+        let w = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let params = ParamsShampoo {
+            grafting: Grafting::Sgd,
+            ..params
+        };
+        let mut optim = Shampoo::new(vec![w.clone()], params)?;
+        let loss = w.as_tensor().mul(&coeffs)?.sum_all()?;
+        optim.backward_step(&loss)?;
+        let stepped = w.to_vec1::<f64>()?;
+        assert_approx_eq!(stepped[0], -0.3, 1e-6);
+        assert_approx_eq!(stepped[1], -0.4, 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn unpreconditioned_test() -> Result<()> {
+        // with no dimension preconditioned the step is the gradient step
+        let params = ParamsShampoo {
+            lr: 0.1,
+            max_preconditioner_dim: 0,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Shampoo::new(vec![w.clone()], params)?;
+        assert_eq!(optim.preconditioned_dims(), [0]);
+        let coeffs = Tensor::new(&[[3f32, 4.]], &Device::Cpu)?;
+        let loss = w.as_tensor().mul(&coeffs)?.sum_all()?;
+        optim.backward_step(&loss)?;
+        let stepped = w.to_vec2::<f32>()?;
+        assert_approx_eq!(stepped[0][0], -0.3);
+        assert_approx_eq!(stepped[0][1], -0.4);
+        Ok(())
+    }
+}
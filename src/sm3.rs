@@ -0,0 +1,318 @@
+/*!
+SM3 optimiser
+
+Described in [Memory-Efficient Adaptive Optimization](https://arxiv.org/abs/1901.11150)
+
+SM3 is an adaptive method like AdaGrad, but rather than accumulating the squared gradient of every element it keeps
+one accumulator for each index of each dimension of a variable: for an $n \\times m$ embedding matrix this is a
+vector of $n$ row accumulators and one of $m$ column accumulators, $n + m$ values rather than $nm$. The accumulated
+squared gradient of an element is bounded by the smallest of the accumulators covering it, and each accumulator then
+keeps the largest of the elements it covers. Variables of rank zero or one keep a full accumulator, for which SM3
+reduces to AdaGrad.
+
+Pseudocode (SM3-II, for a variable with one accumulator $\\mu^{(i)}$ per dimension $i$):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\theta_0 \\text{ (params)}, \\: f(\\theta) \\text{ (objective)},
+        \\: \\epsilon \\text{ (epsilon)}, \\: \\beta_1 \\text{ (momentum)}                   \\\\
+    &\\textbf{initialize} :  \\mu^{(i)}_0 \\leftarrow 0, \\: m_0 \\leftarrow 0               \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\nu_t(j) \\leftarrow \\min_i \\mu^{(i)}_{t-1}(j_i) + g_t(j)^2             \\\\
+    &\\hspace{5mm}\\mu^{(i)}_t(k) \\leftarrow \\max_{j : j_i = k} \\nu_t(j)                    \\\\
+    &\\hspace{5mm}U_t \\leftarrow g_t / \\big(\\sqrt{\\nu_t} + \\epsilon \\big)                 \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\beta_1 \\textbf{ is } \\text{Some}                        \\\\
+    &\\hspace{10mm}m_t \\leftarrow \\beta_1 m_{t-1} + (1 - \\beta_1) U_t, \\: U_t \\leftarrow m_t \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma U_t                            \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+The momentum is optional, and off by default to save memory.
+*/
+
+use candle_core::{Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+use log::warn;
+
+use crate::{OptimParams, OptimVars};
+
+/// SM3 optimiser
+///
+/// Described in [Memory-Efficient Adaptive Optimization](https://arxiv.org/abs/1901.11150)
+#[derive(Debug)]
+pub struct Sm3 {
+    vars: Vec<VarSm3>,
+    params: ParamsSm3,
+}
+
+#[derive(Debug)]
+struct VarSm3 {
+    theta: Var,
+    acc: Accumulators,
+    m: Option<Var>,
+}
+
+/// Accumulated squared gradients of a variable
+#[derive(Debug)]
+enum Accumulators {
+    /// one accumulator for each dimension, indexed along it, for variables of rank two or more
+    Cover(Vec<Var>),
+    /// the full accumulator, for variables of rank zero or one
+    Full(Var),
+}
+
+impl Accumulators {
+    fn new(theta: &Var) -> Result<Self> {
+        let dims = theta.dims();
+        if dims.len() >= 2 {
+            let cover = dims
+                .iter()
+                .map(|&dim| Var::zeros(dim, theta.dtype(), theta.device()))
+                .collect::<Result<Vec<Var>>>()?;
+            Ok(Self::Cover(cover))
+        } else {
+            Ok(Self::Full(Var::zeros(dims, theta.dtype(), theta.device())?))
+        }
+    }
+
+    /// update the accumulators with the squared gradient, returning the accumulated squared gradient of each element
+    fn update(&self, grad_sq: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Cover(cover) => {
+                let rank = cover.len();
+                // the smallest accumulator covering each element, broadcast to the shape of the variable
+                let mut bound: Option<Tensor> = None;
+                for (i, acc) in cover.iter().enumerate() {
+                    let mut shape = vec![1; rank];
+                    shape[i] = acc.dims1()?;
+                    let acc = acc.reshape(shape)?;
+                    bound = Some(match bound {
+                        Some(bound) => bound.broadcast_minimum(&acc)?,
+                        None => acc,
+                    });
+                }
+                let nu = match bound {
+                    Some(bound) => grad_sq.broadcast_add(&bound)?,
+                    None => grad_sq.clone(),
+                };
+                for (i, acc) in cover.iter().enumerate() {
+                    // the largest element covered by each index of dimension i
+                    let mut max = nu.clone();
+                    for dim in (0..rank).rev().filter(|&dim| dim != i) {
+                        max = max.max(dim)?;
+                    }
+                    acc.set(&max)?;
+                }
+                Ok(nu)
+            }
+            Self::Full(acc) => {
+                let nu = (acc.as_tensor() + grad_sq)?;
+                acc.set(&nu)?;
+                Ok(nu)
+            }
+        }
+    }
+}
+
+/// Parameters for the SM3 optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsSm3 {
+    /// Learning rate
+    pub lr: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Coefficient for the moving average of the update, if momentum is used
+    pub beta_1: Option<f64>,
+}
+
+impl Default for ParamsSm3 {
+    fn default() -> Self {
+        Self {
+            lr: 0.1,
+            eps: 1e-30,
+            beta_1: None,
+        }
+    }
+}
+
+impl Optimizer for Sm3 {
+    type Config = ParamsSm3;
+
+    fn new(vars: Vec<Var>, params: ParamsSm3) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let acc = Accumulators::new(&var)?;
+                let m = if params.beta_1.is_some() {
+                    Some(Var::zeros(var.shape(), var.dtype(), var.device())?)
+                } else {
+                    None
+                };
+                Ok(VarSm3 { theta: var, acc, m })
+            })
+            .collect::<Result<Vec<VarSm3>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let nu = var.acc.update(&grad.sqr()?)?;
+                let mut update = grad.div(&(nu.sqrt()? + self.params.eps)?)?;
+                if let (Some(beta_1), Some(m)) = (self.params.beta_1, &var.m) {
+                    let m_next = ((m.as_tensor() * beta_1)? + (update * (1. - beta_1))?)?;
+                    m.set(&m_next)?;
+                    update = m_next;
+                }
+                theta.set(&theta.sub(&(update * self.params.lr)?)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Sm3 {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    /// Set the parameters for the optimiser
+    ///
+    /// # Warning
+    ///
+    /// As using momentum requires having tracked an additional tensor,
+    /// whether `beta_1` is set cannot be changed once set initially on creation of the optimiser.
+    fn set_params(&mut self, config: Self::Config) {
+        if config.beta_1.is_some() == self.params.beta_1.is_some() {
+            self.params = config;
+        } else {
+            warn!("whether momentum is used cannot be changed once set");
+            let beta_1 = self.params.beta_1;
+            self.params = config;
+            self.params.beta_1 = beta_1;
+        }
+    }
+}
+
+impl OptimVars for Sm3 {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Sm3 {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{DType, Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsSm3 {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Sm3::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsSm3::default();
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = Sm3::new(vec![w], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsSm3 {
+            lr: 0.002,
+            eps: 1e-8,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        // momentum cannot be turned on after creation
+        optim.set_params(ParamsSm3 {
+            beta_1: Some(0.9),
+            ..new_params.clone()
+        });
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn accumulator_layout_test() -> Result<()> {
+        let matrix = Var::zeros((3, 4), DType::F32, &Device::Cpu)?;
+        let batch = Var::zeros((2, 3, 4), DType::F32, &Device::Cpu)?;
+        let vector = Var::zeros(4, DType::F32, &Device::Cpu)?;
+        let scalar = Var::zeros((), DType::F32, &Device::Cpu)?;
+        let optim = Sm3::new(vec![matrix, batch, vector, scalar], ParamsSm3::default())?;
+        let layouts: Vec<Vec<Vec<usize>>> = optim
+            .vars
+            .iter()
+            .map(|v| match &v.acc {
+                Accumulators::Cover(cover) => cover.iter().map(|acc| acc.dims().to_vec()).collect(),
+                Accumulators::Full(acc) => vec![acc.dims().to_vec()],
+            })
+            .collect();
+        assert_eq!(
+            layouts,
+            [
+                vec![vec![3], vec![4]],
+                vec![vec![2], vec![3], vec![4]],
+                vec![vec![4]],
+                vec![vec![]],
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cover_update_test() -> Result<()> {
+        let matrix = Var::zeros((2, 2), DType::F64, &Device::Cpu)?;
+        let acc = Accumulators::new(&matrix)?;
+        // on the first step the accumulators are zero, so nu is the squared gradient,
+        // and the accumulators take the row and column maxima
+        let nu = acc.update(&Tensor::new(&[[1f64, 4.], [9., 0.]], &Device::Cpu)?)?;
+        assert_eq!(nu.to_vec2::<f64>()?, [[1., 4.], [9., 0.]]);
+        let Accumulators::Cover(cover) = &acc else {
+            panic!("a matrix has an accumulator per dimension");
+        };
+        assert_eq!(cover[0].to_vec1::<f64>()?, [4., 9.]);
+        assert_eq!(cover[1].to_vec1::<f64>()?, [9., 4.]);
+        // then nu adds the smaller of the row and column accumulators
+        let nu = acc.update(&Tensor::new(&[[1f64, 1.], [1., 1.]], &Device::Cpu)?)?;
+        assert_eq!(nu.to_vec2::<f64>()?, [[5., 5.], [10., 5.]]);
+        Ok(())
+    }
+}
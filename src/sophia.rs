@@ -0,0 +1,358 @@
+/*!
+Sophia optimiser
+
+Described in [Sophia: A Scalable Stochastic Second-order Optimizer for Language Model Pre-training](https://arxiv.org/abs/2305.14342)
+
+Sophia divides a moving average of the gradient by a moving average of an estimate of the diagonal of the Hessian,
+and clips each element of the result so that no element moves by more than the learning rate. The Hessian is only
+estimated every `hessian_interval` steps, either with the [Hutchinson](crate::hessian::hutchinson) estimator
+(Sophia-H) or the [Gauss-Newton-Bartlett](crate::hessian::gauss_newton_bartlett) estimator (Sophia-G).
+
+Pseudocode (with decoupled weight decay):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\rho \\text{ (rho)}, \\: \\epsilon \\text{ (epsilon)}, \\: k \\text{ (hessian interval)}  \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+        h_0 \\leftarrow 0 \\text{ ( Hessian diagonal)}                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{if} \\: t \\equiv 1 \\pmod{k}                                      \\\\
+    &\\hspace{10mm} h_t \\leftarrow \\beta_2 h_{t-1} + (1 - \\beta_2) \\hat{h}_t \\text{ (estimate of the diagonal)}  \\\\
+    &\\hspace{5mm}\\textbf{else}                                                           \\\\
+    &\\hspace{10mm} h_t \\leftarrow h_{t-1}                                                \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\lambda \\theta_{t-1}          \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_t - \\gamma \\,
+        \\mathrm{clip}\\big(m_t / \\max(\\rho h_t, \\epsilon), 1\\big)                      \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+As estimating the Hessian needs the loss to be evaluated again, steps that estimate it are taken with
+[`Sophia::backward_step_with_hessian`], given a closure evaluating the loss. The `Optimizer` trait's `step` and
+`backward_step` only use the current estimate, which can also be updated directly with [`Sophia::update_hessian`].
+*/
+
+use candle_core::{backprop::GradStore, Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    hessian::{gauss_newton_bartlett, hutchinson},
+    warn_unused_config, zero_decay, CurrentHyperparams, Decay, HyperSnapshot, OptimParams,
+    OptimVars, UnusedConfig,
+};
+
+/// Sophia optimiser
+///
+/// Described in [Sophia: A Scalable Stochastic Second-order Optimizer for Language Model Pre-training](https://arxiv.org/abs/2305.14342)
+#[derive(Debug)]
+pub struct Sophia {
+    vars: Vec<VarSophia>,
+    params: ParamsSophia,
+    t: usize,
+}
+
+#[derive(Debug)]
+struct VarSophia {
+    theta: Var,
+    m: Var,
+    /// moving average of the estimates of the diagonal of the Hessian
+    h: Var,
+}
+
+/// Estimator of the diagonal of the Hessian
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum HessianEstimator {
+    /// Hutchinson estimator (Sophia-H), with Hessian-vector products by central differences of the gradient
+    Hutchinson {
+        /// number of random vectors to average over
+        samples: usize,
+        /// step of the central differences
+        eps: f64,
+    },
+    /// Gauss-Newton-Bartlett estimator (Sophia-G): the loss used to estimate the Hessian should be the mean over
+    /// the batch of the loss on labels sampled from the model
+    GaussNewtonBartlett {
+        /// number of inputs in the batch the loss is averaged over
+        batch_size: usize,
+    },
+}
+
+/// Parameters for the Sophia optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsSophia {
+    /// Learning rate: the largest step of any element
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of the Hessian estimates
+    pub beta_2: f64,
+    /// Scale of the Hessian in the denominator, controlling the fraction of elements whose step is clipped
+    pub rho: f64,
+    /// Lower bound of the denominator
+    pub eps: f64,
+    /// Decoupled weight decay
+    pub weight_decay: Option<f64>,
+    /// Number of steps between Hessian estimates by `backward_step_with_hessian`: must be at least 1
+    pub hessian_interval: usize,
+    /// Estimator of the diagonal of the Hessian
+    pub estimator: HessianEstimator,
+}
+
+impl Default for ParamsSophia {
+    fn default() -> Self {
+        Self {
+            lr: 1e-4,
+            beta_1: 0.965,
+            beta_2: 0.99,
+            rho: 0.04,
+            eps: 1e-15,
+            weight_decay: Some(0.1),
+            hessian_interval: 10,
+            estimator: HessianEstimator::Hutchinson {
+                samples: 1,
+                eps: 1e-3,
+            },
+        }
+    }
+}
+
+impl UnusedConfig for ParamsSophia {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay).into_iter().collect()
+    }
+}
+
+impl Optimizer for Sophia {
+    type Config = ParamsSophia;
+
+    fn new(vars: Vec<Var>, params: ParamsSophia) -> Result<Self> {
+        if params.hessian_interval == 0 {
+            candle_core::bail!("hessian_interval must be at least 1");
+        }
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let h = Var::zeros(shape, dtype, device)?;
+                Ok(VarSophia { theta: var, m, h })
+            })
+            .collect::<Result<Vec<VarSophia>>>()?;
+        Ok(Self { vars, params, t: 1 })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        let lr = self.params.lr;
+        let beta_1 = self.params.beta_1;
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                if let Some(decay) = self.params.weight_decay {
+                    theta.set(&(theta.as_tensor() * lr.mul_add(-decay, 1.))?)?;
+                }
+                let m_next = ((beta_1 * var.m.as_tensor())? + ((1. - beta_1) * grad)?)?;
+                let denom = (var.h.as_tensor() * self.params.rho)?.maximum(self.params.eps)?;
+                let ratio = m_next.div(&denom)?.clamp(-1., 1.)?;
+                theta.set(&theta.sub(&(ratio * lr)?)?)?;
+                var.m.set(&m_next)?;
+            }
+        }
+        self.t += 1;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Sophia {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Sophia {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay.map(Decay::DecoupledWeightDecay),
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Sophia {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Sophia {
+    /// whether the next step should estimate the Hessian
+    ///
+    /// this is true on the first step and every `hessian_interval` steps after it
+    #[must_use]
+    pub fn hessian_due(&self) -> bool {
+        (self.t - 1).checked_rem(self.params.hessian_interval) == Some(0)
+    }
+
+    /// update the moving average of the diagonal of the Hessian with an estimate, holding a tensor for each of the vars
+    ///
+    /// # Errors
+    ///
+    /// Errors if the estimate does not match the vars
+    pub fn update_hessian(&mut self, estimate: &[Tensor]) -> Result<()> {
+        if estimate.len() != self.vars.len() {
+            candle_core::bail!(
+                "{} Hessian estimates given for {} variables",
+                estimate.len(),
+                self.vars.len()
+            );
+        }
+        let beta_2 = self.params.beta_2;
+        for (var, estimate) in self.vars.iter().zip(estimate) {
+            let estimate = estimate.to_dtype(var.h.dtype())?;
+            var.h
+                .set(&((beta_2 * var.h.as_tensor())? + ((1. - beta_2) * estimate)?)?)?;
+        }
+        Ok(())
+    }
+
+    /// take a step from the gradient of `loss`, first estimating the Hessian from `hessian_loss` if it is due
+    ///
+    /// `hessian_loss` should evaluate the loss at the current value of the variables: for the Gauss-Newton-Bartlett
+    /// estimator this is the loss on labels sampled from the model rather than the true labels
+    ///
+    /// # Errors
+    ///
+    /// Errors if the Hessian cannot be estimated or the step fails
+    pub fn backward_step_with_hessian<F: FnMut() -> Result<Tensor>>(
+        &mut self,
+        loss: &Tensor,
+        hessian_loss: F,
+    ) -> Result<()> {
+        if self.hessian_due() {
+            let vars = self.vars();
+            let estimate = match self.params.estimator {
+                HessianEstimator::Hutchinson { samples, eps } => {
+                    hutchinson(&vars, hessian_loss, samples, eps)?
+                }
+                HessianEstimator::GaussNewtonBartlett { batch_size } => {
+                    gauss_newton_bartlett(&vars, hessian_loss, batch_size)?
+                }
+            };
+            self.update_hessian(&estimate)?;
+        }
+        self.backward_step(loss)
+    }
+
+    /// the moving average of the diagonal of the Hessian, in the same order as the vars
+    #[must_use]
+    pub fn hessian_diagonal(&self) -> Vec<&Tensor> {
+        self.vars.iter().map(|v| v.h.as_tensor()).collect()
+    }
+
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsSophia {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Sophia::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn hessian_interval_test() -> Result<()> {
+        let params = ParamsSophia {
+            hessian_interval: 3,
+            ..Default::default()
+        };
+        let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+        let mut optim = Sophia::new(vec![w.clone()], params)?;
+        let mut due = Vec::new();
+        for _ in 0..7 {
+            due.push(optim.hessian_due());
+            optim.backward_step_with_hessian(&w.as_tensor().sqr()?.sum_all()?, || {
+                w.as_tensor().sqr()?.sum_all()
+            })?;
+        }
+        assert_eq!(due, [true, false, false, true, false, false, true]);
+        let never = ParamsSophia {
+            hessian_interval: 0,
+            ..Default::default()
+        };
+        assert!(Sophia::new(vec![w.clone()], never).is_err());
+        // three estimates of the Hessian diagonal of 2, so the average is 2 (1 - 0.99^3)
+        let expected = 2. * (1. - 0.99f32.powi(3));
+        for h in optim.hessian_diagonal()[0].to_vec1::<f32>()? {
+            assert_approx_eq!(h, expected, 1e-4);
+        }
+        assert!(optim.update_hessian(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn clip_test() -> Result<()> {
+        // with a Hessian estimate of 100 the first step is (1 - 0.965) g / (0.04 * 100) for g = 1,
+        // while for g = 1000 it is clipped to the learning rate
+        let params = ParamsSophia {
+            lr: 0.1,
+            weight_decay: None,
+            ..Default::default()
+        };
+        let w = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let mut optim = Sophia::new(vec![w.clone()], params)?;
+        optim.update_hessian(&[Tensor::new(&[10_000f64, 10_000.], &Device::Cpu)?])?;
+        let coeffs = Tensor::new(&[1f64, 1000.], &Device::Cpu)?;
+        optim.backward_step(&w.as_tensor().mul(&coeffs)?.sum_all()?)?;
+        let stepped = w.to_vec1::<f64>()?;
+        assert_approx_eq!(stepped[0], -0.1 * 0.035 / 4.);
+        assert_approx_eq!(stepped[1], -0.1);
+        Ok(())
+    }
+}
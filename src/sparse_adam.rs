@@ -0,0 +1,274 @@
+/*!
+SparseAdam optimiser
+
+A variant of [Adam](crate::adam) for large embedding tables, following `torch.optim.SparseAdam`: only the rows of a
+variable that received a non-zero gradient in a step have their moments and values updated. The moments of the other
+rows are left as they were, rather than decaying, and those rows are not moved by the momentum of earlier steps.
+
+Candle has no sparse gradients, so the rows are found from the dense gradient: a row (index along the first dimension)
+is updated if any of its elements has a non-zero gradient, as is the case for the rows looked up by an embedding.
+The arithmetic of the update is only done on these rows, which are gathered from the variable and its moments and
+added back with `index_add`. Variables of rank zero are treated as a single row.
+
+Pseudocode (for the rows $R_t$ with a non-zero gradient):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\beta_1, \\beta_2
+        \\text{ (betas)},\\theta_0 \\text{ (params)},f(\\theta) \\text{ (objective)},
+        \\: \\epsilon \\text{ (epsilon)}                                                  \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+        v_0\\leftarrow 0 \\text{ (second moment)}                                          \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{for} \\: r \\in R_t \\: \\textbf{do}                                \\\\
+    &\\hspace{10mm}m_{t,r}           \\leftarrow   \\beta_1 m_{t-1,r} + (1 - \\beta_1) g_{t,r}   \\\\
+    &\\hspace{10mm}v_{t,r}           \\leftarrow   \\beta_2 v_{t-1,r} + (1-\\beta_2) g^2_{t,r}   \\\\
+    &\\hspace{10mm}\\theta_{t,r} \\leftarrow \\theta_{t-1,r} - \\gamma
+        \\frac{\\sqrt{1-\\beta_2^t}}{1-\\beta_1^t} \\frac{m_{t,r}}{\\sqrt{v_{t,r}} + \\epsilon} \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+As in PyTorch, weight decay is not supported, as it would touch every row.
+*/
+
+use candle_core::{Result, Tensor, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{bias_correction, CurrentHyperparams, HyperSnapshot, OptimParams, OptimVars};
+
+/// SparseAdam optimiser
+///
+/// Adam updating only the rows of each variable with a non-zero gradient
+#[derive(Debug)]
+pub struct SparseAdam {
+    vars: Vec<VarSparseAdam>,
+    params: ParamsSparseAdam,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarSparseAdam {
+    theta: Var,
+    m: Var,
+    v: Var,
+}
+
+/// Parameters for the SparseAdam optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsSparseAdam {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for moving average of second moment
+    pub beta_2: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+}
+
+impl Default for ParamsSparseAdam {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta_1: 0.9,
+            beta_2: 0.999,
+            eps: 1e-8,
+        }
+    }
+}
+
+/// indices of the rows (along the first dimension) of `grad` with any non-zero element,
+/// and `grad` viewed as a matrix with those rows
+fn nonzero_rows(grad: &Tensor) -> Result<(Tensor, Tensor)> {
+    let rows = grad.dims().first().copied().unwrap_or(1);
+    let grad = grad.reshape((rows, ()))?;
+    let nonzero = grad.ne(0.)?.max(1)?.to_vec1::<u8>()?;
+    let ids = nonzero
+        .iter()
+        .enumerate()
+        .filter(|(_, &nonzero)| nonzero != 0)
+        .map(|(row, _)| u32::try_from(row))
+        .collect::<std::result::Result<Vec<u32>, _>>()
+        .map_err(candle_core::Error::wrap)?;
+    let ids = Tensor::new(ids, grad.device())?;
+    Ok((ids, grad))
+}
+
+/// gather the rows `ids` of `xs`, viewed as a matrix of `rows` rows
+fn gather(xs: &Tensor, ids: &Tensor, rows: usize) -> Result<Tensor> {
+    xs.reshape((rows, ()))?.index_select(ids, 0)
+}
+
+/// add `delta`, holding the rows `ids`, to `var`
+fn add_rows(var: &Var, ids: &Tensor, delta: &Tensor, rows: usize) -> Result<()> {
+    let updated = var
+        .as_tensor()
+        .reshape((rows, ()))?
+        .index_add(ids, delta, 0)?
+        .reshape(var.shape())?;
+    var.set(&updated)
+}
+
+impl Optimizer for SparseAdam {
+    type Config = ParamsSparseAdam;
+
+    fn new(vars: Vec<Var>, params: ParamsSparseAdam) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let v = Var::zeros(shape, dtype, device)?;
+                Ok(VarSparseAdam { theta: var, m, v })
+            })
+            .collect::<Result<Vec<VarSparseAdam>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let beta_1 = self.params.beta_1;
+        let beta_2 = self.params.beta_2;
+        let step_size = self.params.lr * bias_correction(beta_2, self.t).sqrt()
+            / bias_correction(beta_1, self.t);
+        for var in &self.vars {
+            let theta = &var.theta;
+            if let Some(grad) = grads.get(theta) {
+                let (ids, grad) = nonzero_rows(grad)?;
+                if ids.elem_count() == 0 {
+                    continue;
+                }
+                let rows = grad.dim(0)?;
+                let grad = grad.index_select(&ids, 0)?;
+                let m = gather(var.m.as_tensor(), &ids, rows)?;
+                let v = gather(var.v.as_tensor(), &ids, rows)?;
+                // the changes to the moments, as added to the moment buffers
+                let m_delta = ((&grad - &m)? * (1. - beta_1))?;
+                let v_delta = ((grad.sqr()? - &v)? * (1. - beta_2))?;
+                let m_next = (m + &m_delta)?;
+                let v_next = (v + &v_delta)?;
+                let delta = (m_next.div(&(v_next.sqrt()? + self.params.eps)?)? * -step_size)?;
+                add_rows(&var.m, &ids, &m_delta, rows)?;
+                add_rows(&var.v, &ids, &v_delta, rows)?;
+                add_rows(theta, &ids, &delta, rows)?;
+            }
+        }
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for SparseAdam {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for SparseAdam {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: None,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for SparseAdam {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl SparseAdam {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{DType, Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsSparseAdam {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = SparseAdam::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsSparseAdam {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let mut optim = SparseAdam::new(vec![w], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsSparseAdam {
+            lr: 0.002,
+            beta_2: 0.99,
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+
+    #[test]
+    fn nonzero_rows_test() -> Result<()> {
+        let grad = Tensor::new(&[[0f32, 0.], [0., -1.], [0., 0.], [2., 3.]], &Device::Cpu)?;
+        let (ids, matrix) = nonzero_rows(&grad)?;
+        assert_eq!(ids.to_vec1::<u32>()?, [1, 3]);
+        assert_eq!(matrix.dims(), [4, 2]);
+        // a scalar is a single row, and a vector has a row per element
+        let (ids, _) = nonzero_rows(&Tensor::new(2f32, &Device::Cpu)?)?;
+        assert_eq!(ids.to_vec1::<u32>()?, [0]);
+        let (ids, _) = nonzero_rows(&Tensor::new(&[0f32, 1., 0.], &Device::Cpu)?)?;
+        assert_eq!(ids.to_vec1::<u32>()?, [1]);
+        let (ids, _) = nonzero_rows(&Tensor::zeros((2, 3), DType::F32, &Device::Cpu)?)?;
+        assert_eq!(ids.elem_count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+/*!
+Random subset updates
+
+[`RandomSubset`] wraps any optimiser in this crate and, on each step, updates only a randomly chosen fraction of the
+variables it manages, as a form of update dropout. The remaining variables are skipped entirely, as if they had no gradient:
+they are left unchanged and their optimiser state (such as momentum buffers) is preserved.
+
+The selection uses a small seeded pseudo random number generator so runs are reproducible.
+*/
+
+use std::collections::HashSet;
+
+use candle_core::{backprop::GradStore, Result, TensorId, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{empty_grad_store, OptimVars};
+
+/// Optimiser wrapper updating a random subset of the variables each step
+#[derive(Debug)]
+pub struct RandomSubset<O: Optimizer + OptimVars> {
+    inner: O,
+    update_fraction: Option<f64>,
+    rng: SplitMix64,
+    last_updated: Vec<TensorId>,
+}
+
+impl<O: Optimizer + OptimVars> Optimizer for RandomSubset<O> {
+    type Config = O::Config;
+
+    /// create the wrapped optimiser, initially updating every variable
+    fn new(vars: Vec<Var>, config: Self::Config) -> Result<Self> {
+        Self::new(O::new(vars, config)?, None, 0)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.inner.learning_rate()
+    }
+
+    fn step(&mut self, grads: &GradStore) -> Result<()> {
+        let selected = self.select();
+        let mut masked = empty_grad_store()?;
+        for var in self.inner.vars() {
+            if !selected.contains(&var.id()) {
+                continue;
+            }
+            if let Some(grad) = grads.get(var) {
+                masked.insert(var, grad.clone());
+            }
+        }
+        self.inner.step(&masked)?;
+        // report in construction order
+        self.last_updated = self
+            .inner
+            .vars()
+            .iter()
+            .map(|v| v.id())
+            .filter(|id| selected.contains(id))
+            .collect();
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.inner.set_learning_rate(lr);
+    }
+}
+
+impl<O: Optimizer + OptimVars> OptimVars for RandomSubset<O> {
+    fn vars(&self) -> Vec<&Var> {
+        self.inner.vars()
+    }
+}
+
+impl<O: Optimizer + OptimVars> RandomSubset<O> {
+    /// wrap an optimiser, updating `update_fraction` of its variables each step, chosen using `seed`
+    ///
+    /// an `update_fraction` of `None` or `1.` updates every variable
+    ///
+    /// # Errors
+    ///
+    /// Errors if `update_fraction` is not in $(0, 1]$
+    pub fn new(inner: O, update_fraction: Option<f64>, seed: u64) -> Result<Self> {
+        if let Some(fraction) = update_fraction {
+            if !(fraction > 0. && fraction <= 1.) {
+                candle_core::bail!("update fraction must be in (0, 1], got {fraction}");
+            }
+        }
+        Ok(Self {
+            inner,
+            update_fraction,
+            rng: SplitMix64(seed),
+            last_updated: Vec::new(),
+        })
+    }
+
+    /// the ids of the variables selected for update on the last step, in construction order
+    #[must_use]
+    pub fn last_updated(&self) -> &[TensorId] {
+        &self.last_updated
+    }
+
+    /// get a reference to the wrapped optimiser
+    #[must_use]
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    /// get a mutable reference to the wrapped optimiser
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// return the wrapped optimiser
+    #[must_use]
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+
+    /// choose the variables to update this step: the fraction is rounded to a whole number of variables, and at least one is chosen
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn select(&mut self) -> HashSet<TensorId> {
+        let mut ids: Vec<TensorId> = self.inner.vars().iter().map(|v| v.id()).collect();
+        let n = ids.len();
+        let n_selected = match self.update_fraction {
+            Some(fraction) if fraction < 1. => {
+                ((n as f64 * fraction).round() as usize).max(1).min(n)
+            }
+            _ => n,
+        };
+        // partial Fisher-Yates shuffle
+        for i in 0..n_selected {
+            let j = i + self.rng.below(n - i);
+            ids.swap(i, j);
+        }
+        ids.truncate(n_selected);
+        ids.into_iter().collect()
+    }
+}
+
+/// [SplitMix64](https://prng.di.unimi.it/splitmix64.c) pseudo random number generator: small and fast, which is all
+/// that is needed to choose subsets
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// a number in `0..n`, with negligible bias for small `n`
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esgd::{ParamsSGD, SGD};
+    use anyhow::Result;
+    use candle_core::{Device, Tensor};
+
+    fn setup(n: usize) -> Result<(Vec<Var>, Tensor, SGD)> {
+        let vars = (0..n)
+            .map(|_| Var::new(&[1f32], &Device::Cpu))
+            .collect::<candle_core::Result<Vec<Var>>>()?;
+        let loss =
+            Tensor::stack(&vars.iter().map(Var::as_tensor).collect::<Vec<_>>(), 0)?.sum_all()?;
+        let params = ParamsSGD {
+            lr: 0.1,
+            ..Default::default()
+        };
+        let optim = SGD::new(vars.clone(), params)?;
+        Ok((vars, loss, optim))
+    }
+
+    fn moved(vars: &[Var]) -> Result<Vec<TensorId>> {
+        let mut ids = Vec::new();
+        for var in vars {
+            if var.to_vec1::<f32>()?[0] != 1. {
+                ids.push(var.id());
+            }
+        }
+        Ok(ids)
+    }
+
+    #[test]
+    fn half_test() -> Result<()> {
+        let (vars, loss, sgd) = setup(10)?;
+        let mut optim = RandomSubset::new(sgd, Some(0.5), 42)?;
+        optim.backward_step(&loss)?;
+        assert_eq!(optim.last_updated().len(), 5);
+        assert_eq!(moved(&vars)?, optim.last_updated());
+        // a different subset is chosen on later steps
+        let first = optim.last_updated().to_vec();
+        let mut differs = false;
+        for _ in 0..5 {
+            optim.backward_step(&loss)?;
+            assert_eq!(optim.last_updated().len(), 5);
+            differs |= optim.last_updated() != first;
+        }
+        assert!(differs);
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_test() -> Result<()> {
+        let mut chosen = Vec::new();
+        for _ in 0..2 {
+            let (vars, loss, sgd) = setup(10)?;
+            let mut optim = RandomSubset::new(sgd, Some(0.5), 7)?;
+            let mut positions = Vec::new();
+            for _ in 0..3 {
+                optim.backward_step(&loss)?;
+                // ids differ between runs, so compare positions
+                positions.push(
+                    vars.iter()
+                        .map(|v| optim.last_updated().contains(&v.id()))
+                        .collect::<Vec<bool>>(),
+                );
+            }
+            chosen.push(positions);
+        }
+        assert_eq!(chosen[0], chosen[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn all_test() -> Result<()> {
+        for fraction in [None, Some(1.)] {
+            let (vars, loss, sgd) = setup(4)?;
+            let mut optim = RandomSubset::new(sgd, fraction, 0)?;
+            optim.backward_step(&loss)?;
+            assert_eq!(moved(&vars)?.len(), 4);
+        }
+        let (_, _, sgd) = setup(4)?;
+        assert!(RandomSubset::new(sgd, Some(0.), 0).is_err());
+        Ok(())
+    }
+}
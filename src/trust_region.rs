@@ -0,0 +1,310 @@
+/*!
+Trust region Newton optimiser
+
+Each step minimises the quadratic model of the loss
+
+$$ m(p) = f + g^{\\top} p + \\frac{1}{2} p^{\\top} H p $$
+
+within a ball $||p||_{2} \\leq \\Delta$, by [Steihaug's conjugate gradient method](crate::cg::steihaug), which only
+needs Hessian-vector products and handles indefinite Hessians by stepping to the boundary along a direction of
+negative curvature. The step is then judged by the ratio of the actual to the predicted reduction
+
+$$ \\rho = \\frac{f(\\theta) - f(\\theta + p)}{m(0) - m(p)} $$
+
+and the radius adapted, as described in [Numerical Optimization](https://doi.org/10.1007/978-0-387-40065-5)
+(algorithm 4.1):
+
+$$
+\\begin{aligned}
+    &\\textbf{if} \\: \\rho < \\frac{1}{4} \\: \\textbf{then} \\: \\Delta \\gets \\frac{1}{4} \\Delta \\\\
+    &\\textbf{else if} \\: \\rho > \\frac{3}{4} \\: \\textbf{and} \\: ||p||_{2} = \\Delta \\: \\textbf{then}
+        \\: \\Delta \\gets \\min(2 \\Delta, \\Delta_{max}) \\\\
+    &\\textbf{if} \\: \\rho > \\eta \\: \\textbf{then} \\: \\theta \\gets \\theta + p
+\\end{aligned}
+$$
+
+Rejected steps leave the variables unchanged, so a step costs one evaluation of the loss beyond the Hessian-vector
+products. These are taken by central differences of the gradient as in
+[`hessian::hessian_vector_product`](crate::hessian::hessian_vector_product), as candle does not differentiate through
+the backward pass.
+
+Unlike a line search, the length of the step is limited before the direction is chosen, which makes trust region
+methods robust on stiff or badly scaled losses where the Newton step is unreliable far from a minimum.
+*/
+
+use crate::{
+    cg::{dot, steihaug, CgStop},
+    flatten_grads,
+    hessian::flat_hessian_vector_product,
+    lbfgs::GradConv,
+    unflatten, LossOptimizer, Model, ModelOutcome, OptimVars,
+};
+use candle_core::Result as CResult;
+use candle_core::{DType, Tensor, Var};
+use log::info;
+
+/// Parameters for the trust region Newton optimiser
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ParamsTrustRegion {
+    /// Initial radius of the trust region
+    pub initial_radius: f64,
+    /// Maximum radius of the trust region
+    pub max_radius: f64,
+    /// Radius below which the optimiser is treated as converged, as no step that reduces the loss can be found
+    pub min_radius: f64,
+    /// Minimum ratio of the actual to the predicted reduction for a step to be accepted, in $[0, \\frac{1}{4})$
+    pub eta: f64,
+    /// Maximum number of conjugate gradient iterations, each taking one Hessian-vector product
+    pub max_cg_iter: usize,
+    /// Relative tolerance of the conjugate gradient residual, or `None` for the forcing term
+    /// $\\min(\\frac{1}{2}, \\sqrt{||g||_{2}})$
+    pub cg_tol: Option<f64>,
+    /// Step of the central differences for the Hessian-vector products
+    pub hvp_eps: f64,
+    /// Convergence criteria for the gradient
+    pub grad_conv: GradConv,
+    /// log the ratio, radius and conjugate gradient iterations of each step at the info level
+    pub verbose: bool,
+}
+
+impl Default for ParamsTrustRegion {
+    fn default() -> Self {
+        Self {
+            initial_radius: 1.,
+            max_radius: 100.,
+            min_radius: 1e-10,
+            eta: 0.1,
+            max_cg_iter: 50,
+            cg_tol: None,
+            hvp_eps: 1e-4,
+            grad_conv: GradConv::MinForce(1e-7),
+            verbose: false,
+        }
+    }
+}
+
+/// Trust region Newton optimiser
+///
+/// Steps are found by Steihaug's conjugate gradient method within a trust region adapted to the agreement of the
+/// actual and predicted reductions in the loss
+#[derive(Debug)]
+pub struct TrustRegion<M: Model> {
+    vars: Vec<Var>,
+    model: M,
+    params: ParamsTrustRegion,
+    radius: f64,
+    last_ratio: Option<f64>,
+}
+
+impl<M: Model> LossOptimizer<M> for TrustRegion<M> {
+    type Config = ParamsTrustRegion;
+
+    fn new(vs: Vec<Var>, params: Self::Config, model: M) -> CResult<Self> {
+        if params.initial_radius <= 0. || params.initial_radius > params.max_radius {
+            candle_core::bail!(
+                "the initial trust region radius must be positive and at most the maximum radius {}, got {}",
+                params.max_radius,
+                params.initial_radius
+            );
+        }
+        Ok(Self {
+            vars: vs,
+            model,
+            radius: params.initial_radius,
+            params,
+            last_ratio: None,
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> CResult<ModelOutcome> {
+        let mut evals = 1;
+        let grad = flatten_grads(&loss.backward()?, &self.vars)?;
+        if self.params.grad_conv.converged(&grad)? == Some(true) {
+            info!("grad converged");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+        if self.radius < self.params.min_radius {
+            info!("trust region radius below min_radius");
+            return Ok(ModelOutcome::Converged(loss.clone(), evals));
+        }
+
+        let grad_norm = dot(&grad, &grad)?.sqrt();
+        let tol = self
+            .params
+            .cg_tol
+            .unwrap_or_else(|| grad_norm.sqrt().min(0.5));
+        let mut products = 0;
+        let (step, hp, stop) = {
+            let vars = self.vars.iter().collect::<Vec<&Var>>();
+            let model = &self.model;
+            let eps = self.params.hvp_eps;
+            let matvec = |v: &Tensor| -> CResult<Tensor> {
+                products += 1;
+                flat_hessian_vector_product(&vars, v, || model.loss(), eps)
+            };
+            steihaug(matvec, &grad, self.radius, self.params.max_cg_iter, tol)?
+        };
+        evals += 2 * products;
+        let predicted = -0.5f64.mul_add(dot(&step, &hp)?, dot(&grad, &step)?);
+
+        let original = self
+            .vars
+            .iter()
+            .map(|v| v.as_tensor().copy())
+            .collect::<CResult<Vec<Tensor>>>()?;
+        for ((var, original), step) in self
+            .vars
+            .iter()
+            .zip(&original)
+            .zip(unflatten(&step, &self.vars)?)
+        {
+            var.set(&(original + step)?)?;
+        }
+        let next_loss = self.model.loss()?;
+        evals += 1;
+        let actual = loss.to_dtype(DType::F64)?.to_scalar::<f64>()?
+            - next_loss.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+        // a step the model does not predict to reduce the loss is rejected
+        let ratio = if predicted > 0. {
+            actual / predicted
+        } else {
+            f64::NEG_INFINITY
+        };
+        self.last_ratio = Some(ratio);
+
+        if ratio < 0.25 {
+            self.radius *= 0.25;
+        } else if ratio > 0.75 && matches!(stop, CgStop::Boundary | CgStop::NegativeCurvature) {
+            // the step reached the boundary, so the region was limiting it
+            self.radius = (2. * self.radius).min(self.params.max_radius);
+        }
+        if self.params.verbose {
+            info!(
+                "ratio {ratio}, radius {}, {products} Hessian-vector products ({stop:?})",
+                self.radius
+            );
+        }
+
+        if ratio > self.params.eta {
+            Ok(ModelOutcome::Stepped(next_loss, evals))
+        } else {
+            for (var, original) in self.vars.iter().zip(original) {
+                var.set(&original)?;
+            }
+            Ok(ModelOutcome::Stepped(loss.clone(), evals))
+        }
+    }
+
+    /// the current radius of the trust region
+    fn learning_rate(&self) -> f64 {
+        self.radius
+    }
+
+    /// set the current radius of the trust region
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.radius = lr;
+    }
+
+    fn into_inner(self) -> Vec<Var> {
+        self.vars
+    }
+}
+
+impl<M: Model> OptimVars for TrustRegion<M> {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().collect()
+    }
+}
+
+impl<M: Model> TrustRegion<M> {
+    /// The current radius of the trust region
+    #[must_use]
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// The ratio of the actual to the predicted reduction of the last step, or `None` before the first step
+    ///
+    /// The step was accepted if this is above `eta`.
+    #[must_use]
+    pub fn last_ratio(&self) -> Option<f64> {
+        self.last_ratio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+
+    use super::*;
+
+    /// $\\frac{1}{2} x^{\\top} A x - b^{\\top} x$ with $A = [[4, 1], [1, 3]]$ and $b = [1, 2]$
+    struct Quadratic {
+        x: Var,
+    }
+
+    impl Model for Quadratic {
+        fn loss(&self) -> CResult<Tensor> {
+            let a = Tensor::new(&[[4f64, 1.], [1., 3.]], &Device::Cpu)?;
+            let b = Tensor::new(&[1f64, 2.], &Device::Cpu)?;
+            let x = self.x.as_tensor();
+            let ax = a.matmul(&x.unsqueeze(1)?)?.squeeze(1)?;
+            ((x * ax)?.sum_all()? * 0.5)? - (x * b)?.sum_all()?
+        }
+    }
+
+    fn setup(params: ParamsTrustRegion) -> Result<(Var, TrustRegion<Quadratic>)> {
+        let x = Var::new(&[0f64, 0.], &Device::Cpu)?;
+        let optim = TrustRegion::new(vec![x.clone()], params, Quadratic { x: x.clone() })?;
+        Ok((x, optim))
+    }
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let (_, mut optim) = setup(ParamsTrustRegion::default())?;
+        assert_approx_eq!(1., optim.learning_rate());
+        optim.set_learning_rate(0.5);
+        assert_approx_eq!(0.5, optim.radius());
+        assert!(setup(ParamsTrustRegion {
+            initial_radius: 0.,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn quadratic_step_test() -> Result<()> {
+        // the model is exact for a quadratic, so the Newton step inside the region has a ratio of 1
+        let (x, mut optim) = setup(ParamsTrustRegion {
+            cg_tol: Some(1e-10),
+            ..Default::default()
+        })?;
+        let loss = optim.model.loss()?;
+        optim.backward_step(&loss)?;
+        assert_approx_eq!(optim.last_ratio().unwrap_or_default(), 1., 1e-6);
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0], 1. / 11.);
+        assert_approx_eq!(x[1], 7. / 11.);
+        // the step was inside the region, so the radius is unchanged
+        assert_approx_eq!(optim.radius(), 1.);
+        Ok(())
+    }
+
+    #[test]
+    fn boundary_step_test() -> Result<()> {
+        // a small region is limiting, and the exact model has a ratio of 1, so the radius doubles
+        let (x, mut optim) = setup(ParamsTrustRegion {
+            initial_radius: 0.1,
+            ..Default::default()
+        })?;
+        let loss = optim.model.loss()?;
+        optim.backward_step(&loss)?;
+        let x = x.to_vec1::<f64>()?;
+        assert_approx_eq!(x[0].hypot(x[1]), 0.1);
+        assert_approx_eq!(optim.radius(), 0.2);
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+/*!
+Yogi optimiser
+
+Described in [Adaptive Methods for Nonconvex Optimization](https://papers.nips.cc/paper/8186-adaptive-methods-for-nonconvex-optimization.pdf)
+
+This replaces the multiplicative update of Adam's second moment with an additive one, whose sign depends on whether
+the squared gradient is above or below the current estimate. The second moment then changes by at most
+$(1 - \\beta_2) g_t^2$ per step, so the effective learning rate cannot grow quickly when the gradients become small.
+
+Pseudocode (including decoupling of weight decay):
+
+$$
+\\begin{aligned}
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{input}      : \\gamma \\text{ (lr)}, \\: \\beta_1, \\beta_2
+        \\text{ (betas)}, \\: \\theta_0 \\text{ (params)}, \\:f(\\theta) \\text{ (objective)}, \\:
+        \\lambda \\text{ (weightdecay)},                                                   \\\\
+    &\\hspace{13mm} \\epsilon \\text{ (epsilon)}, \\: v_{init} \\text{ (initial accumulator)}  \\\\
+    &\\textbf{initialize} :  m_0 \\leftarrow 0 \\text{ ( first moment)},
+        v_0 \\leftarrow v_{init} \\text{ ( second moment)}                                  \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                                 \\\\
+    &\\textbf{for} \\: t=1 \\: \\textbf{to} \\: \\ldots \\: \\textbf{do}                         \\\\
+    &\\hspace{5mm}g_t           \\leftarrow   \\nabla_{\\theta} f_t (\\theta_{t-1})           \\\\
+    &\\hspace{5mm}\\textbf{if} \\: \\lambda \\textbf{ is } \\text{Some}                        \\\\
+    &\\hspace{10mm}\\textbf{if} \\: \\textit{decoupled}                       \\\\
+    &\\hspace{15mm} \\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\lambda \\theta_{t-1}                    \\\\
+    &\\hspace{10mm}\\textbf{else}                                                              \\\\
+    &\\hspace{15mm} g_t \\leftarrow g_t + \\lambda  \\theta_{t-1}                            \\\\
+    &\\hspace{5mm}m_t           \\leftarrow   \\beta_1 m_{t-1} + (1 - \\beta_1) g_t          \\\\
+    &\\hspace{5mm}v_t           \\leftarrow   v_{t-1} - (1-\\beta_2) \\mathrm{sign}\\big(v_{t-1} - g^2_t\\big) g^2_t   \\\\
+    &\\hspace{5mm}\\widehat{m_t} \\leftarrow   m_t/\\big(1-\\beta_1^t \\big)                   \\\\
+    &\\hspace{5mm}\\widehat{v_t} \\leftarrow   v_t/\\big(1-\\beta_2^t \\big)                   \\\\
+    &\\hspace{5mm}\\theta_t \\leftarrow \\theta_{t-1} - \\gamma \\widehat{m_t}/
+        \\big(\\sqrt{\\widehat{v_t}} + \\epsilon \\big)                                       \\\\
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+    &\\bf{return} \\:  \\theta_t                                                     \\\\[-1.ex]
+    &\\rule{110mm}{0.4pt}                                                          \\\\[-1.ex]
+\\end{aligned}
+$$
+
+The defaults, and the bias correction, follow the widely used `torch_optimizer` implementation.
+*/
+
+use candle_core::{Result, Var};
+use candle_nn::optim::Optimizer;
+
+use crate::{
+    bias_correction, sign, warn_unused_config, zero_decay, CurrentHyperparams, Decay,
+    HyperSnapshot, OptimParams, OptimVars, UnusedConfig,
+};
+
+/// Yogi optimiser
+///
+/// Described in [Adaptive Methods for Nonconvex Optimization](https://papers.nips.cc/paper/8186-adaptive-methods-for-nonconvex-optimization.pdf)
+#[derive(Debug)]
+pub struct Yogi {
+    vars: Vec<VarYogi>,
+    params: ParamsYogi,
+    t: f64,
+}
+
+#[derive(Debug)]
+struct VarYogi {
+    theta: Var,
+    m: Var,
+    v: Var,
+}
+
+/// Parameters for the Yogi optimiser
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ParamsYogi {
+    /// Learning rate
+    pub lr: f64,
+    /// Coefficient for moving average of first moment
+    pub beta_1: f64,
+    /// Coefficient for the additive update of the second moment
+    pub beta_2: f64,
+    /// Term added to denominator to improve numerical stability
+    pub eps: f64,
+    /// Initial value of the second moment
+    pub initial_accumulator: f64,
+    /// Weight decay
+    pub weight_decay: Option<Decay>,
+}
+
+impl Default for ParamsYogi {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            beta_1: 0.9,
+            beta_2: 0.999,
+            eps: 1e-3,
+            initial_accumulator: 1e-6,
+            weight_decay: None,
+        }
+    }
+}
+
+impl UnusedConfig for ParamsYogi {
+    fn unused_config(&self) -> Vec<String> {
+        zero_decay(self.weight_decay.map(Decay::coefficient))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Optimizer for Yogi {
+    type Config = ParamsYogi;
+
+    fn new(vars: Vec<Var>, params: ParamsYogi) -> Result<Self> {
+        warn_unused_config(&params);
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let m = Var::zeros(shape, dtype, device)?;
+                let v = Var::from_tensor(&(var.ones_like()? * params.initial_accumulator)?)?;
+                Ok(VarYogi { theta: var, m, v })
+            })
+            .collect::<Result<Vec<VarYogi>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            t: 1.,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn step(&mut self, grads: &candle_core::backprop::GradStore) -> Result<()> {
+        let beta_1 = self.params.beta_1;
+        let beta_2 = self.params.beta_2;
+        let bias_correction_1 = bias_correction(beta_1, self.t);
+        let bias_correction_2 = bias_correction(beta_2, self.t);
+
+        for var in &self.vars {
+            let theta = &var.theta;
+            let m = &var.m;
+            let v = &var.v;
+            if let Some(grad) = grads.get(theta) {
+                let grad = match self.params.weight_decay {
+                    Some(Decay::WeightDecay(wd)) => (grad + (wd * theta.as_tensor())?)?,
+                    Some(Decay::DecoupledWeightDecay(decay)) => {
+                        // decoupled weight decay step
+                        theta.set(&(theta.as_tensor() * self.params.lr.mul_add(-decay, 1.))?)?;
+                        grad.clone()
+                    }
+                    None => grad.clone(),
+                };
+                let m_next = ((beta_1 * m.as_tensor())? + ((1. - beta_1) * &grad)?)?;
+                let grad_sq = grad.sqr()?;
+                let direction = sign(&(v.as_tensor() - &grad_sq)?)?;
+                let v_next = (v.as_tensor() - ((1. - beta_2) * direction.mul(&grad_sq)?)?)?;
+                let m_hat = (&m_next / bias_correction_1)?;
+                let denom = ((&v_next / bias_correction_2)?.sqrt()? + self.params.eps)?;
+                let delta = (self.params.lr * m_hat.div(&denom)?)?;
+                theta.set(&theta.sub(&delta)?)?;
+                m.set(&m_next)?;
+                v.set(&v_next)?;
+            }
+        }
+
+        self.t += 1.;
+        Ok(())
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr;
+    }
+}
+
+impl OptimParams for Yogi {
+    fn params(&self) -> &Self::Config {
+        &self.params
+    }
+
+    fn set_params(&mut self, config: Self::Config) {
+        self.params = config;
+    }
+}
+
+impl CurrentHyperparams for Yogi {
+    fn current_hyperparams(&self) -> HyperSnapshot {
+        HyperSnapshot {
+            lr: self.params.lr,
+            betas: Some((self.params.beta_1, self.params.beta_2)),
+            weight_decay: self.params.weight_decay,
+            momentum: None,
+        }
+    }
+}
+
+impl OptimVars for Yogi {
+    fn vars(&self) -> Vec<&Var> {
+        self.vars.iter().map(|v| &v.theta).collect()
+    }
+}
+
+impl Yogi {
+    /// Return the vars being optimised
+    #[must_use]
+    pub fn into_inner(self) -> Vec<Var> {
+        self.vars.into_iter().map(|v| v.theta).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use assert_approx_eq::assert_approx_eq;
+    use candle_core::{Device, Var};
+    use candle_nn::Optimizer;
+
+    use super::*;
+
+    #[test]
+    fn lr_test() -> Result<()> {
+        let params = ParamsYogi {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Yogi::new(vec![w.clone(), b.clone()], params)?;
+        assert_approx_eq!(0.004, optim.learning_rate());
+        optim.set_learning_rate(0.002);
+        assert_approx_eq!(0.002, optim.learning_rate());
+        Ok(())
+    }
+
+    #[test]
+    fn params_test() -> Result<()> {
+        let params = ParamsYogi {
+            lr: 0.004,
+            ..Default::default()
+        };
+        let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+        let b = Var::new(0f32, &Device::Cpu)?;
+        let mut optim = Yogi::new(vec![w.clone(), b.clone()], params.clone())?;
+        assert_eq!(params, optim.params().clone());
+        let new_params = ParamsYogi {
+            lr: 0.002,
+            weight_decay: Some(Decay::DecoupledWeightDecay(0.1)),
+            ..Default::default()
+        };
+        optim.set_params(new_params.clone());
+        assert_eq!(new_params, optim.params().clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::{
+    adabelief::{AdaBelief, ParamsAdaBelief},
+    Decay,
+};
+
+fn quadratic_loss(x: &Var, target: &Tensor) -> Result<Tensor> {
+    Ok(x.as_tensor().sub(target)?.sqr()?.sum_all()?)
+}
+
+fn converge(params: ParamsAdaBelief, steps: usize) -> Result<Vec<f32>> {
+    let target = Tensor::new(&[3f32, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f32, 0., 0.], &Device::Cpu)?;
+    let mut optim = AdaBelief::new(vec![x.clone()], params)?;
+    for _step in 0..steps {
+        let loss = quadratic_loss(&x, &target)?;
+        optim.backward_step(&loss)?;
+    }
+    Ok(x.to_vec1::<f32>()?)
+}
+
+#[test]
+fn adabelief_first_step_test() -> Result<()> {
+    // with g = 2: m = 0.2, s = 0.001 * 1.8^2 so the bias corrected step is lr * 2 / 1.8
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let params = ParamsAdaBelief {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let mut optim = AdaBelief::new(vec![x.clone()], params)?;
+    let loss = x.as_tensor().affine(2., 0.)?;
+    optim.backward_step(&loss)?;
+    assert_approx_eq!(x.to_scalar::<f32>()?, -0.1 / 0.9, 1e-6);
+    Ok(())
+}
+
+#[test]
+fn adabelief_quadratic_test() -> Result<()> {
+    let params = ParamsAdaBelief {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn adabelief_rectified_quadratic_test() -> Result<()> {
+    let params = ParamsAdaBelief {
+        lr: 0.1,
+        rectify: true,
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn adabelief_decoupled_decay_test() -> Result<()> {
+    let params = ParamsAdaBelief {
+        lr: 0.1,
+        weight_decay: Some(Decay::DecoupledWeightDecay(0.1)),
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    // decay pulls the solution towards zero
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert!(x.abs() < t.abs());
+        assert_approx_eq!(x, t, 0.1);
+    }
+    Ok(())
+}
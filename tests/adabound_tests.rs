@@ -0,0 +1,88 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::{
+    adabound::{AdaBound, ParamsAdaBound},
+    Decay,
+};
+
+fn quadratic_loss(x: &Var, target: &Tensor) -> Result<Tensor> {
+    Ok(x.as_tensor().sub(target)?.sqr()?.sum_all()?)
+}
+
+fn converge(params: ParamsAdaBound, steps: usize) -> Result<Vec<f32>> {
+    let target = Tensor::new(&[3f32, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f32, 0., 0.], &Device::Cpu)?;
+    let mut optim = AdaBound::new(vec![x.clone()], params)?;
+    for _step in 0..steps {
+        let loss = quadratic_loss(&x, &target)?;
+        optim.backward_step(&loss)?;
+    }
+    Ok(x.to_vec1::<f32>()?)
+}
+
+#[test]
+fn adabound_first_step_test() -> Result<()> {
+    // within the bounds the first step is the Adam step of lr
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = AdaBound::new(vec![x.clone()], ParamsAdaBound::default())?;
+    let loss = x.as_tensor().affine(2., 0.)?;
+    optim.backward_step(&loss)?;
+    assert_approx_eq!(x.to_scalar::<f32>()?, -0.001, 1e-6);
+
+    // with gamma = 1 the learning rate is at least final_lr / 2 on the first step, so the step is 0.05 * m = 0.01
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let params = ParamsAdaBound {
+        gamma: 1.,
+        ..Default::default()
+    };
+    let mut optim = AdaBound::new(vec![x.clone()], params)?;
+    let loss = x.as_tensor().affine(2., 0.)?;
+    optim.backward_step(&loss)?;
+    assert_approx_eq!(x.to_scalar::<f32>()?, -0.01, 1e-6);
+    Ok(())
+}
+
+#[test]
+fn adabound_quadratic_test() -> Result<()> {
+    let params = ParamsAdaBound {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn amsbound_quadratic_test() -> Result<()> {
+    let params = ParamsAdaBound {
+        lr: 0.1,
+        amsbound: true,
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn adabound_decay_test() -> Result<()> {
+    // with decoupled weight decay the minimum is shrunk towards zero
+    let params = ParamsAdaBound {
+        lr: 0.1,
+        weight_decay: Some(Decay::DecoupledWeightDecay(0.01)),
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert!(x.abs() < t.abs());
+        assert_approx_eq!(x, t, 0.1);
+    }
+    Ok(())
+}
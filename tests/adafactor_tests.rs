@@ -0,0 +1,72 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::adafactor::{Adafactor, ParamsAdafactor};
+
+/* The Hugging Face Adafactor differs in details from the PyTorch one, so these results are not checked against a
+reference implementation: instead the linear regression should converge. The weight is a matrix so uses the factored
+second moment, while the bias is a scalar so uses the full second moment. */
+
+fn linear_regression(params: ParamsAdafactor, steps: usize) -> Result<(Var, Var, f32)> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Adafactor::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    let mut loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    for _step in 0..steps {
+        optim.backward_step(&loss)?;
+        loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    }
+    Ok((w, b, loss.to_scalar::<f32>()?))
+}
+
+#[test]
+fn adafactor_test() -> Result<()> {
+    let params = ParamsAdafactor {
+        lr: 0.05,
+        relative_step: false,
+        scale_parameter: false,
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 2000)?;
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    assert!(loss < 0.5, "loss {loss}");
+    Ok(())
+}
+
+#[test]
+fn adafactor_relative_step_test() -> Result<()> {
+    // the default relative step size starts small while the parameters are zero, but grows with them
+    let (_, _, initial) = linear_regression(ParamsAdafactor::default(), 0)?;
+    let (_, _, loss) = linear_regression(ParamsAdafactor::default(), 2000)?;
+    assert!(loss < initial / 10., "loss {loss}");
+    Ok(())
+}
+
+#[test]
+fn adafactor_first_moment_test() -> Result<()> {
+    let params = ParamsAdafactor {
+        lr: 0.05,
+        beta_1: Some(0.9),
+        relative_step: false,
+        scale_parameter: false,
+        ..Default::default()
+    };
+    let (w, b, _) = linear_regression(params, 2000)?;
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    Ok(())
+}
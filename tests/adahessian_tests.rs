@@ -0,0 +1,58 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::adahessian::{AdaHessian, ParamsAdaHessian};
+
+/* AdaHessian is not in pytorch, so these results are not checked against a reference implementation:
+instead the optimiser should converge. */
+
+#[test]
+fn adahessian_quadratic_test() -> Result<()> {
+    // curvatures differing by a factor of 1000, so a step scaled by the Hessian is needed for fast convergence
+    let curvature = Tensor::new(&[0.01f64, 1., 10.], &Device::Cpu)?;
+    let target = Tensor::new(&[3f64, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+    let loss_fn = || {
+        x.as_tensor()
+            .sub(&target)?
+            .sqr()?
+            .mul(&curvature)?
+            .sum_all()
+    };
+    let mut optim = AdaHessian::new(vec![x.clone()], ParamsAdaHessian::default())?;
+    for _step in 0..500 {
+        optim.backward_step_with_hessian(&loss_fn()?, loss_fn)?;
+    }
+    for (x, t) in x.to_vec1::<f64>()?.iter().zip([3., -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-2);
+    }
+    Ok(())
+}
+
+#[test]
+fn adahessian_kernel_test() -> Result<()> {
+    // a kernel of shape [2, 1, 2, 2], whose Hessian estimate is averaged over each 2 x 2 window
+    let target = Tensor::new(&[1f64, 2., 3., 4., -1., -2., -3., -4.], &Device::Cpu)?
+        .reshape((2, 1, 2, 2))?;
+    let x = Var::zeros((2, 1, 2, 2), candle_core::DType::F64, &Device::Cpu)?;
+    let loss_fn = || x.as_tensor().sub(&target)?.sqr()?.sum_all();
+    let params = ParamsAdaHessian {
+        hessian_interval: 2,
+        samples: 2,
+        ..Default::default()
+    };
+    let mut optim = AdaHessian::new(vec![x.clone()], params)?;
+    for _step in 0..500 {
+        optim.backward_step_with_hessian(&loss_fn()?, loss_fn)?;
+    }
+    for (x, t) in x
+        .flatten_all()?
+        .to_vec1::<f64>()?
+        .iter()
+        .zip(target.flatten_all()?.to_vec1::<f64>()?)
+    {
+        assert_approx_eq!(x, t, 1e-2);
+    }
+    Ok(())
+}
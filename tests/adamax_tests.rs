@@ -1,9 +1,11 @@
 use candle_core::test_utils::{to_vec0_round, to_vec2_round};
 
 use anyhow::Result;
-use candle_core::{Device, Tensor, Var};
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{DType, Device, Tensor, Var};
 use candle_nn::{Linear, Module, Optimizer};
 use candle_optimisers::adamax::{Adamax, ParamsAdaMax};
+use candle_optimisers::{Decay, OptimState};
 
 /* The results of this test have been checked against the following PyTorch code.
     import torch
@@ -150,3 +152,130 @@ fn adamax_decoupled_weight_decay_test() -> Result<()> {
     assert_eq!(to_vec0_round(&b, 4)?, 0.3263);
     Ok(())
 }
+
+#[test]
+fn adamax_decoupled_decay_semantics_test() -> Result<()> {
+    // with a zero gradient the decoupled decay shrinks theta by (1 - lr * wd), independent of the adaptive update
+    // whilst L2 decay is normalised by the infinity norm, giving a step of lr
+    let step = |decay: Decay| -> Result<f32> {
+        let params = ParamsAdaMax {
+            lr: 0.1,
+            weight_decay: Some(decay),
+            ..Default::default()
+        };
+        let theta = Var::new(&[4f32], &Device::Cpu)?;
+        let mut optim = Adamax::new(vec![theta.clone()], params)?;
+        let zeros = Tensor::zeros(1, candle_core::DType::F32, &Device::Cpu)?;
+        optim.backward_step(&theta.as_tensor().mul(&zeros)?.sum_all()?)?;
+        Ok(theta.to_vec1::<f32>()?[0])
+    };
+    assert_approx_eq!(step(Decay::DecoupledWeightDecay(0.5))?, 3.8);
+    assert_approx_eq!(step(Decay::WeightDecay(0.5))?, 3.9);
+    Ok(())
+}
+
+#[test]
+fn adamax_state_dict_test() -> Result<()> {
+    let params = ParamsAdaMax {
+        lr: 0.1,
+        amsgrad: true,
+        ..Default::default()
+    };
+    let w = Var::new(&[1f32, -2.], &Device::Cpu)?;
+    let mut optim = Adamax::new(vec![w.clone()], params.clone())?;
+    for _ in 0..3 {
+        optim.backward_step(&w.as_tensor().powf(4.)?.sum_all()?)?;
+    }
+
+    let path = std::env::temp_dir().join("adamax_state_dict_test.safetensors");
+    candle_core::safetensors::save(&optim.state_dict()?, &path)?;
+    let resumed_w = Var::from_tensor(w.as_tensor())?;
+    let mut resumed = Adamax::new(vec![resumed_w.clone()], params.clone())?;
+    resumed.load_state_dict(candle_core::safetensors::load(&path, &Device::Cpu)?)?;
+    std::fs::remove_file(&path)?;
+
+    optim.backward_step(&w.as_tensor().powf(4.)?.sum_all()?)?;
+    resumed.backward_step(&resumed_w.as_tensor().powf(4.)?.sum_all()?)?;
+    assert_eq!(w.to_vec1::<f32>()?, resumed_w.to_vec1::<f32>()?);
+    assert_approx_eq!(resumed.state_dict()?["t"].to_scalar::<f64>()?, 5.);
+
+    // a state with a missing or misshapen entry is rejected without loading any of it
+    let fresh_w = Var::new(&[1f32, -2.], &Device::Cpu)?;
+    let mut fresh = Adamax::new(vec![fresh_w], params)?;
+    let mut missing = optim.state_dict()?;
+    missing.remove("u_max.0");
+    assert!(fresh.load_state_dict(missing).is_err());
+    let mut misshapen = optim.state_dict()?;
+    misshapen.insert(
+        "u.0".to_string(),
+        Tensor::zeros(3, DType::F32, &Device::Cpu)?,
+    );
+    assert!(fresh.load_state_dict(misshapen).is_err());
+    let state = fresh.state_dict()?;
+    assert_eq!(state["m.0"].to_vec1::<f32>()?, [0., 0.]);
+    assert_approx_eq!(state["t"].to_scalar::<f64>()?, 1.);
+    Ok(())
+}
+
+#[test]
+fn adamax_groups_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    // weight decay on the weights only, and a larger learning rate for the bias
+    let w_params = ParamsAdaMax {
+        lr: 0.004,
+        weight_decay: Some(Decay::WeightDecay(0.6)),
+        ..Default::default()
+    };
+    let b_params = ParamsAdaMax {
+        lr: 0.01,
+        ..Default::default()
+    };
+
+    // the grouped optimiser should match separate optimisers for each group
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut grouped = Adamax::new_with_groups(vec![
+        (vec![w.clone()], w_params.clone()),
+        (vec![b.clone()], b_params.clone()),
+    ])?;
+    let w_sep = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b_sep = Var::new(0f32, &Device::Cpu)?;
+    let mut w_optim = Adamax::new(vec![w_sep.clone()], w_params)?;
+    let mut b_optim = Adamax::new(vec![b_sep.clone()], b_params.clone())?;
+
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    let lin_sep = Linear::new(w_sep.as_tensor().clone(), Some(b_sep.as_tensor().clone()));
+    for _step in 0..100 {
+        let loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+        grouped.backward_step(&loss)?;
+        let loss = lin_sep
+            .forward(&sample_xs)?
+            .sub(&sample_ys)?
+            .sqr()?
+            .sum_all()?;
+        let grads = loss.backward()?;
+        w_optim.step(&grads)?;
+        b_optim.step(&grads)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, to_vec2_round(&w_sep, 4)?);
+    assert_eq!(to_vec0_round(&b, 4)?, to_vec0_round(&b_sep, 4)?);
+
+    assert_eq!(grouped.num_groups(), 2);
+    assert_eq!(grouped.group_params(1), Some(&b_params));
+    // setting the learning rate scales all the groups
+    grouped.set_learning_rate(0.002);
+    assert_approx_eq!(grouped.group_params(1).unwrap().lr, 0.005);
+    grouped.set_group_lr(1, 0.1)?;
+    assert_approx_eq!(grouped.group_params(1).unwrap().lr, 0.1);
+    assert!(grouped.set_group_lr(2, 0.1).is_err());
+    assert!(grouped.set_group_params(2, b_params).is_err());
+    assert_approx_eq!(grouped.learning_rate(), 0.002);
+    assert!(Adamax::new_with_groups(vec![]).is_err());
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::adan::{Adan, ParamsAdan};
+
+fn quadratic_loss(x: &Var, target: &Tensor) -> Result<Tensor> {
+    Ok(x.as_tensor().sub(target)?.sqr()?.sum_all()?)
+}
+
+fn converge(params: ParamsAdan, steps: usize) -> Result<Vec<f32>> {
+    let target = Tensor::new(&[3f32, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f32, 0., 0.], &Device::Cpu)?;
+    let mut optim = Adan::new(vec![x.clone()], params)?;
+    for _step in 0..steps {
+        let loss = quadratic_loss(&x, &target)?;
+        optim.backward_step(&loss)?;
+    }
+    Ok(x.to_vec1::<f32>()?)
+}
+
+#[test]
+fn adan_first_steps_test() -> Result<()> {
+    // with a constant gradient the difference is always zero, so every step is the bias corrected
+    // gradient over its root mean square: a step of lr
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let params = ParamsAdan {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let mut optim = Adan::new(vec![x.clone()], params)?;
+    let loss = x.as_tensor().affine(2., 0.)?;
+    for expected in [-0.1, -0.2, -0.3] {
+        optim.backward_step(&loss)?;
+        assert_approx_eq!(x.to_scalar::<f32>()?, expected, 1e-6);
+    }
+    Ok(())
+}
+
+#[test]
+fn adan_decay_test() -> Result<()> {
+    // the proximal weight decay divides by 1 + lr * decay after the step, while without it the decay is applied first
+    let loss = |x: &Var| -> Result<Tensor> { Ok(x.as_tensor().affine(2., 0.)?) };
+    let x = Var::new(1f32, &Device::Cpu)?;
+    let params = ParamsAdan {
+        lr: 0.1,
+        weight_decay: Some(1.),
+        ..Default::default()
+    };
+    let mut optim = Adan::new(vec![x.clone()], params.clone())?;
+    optim.backward_step(&loss(&x)?)?;
+    assert_approx_eq!(x.to_scalar::<f32>()?, 0.9 / 1.1, 1e-6);
+
+    let x = Var::new(1f32, &Device::Cpu)?;
+    let params = ParamsAdan {
+        no_prox: true,
+        ..params
+    };
+    let mut optim = Adan::new(vec![x.clone()], params)?;
+    optim.backward_step(&loss(&x)?)?;
+    assert_approx_eq!(x.to_scalar::<f32>()?, 0.8, 1e-6);
+    Ok(())
+}
+
+#[test]
+fn adan_quadratic_test() -> Result<()> {
+    let params = ParamsAdan {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let x = converge(params, 1000)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-3);
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::annealing::{Annealing, Cooling, ParamsAnnealing, Proposal};
+use candle_optimisers::lbfgs::{Lbfgs, ParamsLBFGS};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// a tilted double well $(x^2 - 4)^2 + 2 x$, with a local minimum near 1.9 and the global minimum near -2.1
+#[derive(Debug, Clone)]
+struct DoubleWellModel {
+    x: Var,
+}
+
+impl Model for DoubleWellModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let x = self.x.as_tensor();
+        ((x.sqr()? - 4.)?.sqr()? + (x * 2.)?)?.sum_all()
+    }
+}
+
+/// the Rastrigin function in 2 dimensions, with many local minima and the global minimum 0 at the origin
+#[derive(Debug, Clone)]
+struct RastriginModel {
+    xs: Var,
+}
+
+impl Model for RastriginModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let x = self.xs.as_tensor();
+        let ripples = ((x * (2. * std::f64::consts::PI))?.cos()? * 10.)?;
+        ((x.sqr()? - ripples)? + 10.)?.sum_all()
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model, O: LossOptimizer<M>>(
+    optim: &mut O,
+    model: &M,
+    max_steps: usize,
+) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn annealing_double_well_test() -> Result<()> {
+    // LBFGS started in the right hand well stays in its local minimum
+    let model = DoubleWellModel {
+        x: Var::new(&[2f64], &Device::Cpu)?,
+    };
+    let mut lbfgs = Lbfgs::new(vec![model.x.clone()], ParamsLBFGS::default(), model.clone())?;
+    minimise(&mut lbfgs, &model, 100)?;
+    assert!(model.x.to_vec1::<f64>()?[0] > 0.);
+
+    // while annealing crosses the barrier to the global minimum
+    let model = DoubleWellModel {
+        x: Var::new(&[2f64], &Device::Cpu)?,
+    };
+    let params = ParamsAnnealing {
+        initial_temperature: 10.,
+        min_temperature: 1e-4,
+        cooling: Cooling::Exponential(0.98),
+        proposal: Proposal::Gaussian { std: 0.5 },
+        moves: 20,
+    };
+    let mut optim = Annealing::new(vec![model.x.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 10_000)?;
+    assert!(steps < 10_000, "{steps} steps");
+    assert_approx_eq!(model.x.to_vec1::<f64>()?[0], -2.06, 0.1);
+    Ok(())
+}
+
+#[test]
+fn annealing_rastrigin_test() -> Result<()> {
+    let model = RastriginModel {
+        xs: Var::new(&[2.2f64, -1.8], &Device::Cpu)?,
+    };
+    let params = ParamsAnnealing {
+        initial_temperature: 20.,
+        min_temperature: 1e-3,
+        cooling: Cooling::Exponential(0.97),
+        proposal: Proposal::Cauchy { scale: 0.2 },
+        moves: 50,
+    };
+    let mut optim = Annealing::new(vec![model.xs.clone()], params, model.clone())?;
+    minimise(&mut optim, &model, 10_000)?;
+    for x in model.xs.to_vec1::<f64>()? {
+        assert_approx_eq!(x, 0., 0.1);
+    }
+    Ok(())
+}
+
+#[test]
+fn annealing_f32_test() -> Result<()> {
+    let model = DoubleWellModel {
+        x: Var::new(&[-1f32], &Device::Cpu)?,
+    };
+    let params = ParamsAnnealing {
+        cooling: Cooling::Linear(200),
+        proposal: Proposal::Uniform { half_width: 0.3 },
+        moves: 10,
+        ..Default::default()
+    };
+    let mut optim = Annealing::new(vec![model.x.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 1000)?;
+    assert!(steps <= 200, "{steps} steps");
+    assert_approx_eq!(model.x.to_vec1::<f32>()?[0], -2.06, 0.1);
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use candle_core::test_utils::{to_vec0_round, to_vec2_round};
+
+use anyhow::Result;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::{
+    asgd::{Asgd, ParamsAsgd},
+    Decay,
+};
+
+/* The expected results of these tests follow the update of the following PyTorch code,
+   reproduced step by step in single precision.
+    import torch
+    from torch import optim
+
+    w_gen = torch.tensor([[3., 1.]])
+    b_gen = torch.tensor([-2.])
+
+    sample_xs = torch.tensor([[2., 1.], [7., 4.], [-4., 12.], [5., 8.]])
+    sample_ys = sample_xs.matmul(w_gen.t()) + b_gen
+
+    m = torch.nn.Linear(2, 1)
+    with torch.no_grad():
+        m.weight.zero_()
+        m.bias.zero_()
+    optimiser = optim.ASGD(m.parameters(), lr=0.004)
+    for _step in range(100):
+        optimiser.zero_grad()
+        ys = m(sample_xs)
+        loss = ((ys - sample_ys)**2).sum()
+        loss.backward()
+        optimiser.step()
+    print(m.weight)
+    print(m.bias)
+    print([optimiser.state[p]["ax"] for p in m.parameters()])
+*/
+#[test]
+fn asgd_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsAsgd {
+        lr: 0.004,
+        ..Default::default()
+    };
+    // Now use backprop to run a linear regression between samples and get the coefficients back.
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Asgd::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8809, 0.8513]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -0.5605);
+    // before the trigger point the average is the working variables
+    let averages = optim.averages();
+    assert_eq!(to_vec2_round(averages[0], 4)?, &[[2.8809, 0.8513]]);
+    assert_eq!(to_vec0_round(averages[1], 4)?, -0.5605);
+    Ok(())
+}
+
+/* As above, with
+    optimiser = optim.ASGD(m.parameters(), lr=0.004, t0=50)
+*/
+#[test]
+fn asgd_averaging_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsAsgd {
+        lr: 0.004,
+        t0: 50.,
+        ..Default::default()
+    };
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Asgd::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    // the working variables are unaffected by the averaging
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8809, 0.8513]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -0.5605);
+    let averages = optim.into_averages();
+    assert_eq!(to_vec2_round(&averages[0], 4)?, &[[2.8663, 0.8330]]);
+    assert_eq!(to_vec0_round(&averages[1], 4)?, -0.3837);
+    Ok(())
+}
+
+/* As above, with
+    optimiser = optim.ASGD(m.parameters(), lr=0.004, lambd=0.01, t0=50, weight_decay=0.6)
+*/
+#[test]
+fn asgd_weight_decay_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsAsgd {
+        lr: 0.004,
+        lambd: 0.01,
+        t0: 50.,
+        weight_decay: Some(Decay::WeightDecay(0.6)),
+        ..Default::default()
+    };
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Asgd::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8644, 0.8418]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -0.4702);
+    let averages = optim.averages();
+    assert_eq!(to_vec2_round(averages[0], 4)?, &[[2.8525, 0.8269]]);
+    assert_eq!(to_vec0_round(averages[1], 4)?, -0.3261);
+    Ok(())
+}
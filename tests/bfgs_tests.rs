@@ -0,0 +1,165 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::bfgs::{Bfgs, ParamsBfgs};
+use candle_optimisers::lbfgs::{Lbfgs, LineSearch, ParamsLBFGS};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+impl RosenbrockModel {
+    fn new() -> CResult<Self> {
+        Ok(Self {
+            x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+            y_pos: Var::new(1f64, &Device::Cpu)?,
+        })
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        vec![self.x_pos.clone(), self.y_pos.clone()]
+    }
+}
+
+/// the chained Rosenbrock function $\\sum_{i} (1 - x_i)^2 + 100 (x_{i + 1} - x_i^2)^2$ in 8 dimensions, with minimum
+/// 0 at (1, ..., 1)
+#[derive(Debug, Clone)]
+struct ChainedRosenbrockModel {
+    xs: Var,
+}
+
+impl Model for ChainedRosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let head = self.xs.as_tensor().narrow(0, 0, 7)?;
+        let tail = self.xs.as_tensor().narrow(0, 1, 7)?;
+        ((1. - &head)?.sqr()? + 100. * (tail - head.sqr()?)?.sqr()?)?.sum_all()
+    }
+}
+
+impl ChainedRosenbrockModel {
+    fn new() -> CResult<Self> {
+        Ok(Self {
+            xs: Var::new(&[-1.2f64, 1., -1.2, 1., -1.2, 1., -1.2, 1.], &Device::Cpu)?,
+        })
+    }
+}
+
+/// least squares fit of $a e^{b t}$ to noiseless data from $a = 2$, $b = -0.5$
+#[derive(Debug, Clone)]
+struct ExponentialModel {
+    ab: Var,
+    t: Tensor,
+    y: Tensor,
+}
+
+impl Model for ExponentialModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let a = self.ab.as_tensor().get(0)?;
+        let b = self.ab.as_tensor().get(1)?;
+        let pred = self.t.broadcast_mul(&b)?.exp()?.broadcast_mul(&a)?;
+        (pred - &self.y)?.sqr()?.sum_all()
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model, O: LossOptimizer<M>>(
+    optim: &mut O,
+    model: &M,
+    max_steps: usize,
+) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn bfgs_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel::new()?;
+    let mut optim = Bfgs::new(model.vars(), ParamsBfgs::default(), model.clone())?;
+    let steps = minimise(&mut optim, &model, 200)?;
+    assert!(steps < 200, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-5);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn bfgs_fewer_steps_than_lbfgs_test() -> Result<()> {
+    // with a short history LBFGS discards curvature information that BFGS keeps
+    let model = ChainedRosenbrockModel::new()?;
+    let mut optim = Bfgs::new(vec![model.xs.clone()], ParamsBfgs::default(), model.clone())?;
+    let bfgs_steps = minimise(&mut optim, &model, 500)?;
+    let xs = model.xs.to_vec1::<f64>()?;
+    for x in xs {
+        assert_approx_eq!(x, 1., 1e-5);
+    }
+
+    let model = ChainedRosenbrockModel::new()?;
+    let params = ParamsLBFGS {
+        lr: 1.,
+        history_size: 2,
+        line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+        ..Default::default()
+    };
+    let mut optim = Lbfgs::new(vec![model.xs.clone()], params, model.clone())?;
+    let lbfgs_steps = minimise(&mut optim, &model, 500)?;
+    assert!(
+        bfgs_steps < lbfgs_steps,
+        "BFGS took {bfgs_steps} steps, LBFGS {lbfgs_steps}"
+    );
+    Ok(())
+}
+
+#[test]
+fn bfgs_curve_fit_test() -> Result<()> {
+    let t = Tensor::arange(0u32, 10, &Device::Cpu)?.to_dtype(candle_core::DType::F64)?;
+    let y = ((&t * -0.5)?.exp()? * 2.)?;
+    let model = ExponentialModel {
+        ab: Var::new(&[1f64, 0.], &Device::Cpu)?,
+        t,
+        y,
+    };
+    let mut optim = Bfgs::new(vec![model.ab.clone()], ParamsBfgs::default(), model.clone())?;
+    let steps = minimise(&mut optim, &model, 100)?;
+    assert!(steps < 100, "{steps} steps");
+    let ab = model.ab.to_vec1::<f64>()?;
+    assert_approx_eq!(ab[0], 2., 1e-5);
+    assert_approx_eq!(ab[1], -0.5, 1e-5);
+    Ok(())
+}
+
+#[test]
+fn bfgs_backtracking_test() -> Result<()> {
+    let model = RosenbrockModel::new()?;
+    let params = ParamsBfgs {
+        line_search: Some(LineSearch::Backtracking {
+            c1: 1e-4,
+            rho: 0.5,
+            max_steps: 30,
+        }),
+        ..Default::default()
+    };
+    let mut optim = Bfgs::new(model.vars(), params, model.clone())?;
+    minimise(&mut optim, &model, 500)?;
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-4);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-4);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::block_preconditioner::{BlockPreconditioner, ParamsBlockPreconditioner};
+
+/* The linear regression should converge whether the weight and bias share a preconditioner block or are
+preconditioned diagonally. Close to the minimum the gradient second moment vanishes and the step tends to gradient
+descent with learning rate lr / damping, so the damping is large enough for that to be stable. */
+
+fn linear_regression(grouped: bool, steps: usize) -> Result<(Var, Var, f32)> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let params = ParamsBlockPreconditioner {
+        lr: 1.,
+        damping: 300.,
+        ..Default::default()
+    };
+    let groups = if grouped {
+        vec![vec![w.clone(), b.clone()]]
+    } else {
+        Vec::new()
+    };
+    let mut optim =
+        BlockPreconditioner::new_with_groups(vec![w.clone(), b.clone()], groups, params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    let mut loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    for _step in 0..steps {
+        optim.backward_step(&loss)?;
+        loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    }
+    Ok((w, b, loss.to_scalar::<f32>()?))
+}
+
+#[test]
+fn block_preconditioner_test() -> Result<()> {
+    let (w, b, loss) = linear_regression(true, 3000)?;
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.01);
+    assert_approx_eq!(w[0][1], 1., 0.01);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.05);
+    assert!(loss < 1e-3, "loss {loss}");
+    Ok(())
+}
+
+#[test]
+fn block_preconditioner_diagonal_test() -> Result<()> {
+    let (w, b, loss) = linear_regression(false, 3000)?;
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.01);
+    assert_approx_eq!(w[0][1], 1., 0.01);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.05);
+    assert!(loss < 1e-3, "loss {loss}");
+    Ok(())
+}
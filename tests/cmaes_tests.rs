@@ -0,0 +1,124 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::cmaes::{Cmaes, ParamsCmaes};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+/// an ill-conditioned ellipsoid in 6 dimensions with minimum 0 at (1, ..., 1)
+#[derive(Debug, Clone)]
+struct EllipsoidModel {
+    xs: Var,
+}
+
+impl Model for EllipsoidModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let scales = Tensor::new(&[1f64, 10., 100., 1e3, 1e4, 1e5], self.xs.device())?
+            .to_dtype(self.xs.dtype())?;
+        ((self.xs.as_tensor() - 1.)?.sqr()? * scales)?.sum_all()
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(optim: &mut Cmaes<M>, model: &M, max_steps: usize) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn cmaes_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let mut optim = Cmaes::new(vars, ParamsCmaes::default(), model.clone())?;
+    let steps = minimise(&mut optim, &model, 2000)?;
+    assert!(steps < 2000, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-6);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-6);
+    Ok(())
+}
+
+#[test]
+fn cmaes_ellipsoid_test() -> Result<()> {
+    // the covariance adapts to the scales of the ellipsoid, so the smallest scale is learned along with the largest
+    let model = EllipsoidModel {
+        xs: Var::new(&[0f64; 6], &Device::Cpu)?,
+    };
+    let params = ParamsCmaes {
+        population: Some(12),
+        ..Default::default()
+    };
+    let mut optim = Cmaes::new(vec![model.xs.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 3000)?;
+    assert!(steps < 3000, "{steps} steps");
+    for x in model.xs.to_vec1::<f64>()? {
+        assert_approx_eq!(x, 1., 1e-5);
+    }
+    let cov = optim.covariance().to_vec2::<f64>()?;
+    assert!(cov[0][0] > cov[5][5]);
+    Ok(())
+}
+
+#[test]
+fn cmaes_nan_test() -> Result<()> {
+    // the Rosenbrock function is undefined for x < 0, where the candidates are ranked last
+    #[derive(Debug, Clone)]
+    struct Bounded(RosenbrockModel);
+
+    impl Model for Bounded {
+        fn loss(&self) -> CResult<Tensor> {
+            self.0.loss()? + self.0.x_pos.as_tensor().sqrt()?.zeros_like()?
+        }
+    }
+
+    let model = Bounded(RosenbrockModel {
+        x_pos: Var::new(0.5f64, &Device::Cpu)?,
+        y_pos: Var::new(0f64, &Device::Cpu)?,
+    });
+    let vars = vec![model.0.x_pos.clone(), model.0.y_pos.clone()];
+    let mut optim = Cmaes::new(vars, ParamsCmaes::default(), model.clone())?;
+    minimise(&mut optim, &model, 2000)?;
+    assert_approx_eq!(model.0.x_pos.to_scalar::<f64>()?, 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn cmaes_f32_test() -> Result<()> {
+    let model = EllipsoidModel {
+        xs: Var::new(&[0f32, 0.5, 2., -1., 3., 1.5], &Device::Cpu)?,
+    };
+    let params = ParamsCmaes {
+        sigma: 1.,
+        sigma_tol: 1e-6,
+        ..Default::default()
+    };
+    let mut optim = Cmaes::new(vec![model.xs.clone()], params, model.clone())?;
+    minimise(&mut optim, &model, 3000)?;
+    for x in model.xs.to_vec1::<f32>()? {
+        assert_approx_eq!(x, 1., 1e-3);
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::adam::ParamsAdam;
+use candle_optimisers::convergent::{ConvergentAdapter, ParamsConvergent};
+use candle_optimisers::lbfgs::{ConvergencePolicy, GradConv, Lbfgs, ParamsLBFGS, StepConv};
+use candle_optimisers::{adam::Adam, LossOptimizer, Model, ModelOutcome};
+
+/*
+Drive Adam and LBFGS through the same `ModelOutcome` loop with the same convergence criteria, on an ill conditioned
+quadratic with minimum 0 at (1, -2)
+*/
+
+#[derive(Clone)]
+struct QuadraticModel {
+    x: Var,
+}
+
+impl Model for QuadraticModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let target = Tensor::new(&[1f64, -2.], &Device::Cpu)?;
+        let scale = Tensor::new(&[1f64, 10.], &Device::Cpu)?;
+        (self.x.as_tensor() - target)?.sqr()?.mul(&scale)?.sum_all()
+    }
+}
+
+impl QuadraticModel {
+    fn new() -> CResult<Self> {
+        Ok(Self {
+            x: Var::new(&[0f64, 0.], &Device::Cpu)?,
+        })
+    }
+}
+
+const GRAD_CONV: GradConv = GradConv::MinForce(1e-6);
+const STEP_CONV: StepConv = StepConv::MinStep(1e-9);
+
+/// run the optimiser until it reports convergence, returning the number of steps taken
+fn run<M: Model, O: LossOptimizer<M>>(
+    optim: &mut O,
+    mut loss: Tensor,
+    max_steps: usize,
+) -> Result<usize> {
+    for step in 1..=max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(next, _) | ModelOutcome::Truncated(next, _) => loss = next,
+        }
+    }
+    Ok(max_steps + 1)
+}
+
+#[test]
+fn adam_convergent_test() -> Result<()> {
+    let model = QuadraticModel::new()?;
+    let params = ParamsConvergent {
+        inner: ParamsAdam {
+            lr: 0.05,
+            ..Default::default()
+        },
+        grad_conv: GRAD_CONV,
+        step_conv: STEP_CONV,
+        convergence_policy: ConvergencePolicy::Any,
+        ..Default::default()
+    };
+    let mut optim =
+        ConvergentAdapter::<Adam, _>::new(vec![model.x.clone()], params, model.clone())?;
+    let steps = run(&mut optim, model.loss()?, 100_000)?;
+    // the gradient criterion is met well before the step limit
+    assert!(steps < 1000, "took {steps} steps");
+    let x = model.x.to_vec1::<f64>()?;
+    assert_approx_eq!(x[0], 1., 1e-5);
+    assert_approx_eq!(x[1], -2., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn lbfgs_shared_criteria_test() -> Result<()> {
+    let model = QuadraticModel::new()?;
+    let params = ParamsLBFGS {
+        grad_conv: GRAD_CONV,
+        step_conv: STEP_CONV,
+        convergence_policy: ConvergencePolicy::Any,
+        ..Default::default()
+    };
+    let mut optim = Lbfgs::new(vec![model.x.clone()], params, model.clone())?;
+    let steps = run(&mut optim, model.loss()?, 100)?;
+    assert!(steps < 20, "took {steps} steps");
+    let x = model.x.to_vec1::<f64>()?;
+    assert_approx_eq!(x[0], 1., 1e-6);
+    assert_approx_eq!(x[1], -2., 1e-6);
+    Ok(())
+}
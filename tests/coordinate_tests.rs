@@ -0,0 +1,121 @@
+use anyhow::Result;
+use candle_core::{DType, Device, Result as CResult, Tensor, Var};
+use candle_optimisers::coordinate::{
+    BlockSelection, BlockStep, CoordinateDescent, ParamsCoordinateDescent,
+};
+use candle_optimisers::lbfgs::GradConv;
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the factorisation $\|U V - M\|^{2}$ of a rank 2 matrix $M$
+#[derive(Debug, Clone)]
+struct FactorisationModel {
+    u: Var,
+    v: Var,
+    target: Tensor,
+}
+
+impl Model for FactorisationModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (self.u.as_tensor().matmul(self.v.as_tensor())? - &self.target)?
+            .sqr()?
+            .sum_all()
+    }
+}
+
+fn factorisation(dtype: DType) -> Result<FactorisationModel> {
+    let u_true = Tensor::new(
+        &[[1f64, 0.5], [-0.3, 1.2], [0.8, -0.7], [0.2, 0.4]],
+        &Device::Cpu,
+    )?;
+    let v_true = Tensor::new(&[[0.6f64, -1., 0.3], [0.9, 0.2, -0.5]], &Device::Cpu)?;
+    let target = u_true.matmul(&v_true)?.to_dtype(dtype)?;
+    let u = Tensor::new(
+        &[[0.5f64, 0.1], [0.1, 0.5], [0.3, -0.2], [0.2, 0.3]],
+        &Device::Cpu,
+    )?;
+    let v = Tensor::new(&[[0.4f64, -0.2, 0.1], [0.1, 0.3, -0.3]], &Device::Cpu)?;
+    Ok(FactorisationModel {
+        u: Var::from_tensor(&u.to_dtype(dtype)?)?,
+        v: Var::from_tensor(&v.to_dtype(dtype)?)?,
+        target,
+    })
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(
+    optim: &mut CoordinateDescent<M>,
+    model: &M,
+    max_steps: usize,
+) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn coordinate_exact_factorisation_test() -> Result<()> {
+    // alternating minimisation: the loss is quadratic in each of U and V with the other fixed
+    let model = factorisation(DType::F64)?;
+    let params = ParamsCoordinateDescent {
+        step: BlockStep::Exact,
+        lr: 1.,
+        grad_conv: GradConv::MinForce(1e-6),
+        ..Default::default()
+    };
+    let mut optim = CoordinateDescent::new(
+        vec![model.u.clone(), model.v.clone()],
+        params,
+        model.clone(),
+    )?;
+    let steps = minimise(&mut optim, &model, 5000)?;
+    assert!(steps < 5000, "{steps} steps");
+    assert!(model.loss()?.to_scalar::<f64>()? < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn coordinate_gradient_factorisation_test() -> Result<()> {
+    let model = factorisation(DType::F64)?;
+    let params = ParamsCoordinateDescent {
+        lr: 0.05,
+        selection: BlockSelection::Random { seed: 42 },
+        grad_conv: GradConv::MinForce(1e-5),
+        ..Default::default()
+    };
+    let mut optim = CoordinateDescent::new(
+        vec![model.u.clone(), model.v.clone()],
+        params,
+        model.clone(),
+    )?;
+    let steps = minimise(&mut optim, &model, 50_000)?;
+    assert!(steps < 50_000, "{steps} steps");
+    assert!(model.loss()?.to_scalar::<f64>()? < 1e-8);
+    Ok(())
+}
+
+#[test]
+fn coordinate_f32_test() -> Result<()> {
+    let model = factorisation(DType::F32)?;
+    let params = ParamsCoordinateDescent {
+        step: BlockStep::Exact,
+        lr: 1.,
+        line_tol: 1e-4,
+        grad_conv: GradConv::MinForce(1e-3),
+        ..Default::default()
+    };
+    let mut optim = CoordinateDescent::new(
+        vec![model.u.clone(), model.v.clone()],
+        params,
+        model.clone(),
+    )?;
+    minimise(&mut optim, &model, 5000)?;
+    assert!(model.loss()?.to_scalar::<f32>()? < 1e-4);
+    Ok(())
+}
@@ -47,6 +47,9 @@ fn nesterov_sgd_test() -> Result<()> {
         momentum: Some(Momentum::Nesterov(0.1)),
         dampening: 0.0,
         // nesterov: true,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -112,6 +115,9 @@ fn nesterov_decay_sgd_test() -> Result<()> {
         momentum: Some(Momentum::Nesterov(0.1)),
         dampening: 0.0,
         // nesterov: true,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -171,6 +177,9 @@ fn momentum_sgd_test() -> Result<()> {
         weight_decay: None,
         momentum: Some(Momentum::Classical(0.1)),
         dampening: 0.0,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
         // nesterov: false,s
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
@@ -232,6 +241,9 @@ fn momentum_sgd_decay_test() -> Result<()> {
         momentum: Some(Momentum::Classical(0.1)),
         dampening: 0.0,
         // nesterov: false,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -292,6 +304,9 @@ fn momentum_sgd_dampened_test() -> Result<()> {
         momentum: Some(Momentum::Classical(0.1)),
         dampening: 0.2,
         // nesterov: false,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -324,6 +339,9 @@ fn sgd_test() -> Result<()> {
         momentum: None,
         dampening: 0.0,
         // nesterov: false,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -356,6 +374,9 @@ fn sgd_decay_test() -> Result<()> {
         momentum: None,
         dampening: 0.0,
         // nesterov: false,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -392,6 +413,9 @@ fn sgdw_decay_test() -> Result<()> {
         momentum: None,
         dampening: 0.0,
         // nesterov: false,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -424,6 +448,9 @@ fn momentum_sgdw_decay_test() -> Result<()> {
         momentum: Some(Momentum::Classical(0.1)),
         dampening: 0.0,
         // nesterov: false,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
@@ -456,6 +483,9 @@ fn nesterov_decay_sgdw_test() -> Result<()> {
         momentum: Some(Momentum::Nesterov(0.1)),
         dampening: 0.0,
         // nesterov: true,
+        upcast: false,
+        momentum_warmup_steps: 0,
+        record_trajectory: false,
     };
     // Now use backprop to run a linear regression between samples and get the coefficients back.
     let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
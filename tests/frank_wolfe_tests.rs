@@ -0,0 +1,85 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::frank_wolfe::{
+    FrankWolfe, L1Ball, L2Ball, LinearMinimisationOracle, ParamsFrankWolfe, Simplex,
+};
+
+/// minimise $||x - c||^{2}$ over the set, the projection of $c$ onto it, returning the optimiser
+fn project<C: LinearMinimisationOracle>(
+    x: &Var,
+    c: &Tensor,
+    set: C,
+    steps: usize,
+) -> Result<FrankWolfe<C>> {
+    let params = ParamsFrankWolfe { set, lr: None };
+    let mut optim = FrankWolfe::new(vec![x.clone()], params)?;
+    for _ in 0..steps {
+        let loss = (x.as_tensor() - c)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    Ok(optim)
+}
+
+#[test]
+fn frank_wolfe_simplex_test() -> Result<()> {
+    // the projection of [0.8, 0.5, -0.3] onto the probability simplex subtracts 0.15 and clips at zero
+    let x = Var::new(&[1. / 3., 1. / 3., 1. / 3.], &Device::Cpu)?;
+    let c = Tensor::new(&[0.8f64, 0.5, -0.3], &Device::Cpu)?;
+    let optim = project(&x, &c, Simplex { scale: 1. }, 2000)?;
+    let x = x.to_vec1::<f64>()?;
+    assert_approx_eq!(x[0], 0.65, 1e-3);
+    assert_approx_eq!(x[1], 0.35, 1e-3);
+    assert!(x[2] >= 0. && x[2] < 1e-3);
+    // every iterate is a convex combination of vertices, so stays on the simplex
+    assert_approx_eq!(x.iter().sum::<f64>(), 1., 1e-12);
+    assert!(optim.gap().unwrap_or(1.) < 1e-2);
+    Ok(())
+}
+
+#[test]
+fn frank_wolfe_l2_ball_test() -> Result<()> {
+    let x = Var::new(&[[0f64, 0.], [0., 0.]], &Device::Cpu)?;
+    let c = Tensor::new(&[[3f64, 0.], [0., -4.]], &Device::Cpu)?;
+    project(&x, &c, L2Ball { radius: 1. }, 2000)?;
+    let x = x.flatten_all()?.to_vec1::<f64>()?;
+    for (x, c) in x.iter().zip([0.6, 0., 0., -0.8]) {
+        assert_approx_eq!(x, c, 1e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn frank_wolfe_l1_ball_test() -> Result<()> {
+    // the L1 ball keeps the solution sparse: projecting [2, 0.5, -0.1] onto the ball of radius 1 gives [1, 0, 0]
+    let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+    let c = Tensor::new(&[2f64, 0.5, -0.1], &Device::Cpu)?;
+    let params = ParamsFrankWolfe {
+        set: L1Ball { radius: 1. },
+        lr: None,
+    };
+    let mut optim = FrankWolfe::new(vec![x.clone()], params)?;
+    let mut gaps = vec![];
+    for _ in 0..500 {
+        let loss = (x.as_tensor() - &c)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+        let l1 = x.abs()?.sum_all()?.to_scalar::<f64>()?;
+        assert!(l1 <= 1. + 1e-12);
+        gaps.extend(optim.gap());
+    }
+    assert_eq!(x.to_vec1::<f64>()?, [1., 0., 0.]);
+    assert!(gaps[499] <= gaps[0]);
+    Ok(())
+}
+
+#[test]
+fn frank_wolfe_f32_test() -> Result<()> {
+    let x = Var::new(&[0.5f32, 0.5], &Device::Cpu)?;
+    let c = Tensor::new(&[0.9f32, 0.3], &Device::Cpu)?;
+    project(&x, &c, Simplex { scale: 1. }, 1000)?;
+    let x = x.to_vec1::<f32>()?;
+    assert_approx_eq!(x[0], 0.8, 1e-3);
+    assert_approx_eq!(x[1], 0.2, 1e-3);
+    Ok(())
+}
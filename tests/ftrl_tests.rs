@@ -0,0 +1,91 @@
+use anyhow::Result;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{loss::binary_cross_entropy_with_logit, Optimizer};
+use candle_optimisers::ftrl::{Ftrl, ParamsFtrl};
+
+/// 64 samples of six features of value plus or minus one, from a linear congruential generator, with the label drawn
+/// from a logistic model of the first two and the last four features uninformative
+fn ctr_data() -> Result<(Tensor, Tensor)> {
+    let mut state = 12345u32;
+    let mut next = move || {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        u16::try_from((state >> 16) & 0x7fff).expect("15 bits fit in a u16")
+    };
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    for _sample in 0..64 {
+        let features: Vec<f32> = (0..6)
+            .map(|_| if next() % 2 == 0 { 1. } else { -1. })
+            .collect();
+        // the label is drawn with probability sigmoid(2 x0 - 1.5 x1)
+        let p = 1. / (1. + (1.5f32.mul_add(features[1], -2. * features[0])).exp());
+        ys.push(if f32::from(next()) < p * 32768. {
+            1f32
+        } else {
+            0.
+        });
+        xs.extend(features);
+    }
+    Ok((
+        Tensor::from_vec(xs, (64, 6), &Device::Cpu)?,
+        Tensor::from_vec(ys, (64, 1), &Device::Cpu)?,
+    ))
+}
+
+fn fit(params: ParamsFtrl) -> Result<Vec<f32>> {
+    let (xs, ys) = ctr_data()?;
+    let w = Var::zeros((6, 1), candle_core::DType::F32, &Device::Cpu)?;
+    let mut optim = Ftrl::new(vec![w.clone()], params)?;
+    for _step in 0..500 {
+        let logits = xs.matmul(w.as_tensor())?;
+        let loss = binary_cross_entropy_with_logit(&logits, &ys)?;
+        optim.backward_step(&loss)?;
+    }
+    Ok(w.flatten_all()?.to_vec1::<f32>()?)
+}
+
+#[test]
+fn ftrl_l1_sparsity_test() -> Result<()> {
+    let w = fit(ParamsFtrl {
+        lr: 0.5,
+        l1: 40.0,
+        ..Default::default()
+    })?;
+    // the L1 penalty is compared with the sum of the gradients over all steps, so grows with the number of steps:
+    // here it is a penalty of 0.08 per step. The informative weights have the right signs, and the others are set to
+    // exactly zero
+    assert!(w[0] > 0.5, "{w:?}");
+    assert!(w[1] < -0.5, "{w:?}");
+    assert_eq!(w[2..], [0., 0., 0., 0.]);
+    Ok(())
+}
+
+#[test]
+fn ftrl_dense_test() -> Result<()> {
+    // without an L1 penalty the uninformative weights are fitted to the noise
+    let w = fit(ParamsFtrl {
+        lr: 0.5,
+        ..Default::default()
+    })?;
+    assert!(w[0] > 0.5, "{w:?}");
+    assert!(w[1] < -0.5, "{w:?}");
+    assert!(w[2..].iter().all(|&w| w != 0.), "{w:?}");
+    Ok(())
+}
+
+#[test]
+fn ftrl_l2_test() -> Result<()> {
+    // the L2 penalty shrinks the weights towards zero without making them sparse
+    let dense = fit(ParamsFtrl {
+        lr: 0.5,
+        ..Default::default()
+    })?;
+    let shrunk = fit(ParamsFtrl {
+        lr: 0.5,
+        l2: 0.1,
+        ..Default::default()
+    })?;
+    assert!(shrunk[0] > 0. && shrunk[0] < dense[0], "{shrunk:?}");
+    assert!(shrunk[1] < 0. && shrunk[1] > dense[1], "{shrunk:?}");
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{DType, Device, Result as CResult, Tensor, Var};
+use candle_optimisers::gauss_newton::{GaussNewton, ParamsGaussNewton};
+use candle_optimisers::lbfgs::LineSearch;
+use candle_optimisers::levenberg_marquardt::ResidualConv;
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome, ResidualModel};
+
+/// the 2D Rosenbrock function as the residuals $[1 - x, 10 (y - x^2)]$, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    xy: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        self.residuals()?.sqr()?.sum_all()
+    }
+}
+
+impl ResidualModel for RosenbrockModel {
+    fn residuals(&self) -> CResult<Tensor> {
+        let x = self.xy.as_tensor().get(0)?;
+        let y = self.xy.as_tensor().get(1)?;
+        Tensor::stack(&[(1. - &x)?, ((y - x.sqr()?)? * 10.)?], 0)
+    }
+}
+
+/// calibration of the rate $k$ and initial value $c$ of the decay $c e^{-k t}$ to data
+#[derive(Debug, Clone)]
+struct DecayModel {
+    ck: Var,
+    t: Tensor,
+    y: Tensor,
+}
+
+impl Model for DecayModel {
+    fn loss(&self) -> CResult<Tensor> {
+        self.residuals()?.sqr()?.sum_all()
+    }
+}
+
+impl ResidualModel for DecayModel {
+    fn residuals(&self) -> CResult<Tensor> {
+        let c = self.ck.as_tensor().get(0)?;
+        let k = self.ck.as_tensor().get(1)?;
+        self.t.broadcast_mul(&k.neg()?)?.exp()?.broadcast_mul(&c)? - &self.y
+    }
+}
+
+impl DecayModel {
+    /// data from $c = 3$, $k = 0.4$, with `noise` added to alternate points
+    fn new(noise: f64) -> CResult<Self> {
+        let t = Tensor::arange(0u32, 12, &Device::Cpu)?.to_dtype(DType::F64)?;
+        let noise = Tensor::new(&[noise, -noise], &Device::Cpu)?.repeat(6)?;
+        let y = (((&t * -0.4)?.exp()? * 3.)? + noise)?;
+        Ok(Self {
+            ck: Var::new(&[2f64, 0.3], &Device::Cpu)?,
+            t,
+            y,
+        })
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: ResidualModel>(
+    optim: &mut GaussNewton<M>,
+    model: &M,
+    max_steps: usize,
+) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn gauss_newton_calibration_test() -> Result<()> {
+    // with an exact fit the convergence is quadratic from a nearby start
+    let model = DecayModel::new(0.)?;
+    let mut optim = GaussNewton::new(
+        vec![model.ck.clone()],
+        ParamsGaussNewton::default(),
+        model.clone(),
+    )?;
+    let steps = minimise(&mut optim, &model, 20)?;
+    assert!(steps < 10, "{steps} steps");
+    let ck = model.ck.to_vec1::<f64>()?;
+    assert_approx_eq!(ck[0], 3., 1e-9);
+    assert_approx_eq!(ck[1], 0.4, 1e-9);
+    Ok(())
+}
+
+#[test]
+fn gauss_newton_noisy_calibration_test() -> Result<()> {
+    let model = DecayModel::new(0.02)?;
+    let params = ParamsGaussNewton {
+        residual_conv: ResidualConv::RelativeReduction(1e-12),
+        ..Default::default()
+    };
+    let mut optim = GaussNewton::new(vec![model.ck.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 50)?;
+    assert!(steps < 50, "{steps} steps");
+    let ck = model.ck.to_vec1::<f64>()?;
+    assert_approx_eq!(ck[0], 3., 0.05);
+    assert_approx_eq!(ck[1], 0.4, 0.01);
+    Ok(())
+}
+
+#[test]
+fn gauss_newton_line_search_test() -> Result<()> {
+    for line_search in [
+        LineSearch::StrongWolfe(1e-4, 0.9, 1e-9),
+        LineSearch::Backtracking {
+            c1: 1e-4,
+            rho: 0.5,
+            max_steps: 30,
+        },
+    ] {
+        let model = RosenbrockModel {
+            xy: Var::new(&[-1.2f64, 1.], &Device::Cpu)?,
+        };
+        let params = ParamsGaussNewton {
+            line_search: Some(line_search),
+            ..Default::default()
+        };
+        let mut optim = GaussNewton::new(vec![model.xy.clone()], params, model.clone())?;
+        let steps = minimise(&mut optim, &model, 100)?;
+        assert!(steps < 100, "{line_search:?}: {steps} steps");
+        let xy = model.xy.to_vec1::<f64>()?;
+        assert_approx_eq!(xy[0], 1., 1e-8);
+        assert_approx_eq!(xy[1], 1., 1e-8);
+    }
+    Ok(())
+}
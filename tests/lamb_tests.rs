@@ -0,0 +1,62 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::lamb::{Lamb, ParamsLamb};
+
+/* LAMB is not in pytorch, so these results are not checked against a reference implementation:
+instead the linear regression should converge. The trust ratio keeps the step of the weight proportional to its norm
+even close to the minimum, so the learning rate is decayed as it would be in training. The bias starts at zero, so
+would only take tiny steps under the trust ratio: as in BERT it is excluded. */
+
+fn linear_regression(params: ParamsLamb, steps: usize) -> Result<(Var, Var, f32)> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Lamb::new_with_exclusions(vec![w.clone(), b.clone()], params, &[&b])?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    let mut loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    for _step in 0..steps {
+        optim.backward_step(&loss)?;
+        optim.set_learning_rate(optim.learning_rate() * 0.999);
+        loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    }
+    Ok((w, b, loss.to_scalar::<f32>()?))
+}
+
+#[test]
+fn lamb_test() -> Result<()> {
+    let params = ParamsLamb {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 5000)?;
+    assert!(loss < 0.1, "loss {loss}");
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    Ok(())
+}
+
+#[test]
+fn lamb_decay_test() -> Result<()> {
+    let params = ParamsLamb {
+        lr: 0.1,
+        weight_decay: Some(0.01),
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 5000)?;
+    assert!(loss < 0.1, "loss {loss}");
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    Ok(())
+}
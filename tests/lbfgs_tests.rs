@@ -1,8 +1,10 @@
 use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
 use candle_core::test_utils::to_vec2_round;
 use candle_core::{DType, Device, Result as CResult, Tensor};
+use candle_nn::{Init, VarBuilder, VarMap};
 use candle_optimisers::lbfgs::{GradConv, Lbfgs, LineSearch, ParamsLBFGS, StepConv};
-use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome, OptimState, OptimVars};
 
 /*
 These tests all use the 2D Rosenbrock function as a test function for the optimisers. This has minimum 0 at (1, 1)
@@ -63,8 +65,9 @@ fn lbfgs_test() -> Result<()> {
                                                // println!("end step {}", _step);
         match res {
             ModelOutcome::Converged(_, _) => break,
-            ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
 
@@ -101,8 +104,9 @@ fn lbfgs_test_strong_wolfe() -> Result<()> {
                                                // println!("end step {}", _step);
         match res {
             ModelOutcome::Converged(_, _) => break,
-            ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
 
@@ -139,8 +143,9 @@ fn lbfgs_rms_grad_test() -> Result<()> {
                                                // println!("end step {}", _step);
         match res {
             ModelOutcome::Converged(_, _) => break,
-            ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
 
@@ -178,8 +183,9 @@ fn lbfgs_rms_step_test() -> Result<()> {
                                                // println!("end step {}", _step);
         match res {
             ModelOutcome::Converged(_, _) => break,
-            ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
 
@@ -217,8 +223,9 @@ fn lbfgs_test_strong_wolfe_weight_decay() -> Result<()> {
                                                // println!("end step {}", _step);
         match res {
             ModelOutcome::Converged(_, _) => break,
-            ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
 
@@ -256,8 +263,9 @@ fn lbfgs_test_weight_decay() -> Result<()> {
                                                // println!("end step {}", _step);
         match res {
             ModelOutcome::Converged(_, _) => break,
-            ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
-            // _ => panic!("unexpected outcome"),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
         }
     }
 
@@ -272,3 +280,302 @@ fn lbfgs_test_weight_decay() -> Result<()> {
 
     Ok(())
 }
+
+#[derive(Debug, Clone)]
+pub struct QuarticModel {
+    x: candle_core::Var,
+}
+
+impl Model for QuarticModel {
+    fn loss(&self) -> CResult<Tensor> {
+        self.x.as_tensor().sqr()?.sqr()?.sum_all()
+    }
+}
+
+/// x^4 has a degenerate minimum, so LBFGS converges linearly rather than superlinearly
+#[test]
+fn lbfgs_estimate_remaining_test() -> Result<()> {
+    let params = ParamsLBFGS {
+        lr: 1.,
+        history_size: 1,
+        ..Default::default()
+    };
+    let x = candle_core::Var::new(&[1f64, -2., 0.5], &Device::Cpu)?;
+    let model = QuarticModel { x: x.clone() };
+    let mut lbfgs = Lbfgs::new(vec![x], params, model.clone())?;
+    let mut loss = model.loss()?;
+    let tol = 1e-8;
+    assert_eq!(lbfgs.estimated_iters_remaining(tol), None);
+
+    let step = |lbfgs: &mut Lbfgs<QuarticModel>, loss: &mut Tensor| -> Result<()> {
+        if let ModelOutcome::Stepped(new_loss, _) = lbfgs.backward_step(loss)? {
+            *loss = new_loss;
+        }
+        Ok(())
+    };
+    for _ in 0..10 {
+        step(&mut lbfgs, &mut loss)?;
+    }
+    let estimate = lbfgs
+        .estimated_iters_remaining(tol)
+        .expect("loss should be decreasing");
+    let mut actual = 0;
+    while lbfgs.estimated_iters_remaining(tol) != Some(0) {
+        step(&mut lbfgs, &mut loss)?;
+        actual += 1;
+        assert!(actual < 100, "failed to reach tolerance");
+    }
+    assert!(
+        estimate.abs_diff(actual) <= 2,
+        "estimated {estimate} iterations, took {actual}"
+    );
+    Ok(())
+}
+
+#[test]
+fn lbfgs_state_dict_test() -> Result<()> {
+    let params = ParamsLBFGS {
+        lr: 1.,
+        line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+        ..Default::default()
+    };
+    let model = RosenbrockModel::new()?;
+    let mut lbfgs = Lbfgs::new(model.vars(), params, model.clone())?;
+    let mut loss = model.loss()?;
+    for _ in 0..5 {
+        if let ModelOutcome::Stepped(next, _) = lbfgs.backward_step(&loss)? {
+            loss = next;
+        }
+    }
+
+    let path = std::env::temp_dir().join("lbfgs_state_dict_test.safetensors");
+    candle_core::safetensors::save(&lbfgs.state_dict()?, &path)?;
+    let resumed_model = RosenbrockModel {
+        x_pos: candle_core::Var::from_tensor(model.x_pos.as_tensor())?,
+        y_pos: candle_core::Var::from_tensor(model.y_pos.as_tensor())?,
+    };
+    let mut resumed = Lbfgs::new(resumed_model.vars(), params, resumed_model.clone())?;
+    resumed.load_state_dict(candle_core::safetensors::load(&path, &Device::Cpu)?)?;
+    std::fs::remove_file(&path)?;
+
+    lbfgs.backward_step(&loss)?;
+    resumed.backward_step(&resumed_model.loss()?)?;
+    assert_eq!(
+        model.x_pos.to_vec2::<f64>()?,
+        resumed_model.x_pos.to_vec2::<f64>()?
+    );
+    assert_eq!(
+        model.y_pos.to_vec2::<f64>()?,
+        resumed_model.y_pos.to_vec2::<f64>()?
+    );
+    Ok(())
+}
+
+#[test]
+fn lbfgs_test_backtracking() -> Result<()> {
+    let params = ParamsLBFGS {
+        lr: 1.,
+        line_search: Some(LineSearch::Backtracking {
+            c1: 1e-4,
+            rho: 0.5,
+            max_steps: 20,
+        }),
+        ..Default::default()
+    };
+
+    let model = RosenbrockModel::new()?;
+
+    let mut lbfgs = Lbfgs::new(model.vars(), params, model.clone())?;
+    let mut loss = model.loss()?;
+
+    for _step in 0..500 {
+        match lbfgs.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => break,
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+
+    for v in model.vars() {
+        assert_eq!(to_vec2_round(&v.to_dtype(DType::F32)?, 4)?, &[[1.0000]]);
+    }
+    Ok(())
+}
+
+/// Rosenbrock model counting the number of loss evaluations
+pub struct CountingModel {
+    inner: RosenbrockModel,
+    calls: std::cell::Cell<usize>,
+}
+
+impl Model for CountingModel {
+    fn loss(&self) -> CResult<Tensor> {
+        self.calls.set(self.calls.get() + 1);
+        self.inner.loss()
+    }
+}
+
+#[test]
+fn lbfgs_max_eval_test() -> Result<()> {
+    for line_search in [
+        LineSearch::StrongWolfe(1e-4, 0.9, 1e-9),
+        LineSearch::Backtracking {
+            c1: 1e-4,
+            rho: 0.5,
+            max_steps: 20,
+        },
+    ] {
+        let params = ParamsLBFGS {
+            lr: 1.,
+            line_search: Some(line_search),
+            max_eval: Some(2),
+            ..Default::default()
+        };
+        let inner = RosenbrockModel::new()?;
+        let vars = inner.vars();
+        let mut loss = inner.loss()?;
+        let model = CountingModel {
+            inner,
+            calls: std::cell::Cell::new(0),
+        };
+        let mut lbfgs = Lbfgs::new(vars, params, model)?;
+        let mut truncated = 0;
+        for _step in 0..20 {
+            let before = lbfgs.model().calls.get();
+            let res = lbfgs.backward_step(&loss)?;
+            assert!(lbfgs.model().calls.get() - before <= 2);
+            match res {
+                ModelOutcome::Converged(_, _) => break,
+                ModelOutcome::Stepped(new_loss, _) => loss = new_loss,
+                ModelOutcome::Truncated(new_loss, _) => {
+                    truncated += 1;
+                    loss = new_loss;
+                }
+            }
+        }
+        assert!(truncated > 0, "{line_search:?} was never truncated");
+    }
+    Ok(())
+}
+
+/// Rosenbrock model built from a `VarMap`, which forgets to list its y coordinate
+struct VarMapRosenbrock {
+    x_pos: Tensor,
+    y_pos: Tensor,
+    listed: Vec<candle_core::Var>,
+}
+
+impl Model for VarMapRosenbrock {
+    fn loss(&self) -> CResult<Tensor> {
+        ((1. - &self.x_pos)?.powf(2.)? + 100. * (&self.y_pos - self.x_pos.powf(2.)?)?.powf(2.)?)?
+            .squeeze(1)?
+            .squeeze(0)
+    }
+
+    fn listed_vars(&self) -> Option<Vec<candle_core::Var>> {
+        Some(self.listed.clone())
+    }
+}
+
+#[test]
+fn lbfgs_from_varmap_test() -> Result<()> {
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F64, &Device::Cpu);
+    let x_pos = vb.get_with_hints((1, 1), "x", Init::Const(10.))?;
+    let y_pos = vb.get_with_hints((1, 1), "y", Init::Const(10.))?;
+    let listed = varmap
+        .all_vars()
+        .into_iter()
+        .filter(|v| v.as_tensor().id() == x_pos.id())
+        .collect::<Vec<_>>();
+    let model = VarMapRosenbrock {
+        x_pos,
+        y_pos,
+        listed,
+    };
+
+    let params = ParamsLBFGS {
+        lr: 1.,
+        line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+        ..Default::default()
+    };
+    let mut lbfgs = Lbfgs::from_varmap_and_model(&varmap, params, model)?;
+    assert_eq!(lbfgs.vars().len(), 2);
+    let mut loss = lbfgs.model().loss()?;
+    for _step in 0..500 {
+        match lbfgs.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => break,
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    // both coordinates are optimised, including the one the model did not list
+    for var in varmap.all_vars() {
+        assert_eq!(to_vec2_round(&var.to_dtype(DType::F32)?, 4)?, &[[1.0000]]);
+    }
+    Ok(())
+}
+
+#[test]
+fn lbfgs_trajectory_test() -> Result<()> {
+    let params = ParamsLBFGS {
+        lr: 1.,
+        line_search: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+        record_trajectory: true,
+        ..Default::default()
+    };
+
+    let model = RosenbrockModel::new()?;
+    let mut lbfgs = Lbfgs::new(model.vars(), params, model.clone())?;
+    let mut loss = model.loss()?;
+    let mut steps = 0;
+    for _step in 0..500 {
+        steps += 1;
+        match lbfgs.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => break,
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+
+    // one (x, y) point per step, starting away from (10, 10) and ending at the minimum
+    let trajectory = lbfgs.trajectory();
+    assert_eq!(trajectory.len(), steps);
+    assert_ne!(trajectory[0].to_vec1::<f64>()?, [10., 10.]);
+    let last = trajectory[steps - 1].to_vec1::<f64>()?;
+    assert_approx_eq!(last[0], 1., 1e-4);
+    assert_approx_eq!(last[1], 1., 1e-4);
+    Ok(())
+}
+
+#[test]
+fn lbfgs_single_var_trajectory_test() -> Result<()> {
+    // a single var is recorded without concatenating, so each entry must be a copy rather than a view of it
+    let params = ParamsLBFGS {
+        lr: 0.1,
+        record_trajectory: true,
+        ..Default::default()
+    };
+    let x = candle_core::Var::new(&[1f64, -2., 0.5], &Device::Cpu)?;
+    let model = QuarticModel { x: x.clone() };
+    let mut lbfgs = Lbfgs::new(vec![x.clone()], params, model.clone())?;
+    let mut loss = model.loss()?;
+    let mut points = Vec::new();
+    for _ in 0..5 {
+        if let ModelOutcome::Stepped(new_loss, _) = lbfgs.backward_step(&loss)? {
+            loss = new_loss;
+        }
+        points.push(x.to_vec1::<f64>()?);
+    }
+    let trajectory = lbfgs
+        .trajectory()
+        .iter()
+        .map(Tensor::to_vec1::<f64>)
+        .collect::<CResult<Vec<_>>>()?;
+    assert_eq!(trajectory, points);
+    assert_ne!(trajectory[0], trajectory[4]);
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{DType, Device, Result as CResult, Tensor, Var};
+use candle_optimisers::levenberg_marquardt::{
+    DampingScaling, DampingUpdate, LevenbergMarquardt, ParamsLevenbergMarquardt, ResidualConv,
+};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome, ResidualModel};
+
+/// the 2D Rosenbrock function as the residuals $[1 - x, 10 (y - x^2)]$, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    xy: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        self.residuals()?.sqr()?.sum_all()
+    }
+}
+
+impl ResidualModel for RosenbrockModel {
+    fn residuals(&self) -> CResult<Tensor> {
+        let x = self.xy.as_tensor().get(0)?;
+        let y = self.xy.as_tensor().get(1)?;
+        Tensor::stack(&[(1. - &x)?, ((y - x.sqr()?)? * 10.)?], 0)
+    }
+}
+
+/// least squares fit of $a e^{b t}$ to data
+#[derive(Debug, Clone)]
+struct ExponentialModel {
+    ab: Var,
+    t: Tensor,
+    y: Tensor,
+}
+
+impl Model for ExponentialModel {
+    fn loss(&self) -> CResult<Tensor> {
+        self.residuals()?.sqr()?.sum_all()
+    }
+}
+
+impl ResidualModel for ExponentialModel {
+    fn residuals(&self) -> CResult<Tensor> {
+        let a = self.ab.as_tensor().get(0)?;
+        let b = self.ab.as_tensor().get(1)?;
+        self.t.broadcast_mul(&b)?.exp()?.broadcast_mul(&a)? - &self.y
+    }
+}
+
+impl ExponentialModel {
+    /// data from $a = 2$, $b = -0.5$, with `noise` added to alternate points
+    fn new(dtype: DType, noise: f64) -> CResult<Self> {
+        let t = Tensor::arange(0u32, 10, &Device::Cpu)?.to_dtype(DType::F64)?;
+        let noise = Tensor::new(&[noise, -noise], &Device::Cpu)?.repeat(5)?;
+        let y = (((&t * -0.5)?.exp()? * 2.)? + noise)?;
+        Ok(Self {
+            ab: Var::from_tensor(&Tensor::new(&[1f64, 0.], &Device::Cpu)?.to_dtype(dtype)?)?,
+            t: t.to_dtype(dtype)?,
+            y: y.to_dtype(dtype)?,
+        })
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: ResidualModel>(
+    optim: &mut LevenbergMarquardt<M>,
+    model: &M,
+    max_steps: usize,
+) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn levenberg_marquardt_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel {
+        xy: Var::new(&[-1.2f64, 1.], &Device::Cpu)?,
+    };
+    let mut optim = LevenbergMarquardt::new(
+        vec![model.xy.clone()],
+        ParamsLevenbergMarquardt::default(),
+        model.clone(),
+    )?;
+    let steps = minimise(&mut optim, &model, 100)?;
+    assert!(steps < 100, "{steps} steps");
+    let xy = model.xy.to_vec1::<f64>()?;
+    assert_approx_eq!(xy[0], 1., 1e-8);
+    assert_approx_eq!(xy[1], 1., 1e-8);
+    Ok(())
+}
+
+#[test]
+fn levenberg_marquardt_curve_fit_test() -> Result<()> {
+    for (scaling, update) in [
+        (
+            DampingScaling::Diagonal,
+            ParamsLevenbergMarquardt::default().update,
+        ),
+        (DampingScaling::Identity, DampingUpdate::Nielsen),
+    ] {
+        let model = ExponentialModel::new(DType::F64, 0.)?;
+        let params = ParamsLevenbergMarquardt {
+            scaling,
+            update,
+            ..Default::default()
+        };
+        let mut optim = LevenbergMarquardt::new(vec![model.ab.clone()], params, model.clone())?;
+        let steps = minimise(&mut optim, &model, 100)?;
+        assert!(steps < 100, "{scaling:?}, {update:?}: {steps} steps");
+        let ab = model.ab.to_vec1::<f64>()?;
+        assert_approx_eq!(ab[0], 2., 1e-8);
+        assert_approx_eq!(ab[1], -0.5, 1e-8);
+    }
+    Ok(())
+}
+
+#[test]
+fn levenberg_marquardt_relative_reduction_test() -> Result<()> {
+    // with noisy data the residuals never vanish, so stop once the steps stop reducing the loss
+    let model = ExponentialModel::new(DType::F64, 0.05)?;
+    let params = ParamsLevenbergMarquardt {
+        residual_conv: ResidualConv::RelativeReduction(1e-10),
+        grad_conv: candle_optimisers::lbfgs::GradConv::MinForce(0.),
+        ..Default::default()
+    };
+    let mut optim = LevenbergMarquardt::new(vec![model.ab.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 100)?;
+    assert!(steps < 100, "{steps} steps");
+    assert!(model.loss()?.to_scalar::<f64>()? > 0.);
+    let ab = model.ab.to_vec1::<f64>()?;
+    assert_approx_eq!(ab[0], 2., 0.1);
+    assert_approx_eq!(ab[1], -0.5, 0.05);
+    Ok(())
+}
+
+#[test]
+fn levenberg_marquardt_f32_test() -> Result<()> {
+    let model = ExponentialModel::new(DType::F32, 0.)?;
+    let params = ParamsLevenbergMarquardt {
+        residual_conv: ResidualConv::Norm(1e-5),
+        grad_conv: candle_optimisers::lbfgs::GradConv::MinForce(1e-6),
+        ..Default::default()
+    };
+    let mut optim = LevenbergMarquardt::new(vec![model.ab.clone()], params, model.clone())?;
+    minimise(&mut optim, &model, 100)?;
+    let ab = model.ab.to_vec1::<f32>()?;
+    assert_approx_eq!(ab[0], 2., 1e-4);
+    assert_approx_eq!(ab[1], -0.5, 1e-4);
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::lion::{Lion, ParamsLion};
+
+/* Lion is not part of PyTorch, so unlike the other optimisers these results are not checked against a reference
+implementation: instead the linear regression should converge, and lower the loss faster with a larger learning rate. */
+
+fn linear_regression(optim_lr: f64, steps: usize) -> Result<(Var, Var, f32)> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsLion {
+        lr: 1.,
+        ..Default::default()
+    };
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Lion::new(vec![w.clone(), b.clone()], params)?;
+    // the learning rate set after construction is the one used
+    optim.set_learning_rate(optim_lr);
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    let mut loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    for _step in 0..steps {
+        optim.backward_step(&loss)?;
+        loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    }
+    assert_approx_eq!(optim.learning_rate(), optim_lr);
+    Ok((w, b, loss.to_scalar::<f32>()?))
+}
+
+#[test]
+fn lion_test() -> Result<()> {
+    let (_, _, slow_loss) = linear_regression(0.001, 100)?;
+    let (_, _, fast_loss) = linear_regression(0.004, 100)?;
+    // each step moves every parameter by close to lr, so a larger learning rate makes more progress
+    assert!(fast_loss < slow_loss);
+    Ok(())
+}
+
+#[test]
+fn lion_convergence_test() -> Result<()> {
+    let (w, b, loss) = linear_regression(0.01, 2000)?;
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    assert!(loss < 0.5, "loss {loss}");
+    Ok(())
+}
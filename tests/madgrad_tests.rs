@@ -0,0 +1,153 @@
+use candle_core::test_utils::{to_vec0_round, to_vec2_round};
+
+use anyhow::Result;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::{
+    madgrad::{Madgrad, ParamsMadgrad},
+    Decay,
+};
+
+/* The expected results of these tests follow the update of the following code, using the reference `madgrad`
+   package, reproduced step by step in single precision.
+    import torch
+    from madgrad import MADGRAD
+
+    w_gen = torch.tensor([[3., 1.]])
+    b_gen = torch.tensor([-2.])
+
+    sample_xs = torch.tensor([[2., 1.], [7., 4.], [-4., 12.], [5., 8.]])
+    sample_ys = sample_xs.matmul(w_gen.t()) + b_gen
+
+    m = torch.nn.Linear(2, 1)
+    with torch.no_grad():
+        m.weight.zero_()
+        m.bias.zero_()
+    optimiser = MADGRAD(m.parameters())
+    for _step in range(100):
+        optimiser.zero_grad()
+        ys = m(sample_xs)
+        loss = ((ys - sample_ys)**2).sum()
+        loss.backward()
+        optimiser.step()
+    print(m.weight)
+    print(m.bias)
+*/
+#[test]
+fn madgrad_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsMadgrad::default();
+    // Now use backprop to run a linear regression between samples and get the coefficients back.
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Madgrad::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8759, 0.8368]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -0.4629);
+    Ok(())
+}
+
+/* As above, with
+    optimiser = MADGRAD(m.parameters(), momentum=0.)
+   and 50 steps, as without momentum the later iterates oscillate and are sensitive to rounding
+*/
+#[test]
+fn madgrad_no_momentum_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsMadgrad {
+        momentum: 0.,
+        ..Default::default()
+    };
+    // Now use backprop to run a linear regression between samples and get the coefficients back.
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Madgrad::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..50 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8269, 0.7874]]);
+    assert_eq!(to_vec0_round(&b, 4)?, 0.0531);
+    Ok(())
+}
+
+/* As above, with
+    optimiser = MADGRAD(m.parameters(), weight_decay=0.1)
+*/
+#[test]
+fn madgrad_weight_decay_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsMadgrad {
+        weight_decay: Some(Decay::WeightDecay(0.1)),
+        ..Default::default()
+    };
+    // Now use backprop to run a linear regression between samples and get the coefficients back.
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Madgrad::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8743, 0.8367]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -0.4619);
+    Ok(())
+}
+
+/* As above, with
+    optimiser = MADGRAD(m.parameters(), weight_decay=0.1, decouple_decay=True)
+*/
+#[test]
+fn madgrad_decoupled_decay_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsMadgrad {
+        weight_decay: Some(Decay::DecoupledWeightDecay(0.1)),
+        ..Default::default()
+    };
+    // Now use backprop to run a linear regression between samples and get the coefficients back.
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Madgrad::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.8751, 0.8361]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -0.4541);
+    Ok(())
+}
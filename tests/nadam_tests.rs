@@ -172,3 +172,30 @@ fn nadam_decoupled_weight_decay_test() -> Result<()> {
     assert_eq!(to_vec0_round(&b, 4)?, 0.1762);
     Ok(())
 }
+
+#[test]
+fn nadam_convergence_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsNAdam {
+        lr: 0.01,
+        ..Default::default()
+    };
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut n_sgd = NAdam::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..5000 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        n_sgd.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 2)?, &[[3.0, 1.0]]);
+    assert_eq!(to_vec0_round(&b, 1)?, -2.0);
+    Ok(())
+}
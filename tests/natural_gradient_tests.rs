@@ -0,0 +1,92 @@
+use anyhow::Result;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::{
+    esgd::{ParamsSGD, SGD},
+    natural_gradient::{NaturalGradient, ParamsNaturalGradient},
+};
+
+/* Logistic regression on a small, non separable, dataset */
+
+const XS: [[f64; 2]; 8] = [
+    [1.0, 2.0],
+    [2.0, 0.5],
+    [-1.0, 1.5],
+    [0.5, -1.0],
+    [-2.0, -0.5],
+    [1.5, 1.0],
+    [-0.5, 0.5],
+    [0.0, -2.0],
+];
+const YS: [f64; 8] = [1., 1., 0., 1., 0., 0., 1., 0.];
+
+struct Logistic {
+    w: Var,
+    b: Var,
+}
+
+impl Logistic {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            w: Var::new(&[0f64, 0.], &Device::Cpu)?,
+            b: Var::new(0f64, &Device::Cpu)?,
+        })
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        vec![self.w.clone(), self.b.clone()]
+    }
+
+    /// binary cross entropy with logits for sample i: log(1 + exp(z)) - y z
+    fn sample_loss(&self, i: usize) -> Result<Tensor> {
+        let x = Tensor::new(&XS[i], &Device::Cpu)?;
+        let z = x
+            .mul(self.w.as_tensor())?
+            .sum_all()?
+            .add(self.b.as_tensor())?;
+        Ok(((z.exp()? + 1.)?.log()? - (&z * YS[i])?)?)
+    }
+
+    fn loss(&self) -> Result<Tensor> {
+        let losses = (0..XS.len())
+            .map(|i| self.sample_loss(i))
+            .collect::<Result<Vec<Tensor>>>()?;
+        Ok(Tensor::stack(&losses, 0)?.mean_all()?)
+    }
+}
+
+#[test]
+fn natural_gradient_logistic_test() -> Result<()> {
+    let model = Logistic::new()?;
+    let mut ng = NaturalGradient::new(
+        model.vars(),
+        ParamsNaturalGradient {
+            lr: 1.,
+            damping: 1e-2,
+            ..Default::default()
+        },
+    )?;
+    for _ in 0..3 {
+        let losses = (0..XS.len())
+            .map(|i| model.sample_loss(i))
+            .collect::<Result<Vec<Tensor>>>()?;
+        ng.backward_step_per_sample(&losses)?;
+    }
+    let ng_loss = model.loss()?.to_scalar::<f64>()?;
+
+    let model = Logistic::new()?;
+    let mut sgd = SGD::new(
+        model.vars(),
+        ParamsSGD {
+            lr: 1.,
+            ..Default::default()
+        },
+    )?;
+    // natural gradient after 3 steps is closer to the optimum than SGD after 10
+    for _ in 0..10 {
+        sgd.backward_step(&model.loss()?)?;
+    }
+    let sgd_loss = model.loss()?.to_scalar::<f64>()?;
+    assert!(ng_loss < sgd_loss);
+    Ok(())
+}
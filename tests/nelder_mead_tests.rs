@@ -0,0 +1,101 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::nelder_mead::{NelderMead, ParamsNelderMead};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+/// $\\max(|x - 0.3|, |y + 0.7|) + |z|$, which is not differentiable at its minimum (0.3, -0.7, 0)
+#[derive(Debug, Clone)]
+struct KinkedModel {
+    xyz: Var,
+}
+
+impl Model for KinkedModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let shift = Tensor::new(&[0.3f32, -0.7, 0.], &Device::Cpu)?;
+        let abs = (self.xyz.as_tensor() - shift)?.abs()?;
+        abs.narrow(0, 0, 2)?.max(0)? + abs.get(2)?
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(optim: &mut NelderMead<M>, model: &M, max_steps: usize) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn nelder_mead_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let mut optim = NelderMead::new(vars, ParamsNelderMead::default(), model.clone())?;
+    let steps = minimise(&mut optim, &model, 500)?;
+    assert!(steps < 500, "{steps} steps");
+    assert!(optim.simplex_size()? < 1e-8);
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-6);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-6);
+    Ok(())
+}
+
+#[test]
+fn nelder_mead_non_differentiable_test() -> Result<()> {
+    let model = KinkedModel {
+        xyz: Var::new(&[1f32, 1., 1.], &Device::Cpu)?,
+    };
+    let params = ParamsNelderMead {
+        initial_step: 0.5,
+        simplex_tol: 1e-6,
+        ..Default::default()
+    };
+    let mut optim = NelderMead::new(vec![model.xyz.clone()], params, model.clone())?;
+    minimise(&mut optim, &model, 1000)?;
+    let xyz = model.xyz.to_vec1::<f32>()?;
+    assert_approx_eq!(xyz[0], 0.3, 1e-4);
+    assert_approx_eq!(xyz[1], -0.7, 1e-4);
+    assert_approx_eq!(xyz[2], 0., 1e-4);
+    Ok(())
+}
+
+#[test]
+fn nelder_mead_reset_test() -> Result<()> {
+    // after converging, a reset builds a new simplex around the best point, which converges back to the minimum
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let mut optim = NelderMead::new(vars, ParamsNelderMead::default(), model.clone())?;
+    minimise(&mut optim, &model, 500)?;
+    optim.reset();
+    assert!(optim.simplex_size()?.is_infinite());
+    let steps = minimise(&mut optim, &model, 500)?;
+    assert!(steps < 500, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-6);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-6);
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::cg::CgStop;
+use candle_optimisers::newton_cg::{NewtonCg, ParamsNewtonCg};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+/// $x^2 + (y^2 - 1)^2$, with minima at $(0, \\pm 1)$ and negative curvature in $y$ for $|y| < 1 / \\sqrt{3}$
+#[derive(Debug, Clone)]
+struct DoubleWellModel {
+    xy: Var,
+}
+
+impl Model for DoubleWellModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let x = self.xy.as_tensor().get(0)?;
+        let y = self.xy.as_tensor().get(1)?;
+        x.sqr()? + (y.sqr()? - 1.)?.sqr()?
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(optim: &mut NewtonCg<M>, model: &M, max_steps: usize) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+fn rosenbrock(params: ParamsNewtonCg) -> Result<(RosenbrockModel, usize)> {
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let mut optim = NewtonCg::new(vars, params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 200)?;
+    Ok((model, steps))
+}
+
+#[test]
+fn newton_cg_rosenbrock_test() -> Result<()> {
+    let (model, steps) = rosenbrock(ParamsNewtonCg::default())?;
+    assert!(steps < 200, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-5);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn newton_cg_exact_rosenbrock_test() -> Result<()> {
+    // solving the Newton system fully gives Newton's method with a line search, which takes 21 steps from (-1.2, 1)
+    let (model, steps) = rosenbrock(ParamsNewtonCg {
+        cg_tol: Some(1e-8),
+        ..Default::default()
+    })?;
+    assert!(steps < 25, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-5);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn newton_cg_negative_curvature_test() -> Result<()> {
+    // starting where the Hessian is indefinite, a pure Newton step would head for the saddle at (0, 0)
+    let model = DoubleWellModel {
+        xy: Var::new(&[0.5f64, 0.1], &Device::Cpu)?,
+    };
+    let mut optim = NewtonCg::new(
+        vec![model.xy.clone()],
+        ParamsNewtonCg::default(),
+        model.clone(),
+    )?;
+    let loss = model.loss()?;
+    optim.backward_step(&loss)?;
+    assert_eq!(optim.last_cg_stop(), Some(CgStop::NegativeCurvature));
+    minimise(&mut optim, &model, 100)?;
+    let xy = model.xy.to_vec1::<f64>()?;
+    assert_approx_eq!(xy[0], 0., 1e-5);
+    assert_approx_eq!(xy[1], 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn newton_cg_f32_test() -> Result<()> {
+    // the central differences of the Hessian-vector products are accurate enough in single precision
+    let model = DoubleWellModel {
+        xy: Var::new(&[0.5f32, 2.], &Device::Cpu)?,
+    };
+    let params = ParamsNewtonCg {
+        hvp_eps: 1e-2,
+        grad_conv: candle_optimisers::lbfgs::GradConv::MinForce(1e-4),
+        ..Default::default()
+    };
+    let mut optim = NewtonCg::new(vec![model.xy.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 100)?;
+    assert!(steps < 20, "{steps} steps");
+    let xy = model.xy.to_vec1::<f32>()?;
+    assert_approx_eq!(xy[0], 0., 1e-4);
+    assert_approx_eq!(xy[1], 1., 1e-4);
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::novograd::{NovoGrad, ParamsNovoGrad};
+
+#[test]
+fn novograd_first_steps_test() -> Result<()> {
+    // the gradient [3, 4] has norm 5, so the normalised gradient is [0.6, 0.8] on every step
+    // and the first moment grows as 1, then 1 + 0.95
+    let x = Var::new(&[0f32, 0.], &Device::Cpu)?;
+    let coeffs = Tensor::new(&[3f32, 4.], &Device::Cpu)?;
+    let params = ParamsNovoGrad {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let mut optim = NovoGrad::new(vec![x.clone()], params)?;
+    let loss = || x.as_tensor().mul(&coeffs)?.sum_all();
+    optim.backward_step(&loss()?)?;
+    let x_1 = x.to_vec1::<f32>()?;
+    assert_approx_eq!(x_1[0], -0.06, 1e-6);
+    assert_approx_eq!(x_1[1], -0.08, 1e-6);
+    optim.backward_step(&loss()?)?;
+    let x_2 = x.to_vec1::<f32>()?;
+    assert_approx_eq!(x_2[0], -0.06 * 2.95, 1e-6);
+    assert_approx_eq!(x_2[1], -0.08 * 2.95, 1e-6);
+    Ok(())
+}
+
+#[test]
+fn novograd_layerwise_test() -> Result<()> {
+    // each variable is normalised by the norm of its own gradient, so the scale of the gradient does not matter
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let y = Var::new(0f32, &Device::Cpu)?;
+    let params = ParamsNovoGrad {
+        lr: 0.1,
+        grad_averaging: true,
+        ..Default::default()
+    };
+    let mut optim = NovoGrad::new(vec![x.clone(), y.clone()], params)?;
+    for _step in 0..3 {
+        let loss = (x.as_tensor().affine(1000., 0.)? + y.as_tensor().affine(0.001, 0.)?)?;
+        optim.backward_step(&loss)?;
+    }
+    assert_approx_eq!(x.to_scalar::<f32>()?, y.to_scalar::<f32>()?, 1e-6);
+    Ok(())
+}
+
+fn converge(params: ParamsNovoGrad) -> Result<Vec<f32>> {
+    let target = Tensor::new(&[3f32, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f32, 0., 0.], &Device::Cpu)?;
+    let mut optim = NovoGrad::new(vec![x.clone()], params)?;
+    for _step in 0..2000 {
+        let loss = x.as_tensor().sub(&target)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+        // the steps are normalised, so the learning rate is decayed to settle at the minimum
+        optim.set_learning_rate(optim.learning_rate() * 0.998);
+    }
+    Ok(x.to_vec1::<f32>()?)
+}
+
+#[test]
+fn novograd_quadratic_test() -> Result<()> {
+    let params = ParamsNovoGrad {
+        lr: 0.01,
+        grad_averaging: true,
+        ..Default::default()
+    };
+    let x = converge(params)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-2);
+    }
+    Ok(())
+}
+
+#[test]
+fn novograd_decay_test() -> Result<()> {
+    // with weight decay the minimum is shrunk towards zero
+    let params = ParamsNovoGrad {
+        lr: 0.01,
+        weight_decay: Some(0.1),
+        grad_averaging: true,
+        ..Default::default()
+    };
+    let x = converge(params)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert!(x.abs() < t.abs());
+        assert_approx_eq!(x, t, 0.5);
+    }
+    Ok(())
+}
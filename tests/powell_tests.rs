@@ -0,0 +1,100 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::powell::{ParamsPowell, Powell};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+/// a quadratic in 5 dimensions with minimum 0 at (1, ..., 1), coupling neighbouring parameters
+#[derive(Debug, Clone)]
+struct ChainModel {
+    xs: Var,
+}
+
+impl Model for ChainModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let d = (self.xs.as_tensor() - 1.)?;
+        let head = d.narrow(0, 0, 4)?;
+        let tail = d.narrow(0, 1, 4)?;
+        d.sqr()?.sum_all()? + (head - tail)?.sqr()?.sum_all()? * 10.
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(optim: &mut Powell<M>, model: &M, max_steps: usize) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn powell_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let mut optim = Powell::new(vars, ParamsPowell::default(), model.clone())?;
+    let steps = minimise(&mut optim, &model, 100)?;
+    assert!(steps < 100, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-5);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn powell_quadratic_test() -> Result<()> {
+    // the directions become conjugate, so the quadratic is minimised in about as many steps as parameters
+    let model = ChainModel {
+        xs: Var::new(&[0f64, 0., 0., 0., 0.], &Device::Cpu)?,
+    };
+    let mut optim = Powell::new(
+        vec![model.xs.clone()],
+        ParamsPowell::default(),
+        model.clone(),
+    )?;
+    let steps = minimise(&mut optim, &model, 50)?;
+    assert!(steps <= 10, "{steps} steps");
+    for x in model.xs.to_vec1::<f64>()? {
+        assert_approx_eq!(x, 1., 1e-6);
+    }
+    Ok(())
+}
+
+#[test]
+fn powell_f32_test() -> Result<()> {
+    let model = ChainModel {
+        xs: Var::new(&[0f32, 0.5, 2., -1., 3.], &Device::Cpu)?,
+    };
+    let params = ParamsPowell {
+        line_tol: 1e-4,
+        ftol: 1e-6,
+        ..Default::default()
+    };
+    let mut optim = Powell::new(vec![model.xs.clone()], params, model.clone())?;
+    minimise(&mut optim, &model, 50)?;
+    for x in model.xs.to_vec1::<f32>()? {
+        assert_approx_eq!(x, 1., 1e-3);
+    }
+    Ok(())
+}
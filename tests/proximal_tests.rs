@@ -0,0 +1,152 @@
+use anyhow::Result;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::proximal::{
+    Fista, ParamsProximal, ParamsProximalBacktracking, Prox, ProximalBacktracking, ProximalGradient,
+};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/*
+These tests solve a LASSO problem min 0.5 ||A x - b||^2 + lambda ||x||_1 with a sparse solution
+*/
+
+const LAMBDA: f64 = 0.5;
+
+fn problem() -> Result<(Tensor, Tensor)> {
+    // deterministic, fairly ill conditioned design matrix
+    let a: Vec<f64> = (0..100)
+        .map(|i| {
+            let (row, col) = (f64::from(i / 5), f64::from(i % 5));
+            (0.7 * row + 1.3 * col).sin() + 0.5 * (0.1 * row * (col + 1.)).cos()
+        })
+        .collect();
+    let a = Tensor::from_vec(a, (20, 5), &Device::Cpu)?;
+    let x_true = Tensor::new(&[[2f64], [0.], [0.], [-1.], [0.]], &Device::Cpu)?;
+    let b = a.matmul(&x_true)?;
+    Ok((a, b))
+}
+
+/// the smooth part of the objective
+fn smooth_loss(a: &Tensor, b: &Tensor, x: &Tensor) -> Result<Tensor> {
+    Ok((a.matmul(x)? - b)?.sqr()?.sum_all()?.affine(0.5, 0.)?)
+}
+
+fn objective(a: &Tensor, b: &Tensor, x: &Tensor) -> Result<f64> {
+    let l1 = x.abs()?.sum_all()?.to_scalar::<f64>()?;
+    Ok(smooth_loss(a, b, x)?.to_scalar::<f64>()? + LAMBDA * l1)
+}
+
+fn params(a: &Tensor) -> Result<ParamsProximal> {
+    // the squared Frobenius norm bounds the Lipschitz constant of the gradient
+    let lipschitz = a.sqr()?.sum_all()?.to_scalar::<f64>()?;
+    Ok(ParamsProximal {
+        lr: lipschitz.recip(),
+        prox: Prox::L1(LAMBDA),
+    })
+}
+
+fn run_fista(a: &Tensor, b: &Tensor, steps: usize) -> Result<Tensor> {
+    let x = Var::zeros((5, 1), candle_core::DType::F64, &Device::Cpu)?;
+    let mut optim = Fista::new(vec![x.clone()], params(a)?)?;
+    for _ in 0..steps {
+        optim.backward_step(&smooth_loss(a, b, &x)?)?;
+    }
+    Ok(optim.iterates()[0].clone())
+}
+
+fn run_ista(a: &Tensor, b: &Tensor, steps: usize) -> Result<Tensor> {
+    let x = Var::zeros((5, 1), candle_core::DType::F64, &Device::Cpu)?;
+    let mut optim = ProximalGradient::new(vec![x.clone()], params(a)?)?;
+    for _ in 0..steps {
+        optim.backward_step(&smooth_loss(a, b, &x)?)?;
+    }
+    Ok(x.as_tensor().clone())
+}
+
+#[test]
+fn fista_lasso_test() -> Result<()> {
+    let (a, b) = problem()?;
+    let optimum = objective(&a, &b, &run_fista(&a, &b, 5000)?)?;
+    let fista_gap = objective(&a, &b, &run_fista(&a, &b, 100)?)? - optimum;
+    let ista_gap = objective(&a, &b, &run_ista(&a, &b, 100)?)? - optimum;
+    assert!(fista_gap >= -1e-12 && ista_gap >= -1e-12);
+    assert!(
+        fista_gap < 0.1 * ista_gap,
+        "fista gap {fista_gap}, ista gap {ista_gap}"
+    );
+    Ok(())
+}
+
+#[test]
+fn lasso_sparsity_test() -> Result<()> {
+    let (a, b) = problem()?;
+    let x = run_fista(&a, &b, 5000)?.flatten_all()?.to_vec1::<f64>()?;
+    // the L1 penalty shrinks the solution but keeps the zeros exact
+    assert!(x[0] > 1. && x[3] < -0.5, "{x:?}");
+    assert_eq!([x[1], x[2], x[4]], [0., 0., 0.], "{x:?}");
+    Ok(())
+}
+
+/// the smooth part of the LASSO objective as a model, for backtracking
+#[derive(Debug, Clone)]
+struct LassoModel {
+    a: Tensor,
+    b: Tensor,
+    x: Var,
+}
+
+impl Model for LassoModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (self.a.matmul(self.x.as_tensor())? - &self.b)?
+            .sqr()?
+            .sum_all()?
+            .affine(0.5, 0.)
+    }
+}
+
+/// run with backtracking from a step size far above 1 / L, returning the iterate and the number of steps taken
+fn run_backtracking(a: &Tensor, b: &Tensor, accelerated: bool) -> Result<(Tensor, usize)> {
+    let model = LassoModel {
+        a: a.clone(),
+        b: b.clone(),
+        x: Var::zeros((5, 1), candle_core::DType::F64, &Device::Cpu)?,
+    };
+    let params = ParamsProximalBacktracking {
+        lr: 10.,
+        prox: Prox::L1(LAMBDA),
+        accelerated,
+        ..Default::default()
+    };
+    let mut optim = ProximalBacktracking::new(vec![model.x.clone()], params, model.clone())?;
+    let mut loss = model.loss()?;
+    for step in 0..20_000 {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => {
+                let iterate = optim.iterates()?.expect("set by the steps")[0].clone();
+                return Ok((iterate, step));
+            }
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    anyhow::bail!("backtracking did not converge");
+}
+
+#[test]
+fn backtracking_lasso_test() -> Result<()> {
+    let (a, b) = problem()?;
+    let optimum = objective(&a, &b, &run_fista(&a, &b, 5000)?)?;
+    let (fista, fista_steps) = run_backtracking(&a, &b, true)?;
+    let (ista, ista_steps) = run_backtracking(&a, &b, false)?;
+    for x in [fista, ista] {
+        assert!((objective(&a, &b, &x)? - optimum).abs() < 1e-8);
+        let x = x.flatten_all()?.to_vec1::<f64>()?;
+        assert_eq!([x[1], x[2], x[4]], [0., 0., 0.], "{x:?}");
+    }
+    assert!(
+        fista_steps < ista_steps,
+        "fista {fista_steps} steps, ista {ista_steps} steps"
+    );
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::pso::{ParamsPso, Pso};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+/// the Rastrigin function in 2 dimensions, with many local minima and the global minimum 0 at the origin
+#[derive(Debug, Clone)]
+struct RastriginModel {
+    xs: Var,
+}
+
+impl Model for RastriginModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let x = self.xs.as_tensor();
+        let ripples = ((x * (2. * std::f64::consts::PI))?.cos()? * 10.)?;
+        ((x.sqr()? - ripples)? + 10.)?.sum_all()
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(optim: &mut Pso<M>, model: &M, max_steps: usize) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn pso_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let params = ParamsPso {
+        particles: 30,
+        initial_spread: 2.,
+        ..Default::default()
+    };
+    let mut optim = Pso::new(vars, params, model.clone())?;
+    minimise(&mut optim, &model, 2000)?;
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-4);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-4);
+    Ok(())
+}
+
+#[test]
+fn pso_rastrigin_test() -> Result<()> {
+    // the swarm explores past the local minima around its start at (2.2, -1.8)
+    let model = RastriginModel {
+        xs: Var::new(&[2.2f64, -1.8], &Device::Cpu)?,
+    };
+    let params = ParamsPso {
+        particles: 40,
+        initial_spread: 3.,
+        ..Default::default()
+    };
+    let mut optim = Pso::new(vec![model.xs.clone()], params, model.clone())?;
+    let steps = minimise(&mut optim, &model, 1000)?;
+    assert!(steps < 1000, "{steps} steps");
+    for x in model.xs.to_vec1::<f64>()? {
+        assert_approx_eq!(x, 0., 1e-6);
+    }
+    Ok(())
+}
+
+#[test]
+fn pso_f32_test() -> Result<()> {
+    let model = RastriginModel {
+        xs: Var::new(&[0.3f32, -0.2], &Device::Cpu)?,
+    };
+    let params = ParamsPso {
+        initial_spread: 0.4,
+        max_velocity: Some(0.2),
+        swarm_tol: 1e-5,
+        ..Default::default()
+    };
+    let mut optim = Pso::new(vec![model.xs.clone()], params, model.clone())?;
+    minimise(&mut optim, &model, 1000)?;
+    assert_eq!(optim.positions().dims(), [20, 2]);
+    for x in model.xs.to_vec1::<f32>()? {
+        assert_approx_eq!(x, 0., 1e-3);
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::ranger::{ParamsRanger, Ranger};
+
+/* Ranger is not in pytorch, so these results are not checked against a reference implementation:
+instead the linear regression should converge. */
+
+fn linear_regression(params: ParamsRanger, steps: usize) -> Result<(Var, Var, f32)> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Ranger::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..steps {
+        let loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    let loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    Ok((w, b, loss.to_scalar::<f32>()?))
+}
+
+#[test]
+fn ranger_test() -> Result<()> {
+    // the weight has a single output, so centralization would remove its gradient entirely, and as for RAdam
+    // the first few steps are plain momentum steps, so the learning rate must be small enough for these to be stable
+    let params = ParamsRanger {
+        lr: 0.01,
+        centralize: false,
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 5000)?;
+    assert!(loss < 0.1, "loss {loss}");
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    Ok(())
+}
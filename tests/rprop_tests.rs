@@ -0,0 +1,87 @@
+use candle_core::test_utils::{to_vec0_round, to_vec2_round};
+
+use anyhow::Result;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::rprop::{ParamsRprop, Rprop};
+
+/* The expected results of these tests follow the update of the following PyTorch code,
+   reproduced step by step in single precision.
+    import torch
+    from torch import optim
+
+    w_gen = torch.tensor([[3., 1.]])
+    b_gen = torch.tensor([-2.])
+
+    sample_xs = torch.tensor([[2., 1.], [7., 4.], [-4., 12.], [5., 8.]])
+    sample_ys = sample_xs.matmul(w_gen.t()) + b_gen
+
+    m = torch.nn.Linear(2, 1)
+    with torch.no_grad():
+        m.weight.zero_()
+        m.bias.zero_()
+    optimiser = optim.Rprop(m.parameters())
+    for _step in range(100):
+        optimiser.zero_grad()
+        ys = m(sample_xs)
+        loss = ((ys - sample_ys)**2).sum()
+        loss.backward()
+        optimiser.step()
+    print(m.weight)
+    print(m.bias)
+*/
+#[test]
+fn rprop_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsRprop::default();
+    // Now use backprop to run a linear regression between samples and get the coefficients back.
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Rprop::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.9994, 0.9994]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -1.9935);
+    Ok(())
+}
+
+/* As above, with
+    optimiser = optim.Rprop(m.parameters(), lr=0.01, etas=(0.3, 1.5), step_sizes=(1e-3, 1.))
+*/
+#[test]
+fn rprop_etas_step_sizes_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let params = ParamsRprop {
+        lr: 0.01,
+        etas: (0.3, 1.5),
+        step_sizes: (1e-3, 1.),
+    };
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Rprop::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..100 {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    assert_eq!(to_vec2_round(&w, 4)?, &[[2.9974, 0.9945]]);
+    assert_eq!(to_vec0_round(&b, 4)?, -1.954);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::shampoo::{Grafting, ParamsShampoo, Shampoo};
+
+/* Shampoo is not in pytorch, so these results are not checked against a reference implementation:
+instead the linear regression should converge, with and without grafting. */
+
+fn linear_regression(params: ParamsShampoo, steps: usize) -> Result<(Var, Var, f32)> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Shampoo::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    let mut loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    for _step in 0..steps {
+        optim.backward_step(&loss)?;
+        loss = lin.forward(&sample_xs)?.sub(&sample_ys)?.sqr()?.sum_all()?;
+    }
+    Ok((w, b, loss.to_scalar::<f32>()?))
+}
+
+fn assert_converged(w: &Var, b: &Var, loss: f32) -> Result<()> {
+    assert!(loss < 0.1, "loss {loss}");
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    Ok(())
+}
+
+#[test]
+fn shampoo_test() -> Result<()> {
+    let params = ParamsShampoo {
+        lr: 1.,
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 1000)?;
+    assert_converged(&w, &b, loss)
+}
+
+#[test]
+fn shampoo_frequency_test() -> Result<()> {
+    let params = ParamsShampoo {
+        lr: 0.004,
+        precondition_frequency: 10,
+        grafting: Grafting::Sgd,
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 1000)?;
+    assert_converged(&w, &b, loss)
+}
+
+#[test]
+fn shampoo_sgd_graft_test() -> Result<()> {
+    let params = ParamsShampoo {
+        lr: 0.004,
+        grafting: Grafting::Sgd,
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 1000)?;
+    assert_converged(&w, &b, loss)
+}
+
+#[test]
+fn shampoo_adam_graft_test() -> Result<()> {
+    let params = ParamsShampoo {
+        lr: 0.1,
+        grafting: Grafting::Adam {
+            beta_1: 0.9,
+            beta_2: 0.999,
+            eps: 1e-8,
+        },
+        ..Default::default()
+    };
+    let (w, b, loss) = linear_regression(params, 1000)?;
+    assert_converged(&w, &b, loss)
+}
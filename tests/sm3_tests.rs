@@ -0,0 +1,59 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{DType, Device, Tensor, Var};
+use candle_nn::{Embedding, Module, Optimizer};
+use candle_optimisers::sm3::{ParamsSm3, Sm3};
+
+/* SM3 is not in pytorch, so these results are not checked against a reference implementation:
+instead the optimiser should converge. */
+
+#[test]
+fn sm3_vector_test() -> Result<()> {
+    // with a full accumulator the first step is lr times the sign of the gradient, as for AdaGrad
+    let x = Var::new(&[1f32, -2.], &Device::Cpu)?;
+    let mut optim = Sm3::new(vec![x.clone()], ParamsSm3::default())?;
+    let coeffs = Tensor::new(&[3f32, -0.5], &Device::Cpu)?;
+    optim.backward_step(&x.as_tensor().mul(&coeffs)?.sum_all()?)?;
+    let x = x.to_vec1::<f32>()?;
+    assert_approx_eq!(x[0], 0.9);
+    assert_approx_eq!(x[1], -1.9);
+    Ok(())
+}
+
+#[test]
+fn sm3_embedding_test() -> Result<()> {
+    // an embedding table of which only some rows are looked up each step
+    let target = Tensor::new(
+        &[
+            [1f32, -1., 0.5],
+            [2., 0., -2.],
+            [-0.5, 1.5, 1.],
+            [0., 3., -1.],
+        ],
+        &Device::Cpu,
+    )?;
+    let table = Var::zeros((4, 3), DType::F32, &Device::Cpu)?;
+    let embedding = Embedding::new(table.as_tensor().clone(), 3);
+    let params = ParamsSm3 {
+        lr: 0.5,
+        beta_1: Some(0.5),
+        ..Default::default()
+    };
+    let mut optim = Sm3::new(vec![table.clone()], params)?;
+    let batches = [[0u32, 1], [2, 3], [1, 3], [0, 2]];
+    for step in 0..2000 {
+        let ids = Tensor::new(&batches[step % batches.len()], &Device::Cpu)?;
+        let loss = embedding
+            .forward(&ids)?
+            .sub(&target.index_select(&ids, 0)?)?
+            .sqr()?
+            .sum_all()?;
+        optim.backward_step(&loss)?;
+    }
+    for (row, target) in table.to_vec2::<f32>()?.iter().zip(target.to_vec2::<f32>()?) {
+        for (x, t) in row.iter().zip(target) {
+            assert_approx_eq!(x, t, 1e-2);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::{Linear, Module, Optimizer};
+use candle_optimisers::sophia::{HessianEstimator, ParamsSophia, Sophia};
+
+/* Sophia is not in pytorch, so these results are not checked against a reference implementation:
+instead the optimiser should converge, with the Hessian estimated by each of the estimators. */
+
+#[test]
+fn sophia_h_quadratic_test() -> Result<()> {
+    // curvatures differing by a factor of 1000, so a step scaled by the Hessian is needed for fast convergence
+    let curvature = Tensor::new(&[0.01f64, 1., 10.], &Device::Cpu)?;
+    let target = Tensor::new(&[3f64, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+    let loss_fn = || {
+        x.as_tensor()
+            .sub(&target)?
+            .sqr()?
+            .mul(&curvature)?
+            .sum_all()
+    };
+    let params = ParamsSophia {
+        lr: 0.01,
+        weight_decay: None,
+        ..Default::default()
+    };
+    let mut optim = Sophia::new(vec![x.clone()], params)?;
+    for _step in 0..2000 {
+        optim.backward_step_with_hessian(&loss_fn()?, loss_fn)?;
+    }
+    for (x, t) in x.to_vec1::<f64>()?.iter().zip([3., -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-2);
+    }
+    Ok(())
+}
+
+#[test]
+fn sophia_g_regression_test() -> Result<()> {
+    // Generate some linear data, y = 3.x1 + x2 - 2.
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let params = ParamsSophia {
+        lr: 0.01,
+        weight_decay: None,
+        estimator: HessianEstimator::GaussNewtonBartlett { batch_size: 4 },
+        ..Default::default()
+    };
+    let mut optim = Sophia::new(vec![w.clone(), b.clone()], params)?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    // the model predicts a unit variance gaussian about its output, so labels are sampled by adding noise
+    let sampled_loss = || {
+        let ys = lin.forward(&sample_xs)?;
+        let sampled = (ys.detach() + ys.randn_like(0., 1.)?)?;
+        ys.sub(&sampled)?.sqr()?.mean_all()? * 0.5
+    };
+    for _step in 0..2000 {
+        let loss = lin
+            .forward(&sample_xs)?
+            .sub(&sample_ys)?
+            .sqr()?
+            .mean_all()?;
+        optim.backward_step_with_hessian(&loss, sampled_loss)?;
+    }
+    let w = w.to_vec2::<f32>()?;
+    assert_approx_eq!(w[0][0], 3., 0.1);
+    assert_approx_eq!(w[0][1], 1., 0.1);
+    assert_approx_eq!(b.to_scalar::<f32>()?, -2., 0.2);
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use candle_core::test_utils::to_vec2_round;
+
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor, Var};
+use candle_nn::{Embedding, Module, Optimizer};
+use candle_optimisers::{
+    adam::{Adam, ParamsAdam},
+    sparse_adam::{ParamsSparseAdam, SparseAdam},
+};
+
+fn embedding_loss(embedding: &Embedding, target: &Tensor, ids: &[u32]) -> Result<Tensor> {
+    let ids = Tensor::new(ids, &Device::Cpu)?;
+    Ok(embedding
+        .forward(&ids)?
+        .sub(&target.index_select(&ids, 0)?)?
+        .sqr()?
+        .sum_all()?)
+}
+
+/* The expected results of this test follow the update of the following PyTorch code,
+   reproduced step by step in single precision.
+    import torch
+    from torch import optim
+
+    target = torch.tensor([[1., -1.], [2., 0.5], [-0.5, 1.5], [0., 3.]])
+    batches = [[0, 1], [1, 3], [0, 1], [1, 3]]
+
+    m = torch.nn.Embedding(4, 2, sparse=True)
+    with torch.no_grad():
+        m.weight.zero_()
+    optimiser = optim.SparseAdam(m.parameters(), lr=0.01)
+    for step in range(100):
+        optimiser.zero_grad()
+        ids = torch.tensor(batches[step % 4])
+        loss = ((m(ids) - target[ids])**2).sum()
+        loss.backward()
+        optimiser.step()
+    print(m.weight)
+*/
+#[test]
+fn sparse_adam_test() -> Result<()> {
+    let target = Tensor::new(
+        &[[1f32, -1.], [2., 0.5], [-0.5, 1.5], [0., 3.]],
+        &Device::Cpu,
+    )?;
+    let table = Var::zeros((4, 2), DType::F32, &Device::Cpu)?;
+    let embedding = Embedding::new(table.as_tensor().clone(), 2);
+    let params = ParamsSparseAdam {
+        lr: 0.01,
+        ..Default::default()
+    };
+    let mut optim = SparseAdam::new(vec![table.clone()], params)?;
+    let batches = [[0u32, 1], [1, 3], [0, 1], [1, 3]];
+    for step in 0..100 {
+        optim.backward_step(&embedding_loss(&embedding, &target, &batches[step % 4])?)?;
+    }
+    // the row never looked up is untouched
+    assert_eq!(
+        to_vec2_round(&table, 4)?,
+        &[[0.5528, -0.5528], [0.9016, 0.5039], [0., 0.], [0., 0.5964]]
+    );
+    Ok(())
+}
+
+#[test]
+fn sparse_adam_momentum_test() -> Result<()> {
+    // after a row stops being looked up dense Adam keeps moving it with its momentum, while SparseAdam leaves it
+    let target = Tensor::new(&[[1f32, -1.], [2., 0.5]], &Device::Cpu)?;
+    let sparse_table = Var::zeros((2, 2), DType::F32, &Device::Cpu)?;
+    let sparse_embedding = Embedding::new(sparse_table.as_tensor().clone(), 2);
+    let mut sparse = SparseAdam::new(vec![sparse_table.clone()], ParamsSparseAdam::default())?;
+    let dense_table = Var::zeros((2, 2), DType::F32, &Device::Cpu)?;
+    let dense_embedding = Embedding::new(dense_table.as_tensor().clone(), 2);
+    let mut dense = Adam::new(vec![dense_table.clone()], ParamsAdam::default())?;
+
+    for ids in [[0u32, 1], [1, 1], [1, 1]] {
+        sparse.backward_step(&embedding_loss(&sparse_embedding, &target, &ids)?)?;
+        dense.backward_step(&embedding_loss(&dense_embedding, &target, &ids)?)?;
+    }
+    // both take the same first step of lr towards the target
+    assert_eq!(
+        to_vec2_round(&sparse_table.as_tensor().get(0)?.unsqueeze(0)?, 4)?,
+        &[[0.001, -0.001]]
+    );
+    let dense_row = dense_table.as_tensor().get(0)?.to_vec1::<f32>()?;
+    assert!(dense_row[0] > 0.002);
+    assert!(dense_row[1] < -0.002);
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, TensorId, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::{
+    adam::{Adam, ParamsAdam},
+    esgd::{ParamsSGD, SGD},
+    StepWithGrads,
+};
+
+/// loss of the quadratic sum_i (x_i - c_i)^2 with c = [1, -2, 3]
+fn quadratic(x: &[f64]) -> f64 {
+    x.iter()
+        .zip([1., -2., 3.])
+        .map(|(x, c)| (x - c).powi(2))
+        .sum()
+}
+
+/// central finite difference gradient of the quadratic
+fn finite_difference(x: &Var) -> Result<HashMap<TensorId, Tensor>> {
+    let values = x.to_vec1::<f64>()?;
+    let h = 1e-6;
+    let grad: Vec<f64> = (0..values.len())
+        .map(|i| {
+            let mut plus = values.clone();
+            let mut minus = values.clone();
+            plus[i] += h;
+            minus[i] -= h;
+            (quadratic(&plus) - quadratic(&minus)) / (2. * h)
+        })
+        .collect();
+    Ok(HashMap::from([(x.id(), Tensor::new(grad, &Device::Cpu)?)]))
+}
+
+#[test]
+fn sgd_finite_difference_test() -> Result<()> {
+    let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+    let params = ParamsSGD {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let mut optim = SGD::new(vec![x.clone()], params)?;
+    for _ in 0..100 {
+        optim.step_with_grads(finite_difference(&x)?)?;
+    }
+    for (x, c) in x.to_vec1::<f64>()?.iter().zip([1., -2., 3.]) {
+        assert_approx_eq!(x, c, 1e-6);
+    }
+    Ok(())
+}
+
+#[test]
+fn adam_finite_difference_test() -> Result<()> {
+    let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+    let params = ParamsAdam {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let mut optim = Adam::new(vec![x.clone()], params)?;
+    for _ in 0..500 {
+        optim.step_with_grads(finite_difference(&x)?)?;
+    }
+    for (x, c) in x.to_vec1::<f64>()?.iter().zip([1., -2., 3.]) {
+        assert_approx_eq!(x, c, 1e-2);
+    }
+    Ok(())
+}
+
+#[test]
+fn invalid_grads_test() -> Result<()> {
+    let x = Var::new(&[0f64, 0., 0.], &Device::Cpu)?;
+    let mut optim = SGD::new(vec![x.clone()], ParamsSGD::default())?;
+    // wrong shape
+    let grads = HashMap::from([(x.id(), Tensor::new(&[1f64, 1.], &Device::Cpu)?)]);
+    assert!(optim.step_with_grads(grads).is_err());
+    // unknown variable
+    let other = Var::new(&[0f64], &Device::Cpu)?;
+    let grads = HashMap::from([(other.id(), Tensor::new(&[1f64], &Device::Cpu)?)]);
+    assert!(optim.step_with_grads(grads).is_err());
+    // no step is taken on either error, and an empty map is a step without gradients
+    optim.step_with_grads(HashMap::new())?;
+    assert_eq!(x.to_vec1::<f64>()?, [0., 0., 0.]);
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Result as CResult, Tensor, Var};
+use candle_optimisers::trust_region::{ParamsTrustRegion, TrustRegion};
+use candle_optimisers::{LossOptimizer, Model, ModelOutcome};
+
+/// the 2D Rosenbrock function, with minimum 0 at (1, 1)
+#[derive(Debug, Clone)]
+struct RosenbrockModel {
+    x_pos: Var,
+    y_pos: Var,
+}
+
+impl Model for RosenbrockModel {
+    fn loss(&self) -> CResult<Tensor> {
+        (1. - self.x_pos.as_tensor())?.sqr()?
+            + 100. * (self.y_pos.as_tensor() - self.x_pos.as_tensor().sqr()?)?.sqr()?
+    }
+}
+
+/// $x^2 + (y^2 - 1)^2$, with minima at $(0, \\pm 1)$ and negative curvature in $y$ for $|y| < 1 / \\sqrt{3}$
+#[derive(Debug, Clone)]
+struct DoubleWellModel {
+    xy: Var,
+}
+
+impl Model for DoubleWellModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let x = self.xy.as_tensor().get(0)?;
+        let y = self.xy.as_tensor().get(1)?;
+        x.sqr()? + (y.sqr()? - 1.)?.sqr()?
+    }
+}
+
+/// a stiff loss, $e^{10 x} - 10 x + 10^4 (y - x)^2$, with minimum at (0, 0) and a Newton step from far to the right
+/// that overshoots to where the exponential is negligible
+#[derive(Debug, Clone)]
+struct StiffModel {
+    xy: Var,
+}
+
+impl Model for StiffModel {
+    fn loss(&self) -> CResult<Tensor> {
+        let x = self.xy.as_tensor().get(0)?;
+        let y = self.xy.as_tensor().get(1)?;
+        ((x.affine(10., 0.)?.exp()? - x.affine(10., 0.)?)? + ((y - &x)?.sqr()? * 1e4)?)?.sum_all()
+    }
+}
+
+/// run until convergence, returning the number of steps taken
+fn minimise<M: Model>(optim: &mut TrustRegion<M>, model: &M, max_steps: usize) -> Result<usize> {
+    let mut loss = model.loss()?;
+    for step in 0..max_steps {
+        match optim.backward_step(&loss)? {
+            ModelOutcome::Converged(_, _) => return Ok(step),
+            ModelOutcome::Stepped(new_loss, _) | ModelOutcome::Truncated(new_loss, _) => {
+                loss = new_loss;
+            }
+        }
+    }
+    Ok(max_steps)
+}
+
+#[test]
+fn trust_region_rosenbrock_test() -> Result<()> {
+    let model = RosenbrockModel {
+        x_pos: Var::new(-1.2f64, &Device::Cpu)?,
+        y_pos: Var::new(1f64, &Device::Cpu)?,
+    };
+    let vars = vec![model.x_pos.clone(), model.y_pos.clone()];
+    let mut optim = TrustRegion::new(vars, ParamsTrustRegion::default(), model.clone())?;
+    let steps = minimise(&mut optim, &model, 200)?;
+    assert!(steps < 200, "{steps} steps");
+    assert_approx_eq!(model.x_pos.to_scalar::<f64>()?, 1., 1e-5);
+    assert_approx_eq!(model.y_pos.to_scalar::<f64>()?, 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn trust_region_negative_curvature_test() -> Result<()> {
+    // starting where the Hessian is indefinite, the step follows the negative curvature away from the saddle
+    let model = DoubleWellModel {
+        xy: Var::new(&[0.5f64, 0.1], &Device::Cpu)?,
+    };
+    let mut optim = TrustRegion::new(
+        vec![model.xy.clone()],
+        ParamsTrustRegion::default(),
+        model.clone(),
+    )?;
+    minimise(&mut optim, &model, 100)?;
+    let xy = model.xy.to_vec1::<f64>()?;
+    assert_approx_eq!(xy[0], 0., 1e-5);
+    assert_approx_eq!(xy[1], 1., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn trust_region_stiff_test() -> Result<()> {
+    let model = StiffModel {
+        xy: Var::new(&[2f64, 2.], &Device::Cpu)?,
+    };
+    let mut optim = TrustRegion::new(
+        vec![model.xy.clone()],
+        ParamsTrustRegion::default(),
+        model.clone(),
+    )?;
+    let loss = model.loss()?;
+    optim.backward_step(&loss)?;
+    // the first step is limited to the initial radius
+    let xy = model.xy.to_vec1::<f64>()?;
+    assert!((xy[0] - 2.).hypot(xy[1] - 2.) <= 1. + 1e-9, "{xy:?}");
+    let steps = minimise(&mut optim, &model, 200)?;
+    assert!(steps < 200, "{steps} steps");
+    let xy = model.xy.to_vec1::<f64>()?;
+    assert_approx_eq!(xy[0], 0., 1e-5);
+    assert_approx_eq!(xy[1], 0., 1e-5);
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor, TensorId, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::{
+    adam::{Adam, ParamsAdam},
+    adamax::{Adamax, ParamsAdaMax},
+    esgd::{ParamsSGD, SGD},
+    rmsprop::{ParamsRMSprop, RMSprop},
+    OptimVars,
+};
+
+fn make_vars() -> Result<Vec<Var>> {
+    Ok(vec![
+        Var::new(&[[1f32, 2.]], &Device::Cpu)?,
+        Var::new(3f32, &Device::Cpu)?,
+        // non float vars are filtered out on construction
+        Var::from_tensor(&Tensor::zeros(2, DType::U32, &Device::Cpu)?)?,
+        Var::new(&[4f32, 5., 6.], &Device::Cpu)?,
+        Var::new(7f32, &Device::Cpu)?,
+    ])
+}
+
+fn ids<O: OptimVars>(optim: &O) -> Vec<TensorId> {
+    optim.vars().iter().map(|v| v.id()).collect()
+}
+
+#[test]
+fn construction_order_test() -> Result<()> {
+    let vars = make_vars()?;
+    let expected: Vec<TensorId> = vars
+        .iter()
+        .filter(|v| v.dtype().is_float())
+        .map(|v| v.id())
+        .collect();
+
+    let adam = Adam::new(vars.clone(), ParamsAdam::default())?;
+    assert_eq!(ids(&adam), expected);
+    let amsgrad = Adam::new(
+        vars.clone(),
+        ParamsAdam {
+            amsgrad: true,
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(ids(&amsgrad), expected);
+    let adamax = Adamax::new(vars.clone(), ParamsAdaMax::default())?;
+    assert_eq!(ids(&adamax), expected);
+    let sgd = SGD::new(vars.clone(), ParamsSGD::default())?;
+    assert_eq!(ids(&sgd), expected);
+    let rms = RMSprop::new(
+        vars,
+        ParamsRMSprop {
+            centered: true,
+            momentum: Some(0.1),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(ids(&rms), expected);
+    Ok(())
+}
+
+#[test]
+fn stable_after_step_test() -> Result<()> {
+    let vars = make_vars()?;
+    let mut adam = Adam::new(vars.clone(), ParamsAdam::default())?;
+    let before = ids(&adam);
+    let loss = vars[0]
+        .as_tensor()
+        .sum_all()?
+        .add(&vars[3].as_tensor().sqr()?.sum_all()?)?;
+    for _ in 0..3 {
+        adam.backward_step(&loss)?;
+        assert_eq!(ids(&adam), before);
+    }
+    Ok(())
+}
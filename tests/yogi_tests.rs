@@ -0,0 +1,67 @@
+use anyhow::Result;
+use assert_approx_eq::assert_approx_eq;
+use candle_core::{Device, Tensor, Var};
+use candle_nn::Optimizer;
+use candle_optimisers::{
+    yogi::{ParamsYogi, Yogi},
+    Decay,
+};
+
+fn quadratic_loss(x: &Var, target: &Tensor) -> Result<Tensor> {
+    Ok(x.as_tensor().sub(target)?.sqr()?.sum_all()?)
+}
+
+fn converge(params: ParamsYogi, steps: usize) -> Result<Vec<f32>> {
+    let target = Tensor::new(&[3f32, -1., 0.5], &Device::Cpu)?;
+    let x = Var::new(&[0f32, 0., 0.], &Device::Cpu)?;
+    let mut optim = Yogi::new(vec![x.clone()], params)?;
+    for _step in 0..steps {
+        let loss = quadratic_loss(&x, &target)?;
+        optim.backward_step(&loss)?;
+    }
+    Ok(x.to_vec1::<f32>()?)
+}
+
+#[test]
+fn yogi_first_step_test() -> Result<()> {
+    // with g = 2 the squared gradient is above the initial accumulator, so v = 1e-6 + 0.001 * 4
+    // and the bias corrected step is lr * 2 / (sqrt(4.001) + eps)
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let mut optim = Yogi::new(vec![x.clone()], ParamsYogi::default())?;
+    let loss = x.as_tensor().affine(2., 0.)?;
+    optim.backward_step(&loss)?;
+    let expected = -0.01 * 2. / (4.001f32.sqrt() + 1e-3);
+    assert_approx_eq!(x.to_scalar::<f32>()?, expected, 1e-6);
+    Ok(())
+}
+
+#[test]
+fn yogi_quadratic_test() -> Result<()> {
+    let params = ParamsYogi {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let x = converge(params, 500)?;
+    for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+        assert_approx_eq!(x, t, 1e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn yogi_decay_test() -> Result<()> {
+    // with weight decay the minimum is shrunk towards zero
+    for decay in [Decay::WeightDecay(0.1), Decay::DecoupledWeightDecay(0.1)] {
+        let params = ParamsYogi {
+            lr: 0.1,
+            weight_decay: Some(decay),
+            ..Default::default()
+        };
+        let x = converge(params, 500)?;
+        for (x, t) in x.iter().zip([3f32, -1., 0.5]) {
+            assert!(x.abs() < t.abs());
+            assert_approx_eq!(x, t, 0.2);
+        }
+    }
+    Ok(())
+}